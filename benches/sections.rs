@@ -0,0 +1,76 @@
+//! Compares loading one struct per section from a large config through the whole
+//! tree (`load_str`) against loading only the needed section (`load_str_section`).
+//!
+//! Run with `cargo bench --bench sections`.
+
+use std::time::{Duration, Instant};
+
+use serde::Deserialize;
+use unconfig::Config;
+
+const SECTIONS: usize = 300;
+const FIELDS: usize = 20;
+
+#[derive(Deserialize)]
+#[allow(dead_code)]
+struct Section {
+    field_0: String,
+    field_1: u64,
+}
+
+#[derive(Deserialize)]
+#[allow(dead_code)]
+struct UpperSection {
+    section_150: Section,
+}
+
+fn generate() -> &'static str {
+    let mut src = String::new();
+
+    for section in 0..SECTIONS {
+        src += &format!("section_{section}:\n");
+
+        for field in 0..FIELDS {
+            if field % 2 == 0 {
+                src += &format!("  field_{field}: \"value ${{UNSET_VAR:{section}}}\"\n");
+            } else {
+                src += &format!("  field_{field}: {field}\n");
+            }
+        }
+    }
+
+    Box::leak(src.into_boxed_str())
+}
+
+fn measure(name: &str, iterations: u32, f: impl Fn()) -> Duration {
+    // Warm up the parse cache so both variants measure the per-struct work only
+    f();
+
+    let start = Instant::now();
+    for _ in 0..iterations {
+        f();
+    }
+    let elapsed = start.elapsed() / iterations;
+
+    println!("{name:>16}: {elapsed:?} per struct");
+
+    elapsed
+}
+
+fn main() {
+    let src = generate();
+    let iterations = 50;
+
+    let whole = measure("whole tree", iterations, || {
+        UpperSection::load_str(src).unwrap();
+    });
+    let section = measure("section only", iterations, || {
+        UpperSection::load_str_section(src, "section_150").unwrap();
+    });
+
+    println!(
+        "{:>16}: {:.1}x",
+        "speedup",
+        whole.as_secs_f64() / section.as_secs_f64()
+    );
+}
@@ -2,19 +2,30 @@ use std::{env::var, path::Path};
 
 use quote::ToTokens;
 use syn::{
+    meta::ParseNestedMeta,
     parse::{Parse, ParseStream, Result},
     punctuated::Punctuated,
-    Ident, Lit, Path as SynPath, Token,
+    Attribute, Expr, ExprArray, ExprLit, Ident, Lit, LitBool, LitStr, Meta, Path as SynPath, Token,
 };
 
 mod kw {
     syn::custom_keyword!(path);
     syn::custom_keyword!(parse);
+    syn::custom_keyword!(config);
+    syn::custom_keyword!(watch);
+    syn::custom_keyword!(timeout);
+    syn::custom_keyword!(limits);
 }
 
 pub struct ConfigArgs {
     pub config_idents: Vec<Ident>,
     pub path: Option<SynPath>,
+    // `watch`: the statics are `ConfigWatcher`s
+    pub watch: bool,
+    // `timeout = 30`: seconds each config may take to load, fractions allowed
+    pub timeout: Option<f64>,
+    // `limits = "config.yml"`: the file whose `limits` section is applied first
+    pub limits: Option<LitStr>,
 }
 
 impl Parse for ConfigArgs {
@@ -32,6 +43,34 @@ impl Parse for ConfigArgs {
             .parse::<Token![,]>()
             .and_then(|_| input.parse::<kw::parse>())
             .and_then(|_| input.parse::<Token![=]>());
+        let watch = input.parse::<kw::watch>().is_ok();
+        if watch {
+            input.parse::<Token![,]>()?;
+        }
+        let timeout = if input.peek(kw::timeout) {
+            input.parse::<kw::timeout>()?;
+            input.parse::<Token![=]>()?;
+            let timeout = match input.parse::<Lit>()? {
+                Lit::Int(secs) => secs.base10_parse::<u64>()? as f64,
+                Lit::Float(secs) => secs.base10_parse()?,
+                other => return Err(syn::Error::new_spanned(other, "expected seconds")),
+            };
+            input.parse::<Token![,]>()?;
+
+            Some(timeout)
+        } else {
+            None
+        };
+        let limits = if input.peek(kw::limits) {
+            input.parse::<kw::limits>()?;
+            input.parse::<Token![=]>()?;
+            let limits = input.parse::<LitStr>()?;
+            input.parse::<Token![,]>()?;
+
+            Some(limits)
+        } else {
+            None
+        };
         let config_idents = Punctuated::<Ident, Token![,]>::parse_terminated(input)?
             .into_iter()
             .collect();
@@ -39,41 +78,547 @@ impl Parse for ConfigArgs {
         Ok(Self {
             config_idents,
             path,
+            watch,
+            timeout,
+            limits,
         })
     }
 }
 
+// `#[test_config(User, yaml = "...", runtime = "...")]`
+pub struct TestConfigArgs {
+    pub config_ident: Ident,
+    pub yaml: LitStr,
+    // Merged over `yaml` like a runtime config file
+    pub runtime: Option<LitStr>,
+    // `env = ["NAME=value", ...]`: set for the test only, through an `unconfig::EnvOverlay`
+    pub env: Vec<(String, String)>,
+}
+
+impl Parse for TestConfigArgs {
+    fn parse(input: ParseStream) -> Result<Self> {
+        let config_ident = input.parse()?;
+        let mut yaml = None;
+        let mut runtime = None;
+        let mut env = vec![];
+
+        while !input.is_empty() {
+            input.parse::<Token![,]>()?;
+
+            if input.is_empty() {
+                break;
+            }
+
+            let key: Ident = input.parse()?;
+            input.parse::<Token![=]>()?;
+
+            if key == "yaml" {
+                yaml = Some(input.parse()?);
+            } else if key == "runtime" {
+                runtime = Some(input.parse()?);
+            } else if key == "env" {
+                let vars: ExprArray = input.parse()?;
+
+                for var in vars.elems {
+                    let Expr::Lit(ExprLit {
+                        lit: Lit::Str(var), ..
+                    }) = &var
+                    else {
+                        return Err(syn::Error::new_spanned(var, "expected \"NAME=value\""));
+                    };
+
+                    match var.value().split_once('=') {
+                        Some((name, value)) if !name.is_empty() => {
+                            env.push((name.to_string(), value.to_string()))
+                        }
+                        _ => return Err(syn::Error::new(var.span(), "expected \"NAME=value\"")),
+                    }
+                }
+            } else {
+                return Err(syn::Error::new(
+                    key.span(),
+                    "unsupported option, expected `yaml`, `runtime` or `env`",
+                ));
+            }
+        }
+
+        Ok(Self {
+            config_ident,
+            yaml: yaml.ok_or(input.error("expected `yaml = \"...\"`"))?,
+            runtime,
+            env,
+        })
+    }
+}
+
+// Per-field options of `#[configurable]` structs
+#[derive(Default)]
+pub struct FieldArgs {
+    pub deep_merge: bool,
+    // `merge = "custom"`: the field type's `unconfig::Merge`
+    pub custom_merge: bool,
+    // Gauge the value is exported as
+    pub metric: Option<LitStr>,
+    // `getter = false` / `setter = false`, e.g. for a hand-written `#[implicate]` method
+    pub skip_getter: bool,
+    pub skip_setter: bool,
+    // `#[validate(...)]` rules
+    pub validations: Vec<Validation>,
+    // `#[required]`: unset after merging fails the load
+    pub required: bool,
+    // `#[default = ...]` / `#[default(...)]`: what the getter returns when unset
+    pub default: Option<FieldDefault>,
+    // `#[secret]`: stored and returned as `unconfig::Secret`
+    pub secret: bool,
+    // `#[vault(path = "...", key = "...")]`: read from Vault at init
+    pub vault: Option<(LitStr, LitStr)>,
+}
+
+pub enum FieldDefault {
+    // `#[default = "localhost"]`, parsed with the field type's `FromStr`, or a number
+    // or bool taken as it is
+    Literal(Lit),
+    // `#[default(path::to::fn)]`
+    Function(SynPath),
+}
+
+pub enum Validation {
+    // `range(min = 1, max = 65535)`, either bound may be left out
+    Range { min: Option<Lit>, max: Option<Lit> },
+    // `regex = "^[a-z]+$"`, checked when expanding
+    Regex(LitStr),
+    // `custom = path::to::fn`
+    Custom(SynPath),
+}
+
+impl FieldArgs {
+    // Takes the `#[unconfig(...)]`, `#[validate(...)]`, `#[required]`, `#[default]`,
+    // `#[secret]` and `#[vault(...)]` attributes off a field, the rest stays in place
+    pub fn take(attrs: &mut Vec<Attribute>) -> Result<Self> {
+        let mut args = Self::default();
+        let mut result = Ok(());
+        let mut default_attr = None;
+        let mut secret_attr = None;
+
+        attrs.retain(|attr| {
+            if attr.path().is_ident("secret") {
+                if result.is_ok() {
+                    result = attr.meta.require_path_only().map(|_| ());
+                }
+                args.secret = true;
+                secret_attr = Some(attr.clone());
+
+                return false;
+            }
+
+            if attr.path().is_ident("default") {
+                if result.is_ok() {
+                    result = FieldDefault::parse(attr).map(|default| args.default = Some(default));
+                }
+                default_attr = Some(attr.clone());
+
+                return false;
+            }
+
+            if attr.path().is_ident("vault") {
+                if result.is_ok() {
+                    result = parse_vault(attr).map(|vault| args.vault = Some(vault));
+                }
+
+                return false;
+            }
+
+            if attr.path().is_ident("required") {
+                if result.is_ok() {
+                    result = attr.meta.require_path_only().map(|_| ());
+                }
+                args.required = true;
+
+                return false;
+            }
+
+            if attr.path().is_ident("validate") {
+                if result.is_ok() {
+                    result = attr.parse_nested_meta(|meta| {
+                        args.validations.push(Validation::parse(&meta)?);
+
+                        Ok(())
+                    });
+                }
+
+                return false;
+            }
+
+            if !attr.path().is_ident("unconfig") {
+                return true;
+            }
+
+            if result.is_ok() {
+                result = attr.parse_nested_meta(|meta| {
+                    if meta.path.is_ident("merge") {
+                        let strategy: LitStr = meta.value()?.parse()?;
+
+                        (args.deep_merge, args.custom_merge) = match strategy.value().as_str() {
+                            "deep" => (true, false),
+                            "custom" => (false, true),
+                            "replace" => (false, false),
+                            other => {
+                                return Err(meta.error(format!(
+                                    "unknown merge strategy `{other}`, expected `deep`, `custom` or `replace`"
+                                )))
+                            }
+                        };
+
+                        Ok(())
+                    } else if meta.path.is_ident("metric") {
+                        args.metric = Some(meta.value()?.parse()?);
+
+                        Ok(())
+                    } else if meta.path.is_ident("getter") {
+                        args.skip_getter = !meta.value()?.parse::<LitBool>()?.value;
+
+                        Ok(())
+                    } else if meta.path.is_ident("setter") {
+                        args.skip_setter = !meta.value()?.parse::<LitBool>()?.value;
+
+                        Ok(())
+                    } else {
+                        Err(meta.error("unsupported unconfig attribute"))
+                    }
+                });
+            }
+
+            false
+        });
+
+        result?;
+
+        if let Some(attr) = default_attr.filter(|_| args.required) {
+            return Err(syn::Error::new_spanned(
+                attr,
+                "a `#[required]` field can't have a `#[default]`",
+            ));
+        }
+
+        // Their messages quote the value
+        let printing = args.validations.iter().any(|validation| {
+            matches!(validation, Validation::Range { .. } | Validation::Regex(_))
+        });
+
+        match secret_attr {
+            Some(attr) if printing => Err(syn::Error::new_spanned(
+                attr,
+                "a `#[secret]` field only supports `#[validate(custom = ...)]`",
+            )),
+            Some(attr) if args.metric.is_some() => Err(syn::Error::new_spanned(
+                attr,
+                "a `#[secret]` field can't be exported as a metric",
+            )),
+            _ => Ok(args),
+        }
+    }
+}
+
+// `#[vault(path = "secret/data/app", key = "db_password")]`
+fn parse_vault(attr: &Attribute) -> Result<(LitStr, LitStr)> {
+    let (mut path, mut key) = (None, None);
+
+    attr.parse_nested_meta(|meta| {
+        if meta.path.is_ident("path") {
+            path = Some(meta.value()?.parse()?);
+        } else if meta.path.is_ident("key") {
+            key = Some(meta.value()?.parse()?);
+        } else {
+            return Err(meta.error("unsupported option, expected `path` or `key`"));
+        }
+
+        Ok(())
+    })?;
+
+    match (path, key) {
+        (Some(path), Some(key)) => Ok((path, key)),
+        _ => Err(syn::Error::new_spanned(
+            attr,
+            "expected `#[vault(path = \"...\", key = \"...\")]`",
+        )),
+    }
+}
+
+impl FieldDefault {
+    fn parse(attr: &Attribute) -> Result<Self> {
+        match &attr.meta {
+            Meta::NameValue(meta) => match &meta.value {
+                Expr::Lit(ExprLit { lit, .. }) => Ok(Self::Literal(lit.clone())),
+                value => Err(syn::Error::new_spanned(
+                    value,
+                    "expected a literal, use `#[default(path::to::fn)]` for anything else",
+                )),
+            },
+            Meta::List(meta) => Ok(Self::Function(meta.parse_args()?)),
+            Meta::Path(path) => Err(syn::Error::new_spanned(
+                path,
+                "expected `#[default = \"...\"]` or `#[default(path::to::fn)]`",
+            )),
+        }
+    }
+}
+
+impl Validation {
+    fn parse(meta: &ParseNestedMeta) -> Result<Self> {
+        if meta.path.is_ident("range") {
+            let (mut min, mut max) = (None, None);
+
+            meta.parse_nested_meta(|bound| {
+                if bound.path.is_ident("min") {
+                    min = Some(bound.value()?.parse()?);
+                } else if bound.path.is_ident("max") {
+                    max = Some(bound.value()?.parse()?);
+                } else {
+                    return Err(bound.error("unsupported bound, expected `min` or `max`"));
+                }
+
+                Ok(())
+            })?;
+
+            if min.is_none() && max.is_none() {
+                return Err(meta.error("expected `min` or `max`"));
+            }
+
+            Ok(Self::Range { min, max })
+        } else if meta.path.is_ident("regex") {
+            let pattern: LitStr = meta.value()?.parse()?;
+
+            if let Err(e) = regex::Regex::new(&pattern.value()) {
+                return Err(syn::Error::new(
+                    pattern.span(),
+                    format!("invalid regex: {e}"),
+                ));
+            }
+
+            Ok(Self::Regex(pattern))
+        } else if meta.path.is_ident("custom") {
+            Ok(Self::Custom(meta.value()?.parse()?))
+        } else {
+            Err(meta.error("unsupported validation, expected `range`, `regex` or `custom`"))
+        }
+    }
+}
+
+// `ct_cp` is embedded from the build machine, relative to the manifest, `rt_cp` is read
+// on the host the binary runs on, relative to its current directory or, with `exe:`, to
+// the binary
 pub struct PathArgsLogger {
     pub rt_cp: proc_macro2::TokenStream,
     pub ct_cp: proc_macro2::TokenStream,
     pub env_cp: Option<proc_macro2::TokenStream>,
 }
 
+// Paths as for `PathArgsLogger`
 pub struct PathArgsConfigurable {
     pub rt_cp: proc_macro2::TokenStream,
-    pub ct_cp: proc_macro2::TokenStream,
+    // None when there is no file to embed at all
+    pub ct_cp: Option<proc_macro2::TokenStream>,
     pub env_cp: Option<proc_macro2::TokenStream>,
+    pub accessors: Accessors,
+    // Generate `set_x` for every field
+    pub setters: bool,
+    // Structs to generate a `TryFrom` from
+    pub from: Vec<SynPath>,
+    // `PREFIX__SECTION__FIELD` variables merged over the files
+    pub env_prefix: Option<LitStr>,
+    // Generate `json_schema()`
+    pub json_schema: bool,
+    // `consul = "app/config.yml"`: the runtime layer is this Consul KV key, not a file
+    pub consul: Option<LitStr>,
+    // `etcd = "/app/config/"`: the runtime layer is the keys under this etcd prefix
+    pub etcd: Option<LitStr>,
+    // `format = "custom:myconf"`: every file of the struct is in this format
+    pub format: Option<LitStr>,
+    // `strict_types = true`: the files must have the types of the fields
+    pub strict_types: bool,
+    // `deprecated = "moved to network"`: the section warns in the files that have it
+    pub deprecated: Option<LitStr>,
+    // `sunset = "2027-01-31" | "2.0.0"`: and fails the load from then on
+    pub sunset: Option<LitStr>,
+}
+
+// Naming of the generated getters
+#[derive(Clone, Copy, Default, PartialEq)]
+pub enum Accessors {
+    // `name()`
+    #[default]
+    Plain,
+    // `get_name()`
+    Get,
+}
+
+impl Accessors {
+    pub fn prefix(self) -> &'static str {
+        match self {
+            Self::Plain => "",
+            Self::Get => "get_",
+        }
+    }
+}
+
+// Options after the path
+#[derive(Default)]
+struct Options {
+    // `accessors = "plain" | "get"`
+    accessors: Accessors,
+    // `setters = true | false`
+    setters: bool,
+    // Any `from = Old`
+    from: Vec<SynPath>,
+    // `env_prefix = "APP"`
+    env_prefix: Option<LitStr>,
+    // `json_schema = true | false`
+    json_schema: bool,
+    // `runtime = "/etc/app/config.yml"`, the runtime file when it's not the embedded one
+    runtime: Option<LitStr>,
+    // `consul = "app/config.yml"`
+    consul: Option<LitStr>,
+    // `etcd = "/app/config/"`
+    etcd: Option<LitStr>,
+    // `format = "yaml" | "json" | "toml" | "custom:<name>"`
+    format: Option<LitStr>,
+    // `strict_types = true | false`
+    strict_types: bool,
+    // `deprecated = "note"`
+    deprecated: Option<LitStr>,
+    // `sunset = "2027-01-31" | "2.0.0"`
+    sunset: Option<LitStr>,
+}
+
+fn parse_options(input: ParseStream) -> Result<Options> {
+    let mut options = Options::default();
+
+    while !input.is_empty() {
+        // No comma before the first option when there's no path
+        if input.peek(Token![,]) {
+            input.parse::<Token![,]>()?;
+
+            if input.is_empty() {
+                break;
+            }
+        }
+
+        let key: Ident = input.parse()?;
+        input.parse::<Token![=]>()?;
+
+        if key == "accessors" {
+            let value: LitStr = input.parse()?;
+
+            options.accessors = match value.value().as_str() {
+                "plain" => Accessors::Plain,
+                "get" => Accessors::Get,
+                other => {
+                    return Err(syn::Error::new(
+                        value.span(),
+                        format!("unknown accessors `{other}`, expected `plain` or `get`"),
+                    ))
+                }
+            };
+        } else if key == "setters" {
+            options.setters = input.parse::<LitBool>()?.value;
+        } else if key == "from" {
+            options.from.push(input.parse()?);
+        } else if key == "env_prefix" {
+            options.env_prefix = Some(input.parse()?);
+        } else if key == "json_schema" {
+            options.json_schema = input.parse::<LitBool>()?.value;
+        } else if key == "runtime" {
+            options.runtime = Some(input.parse()?);
+        } else if key == "consul" {
+            options.consul = Some(input.parse()?);
+        } else if key == "etcd" {
+            options.etcd = Some(input.parse()?);
+        } else if key == "format" {
+            let value: LitStr = input.parse()?;
+
+            match value.value().as_str() {
+                "yaml" | "json" | "toml" => {}
+                custom if custom.strip_prefix("custom:").is_some_and(|name| !name.is_empty()) => {}
+                other => {
+                    return Err(syn::Error::new(
+                        value.span(),
+                        format!("unknown format `{other}`, expected `yaml`, `json`, `toml` or `custom:<name>`"),
+                    ))
+                }
+            }
+
+            options.format = Some(value);
+        } else if key == "strict_types" {
+            options.strict_types = input.parse::<LitBool>()?.value;
+        } else if key == "deprecated" {
+            options.deprecated = Some(input.parse()?);
+        } else if key == "sunset" {
+            let value: LitStr = input.parse()?;
+
+            // A date or a version, both start with a number
+            if !value.value().starts_with(|c: char| c.is_ascii_digit()) {
+                return Err(syn::Error::new(
+                    value.span(),
+                    "expected a date such as `2027-01-31` or a version such as `2.0.0`",
+                ));
+            }
+
+            options.sunset = Some(value);
+        } else {
+            return Err(syn::Error::new(
+                key.span(),
+                "unsupported option, expected `accessors`, `setters`, `from`, `env_prefix`, `json_schema`, `runtime`, `consul`, `etcd`, `format`, `strict_types`, `deprecated` or `sunset`",
+            ));
+        }
+    }
+
+    if let (Some(_), Some(etcd)) = (&options.consul, &options.etcd) {
+        return Err(syn::Error::new(
+            etcd.span(),
+            "`consul` and `etcd` can't both be the runtime layer",
+        ));
+    }
+    if let (None, Some(sunset)) = (&options.deprecated, &options.sunset) {
+        return Err(syn::Error::new(
+            sunset.span(),
+            "`sunset` needs `deprecated = \"...\"`, saying what to do instead",
+        ));
+    }
+
+    Ok(options)
 }
 
 // Replace slashes
 impl Parse for PathArgsConfigurable {
     fn parse(input: ParseStream) -> Result<Self> {
-        let root_dir = format!("{}", var("CARGO_MANIFEST_DIR").unwrap());
+        let root_dir = var("CARGO_MANIFEST_DIR").unwrap();
         let (cp, ep) = parse(input);
+        let Options {
+            accessors,
+            setters,
+            from,
+            env_prefix,
+            json_schema,
+            runtime,
+            consul,
+            etcd,
+            format,
+            strict_types,
+            deprecated,
+            sunset,
+        } = parse_options(input)?;
         let parsed = cp.unwrap_or("config.yml".to_string());
 
-        let cp = Path::new(&root_dir).join(parsed);
-        let (rt_cp, ct_cp) = if cp.exists() {
-            let cp = cp.to_str().into_token_stream();
-            (cp.clone(), cp)
+        let cp = Path::new(&root_dir).join(build_path(&parsed));
+        let ct_cp = if cp.exists() {
+            Some(cp.to_str().into_token_stream())
         } else {
-            let ct_cp = Path::new(&root_dir)
-                .join("config.yml")
-                .to_str()
-                .into_token_stream();
-            let rt_cp = cp.to_str().into_token_stream();
-
-            (rt_cp, ct_cp)
+            let ct_cp = Path::new(&root_dir).join("config.yml");
+            ct_cp.exists().then(|| ct_cp.to_str().into_token_stream())
+        };
+        let rt_cp = match runtime {
+            Some(runtime) => runtime.into_token_stream(),
+            None => parsed.into_token_stream(),
         };
         let env_cp = ep.map(ToTokens::into_token_stream);
 
@@ -81,29 +626,53 @@ impl Parse for PathArgsConfigurable {
             ct_cp,
             rt_cp,
             env_cp,
+            accessors,
+            setters,
+            from,
+            env_prefix,
+            json_schema,
+            consul,
+            etcd,
+            format,
+            strict_types,
+            deprecated,
+            sunset,
         })
     }
 }
 
 impl Parse for PathArgsLogger {
     fn parse(input: ParseStream) -> Result<Self> {
-        let root_dir = format!("{}", var("CARGO_MANIFEST_DIR").unwrap());
+        let root_dir = var("CARGO_MANIFEST_DIR").unwrap();
+
+        // `config = "config.yml"` reads the `logger` section of the main config file
+        if input.peek(kw::config) {
+            input.parse::<kw::config>()?;
+            input.parse::<Token![=]>()?;
+        }
+
         let (cp, ep) = parse(input);
-        let parsed = cp.unwrap_or("logger.yml".to_string());
+        // Without a `logger.yml` the section is looked up in `config.yml`
+        let parsed = cp.unwrap_or_else(|| {
+            let root_dir = Path::new(&root_dir);
+
+            if !root_dir.join("logger.yml").exists() && root_dir.join("config.yml").exists() {
+                "config.yml".to_string()
+            } else {
+                "logger.yml".to_string()
+            }
+        });
 
-        let cp = Path::new(&root_dir).join(parsed);
-        let (rt_cp, ct_cp) = if cp.exists() {
-            let cp = cp.to_str().into_token_stream();
-            (cp.clone(), cp)
+        let cp = Path::new(&root_dir).join(build_path(&parsed));
+        let ct_cp = if cp.exists() {
+            cp.to_str().into_token_stream()
         } else {
-            let ct_cp = Path::new(&root_dir)
+            Path::new(&root_dir)
                 .join("logger.yml")
                 .to_str()
-                .into_token_stream();
-            let rt_cp = cp.to_str().into_token_stream();
-
-            (rt_cp, ct_cp)
+                .into_token_stream()
         };
+        let rt_cp = parsed.into_token_stream();
         let env_cp = ep.map(ToTokens::into_token_stream);
 
         Ok(Self {
@@ -114,6 +683,12 @@ impl Parse for PathArgsLogger {
     }
 }
 
+// `exe:config.yml` is looked up next to the binary at runtime, and in the manifest
+// directory like `config.yml` when building
+fn build_path(path: &str) -> &str {
+    path.strip_prefix("exe:").unwrap_or(path)
+}
+
 // Return compile and runtime path
 fn parse(input: ParseStream) -> (Option<String>, Option<String>) {
     input
@@ -126,33 +701,33 @@ fn parse(input: ParseStream) -> (Option<String>, Option<String>) {
                 None
             }
         })
-        .and_then(|parsed| {
+        .map(|parsed| {
             if parsed.contains("${") {
                 let last_curly = parsed.find('}').unwrap();
                 let env_var_s = parsed[2..last_curly].to_string();
 
                 match var(&env_var_s) {
-                    Ok(value) => return Some((Some(value), Some(env_var_s))),
+                    Ok(value) => return (Some(value), Some(env_var_s)),
                     Err(_) => {
                         if env_var_s.contains(':') {
                             if let Some((varname, tail)) = env_var_s.split_once(':') {
                                 if let Ok(value) = var(varname) {
-                                    return Some((Some(value), Some(varname.to_string())));
+                                    return (Some(value), Some(varname.to_string()));
                                 } else {
-                                    return Some((
-                                        Some(tail.to_string()),
-                                        Some(varname.to_string()),
-                                    ));
+                                    // `${VAR:-default}` as well as `${VAR:default}`
+                                    let tail = tail.strip_prefix('-').unwrap_or(tail);
+
+                                    return (Some(tail.to_string()), Some(varname.to_string()));
                                 }
                             }
                         }
 
-                        return Some((None, Some(env_var_s)));
+                        return (None, Some(env_var_s));
                     }
                 }
             }
 
-            Some((Some(parsed), None))
+            (Some(parsed), None)
         })
         .unwrap_or((None, None))
 }
@@ -4,9 +4,16 @@ use convert_case::{Case, Casing};
 use darling::FromMeta;
 use proc_macro::TokenStream;
 use quote::{format_ident, quote, ToTokens};
-use syn::{parse_macro_input, ItemFn, ItemStruct, Type};
+use syn::{
+    parse_macro_input, Attribute, FnArg, Generics, Ident, Item, ItemEnum, ItemFn, ItemStruct, Lit,
+    Type,
+};
+use unconfig_build::schema;
 
-use args::{ConfigArgs, PathArgsConfigurable, PathArgsLogger};
+use args::{
+    ConfigArgs, FieldArgs, FieldDefault, PathArgsConfigurable, PathArgsLogger, TestConfigArgs,
+    Validation,
+};
 
 #[proc_macro_attribute]
 pub fn implicate(args: TokenStream, item: TokenStream) -> TokenStream {
@@ -21,6 +28,7 @@ pub fn implicate(args: TokenStream, item: TokenStream) -> TokenStream {
     });
     let vis = input.vis.to_token_stream();
     let sig = input.sig.to_token_stream();
+    let fn_ident = &input.sig.ident;
 
     let impl_idents = args
         .config_idents
@@ -33,23 +41,27 @@ pub fn implicate(args: TokenStream, item: TokenStream) -> TokenStream {
                 quote! {
                     #acc
 
-                    impl #path::#config_macro::#ident {
-                        #prev_attrs
-                        #vis #sig {
-                            #prev_fn_body
+                    #path::#config_macro::implicate_check! { #fn_ident {
+                        impl #path::#config_macro::#ident {
+                            #prev_attrs
+                            #vis #sig {
+                                #prev_fn_body
+                            }
                         }
-                    }
+                    }}
                 }
             } else {
                 quote! {
                     #acc
 
-                    impl self::#config_macro::#ident {
-                        #prev_attrs
-                        #vis #sig {
-                            #prev_fn_body
+                    self::#config_macro::implicate_check! { #fn_ident {
+                        impl self::#config_macro::#ident {
+                            #prev_attrs
+                            #vis #sig {
+                                #prev_fn_body
+                            }
                         }
-                    }
+                    }}
                 }
             }
         });
@@ -71,6 +83,12 @@ pub fn config(args: TokenStream, item: TokenStream) -> TokenStream {
     let vis = input.vis.to_token_stream();
     let sig = input.sig.to_token_stream();
 
+    let mut init_all_func = quote! {};
+    let mut config_names = quote! {};
+    let timeout = args.timeout.map_or(quote! { None }, |secs| {
+        quote! { Some(std::time::Duration::from_secs_f64(#secs)) }
+    });
+
     let config_idents = args
         .config_idents
         .into_iter()
@@ -79,163 +97,1080 @@ pub fn config(args: TokenStream, item: TokenStream) -> TokenStream {
             let config_ident_name = format_ident!("CONFIG_{}", ident.to_string().to_case(Case::UpperSnake));
             let config_macro = format_ident!("{}__config__macro", ident.to_string().to_case(Case::Snake));
 
-            if let Some(path) = args.path.as_ref() {
-               quote! {
+            init_all_func = quote! {
+                #init_all_func
+
+                scope.spawn(|| {
+                    std::sync::LazyLock::force(&#config_ident_name);
+                }),
+            };
+            config_names = quote! { #config_names stringify!(#ident), };
+
+            let module = if let Some(path) = args.path.as_ref() {
+                quote! { #path::#config_macro }
+            } else {
+                quote! { self::#config_macro }
+            };
+
+            if args.watch {
+                quote! {
                     #acc
 
-                    static #config_ident_name: std::sync::LazyLock<#path::#config_macro::#ident> = std::sync::LazyLock::new(#path::#config_macro::#upper_ident::init);
+                    static #config_ident_name: std::sync::LazyLock<unconfig::ConfigWatcher<#module::#ident>> = std::sync::LazyLock::new(|| unconfig::init_within(stringify!(#ident), #timeout, #module::#upper_ident::watch));
                 }
-
             } else {
                 quote! {
                     #acc
 
-                    static #config_ident_name: std::sync::LazyLock<self::#config_macro::#ident> = std::sync::LazyLock::new(self::#config_macro::#upper_ident::init);
+                    static #config_ident_name: std::sync::LazyLock<#module::#ident> = std::sync::LazyLock::new(|| unconfig::init_within(stringify!(#ident), #timeout, #module::#upper_ident::init));
                 }
             }
         });
 
+    let apply_limits = args.limits.map(|path| {
+        quote! { unconfig::apply_resource_limits(#path); }
+    });
+
     quote! {
         #config_idents
 
         #prev_attrs
         #vis #sig {
+            // Resolve every listed config concurrently instead of on first access, then sum
+            // them up in one event
+            {
+                #apply_limits
+
+                let started = std::time::Instant::now();
+                std::thread::scope(|scope| {
+                    let handles: Vec<std::thread::ScopedJoinHandle<()>> = vec![#init_all_func];
+
+                    // With the panic of the config, not the scope's own
+                    for handle in handles {
+                        if let Err(panic) = handle.join() {
+                            std::panic::resume_unwind(panic);
+                        }
+                    }
+                });
+                unconfig::report_init(&[#config_names], started.elapsed());
+            }
+
             #prev_fn_body
         }
     }
     .into()
 }
 
+// `T` of an `Option<T>` field
+fn option_inner(ty: &Type) -> Option<&Type> {
+    let Type::Path(path) = ty else {
+        return None;
+    };
+    let segment = path.path.segments.last()?;
+
+    if segment.ident != "Option" {
+        return None;
+    }
+
+    match &segment.arguments {
+        syn::PathArguments::AngleBracketed(args) => match args.args.first()? {
+            syn::GenericArgument::Type(inner) if args.args.len() == 1 => Some(inner),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+// `T` or `Option<T>` of a `#[secret]` field with `T` wrapped
+fn secret_type(ty: &Type) -> Type {
+    match option_inner(ty) {
+        Some(inner) => syn::parse_quote! { Option<unconfig::Secret<#inner>> },
+        None => syn::parse_quote! { unconfig::Secret<#ty> },
+    }
+}
+
+// Collections get borrowing accessors besides the cloning getter
+fn is_collection(ty: &Type) -> bool {
+    let Type::Path(path) = ty else {
+        return false;
+    };
+
+    path.path.segments.last().is_some_and(|segment| {
+        matches!(
+            segment.ident.to_string().as_str(),
+            "Vec"
+                | "VecDeque"
+                | "HashMap"
+                | "BTreeMap"
+                | "IndexMap"
+                | "HashSet"
+                | "BTreeSet"
+                | "IndexSet"
+        )
+    })
+}
+
 // Config
 #[proc_macro_attribute]
 pub fn configurable(args: TokenStream, item: TokenStream) -> TokenStream {
-    let input = parse_macro_input!(item as ItemStruct);
     let args = parse_macro_input!(args as PathArgsConfigurable);
 
+    match parse_macro_input!(item as Item) {
+        Item::Struct(input) => configurable_struct(args, input),
+        Item::Enum(input) => configurable_enum(args, input),
+        item => syn::Error::new_spanned(item, "#[configurable] expects a struct or an enum")
+            .to_compile_error()
+            .into(),
+    }
+}
+
+fn configurable_struct(args: PathArgsConfigurable, mut input: ItemStruct) -> TokenStream {
+    let field_args = match input
+        .fields
+        .iter_mut()
+        .map(|field| FieldArgs::take(&mut field.attrs))
+        .collect::<syn::Result<Vec<_>>>()
+    {
+        Ok(field_args) => field_args,
+        Err(e) => return e.to_compile_error().into(),
+    };
+
     let ident = input.ident;
-    let upper_ident = format_ident!("Upper{ident}");
     let prev_ident = format_ident!("{}", ident.to_string().to_case(Case::Snake));
 
     let PathArgsConfigurable {
-        rt_cp,
-        ct_cp,
-        env_cp,
+        accessors,
+        setters,
+        ref from,
+        json_schema,
+        ..
     } = args;
 
-    let init_runtime = if let Some(env_var) = env_cp {
-        quote! {
-            if let Ok(config_rt) = <#upper_ident as unconfig::Config>::load_env(#env_var, #rt_cp) {
-                let merged = config_ct.#prev_ident.merge(config_rt.#prev_ident);
-
-                merged
-            } else {
-                config_ct.#prev_ident
-            }
-        }
-    } else {
-        quote! {
-            if let Ok(config_rt) = <#upper_ident as unconfig::Config>::load_path(#rt_cp) {
-                let merged = config_ct.#prev_ident.merge(config_rt.#prev_ident);
-
-                merged
-            } else {
-                config_ct.#prev_ident
-            }
+    // Files named when a `#[required]` field is missing
+    let sources = {
+        let compile_time = args.ct_cp.iter();
+        let watched_path = watched_path(&args);
 
-        }
+        quote! { &[#(#compile_time,)* #watched_path.to_string().as_str()] }
     };
 
     let mut merge_func = quote! {};
     let mut getters_func = quote! {};
+    let mut field_names = quote! {};
+    let mut deep_names = quote! {};
+    let mut vault_fields = quote! {};
+    let mut deep_checks = quote! {};
+    let mut field_checks = quote! {};
+    let mut schema_fields = vec![];
+    let mut gauges = quote! {};
+    let mut field_inserts = quote! {};
+    let mut field_takes = quote! {};
+    // Generated methods, with the field and the kind of accessor they come from
+    let mut reserved = vec![];
+    let struct_ident = &ident;
 
-    let prev_struct_fields = input.fields.iter().fold(quote! {}, |acc, field| {
-        let vis = &field.vis;
-        let attrs = field.attrs.iter().fold(quote! {}, |acc, attr| {
-            quote! { #acc #attr }
-        });
-        let ty = &field.ty;
-        let colon = field.colon_token.as_ref().unwrap();
-        let ident = field.ident.as_ref().unwrap();
-
-        merge_func = quote! {#merge_func #ident: rhs.#ident.or(self.#ident),};
-        getters_func = quote! {
-            #getters_func
-
-            pub fn #ident(&self) -> #ty {
-                self.#ident
-                    .clone()
-                    .unwrap_or_default()
-            }
-        };
+    let prev_struct_fields =
+        input
+            .fields
+            .iter()
+            .zip(field_args)
+            .fold(quote! {}, |acc, (field, field_args)| {
+                let vis = &field.vis;
+                let attrs = field.attrs.iter().fold(quote! {}, |acc, attr| {
+                    quote! { #acc #attr }
+                });
+                let colon = field.colon_token.as_ref().unwrap();
+                let ident = field.ident.as_ref().unwrap();
+                // Saved configs only hold the fields that are set, and never secrets
+                let attrs = if field_args.secret {
+                    quote! { #attrs #[serde(skip_serializing_if = "unconfig::skip_secret")] }
+                } else {
+                    quote! { #attrs #[serde(skip_serializing_if = "Option::is_none")] }
+                };
+                let getter = format_ident!("{}{ident}", accessors.prefix());
 
-        quote! { #acc #attrs #vis #ident #colon Option<#ty>,}
-    });
-    let prev_struct_attrs = input.attrs.iter().fold(quote! {}, |acc, attr| {
-        let attr_parsed = attr.meta.to_token_stream().to_string();
-        if let Some((_, attr_name)) = attr_parsed.split_once("derive(") {
-            let attr_idents = &attr_name[0..attr_name.len() - 1]
-                .to_string()
-                .split(',')
-                .fold(quote! {}, |attr_derive_acc, attr_derive_name| {
-                    let attr_derive_ident = Type::from_string(attr_derive_name.into()).unwrap();
+                field_names = quote! {#field_names stringify!(#ident),};
+                if let Some((path, key)) = &field_args.vault {
+                    vault_fields = quote! {#vault_fields (stringify!(#ident), #path, #key),};
+                }
+                field_inserts = quote! {#field_inserts fields.insert(stringify!(#ident), self.#ident);};
+                field_takes = quote! {#field_takes #ident: fields.take(stringify!(#struct_ident), stringify!(#ident))?,};
+                schema_fields.push((ident.to_string(), schema::field_schema(&field.ty, &field.attrs)));
 
-                    quote! { #attr_derive_acc #attr_derive_ident,}
+                // `Option<T>` fields are stored as they are, absent values stay `None`
+                let plain_ty = option_inner(&field.ty).unwrap_or(&field.ty);
+                let secret_ty = field_args.secret.then(|| secret_type(&field.ty));
+                let ty = secret_ty.as_ref().unwrap_or(&field.ty);
+                let inner_ty = option_inner(ty);
+                let stored_ty = inner_ty.unwrap_or(ty);
+                let default = field_args.default.as_ref().map(|default| match default {
+                    FieldDefault::Literal(Lit::Str(literal)) => quote! {
+                        <#plain_ty as ::std::str::FromStr>::from_str(#literal).unwrap_or_else(|e| {
+                            panic!("Invalid default of {}.{}: {e}", stringify!(#struct_ident), stringify!(#ident))
+                        })
+                    },
+                    FieldDefault::Literal(literal) => quote! { #literal },
+                    FieldDefault::Function(path) => quote! { #path() },
                 });
+                let default = match default {
+                    Some(default) if field_args.secret => Some(quote! { unconfig::Secret::new(#default) }),
+                    default => default,
+                };
+                let unwrap = match (inner_ty.is_some(), default) {
+                    (true, None) => quote! {},
+                    (true, Some(default)) => quote! { .or_else(|| Some(#default)) },
+                    (false, None) => quote! { .unwrap_or_default() },
+                    (false, Some(default)) => quote! { .unwrap_or_else(|| #default) },
+                };
+                let value = if field_args.deep_merge {
+                    quote! { self.#ident.clone().and_then(unconfig::Deep::into_inner)#unwrap }
+                } else {
+                    quote! { self.#ident.clone()#unwrap }
+                };
 
-            quote! { #acc #attr_idents }
-        } else {
-            quote! { #acc #attr }
+                let borrow = if field_args.deep_merge {
+                    quote! { self.#ident.as_ref().and_then(unconfig::Deep::get) }
+                } else {
+                    quote! { self.#ident.as_ref() }
+                };
+
+                if field_args.required {
+                    field_checks = quote! { #field_checks .required(stringify!(#ident), self.#ident.is_some(), #sources) };
+                }
+
+                for validation in &field_args.validations {
+                    let check = match validation {
+                        Validation::Range { min, max } => {
+                            let min = min.as_ref().map_or(quote! { None }, |min| quote! { Some(#min) });
+                            let max = max.as_ref().map_or(quote! { None }, |max| quote! { Some(#max) });
+
+                            quote! { .range(stringify!(#ident), #borrow, #min, #max) }
+                        }
+                        Validation::Regex(pattern) => quote! { .regex(stringify!(#ident), #borrow, #pattern) },
+                        Validation::Custom(path) => quote! { .custom(stringify!(#ident), #borrow, #path) },
+                    };
+                    field_checks = quote! { #field_checks #check };
+                }
+
+                if let Some(metric) = &field_args.metric {
+                    gauges = quote! {
+                        #gauges
+
+                        if self.#ident.is_some() {
+                            let value: #ty = #value;
+                            unconfig::set_gauge(#metric, unconfig::GaugeValue::gauge_value(&value));
+                        }
+                    };
+                }
+
+                if !field_args.skip_getter {
+                    reserved.push((getter.to_string(), ident.to_string(), "getter"));
+                    getters_func = quote! {
+                        #getters_func
+
+                        pub fn #getter(&self) -> #ty {
+                            #value
+                        }
+                    };
+                }
+
+                // Collections can also be borrowed, telling an unset one from an empty one
+                if is_collection(stored_ty) && !field_args.skip_getter {
+                    let ref_ident = format_ident!("{getter}_ref");
+                    let iter_ident = format_ident!("{getter}_iter");
+                    reserved.push((ref_ident.to_string(), ident.to_string(), "getter"));
+                    reserved.push((iter_ident.to_string(), ident.to_string(), "getter"));
+
+                    getters_func = quote! {
+                        #getters_func
+
+                        pub fn #ref_ident(&self) -> Option<&#stored_ty> {
+                            #borrow
+                        }
+
+                        pub fn #iter_ident<'a>(&'a self) -> impl Iterator<Item = <&'a #stored_ty as IntoIterator>::Item> {
+                            self.#ref_ident().into_iter().flatten()
+                        }
+                    };
+                }
+
+                if setters && !field_args.skip_setter {
+                    let setter = format_ident!("set_{ident}");
+                    reserved.push((setter.to_string(), ident.to_string(), "setter"));
+                    let store = match (field_args.deep_merge, inner_ty.is_some()) {
+                        (true, true) => quote! { value.map(unconfig::Deep::new) },
+                        (true, false) => quote! { Some(unconfig::Deep::new(value)) },
+                        (false, true) => quote! { value },
+                        (false, false) => quote! { Some(value) },
+                    };
+
+                    getters_func = quote! {
+                        #getters_func
+
+                        pub fn #setter(&mut self, value: #ty) {
+                            self.#ident = #store;
+                        }
+                    };
+                }
+
+                if field_args.deep_merge {
+                    deep_names = quote! {#deep_names stringify!(#ident),};
+                    merge_func = quote! {#merge_func #ident: unconfig::Merge::merge(self.#ident, rhs.#ident),};
+                    deep_checks = quote! {
+                        #deep_checks
+
+                        let result = result.and(match self.#ident.as_ref().and_then(unconfig::Deep::error) {
+                            Some(e) => {
+                                unconfig::tracing::error!("Invalid {}.{}: {e}", stringify!(#struct_ident), stringify!(#ident));
+
+                                Err(unconfig::UnconfigError::Merge {
+                                    field: format!("{}.{}", stringify!(#struct_ident), stringify!(#ident)),
+                                    message: e.to_string(),
+                                })
+                            }
+                            None => Ok(()),
+                        });
+                    };
+
+                    quote! { #acc #attrs #vis #ident #colon Option<unconfig::Deep<#stored_ty>>,}
+                } else if field_args.custom_merge {
+                    merge_func = quote! {#merge_func #ident: unconfig::Merge::merge(self.#ident, rhs.#ident),};
+
+                    quote! { #acc #attrs #vis #ident #colon Option<#stored_ty>,}
+                } else {
+                    merge_func = quote! {#merge_func #ident: rhs.#ident.or(self.#ident),};
+
+                    quote! { #acc #attrs #vis #ident #colon Option<#stored_ty>,}
+                }
+            });
+
+    let mut internal = vec![
+        "check_deep",
+        "check_fields",
+        "export_gauges",
+        "into_fields",
+        "provenance",
+    ];
+    let json_schema = json_schema.then(|| {
+        let schema = schema::section_schema(&prev_ident.to_string(), &schema_fields);
+        internal.push("json_schema");
+
+        quote! {
+            /// JSON Schema of this config section, told from the field types and docs
+            pub fn json_schema() -> String {
+                #schema.to_string()
+            }
         }
     });
+
+    let (prev_struct_attrs, prev_struct_others) = derive_attrs(&input.attrs);
     let struct_token = input.struct_token;
     let prev_struct_generics = input.generics;
+    let loaders = loaders(
+        &ident,
+        &args,
+        &prev_struct_attrs,
+        &prev_struct_generics,
+        &field_names,
+        &deep_names,
+        &vault_fields,
+    );
     let config_macro = format_ident!("{}__config__macro", ident.to_string().to_case(Case::Snake));
 
+    // `#[implicate]` impls go through this macro, which rejects methods named like a
+    // generated one
+    let check_macro = format_ident!("{prev_ident}__implicate__check");
+    let check_arms = reserved
+        .iter()
+        .map(|(method, field, kind)| {
+            let method = format_ident!("{method}");
+            let message = format!(
+                "`{method}` is already generated by #[configurable] as the {kind} of `{ident}::{field}`, \
+                 rename the method or add #[unconfig({kind} = false)] to the field"
+            );
+
+            quote! { (#method { $($item:tt)* }) => { compile_error!(#message); }; }
+        })
+        .chain(internal.into_iter().map(|method| internal_arm(&ident, method)))
+        .fold(quote! {}, |acc, arm| quote! { #acc #arm });
+
+    // Old structs are resolved next to this one, through their own module
+    let try_from = from.iter().fold(quote! {}, |acc, path| {
+        let mut path = path.clone();
+        if let Some(last) = path.segments.last_mut() {
+            let old_ident = last.ident.clone();
+            let old_macro = format_ident!(
+                "{}__config__macro",
+                old_ident.to_string().to_case(Case::Snake)
+            );
+
+            last.ident = old_macro;
+            path.segments.push(old_ident.into());
+        }
+
+        quote! {
+            #acc
+
+            impl TryFrom<#path> for #ident {
+                type Error = unconfig::ConvertError;
+
+                fn try_from(old: #path) -> Result<Self, Self::Error> {
+                    let mut fields = old.into_fields();
+
+                    Ok(Self {
+                        #field_takes
+                    })
+                }
+            }
+        }
+    });
+
     quote! {
         pub(crate) mod #config_macro {
-            #[derive(#prev_struct_attrs unconfig::serde::Deserialize)]
+            #[doc(hidden)]
+            macro_rules! #check_macro {
+                #check_arms
+                ($other:ident { $($item:tt)* }) => { $($item)* };
+            }
+            #[allow(unused_imports)]
+            pub(crate) use #check_macro as implicate_check;
+
+            // Field types may be defined next to the struct
+            #[allow(unused_imports)]
+            use super::*;
+
+            #[derive(#prev_struct_attrs unconfig::serde::Serialize, unconfig::serde::Deserialize)]
+            #(#prev_struct_others)*
             #[serde(crate = "unconfig::serde")]
             pub #struct_token #ident #prev_struct_generics {
                 #prev_struct_fields
             }
 
-            impl #ident {
-                fn merge(self, rhs: Self) -> Self
-                where
-                    Self: Sized,
-                {
+            impl unconfig::Merge for #ident {
+                fn merge(self, rhs: Self) -> Self {
                     Self {
                         #merge_func
                     }
                 }
+            }
+
+            impl #ident {
+                // Deep merged fields only have to be valid once every layer is merged, each
+                // invalid one is logged and the first returned
+                fn check_deep(&self) -> ::std::result::Result<(), unconfig::UnconfigError> {
+                    let result = Ok(());
+                    #deep_checks
+
+                    result
+                }
+
+                // `#[validate(...)]` rules, on the merged config
+                fn check_fields(&self) -> ::std::result::Result<(), unconfig::UnconfigError> {
+                    unconfig::Validator::new(stringify!(#ident)) #field_checks .finish()
+                }
+
+                // Values of `#[unconfig(metric = "...")]` fields
+                fn export_gauges(&self) {
+                    #gauges
+                }
+
+                // Stored fields, for the `TryFrom` of structs listing this one with `from`
+                #[doc(hidden)]
+                pub fn into_fields(self) -> unconfig::Fields {
+                    let mut fields = unconfig::Fields::new(stringify!(#ident));
+                    #field_inserts
+
+                    fields
+                }
+
+                #json_schema
 
                 #getters_func
             }
 
-            #[derive(#prev_struct_attrs unconfig::serde::Deserialize)]
+            #try_from
+
+            impl unconfig::Validate for #ident {
+                fn validate(&self) -> ::std::result::Result<(), unconfig::UnconfigError> {
+                    self.check_deep()?;
+                    self.check_fields()
+                }
+            }
+
+            #loaders
+        }
+    }
+    .into()
+}
+
+// The derives of the item, folded into the one of the generated items, and its other
+// attributes, kept on the item only, e.g. `#[serde(tag = "kind")]`. `Serialize` is
+// generated already.
+fn derive_attrs(attrs: &[Attribute]) -> (proc_macro2::TokenStream, Vec<&Attribute>) {
+    let (derives, others) = attrs
+        .iter()
+        .partition::<Vec<_>, _>(|attr| attr.path().is_ident("derive"));
+
+    let derives = derives.into_iter().fold(quote! {}, |acc, attr| {
+        let attr_parsed = attr.meta.to_token_stream().to_string();
+        if let Some((_, attr_name)) = attr_parsed.split_once("derive(") {
+            let attr_idents = &attr_name[0..attr_name.len() - 1].split(',').fold(
+                quote! {},
+                |attr_derive_acc, attr_derive_name| {
+                    if attr_derive_name.trim().ends_with("Serialize") {
+                        return attr_derive_acc;
+                    }
+
+                    let attr_derive_ident = Type::from_string(attr_derive_name).unwrap();
+
+                    quote! { #attr_derive_acc #attr_derive_ident,}
+                },
+            );
+
+            quote! { #acc #attr_idents }
+        } else {
+            acc
+        }
+    });
+
+    (derives, others)
+}
+
+// `implicate_check!` arm rejecting a method the macro generates for itself
+fn internal_arm(ident: &Ident, method: &str) -> proc_macro2::TokenStream {
+    let method = format_ident!("{method}");
+    let message = format!("`{method}` is used by #[configurable] on `{ident}`, rename the method");
+
+    quote! { (#method { $($item:tt)* }) => { compile_error!(#message); }; }
+}
+
+// An enum section is read whole, a later layer replacing it with any of its variants
+fn configurable_enum(args: PathArgsConfigurable, input: ItemEnum) -> TokenStream {
+    let unsupported = if args.ct_cp.is_none() {
+        Some("an enum needs a config file to start from, there are no fields to read from the environment")
+    } else if args.env_prefix.is_some() {
+        Some("`env_prefix` is not supported on enums")
+    } else if !args.from.is_empty() {
+        Some("`from` is not supported on enums")
+    } else if args.json_schema {
+        Some("`json_schema` is not supported on enums")
+    } else {
+        None
+    };
+
+    if let Some(message) = unsupported {
+        return syn::Error::new_spanned(&input.ident, message)
+            .to_compile_error()
+            .into();
+    }
+
+    let ident = &input.ident;
+    let prev_ident = format_ident!("{}", ident.to_string().to_case(Case::Snake));
+    let (attrs, others) = derive_attrs(&input.attrs);
+    let generics = &input.generics;
+    let variants = &input.variants;
+    let loaders = loaders(
+        ident,
+        &args,
+        &attrs,
+        generics,
+        &quote! {},
+        &quote! {},
+        &quote! {},
+    );
+
+    let config_macro = format_ident!("{prev_ident}__config__macro");
+    let check_macro = format_ident!("{prev_ident}__implicate__check");
+    let check_arms = ["check_deep", "check_fields", "export_gauges", "provenance"]
+        .into_iter()
+        .map(|method| internal_arm(ident, method))
+        .fold(quote! {}, |acc, arm| quote! { #acc #arm });
+
+    quote! {
+        pub(crate) mod #config_macro {
+            #[doc(hidden)]
+            macro_rules! #check_macro {
+                #check_arms
+                ($other:ident { $($item:tt)* }) => { $($item)* };
+            }
+            #[allow(unused_imports)]
+            pub(crate) use #check_macro as implicate_check;
+
+            // Variant field types may be defined next to the enum
+            #[allow(unused_imports)]
+            use super::*;
+
+            #[derive(#attrs unconfig::serde::Serialize, unconfig::serde::Deserialize)]
+            #(#others)*
             #[serde(crate = "unconfig::serde")]
-            #[serde(rename_all = "snake_case")]
-            pub #struct_token #upper_ident #prev_struct_generics {
-                #prev_ident: #ident,
+            pub enum #ident #generics {
+                #variants
+            }
+
+            impl unconfig::Merge for #ident {
+                fn merge(self, rhs: Self) -> Self {
+                    rhs
+                }
             }
 
-            impl #upper_ident {
-                pub fn init() -> #ident {
-                    // Compile time config
-                    let config_ct = <#upper_ident as unconfig::Config>::load_str(include_str!(#ct_cp)).unwrap();
+            // Nothing to check or export, for the loaders shared with structs
+            impl #ident {
+                fn check_deep(&self) -> ::std::result::Result<(), unconfig::UnconfigError> {
+                    Ok(())
+                }
+
+                fn check_fields(&self) -> ::std::result::Result<(), unconfig::UnconfigError> {
+                    Ok(())
+                }
+
+                fn export_gauges(&self) {}
+            }
 
-                    // Runtime config
-                    #init_runtime
+            impl unconfig::Validate for #ident {
+                fn validate(&self) -> ::std::result::Result<(), unconfig::UnconfigError> {
+                    Ok(())
                 }
             }
+
+            #loaders
         }
-    }.into()
+    }
+    .into()
+}
+
+// The `Upper` struct a section is read into and its loading functions, for structs and
+// enums alike
+fn loaders(
+    ident: &Ident,
+    args: &PathArgsConfigurable,
+    attrs: &proc_macro2::TokenStream,
+    generics: &Generics,
+    field_names: &proc_macro2::TokenStream,
+    deep_names: &proc_macro2::TokenStream,
+    vault_fields: &proc_macro2::TokenStream,
+) -> proc_macro2::TokenStream {
+    let upper_ident = format_ident!("Upper{ident}");
+    let prev_ident = format_ident!("{}", ident.to_string().to_case(Case::Snake));
+    let PathArgsConfigurable {
+        rt_cp,
+        ct_cp,
+        env_cp,
+        env_prefix,
+        consul,
+        etcd,
+        format,
+        strict_types,
+        deprecated,
+        sunset,
+        ..
+    } = args;
+    // Loader, watcher and name of a runtime layer kept in a Consul key or etcd prefix
+    let remote = match (consul, etcd) {
+        (Some(key), _) => Some((
+            quote! { load_consul_section },
+            quote! { consul },
+            "Consul",
+            key,
+        )),
+        (None, Some(prefix)) => Some((
+            quote! { load_etcd_section },
+            quote! { etcd },
+            "etcd",
+            prefix,
+        )),
+        (None, None) => None,
+    };
+
+    let init_runtime = if let Some((load, _, name, key)) = &remote {
+        quote! {
+            match <#upper_ident as unconfig::Config>::#load(#key, stringify!(#prev_ident)) {
+                Ok(config_rt) => unconfig::tracing::debug_span!("config_merge", config = stringify!(#ident))
+                    .in_scope(|| unconfig::Merge::merge(config_ct.#prev_ident, config_rt.#prev_ident)),
+                Err(e @ unconfig::UnconfigError::Sunset { .. }) => panic!("{e}"),
+                Err(e) => {
+                    unconfig::tracing::warn!("Failed to load the {} config of {}: {e}", #name, stringify!(#ident));
+
+                    config_ct.#prev_ident
+                }
+            }
+        }
+    } else if let Some(env_var) = env_cp {
+        quote! {
+            match <#upper_ident as unconfig::Config>::load_env_section(#env_var, #rt_cp, stringify!(#prev_ident)) {
+                Ok(config_rt) => unconfig::tracing::debug_span!("config_merge", config = stringify!(#ident))
+                    .in_scope(|| unconfig::Merge::merge(config_ct.#prev_ident, config_rt.#prev_ident)),
+                // Unlike a broken file, a section past its sunset has to be noticed
+                Err(e @ unconfig::UnconfigError::Sunset { .. }) => panic!("{e}"),
+                Err(_) => config_ct.#prev_ident,
+            }
+        }
+    } else {
+        quote! {
+            match <#upper_ident as unconfig::Config>::load_path_section(#rt_cp, stringify!(#prev_ident)) {
+                Ok(config_rt) => unconfig::tracing::debug_span!("config_merge", config = stringify!(#ident))
+                    .in_scope(|| unconfig::Merge::merge(config_ct.#prev_ident, config_rt.#prev_ident)),
+                // Unlike a broken file, a section past its sunset has to be noticed
+                Err(e @ unconfig::UnconfigError::Sunset { .. }) => panic!("{e}"),
+                Err(_) => config_ct.#prev_ident,
+            }
+        }
+    };
+
+    // Same as above, but a broken runtime file fails instead of being skipped
+    let reload_runtime = if let Some((load, _, _, key)) = &remote {
+        quote! { <#upper_ident as unconfig::Config>::#load(#key, stringify!(#prev_ident)) }
+    } else if let Some(env_var) = env_cp {
+        quote! { <#upper_ident as unconfig::Config>::load_env_section(#env_var, #rt_cp, stringify!(#prev_ident)) }
+    } else {
+        quote! { <#upper_ident as unconfig::Config>::load_path_section(#rt_cp, stringify!(#prev_ident)) }
+    };
+    let watched_path = watched_path(args);
+    let watcher = match &remote {
+        Some((_, watch, _, key)) => {
+            quote! { unconfig::ConfigWatcher::#watch(#key, Self::init(), Self::reload) }
+        }
+        None => quote! { unconfig::ConfigWatcher::new(#watched_path, Self::init(), Self::reload) },
+    };
+
+    // Every source of the struct read in the `format = "..."` one, until the guard drops
+    let (init_format, reload_format) = match format {
+        Some(format) => (
+            quote! { let _format = unconfig::force_format(#format).unwrap_or_else(|e| panic!("{e}")); },
+            quote! { let _format = unconfig::force_format(#format)?; },
+        ),
+        None => (quote! {}, quote! {}),
+    };
+
+    // The files must have the types of the fields, the environment and Vault layers are
+    // text and still convert
+    let (strict, relaxed, test_strict) = if *strict_types {
+        (
+            quote! { let strict = unconfig::strict_types(); },
+            quote! { drop(strict); },
+            quote! { let _strict = unconfig::strict_types(); },
+        )
+    } else {
+        (quote! {}, quote! {}, quote! {})
+    };
+
+    // The section warns in the sources that have it, and fails them after its sunset
+    let (init_deprecation, reload_deprecation) = match deprecated {
+        Some(note) => {
+            let sunset = match sunset {
+                Some(sunset) => quote! { Some(#sunset.to_string()) },
+                None => quote! { None },
+            };
+            let deprecate = quote! {
+                unconfig::deprecate_section(stringify!(#prev_ident), unconfig::Deprecation {
+                    note: #note.to_string(),
+                    sunset: #sunset,
+                    version: Some(env!("CARGO_PKG_VERSION").to_string()),
+                })
+            };
+
+            (
+                quote! { #deprecate.unwrap_or_else(|e| panic!("{e}")); },
+                quote! { #deprecate?; },
+            )
+        }
+        None => (quote! {}, quote! {}),
+    };
+
+    // `config.<profile>.yml` over the runtime file, skipped like it when broken
+    let init_profile = quote! {
+        let config = match unconfig::load_profile::<#upper_ident>(#watched_path, stringify!(#prev_ident)) {
+            Ok(Some(config_profile)) => unconfig::tracing::debug_span!("config_merge", config = stringify!(#ident))
+                .in_scope(|| unconfig::Merge::merge(config, config_profile.#prev_ident)),
+            Ok(None) => config,
+            Err(e @ unconfig::UnconfigError::Sunset { .. }) => panic!("{e}"),
+            Err(e) => {
+                unconfig::tracing::warn!("Failed to load the profile config of {}: {e}", stringify!(#ident));
+
+                config
+            }
+        };
+    };
+    let reload_profile = quote! {
+        let config = match unconfig::load_profile::<#upper_ident>(#watched_path, stringify!(#prev_ident))? {
+            Some(config_profile) => unconfig::tracing::debug_span!("config_merge", config = stringify!(#ident))
+                .in_scope(|| unconfig::Merge::merge(config, config_profile.#prev_ident)),
+            None => config,
+        };
+    };
+
+    // `#[vault]` fields over the files, a secret that can't be read fails the load
+    let (init_vault, reload_vault) = if vault_fields.is_empty() {
+        (quote! {}, quote! {})
+    } else {
+        let load = quote! {
+            unconfig::load_vault::<#upper_ident>(stringify!(#prev_ident), &[#vault_fields])
+        };
+
+        (
+            quote! {
+                let config = match #load {
+                    Ok(config_vault) => unconfig::tracing::debug_span!("config_merge", config = stringify!(#ident))
+                        .in_scope(|| unconfig::Merge::merge(config, config_vault.#prev_ident)),
+                    Err(e) => panic!("Failed to read the Vault secrets of {}: {e}", stringify!(#ident)),
+                };
+            },
+            quote! {
+                let config_vault = #load?;
+                let config = unconfig::tracing::debug_span!("config_merge", config = stringify!(#ident))
+                    .in_scope(|| unconfig::Merge::merge(config, config_vault.#prev_ident));
+            },
+        )
+    };
+
+    // Environment variables are the last layer
+    let prefixed = |sunset: proc_macro2::TokenStream| {
+        env_prefix.as_ref().map(|prefix| {
+            quote! {
+                let config = match <#upper_ident as unconfig::Config>::load_prefixed_section(#prefix, stringify!(#prev_ident)) {
+                    Ok(config_env) => unconfig::tracing::debug_span!("config_merge", config = stringify!(#ident))
+                        .in_scope(|| unconfig::Merge::merge(config, config_env.#prev_ident)),
+                    Err(e @ unconfig::UnconfigError::Sunset { .. }) => #sunset,
+                    Err(_) => config,
+                };
+            }
+        })
+    };
+    // A sunset passing in a running process fails the reload, not the watcher
+    let init_prefixed = prefixed(quote! { panic!("{e}") });
+    let reload_prefixed = prefixed(quote! { return Err(e) });
+
+    let init_compile_time = if let Some(ct_cp) = ct_cp {
+        quote! {
+            <#upper_ident as unconfig::Config>::load_str_section(include_str!(#ct_cp), stringify!(#prev_ident)).unwrap()
+        }
+    } else {
+        // Convention mode: nothing to embed, start from the environment alone
+        quote! {
+            {
+                unconfig::tracing::warn!(
+                    "No config file found for {}, using environment variables and defaults",
+                    stringify!(#ident)
+                );
+
+                <#upper_ident as unconfig::Config>::load_vars_section(stringify!(#prev_ident), &[#field_names]).unwrap()
+            }
+        }
+    };
+
+    quote! {
+        #[derive(#attrs unconfig::serde::Serialize, unconfig::serde::Deserialize)]
+        #[serde(crate = "unconfig::serde")]
+        #[serde(rename_all = "snake_case")]
+        pub struct #upper_ident #generics {
+            #prev_ident: #ident,
+        }
+
+        impl #ident {
+            /// Where each value of the last loaded config came from, by key path such as
+            /// `database.host`: the embedded file, the runtime file or the environment,
+            /// with the value as written if `${...}` substitution replaced it
+            pub fn provenance(&self) -> unconfig::IndexMap<String, unconfig::Provenance> {
+                unconfig::provenance_of(stringify!(#ident))
+            }
+        }
+
+        impl #upper_ident {
+            pub fn init() -> #ident {
+                #init_format
+                #init_deprecation
+                let provenance = unconfig::track_provenance();
+                #strict
+
+                // Compile time config
+                let config_ct = #init_compile_time;
+
+                // Runtime config
+                let config = #init_runtime;
+                #init_profile
+                #relaxed
+                #init_vault
+                #init_prefixed
+                provenance.finish(stringify!(#ident), stringify!(#prev_ident), &[#deep_names]);
+                // Already logged
+                let _ = config.check_deep();
+                if let Err(e) = config.check_fields() {
+                    panic!("{e}");
+                }
+                config.export_gauges();
+                unconfig::publish_loaded(stringify!(#ident));
+
+                config
+            }
+
+            // `init` for reloads, failing on a runtime file that doesn't load
+            pub fn reload() -> ::std::result::Result<#ident, unconfig::UnconfigError> {
+                #reload_format
+                #reload_deprecation
+                let provenance = unconfig::track_provenance();
+                #strict
+                let config_ct = #init_compile_time;
+                let config_rt = #reload_runtime?;
+                let config = unconfig::tracing::debug_span!("config_merge", config = stringify!(#ident))
+                    .in_scope(|| unconfig::Merge::merge(config_ct.#prev_ident, config_rt.#prev_ident));
+                #reload_profile
+                #relaxed
+                #reload_vault
+                #reload_prefixed
+                provenance.finish(stringify!(#ident), stringify!(#prev_ident), &[#deep_names]);
+                config.check_deep()?;
+                config.check_fields()?;
+                config.export_gauges();
+
+                Ok(config)
+            }
+
+            // Current config, reloaded whenever the runtime file, Consul key or etcd prefix changes
+            pub fn watch() -> unconfig::ConfigWatcher<#ident> {
+                #watcher
+            }
+
+            // Write `config` back to the runtime file, keeping its other sections
+            pub fn save(config: &#ident) -> ::std::result::Result<(), unconfig::UnconfigError> {
+                unconfig::Config::save_path_section(config, #watched_path, stringify!(#prev_ident))
+            }
+
+            // `init` with inline sources instead of the files, for `#[test_config]`
+            #[doc(hidden)]
+            pub fn load_test(
+                compile_time: &'static str,
+                runtime: Option<&'static str>,
+            ) -> ::std::result::Result<#ident, unconfig::UnconfigError> {
+                #reload_format
+                #reload_deprecation
+                #test_strict
+                let config = <#upper_ident as unconfig::Config>::load_str_section(compile_time, stringify!(#prev_ident))?.#prev_ident;
+                let config = match runtime {
+                    Some(runtime) => unconfig::Merge::merge(config, <#upper_ident as unconfig::Config>::load_str_section(runtime, stringify!(#prev_ident))?.#prev_ident),
+                    None => config,
+                };
+                config.check_deep()?;
+                config.check_fields()?;
+
+                Ok(config)
+            }
+        }
+    }
+}
+
+// Runtime file, as named by its environment variable when set
+fn watched_path(args: &PathArgsConfigurable) -> proc_macro2::TokenStream {
+    let rt_cp = &args.rt_cp;
+
+    match &args.env_cp {
+        Some(env_var) => {
+            quote! { unconfig::overlay_var(#env_var).unwrap_or_else(|_| #rt_cp.to_string()) }
+        }
+        None => quote! { #rt_cp },
+    }
+}
+
+// Test
+#[proc_macro_attribute]
+pub fn test_config(args: TokenStream, item: TokenStream) -> TokenStream {
+    let mut input = parse_macro_input!(item as ItemFn);
+    let TestConfigArgs {
+        config_ident,
+        yaml,
+        runtime,
+        env,
+    } = parse_macro_input!(args as TestConfigArgs);
+
+    // The only argument receives the config, or the load error with a `Result` type. The
+    // type only picks one of them, config structs aren't in scope outside their module.
+    let Some(FnArg::Typed(arg)) = input.sig.inputs.pop().map(|pair| pair.into_value()) else {
+        return syn::Error::new_spanned(
+            &input.sig,
+            "expected one argument taking the config, e.g. `user: User`",
+        )
+        .to_compile_error()
+        .into();
+    };
+    let pat = &arg.pat;
+    let ty = &arg.ty;
+
+    let upper_ident = format_ident!("Upper{config_ident}");
+    let config_macro = format_ident!(
+        "{}__config__macro",
+        config_ident.to_string().to_case(Case::Snake)
+    );
+    let runtime = match runtime {
+        Some(runtime) => quote! { Some(#runtime) },
+        None => quote! { None },
+    };
+    let load = quote! { #config_macro::#upper_ident::load_test(#yaml, #runtime) };
+    let is_result = matches!(
+        ty.as_ref(),
+        Type::Path(path) if path.path.segments.last().is_some_and(|segment| segment.ident == "Result")
+    );
+    let load = if is_result {
+        load
+    } else {
+        quote! { #load.unwrap_or_else(|e| panic!("{e:#}")) }
+    };
+
+    let has_test = input.attrs.iter().any(|attr| attr.path().is_ident("test"));
+    let test_attr = if has_test {
+        quote! {}
+    } else {
+        quote! { #[test] }
+    };
+    let attrs = input.attrs.iter().fold(quote! {}, |acc, attr| {
+        quote! { #acc #attr }
+    });
+    let vis = &input.vis;
+    let sig = &input.sig;
+    let stmts = &input.block.stmts;
+    // Kept in place for the whole test, the body may load configs too
+    let env = (!env.is_empty()).then(|| {
+        let (names, values): (Vec<_>, Vec<_>) = env.into_iter().unzip();
+
+        quote! {
+            let _env = unconfig::EnvOverlay::new()#(.set(#names, #values))*.enter();
+        }
+    });
+
+    quote! {
+        #test_attr
+        #attrs
+        #vis #sig {
+            #env
+            let #pat = #load;
+
+            #(#stmts)*
+        }
+    }
+    .into()
 }
 
 // Logger
 #[proc_macro_attribute]
 pub fn logger(args: TokenStream, item: TokenStream) -> TokenStream {
-    let input = parse_macro_input!(item as ItemFn);
+    let raw_args = proc_macro2::TokenStream::from(args.clone());
+    let mut input = parse_macro_input!(item as ItemFn);
+
+    // A `#[config]` below would wrap the logger setup and load every config before it,
+    // so let it expand first and come back to the logger afterwards
+    if let Some(index) = input.attrs.iter().position(|attr| {
+        attr.path()
+            .segments
+            .last()
+            .is_some_and(|segment| segment.ident == "config")
+    }) {
+        let config_attr = input.attrs.remove(index);
+
+        return quote! {
+            #config_attr
+            #[unconfig::logger(#raw_args)]
+            #input
+        }
+        .into();
+    }
+
     let args = parse_macro_input!(args as PathArgsLogger);
 
     let prev_fn_body = input.block.stmts.iter().fold(quote! {}, |acc, stmt| {
@@ -256,17 +1191,23 @@ pub fn logger(args: TokenStream, item: TokenStream) -> TokenStream {
     let init_runtime = if let Some(env_var) = env_cp {
         quote! {
             if let Ok(ulp_rt) =
-                <unconfig::UpperLoggerParams as unconfig::Config>::load_env(#env_var, #rt_cp)
+                <unconfig::UpperLoggerParams as unconfig::Config>::load_env_section(#env_var, #rt_cp, "logger")
             {
-                unconfig::Logger::init(&ulp_rt.merge(ulp_ct))?
+                let merged = unconfig::tracing::debug_span!("config_merge", config = "logger")
+                    .in_scope(|| ulp_rt.merge(ulp_ct));
+
+                unconfig::Logger::init(&merged)?
             } else {
                 unconfig::Logger::init(&ulp_ct)?
             };
         }
     } else {
         quote! {
-            if let Ok(ulp_rt) = <unconfig::UpperLoggerParams as unconfig::Config>::load_path(#rt_cp) {
-                unconfig::Logger::init(&ulp_rt.merge(ulp_ct))?
+            if let Ok(ulp_rt) = <unconfig::UpperLoggerParams as unconfig::Config>::load_path_section(#rt_cp, "logger") {
+                let merged = unconfig::tracing::debug_span!("config_merge", config = "logger")
+                    .in_scope(|| ulp_rt.merge(ulp_ct));
+
+                unconfig::Logger::init(&merged)?
             } else {
                 unconfig::Logger::init(&ulp_ct)?
             };
@@ -277,11 +1218,17 @@ pub fn logger(args: TokenStream, item: TokenStream) -> TokenStream {
     quote! {
         #prev_attrs
         #vis #sig {
+            // Events from loading the logger config come before the logger itself
+            let startup = unconfig::StartupBuffer::install();
+
             // Compile time logger
-            let ulp_ct = <unconfig::UpperLoggerParams as unconfig::Config>::load_str(include_str!(#ct_cp)).unwrap();
+            let ulp_ct = <unconfig::UpperLoggerParams as unconfig::Config>::load_str_section(include_str!(#ct_cp), "logger").unwrap();
 
             // Runtime logger
             let _logger = #init_runtime
+            startup.replay();
+            // Flushes the log files once the body is done, before the process exits
+            let _shutdown = _logger.shutdown_guard();
 
             #prev_fn_body
         }
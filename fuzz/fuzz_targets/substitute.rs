@@ -0,0 +1,9 @@
+//! Run with `cargo fuzz run substitute` from the repository root.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    unconfig::fuzz::substitute_arbitrary(data);
+});
@@ -0,0 +1,40 @@
+/* C API of unconfig, built with its `ffi` feature */
+
+#ifndef UNCONFIG_H
+#define UNCONFIG_H
+
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* Called with the new generation and the user_data it was registered with */
+typedef void (*unconfig_reload_callback)(uint64_t generation, void *user_data);
+
+/* Value at a dotted path such as "user.name", NULL when unset or not a scalar.
+ * Free it with unconfig_free_str. */
+char *unconfig_get_str(const char *path);
+
+void unconfig_free_str(char *text);
+
+/* Changes whenever an exposed value may have */
+uint64_t unconfig_generation(void);
+
+/* Called after every change of the exposed configs, on the thread that made it */
+void unconfig_on_reload(unconfig_reload_callback callback, void *user_data);
+
+/* With the `python` feature: the results as JSON, NULL on errors. Free them with
+ * unconfig_free_str. */
+char *unconfig_load(const char *path);
+char *unconfig_expand(const char *config);
+char *unconfig_merge(const char *base, const char *overlay);
+
+/* Why the last call on this thread returned NULL, valid until the next call */
+const char *unconfig_last_error(void);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
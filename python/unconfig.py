@@ -0,0 +1,77 @@
+"""Python bindings of the unconfig loader
+
+Configs are loaded, expanded and merged by the Rust library itself, so `${...}`
+substitution, overlays, limits and the env policy match the Rust services exactly.
+
+Needs unconfig built with its `python` feature into a shared library, e.g. a crate with
+`crate-type = ["cdylib"]` re-exporting it. The library is looked up at `UNCONFIG_LIB`,
+then on the system library path.
+"""
+
+import ctypes
+import ctypes.util
+import json
+import os
+
+__all__ = ["UnconfigError", "load", "expand", "merge"]
+
+
+class UnconfigError(Exception):
+    """A config that failed to load, expand or merge"""
+
+
+def _library():
+    path = os.environ.get("UNCONFIG_LIB") or ctypes.util.find_library("unconfig")
+    if path is None:
+        raise UnconfigError("unconfig library not found, set UNCONFIG_LIB to its path")
+
+    lib = ctypes.CDLL(path)
+    for name, args in [
+        ("unconfig_load", [ctypes.c_char_p]),
+        ("unconfig_expand", [ctypes.c_char_p]),
+        ("unconfig_merge", [ctypes.c_char_p, ctypes.c_char_p]),
+    ]:
+        function = getattr(lib, name)
+        function.argtypes = args
+        # Not c_char_p, the string must be freed by the library
+        function.restype = ctypes.c_void_p
+
+    lib.unconfig_free_str.argtypes = [ctypes.c_void_p]
+    lib.unconfig_free_str.restype = None
+    lib.unconfig_last_error.argtypes = []
+    lib.unconfig_last_error.restype = ctypes.c_char_p
+
+    return lib
+
+
+_lib = None
+
+
+def _call(name, *args):
+    global _lib
+    if _lib is None:
+        _lib = _library()
+
+    result = getattr(_lib, name)(*(arg.encode() for arg in args))
+    if not result:
+        raise UnconfigError(_lib.unconfig_last_error().decode())
+
+    try:
+        return json.loads(ctypes.string_at(result).decode())
+    finally:
+        _lib.unconfig_free_str(result)
+
+
+def load(path):
+    """The config file at `path`, with its overlays and `${...}` references resolved"""
+    return _call("unconfig_load", os.fspath(path))
+
+
+def expand(text):
+    """Config text with its overlays and `${...}` references resolved"""
+    return _call("unconfig_expand", text)
+
+
+def merge(base, overlay):
+    """`overlay` merged over `base`: dicts key by key, other values replaced"""
+    return _call("unconfig_merge", json.dumps(base), json.dumps(overlay))
@@ -0,0 +1,10 @@
+//! Admin surface of a running service: reading and replacing its config, changing log
+//! levels and reporting health
+//!
+//! The wire format is defined in [`api`] and answered by [`AdminService`], serving it
+//! over HTTP or gRPC (see `proto/admin.proto`) is up to the application.
+
+pub mod api;
+mod service;
+
+pub use service::AdminService;
@@ -0,0 +1,217 @@
+//! Versioned JSON messages of the admin API
+//!
+//! Every message is an object with the `version` of the format it follows, a request
+//! names its `method` and a response its `status`:
+//!
+//! ```json
+//! {"version":1,"method":"get_config","section":"server"}
+//! {"version":1,"status":"config","config":{"port":8080}}
+//! ```
+//!
+//! Within a version fields are only added, optional ones, and unknown fields are ignored,
+//! so older clients keep working against newer servers. Anything else makes a new
+//! version, a server answers the versions it knows and rejects others with
+//! [`ErrorCode::UnsupportedVersion`].
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_yaml::Value;
+use thiserror::Error;
+
+use crate::{json, Patch, UnconfigError};
+
+/// Version of the messages defined here
+pub const VERSION: u32 = 1;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "method", rename_all = "snake_case")]
+pub enum Request {
+    /// The config in effect, or only its top-level `section`
+    GetConfig {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        section: Option<String>,
+    },
+    /// Replace the config, or only its top-level `section`, as a changed runtime file
+    /// would
+    PutConfig {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        section: Option<String>,
+        config: Value,
+    },
+    /// Change the config, or only its top-level `section`, with a JSON Patch or a merge
+    /// patch, then put it in effect as [`Request::PutConfig`] would
+    PatchConfig {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        section: Option<String>,
+        patch: Patch,
+    },
+    /// Log level of `target`, or the default level without one, e.g. `debug`
+    SetLevel {
+        level: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        target: Option<String>,
+    },
+    GetHealth,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum Response {
+    /// Answer to [`Request::GetConfig`]
+    Config { config: Value },
+    /// Answer to [`Request::PutConfig`], [`Request::PatchConfig`] and [`Request::SetLevel`]
+    Ok,
+    /// Answer to [`Request::GetHealth`]
+    Health(Health),
+    /// A config put in effect, streamed by [`crate::admin::AdminService::stream_config_changes`]
+    Changed { section: String, config: Value },
+    /// Any request that failed
+    Error(ApiError),
+}
+
+/// Config and logger state, see [`crate::Health`]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Health {
+    pub healthy: bool,
+    pub generation: u64,
+    #[serde(default)]
+    pub last_reload: Option<Reload>,
+    #[serde(default)]
+    pub providers: Vec<Provider>,
+    #[serde(default)]
+    pub sinks: Vec<Sink>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Reload {
+    /// RFC 3339
+    pub timestamp: String,
+    pub source: String,
+    #[serde(default)]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Provider {
+    pub name: String,
+    pub connected: bool,
+    #[serde(default)]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Sink {
+    pub name: String,
+    pub dropped_lines: usize,
+}
+
+/// Body of [`Response::Error`]
+#[derive(Error, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[error("{message}")]
+pub struct ApiError {
+    pub code: ErrorCode,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    /// Not a message of any version
+    BadRequest,
+    UnsupportedVersion,
+    /// No such config section
+    NotFound,
+    /// The submitted config or level was rejected
+    Invalid,
+    Internal,
+}
+
+impl ApiError {
+    pub fn new(code: ErrorCode, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+impl From<UnconfigError> for ApiError {
+    fn from(e: UnconfigError) -> Self {
+        let code = match e {
+            UnconfigError::Io { .. } => ErrorCode::Internal,
+            _ => ErrorCode::Invalid,
+        };
+
+        Self::new(code, e.to_string())
+    }
+}
+
+impl From<crate::Health> for Health {
+    fn from(health: crate::Health) -> Self {
+        Self {
+            healthy: health.is_healthy(),
+            generation: health.generation,
+            last_reload: health.last_reload.map(|reload| Reload {
+                timestamp: reload.timestamp,
+                source: reload.source,
+                error: reload.error,
+            }),
+            providers: health
+                .providers
+                .into_iter()
+                .map(|provider| Provider {
+                    name: provider.name,
+                    connected: provider.connected,
+                    error: provider.error,
+                })
+                .collect(),
+            sinks: health
+                .sinks
+                .into_iter()
+                .map(|sink| Sink {
+                    name: sink.name,
+                    dropped_lines: sink.dropped_lines,
+                })
+                .collect(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct Versioned<T> {
+    version: u32,
+    #[serde(flatten)]
+    message: T,
+}
+
+/// A request or response as a JSON object of the current [`VERSION`]
+pub fn encode<T: Serialize>(message: &T) -> String {
+    let versioned = Versioned {
+        version: VERSION,
+        message,
+    };
+
+    serde_yaml::to_value(&versioned)
+        .map(|value| json::to_string(&value))
+        .unwrap_or_default()
+}
+
+/// A request or response of the current [`VERSION`] from JSON
+pub fn decode<T: DeserializeOwned>(text: &str) -> Result<T, ApiError> {
+    let value: Value = serde_yaml::from_str(text)
+        .map_err(|e| ApiError::new(ErrorCode::BadRequest, format!("invalid JSON: {e}")))?;
+
+    match value.get("version").and_then(Value::as_u64) {
+        Some(version) if version == VERSION as u64 => {}
+        Some(version) => {
+            return Err(ApiError::new(
+                ErrorCode::UnsupportedVersion,
+                format!("version {version} is not supported, expected {VERSION}"),
+            ))
+        }
+        None => return Err(ApiError::new(ErrorCode::BadRequest, "no `version`")),
+    }
+
+    serde_yaml::from_value::<Versioned<T>>(value)
+        .map(|versioned| versioned.message)
+        .map_err(|e| ApiError::new(ErrorCode::BadRequest, e.to_string()))
+}
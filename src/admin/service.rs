@@ -0,0 +1,272 @@
+use std::sync::mpsc::{self, Receiver};
+
+use indexmap::IndexMap;
+use serde::{de::DeserializeOwned, Serialize};
+use serde_yaml::{Mapping, Value};
+
+use super::api::{self, ApiError, ErrorCode, Request, Response};
+use crate::{
+    drift, events, health, patch, pipeline, secret, ConfigWatcher, Event, Logger, LoggerError,
+    Patch, Validate,
+};
+
+/// Answers the [`api`] requests for the configs and the logger registered with it
+///
+/// The same service backs every transport: [`AdminService::handle_json`] for an HTTP
+/// body, [`AdminService::handle`] and [`AdminService::stream_config_changes`] for the
+/// gRPC service of `proto/admin.proto`. Applied configs go through the loading pipeline
+/// and [`Validate`] before being put in effect, as a changed runtime file would.
+///
+/// ```no_run
+/// # use unconfig::{admin::AdminService, Logger};
+/// # fn serve(logger: Logger, body: &str) {
+/// let admin = AdminService::new().logger(logger);
+/// let response = admin.handle_json(body);
+/// # }
+/// ```
+#[derive(Default)]
+pub struct AdminService {
+    sections: IndexMap<String, Box<dyn Section>>,
+    logger: Option<Logger>,
+}
+
+// A watched config with its type erased
+trait Section: Send + Sync {
+    fn get(&self) -> Result<Value, ApiError>;
+    // Secrets included, to be changed and applied again
+    fn exposed(&self) -> Result<Value, ApiError>;
+    // Checks `config`, putting it in effect is left to the returned closure so that a
+    // whole config is only applied once all of its sections pass
+    fn prepare(&self, config: Value) -> Result<Box<dyn FnOnce() + '_>, ApiError>;
+    fn on_change(&self, f: Box<dyn Fn(Value) + Send + Sync>);
+}
+
+impl<T> Section for ConfigWatcher<T>
+where
+    T: Serialize + DeserializeOwned + Validate + Send + Sync + 'static,
+{
+    fn get(&self) -> Result<Value, ApiError> {
+        serde_yaml::to_value(&*self.load())
+            .map_err(|e| ApiError::new(ErrorCode::Internal, e.to_string()))
+    }
+
+    fn exposed(&self) -> Result<Value, ApiError> {
+        secret::exposing(|| serde_yaml::to_value(&*self.load()))
+            .map_err(|e| ApiError::new(ErrorCode::Internal, e.to_string()))
+    }
+
+    fn prepare(&self, config: Value) -> Result<Box<dyn FnOnce() + '_>, ApiError> {
+        let value = pipeline::load::<T>("admin", None, config)?;
+        value.validate()?;
+
+        Ok(Box::new(move || {
+            let old = self.get().unwrap_or_default();
+            self.store(value, "admin");
+
+            events::publish(Event::ReloadSucceeded {
+                source: "admin".to_string(),
+                diff: drift::diff(&old, &self.get().unwrap_or_default(), &[]),
+            });
+        }))
+    }
+
+    fn on_change(&self, f: Box<dyn Fn(Value) + Send + Sync>) {
+        self.on_change(move |_, new| {
+            if let Ok(value) = serde_yaml::to_value(new) {
+                f(value);
+            }
+        });
+    }
+}
+
+impl From<LoggerError> for ApiError {
+    fn from(e: LoggerError) -> Self {
+        let code = match e {
+            LoggerError::Filter | LoggerError::Params(_) => ErrorCode::Invalid,
+            _ => ErrorCode::Internal,
+        };
+
+        Self::new(code, e.to_string())
+    }
+}
+
+impl AdminService {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Serve the config of `watcher` as the top-level `section`
+    pub fn config<T>(mut self, section: &str, watcher: ConfigWatcher<T>) -> Self
+    where
+        T: Serialize + DeserializeOwned + Validate + Send + Sync + 'static,
+    {
+        self.sections.insert(section.to_string(), Box::new(watcher));
+        self
+    }
+
+    /// Serve the levels of `logger`
+    pub fn logger(mut self, logger: Logger) -> Self {
+        self.logger = Some(logger);
+        self
+    }
+
+    /// The config in effect, or only its `section`
+    pub fn get_effective_config(&self, section: Option<&str>) -> Result<Value, ApiError> {
+        self.collect(section, |section| section.get())
+    }
+
+    /// Replace the config of `section`, or of every section `config` has when `None`
+    ///
+    /// Nothing is applied unless every section deserializes and validates.
+    pub fn apply_config(&self, section: Option<&str>, config: Value) -> Result<(), ApiError> {
+        let sections = match section {
+            Some(section) => vec![(section.to_string(), config)],
+            None => match config {
+                Value::Mapping(mapping) => mapping
+                    .into_iter()
+                    .map(|(name, config)| match name {
+                        Value::String(name) => Ok((name, config)),
+                        _ => Err(ApiError::new(
+                            ErrorCode::Invalid,
+                            "section names must be strings",
+                        )),
+                    })
+                    .collect::<Result<_, _>>()?,
+                _ => {
+                    return Err(ApiError::new(
+                        ErrorCode::Invalid,
+                        "the config must be a mapping of sections",
+                    ))
+                }
+            },
+        };
+
+        let stores = sections
+            .into_iter()
+            .map(|(name, config)| {
+                self.section(&name)?.prepare(config).map_err(|e| ApiError {
+                    message: format!("{name}: {}", e.message),
+                    ..e
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        for store in stores {
+            store();
+        }
+
+        Ok(())
+    }
+
+    /// Patch the config in effect, or only its `section`, and apply the result as
+    /// [`Self::apply_config`] does
+    pub fn patch_config(&self, section: Option<&str>, patch: &Patch) -> Result<(), ApiError> {
+        let mut config = self.collect(section, |section| section.exposed())?;
+        patch::apply(&mut config, patch)
+            .map_err(|e| ApiError::new(ErrorCode::Invalid, format!("{e:#}")))?;
+
+        self.apply_config(section, config)
+    }
+
+    /// Change the level of `target`, or the default level without one
+    pub fn set_log_level(
+        &self,
+        target: Option<&str>,
+        level: &str,
+        actor: Option<&str>,
+    ) -> Result<(), ApiError> {
+        let logger = self
+            .logger
+            .as_ref()
+            .ok_or_else(|| ApiError::new(ErrorCode::NotFound, "no logger is served"))?;
+
+        Ok(logger.set_level(target, level, actor)?)
+    }
+
+    /// A [`Response::Changed`] every time a config, or only `section`, is put in effect
+    ///
+    /// Changes stop being sent once the receiver is dropped.
+    pub fn stream_config_changes(
+        &self,
+        section: Option<&str>,
+    ) -> Result<Receiver<Response>, ApiError> {
+        let (sender, receiver) = mpsc::channel();
+
+        let sections = match section {
+            Some(name) => vec![(name, self.section(name)?)],
+            None => self
+                .sections
+                .iter()
+                .map(|(name, section)| (name.as_str(), &**section))
+                .collect(),
+        };
+
+        for (name, section) in sections {
+            let sender = sender.clone();
+            let name = name.to_string();
+
+            section.on_change(Box::new(move |config| {
+                let _ = sender.send(Response::Changed {
+                    section: name.clone(),
+                    config,
+                });
+            }));
+        }
+
+        Ok(receiver)
+    }
+
+    /// Answer one request, errors included
+    pub fn handle(&self, request: Request) -> Response {
+        let response = match request {
+            Request::GetConfig { section } => self
+                .get_effective_config(section.as_deref())
+                .map(|config| Response::Config { config }),
+            Request::PutConfig { section, config } => self
+                .apply_config(section.as_deref(), config)
+                .map(|_| Response::Ok),
+            Request::PatchConfig { section, patch } => self
+                .patch_config(section.as_deref(), &patch)
+                .map(|_| Response::Ok),
+            Request::SetLevel { level, target } => self
+                .set_log_level(target.as_deref(), &level, Some("admin"))
+                .map(|_| Response::Ok),
+            Request::GetHealth => Ok(Response::Health(health::health().into())),
+        };
+
+        response.unwrap_or_else(Response::Error)
+    }
+
+    /// Answer one request as [`api`] JSON, e.g. the body of an HTTP request
+    pub fn handle_json(&self, request: &str) -> String {
+        let response = match api::decode(request) {
+            Ok(request) => self.handle(request),
+            Err(e) => Response::Error(e),
+        };
+
+        api::encode(&response)
+    }
+
+    fn collect(
+        &self,
+        section: Option<&str>,
+        get: impl Fn(&dyn Section) -> Result<Value, ApiError>,
+    ) -> Result<Value, ApiError> {
+        match section {
+            Some(section) => get(self.section(section)?),
+            None => self
+                .sections
+                .iter()
+                .map(|(name, section)| Ok((Value::from(name.as_str()), get(&**section)?)))
+                .collect::<Result<Mapping, ApiError>>()
+                .map(Value::Mapping),
+        }
+    }
+
+    fn section(&self, name: &str) -> Result<&dyn Section, ApiError> {
+        self.sections
+            .get(name)
+            .map(|section| &**section)
+            .ok_or_else(|| ApiError::new(ErrorCode::NotFound, format!("no section `{name}`")))
+    }
+}
@@ -0,0 +1,187 @@
+use std::{
+    future::Future,
+    panic::{self, AssertUnwindSafe},
+    path::Path,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll, Waker},
+    thread,
+};
+
+use serde::de::DeserializeOwned;
+
+use crate::{Config, UnconfigError};
+
+/// [`Config`] loading that doesn't block the async runtime it's awaited on
+///
+/// Files are read and parsed on a separate thread, which wakes the task when done, so it
+/// works with any executor. Both traits are implemented for the same types, import only
+/// one of them or call through `<T as AsyncConfig>::load_path`.
+pub trait AsyncConfig: Sized {
+    fn load_path<S: AsRef<Path>>(
+        path: S,
+    ) -> impl Future<Output = Result<Self, UnconfigError>> + Send;
+    fn load_env<S: AsRef<Path>>(
+        env: &'static str,
+        alt_path: S,
+    ) -> impl Future<Output = Result<Self, UnconfigError>> + Send;
+
+    // Same as above, but only the top-level `section` of the source is expanded and deserialized
+    fn load_path_section<S: AsRef<Path>>(
+        path: S,
+        section: &str,
+    ) -> impl Future<Output = Result<Self, UnconfigError>> + Send;
+    fn load_env_section<S: AsRef<Path>>(
+        env: &'static str,
+        alt_path: S,
+        section: &str,
+    ) -> impl Future<Output = Result<Self, UnconfigError>> + Send;
+}
+
+impl<T: DeserializeOwned + Send + 'static> AsyncConfig for T {
+    fn load_path<S: AsRef<Path>>(
+        path: S,
+    ) -> impl Future<Output = Result<Self, UnconfigError>> + Send {
+        let path = path.as_ref().to_path_buf();
+
+        Blocking::new(move || <T as Config>::load_path(path))
+    }
+
+    fn load_env<S: AsRef<Path>>(
+        env: &'static str,
+        alt_path: S,
+    ) -> impl Future<Output = Result<Self, UnconfigError>> + Send {
+        let alt_path = alt_path.as_ref().to_path_buf();
+
+        Blocking::new(move || <T as Config>::load_env(env, alt_path))
+    }
+
+    fn load_path_section<S: AsRef<Path>>(
+        path: S,
+        section: &str,
+    ) -> impl Future<Output = Result<Self, UnconfigError>> + Send {
+        let path = path.as_ref().to_path_buf();
+        let section = section.to_string();
+
+        Blocking::new(move || <T as Config>::load_path_section(path, &section))
+    }
+
+    fn load_env_section<S: AsRef<Path>>(
+        env: &'static str,
+        alt_path: S,
+        section: &str,
+    ) -> impl Future<Output = Result<Self, UnconfigError>> + Send {
+        let alt_path = alt_path.as_ref().to_path_buf();
+        let section = section.to_string();
+
+        Blocking::new(move || <T as Config>::load_env_section(env, alt_path, &section))
+    }
+}
+
+type Job<T> = Box<dyn FnOnce() -> T + Send>;
+
+// Runs `job` on its own thread once polled, with the caller's span and subscriber. A panic
+// of the job is resumed in the task awaiting it
+struct Blocking<T> {
+    job: Option<Job<T>>,
+    shared: Arc<Mutex<Shared<T>>>,
+}
+
+struct Shared<T> {
+    result: Option<thread::Result<T>>,
+    waker: Option<Waker>,
+}
+
+impl<T: Send + 'static> Blocking<T> {
+    fn new(job: impl FnOnce() -> T + Send + 'static) -> Self {
+        Self {
+            job: Some(Box::new(job)),
+            shared: Arc::new(Mutex::new(Shared {
+                result: None,
+                waker: None,
+            })),
+        }
+    }
+}
+
+impl<T: Send + 'static> Future for Blocking<T> {
+    type Output = T;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<T> {
+        let mut shared = self.shared.lock().unwrap();
+
+        if let Some(result) = shared.result.take() {
+            drop(shared);
+
+            return match result {
+                Ok(result) => Poll::Ready(result),
+                Err(panic) => panic::resume_unwind(panic),
+            };
+        }
+
+        shared.waker = Some(cx.waker().clone());
+        drop(shared);
+
+        if let Some(job) = self.job.take() {
+            let shared = self.shared.clone();
+
+            crate::spawn_traced(move || {
+                let result = panic::catch_unwind(AssertUnwindSafe(job));
+                let mut shared = shared.lock().unwrap();
+                shared.result = Some(result);
+
+                if let Some(waker) = shared.waker.take() {
+                    waker.wake();
+                }
+            });
+        }
+
+        Poll::Pending
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::Arc,
+        task::{Wake, Waker},
+        thread::Thread,
+    };
+
+    use super::*;
+
+    struct Unpark(Thread);
+
+    impl Wake for Unpark {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    fn block_on<F: Future>(future: F) -> F::Output {
+        let waker = Waker::from(Arc::new(Unpark(thread::current())));
+        let mut cx = Context::from_waker(&waker);
+        let mut future = std::pin::pin!(future);
+
+        loop {
+            match future.as_mut().poll(&mut cx) {
+                Poll::Ready(output) => return output,
+                Poll::Pending => thread::park(),
+            }
+        }
+    }
+
+    #[test]
+    fn result() {
+        assert_eq!(block_on(Blocking::new(|| 42)), 42);
+    }
+
+    #[test]
+    fn panicking_job() {
+        let panic =
+            panic::catch_unwind(|| block_on(Blocking::new(|| -> u8 { panic!("bad config") })))
+                .unwrap_err();
+
+        assert_eq!(panic.downcast_ref::<&str>(), Some(&"bad config"));
+    }
+}
@@ -0,0 +1,110 @@
+use std::{
+    env,
+    fs::OpenOptions,
+    hash::{DefaultHasher, Hash, Hasher},
+    io::Write,
+    path::PathBuf,
+    sync::{Mutex, OnceLock},
+};
+
+use serde::Serialize;
+use serde_yaml::Value;
+use tracing::warn;
+
+use crate::{drift, json, schedule, Difference};
+
+static AUDIT_PATH: OnceLock<Option<PathBuf>> = OnceLock::new();
+// Serializes appends from concurrent reloads
+static AUDIT_LOCK: Mutex<()> = Mutex::new(());
+
+/// One line of the audit trail
+#[derive(Debug, Clone, Serialize)]
+pub struct AuditEntry {
+    /// RFC 3339 time of the reload
+    pub timestamp: String,
+    /// What was reloaded, e.g. `logger` or a config file
+    pub source: String,
+    /// Counter of successful reloads of `source`, starting at 1
+    pub generation: u64,
+    /// Changed keys, one `path: old -> new` description each
+    pub changes: Vec<String>,
+    /// Who asked for the reload, when known
+    pub actor: Option<String>,
+    /// Hash of the applied config
+    pub fingerprint: String,
+}
+
+/// Append every successful reload as a JSON line to `path`
+///
+/// Unless this is called before the first reload, the path is read from
+/// `CONFIG_AUDIT_PATH`; without either the trail is disabled. The rejected path is
+/// handed back when the trail is already set up.
+pub fn set_audit_path(path: impl Into<PathBuf>) -> Result<(), PathBuf> {
+    let path = path.into();
+
+    AUDIT_PATH.set(Some(path.clone())).map_err(|_| path)
+}
+
+fn audit_path() -> Option<&'static PathBuf> {
+    AUDIT_PATH
+        .get_or_init(|| env::var_os("CONFIG_AUDIT_PATH").map(PathBuf::from))
+        .as_ref()
+}
+
+/// Hex fingerprint of a config value
+pub(crate) fn fingerprint(value: &Value) -> String {
+    let mut hasher = DefaultHasher::new();
+    value.hash(&mut hasher);
+
+    format!("{:016x}", hasher.finish())
+}
+
+fn describe(difference: &Difference) -> String {
+    let inline = |value: &Value| json::to_string(value);
+
+    match difference {
+        Difference::Missing { path } => format!("{path}: removed"),
+        Difference::Unexpected { path } => format!("{path}: added"),
+        Difference::Changed {
+            path,
+            expected,
+            actual,
+        } => format!("{path}: {} -> {}", inline(expected), inline(actual)),
+    }
+}
+
+/// Record a successful reload from `old` to `new`, if the trail is enabled
+pub(crate) fn record(source: &str, generation: u64, old: &Value, new: &Value, actor: Option<&str>) {
+    let Some(path) = audit_path() else {
+        return;
+    };
+
+    let entry = AuditEntry {
+        timestamp: schedule::now_timestamp(),
+        source: source.to_string(),
+        generation,
+        changes: drift::diff(old, new, &[])
+            .differences
+            .iter()
+            .map(describe)
+            .collect(),
+        actor: actor.map(String::from),
+        fingerprint: fingerprint(new),
+    };
+
+    let line = match serde_yaml::to_value(&entry) {
+        Ok(entry) => json::to_string(&entry),
+        Err(e) => return warn!("Failed to encode audit entry: {e}"),
+    };
+
+    let _lock = AUDIT_LOCK.lock().unwrap();
+    let written = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .and_then(|mut file| writeln!(file, "{line}"));
+
+    if let Err(e) = written {
+        warn!("Failed to write audit entry to {}: {e}", path.display());
+    }
+}
@@ -0,0 +1,499 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{LazyLock, Mutex},
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, bail, Context, Result};
+use serde_yaml::{Mapping, Value};
+use tracing::debug;
+
+use crate::{env_overlay, health, http, json, schedule, UrlOptions};
+
+// Most names SSM `GetParameters` and Secrets Manager `BatchGetSecretValue` take at once
+const SSM_BATCH: usize = 10;
+const SECRETS_BATCH: usize = 20;
+
+// Values fetched for `${ssm:...}` and `${secretsmanager:...}` references, by reference,
+// and when they were
+static CACHE: LazyLock<Mutex<HashMap<Reference, (String, Instant)>>> =
+    LazyLock::new(Default::default);
+static CACHE_TTL: Mutex<Duration> = Mutex::new(Duration::from_secs(300));
+
+/// How long values fetched from SSM Parameter Store and Secrets Manager are reused, 5
+/// minutes by default
+///
+/// Loads and reloads within it resolve `${ssm:...}` and `${secretsmanager:...}` from
+/// memory, so a rotated value is picked up by the first reload after it. Zero fetches
+/// them on every load.
+pub fn set_aws_cache_ttl(ttl: Duration) {
+    *CACHE_TTL.lock().unwrap() = ttl;
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Reference {
+    // Parameter name, with an optional `:version` or `:label` selector
+    Ssm(String),
+    // Secret name or ARN
+    Secret(String),
+}
+
+/// Values of the `${ssm:<name>}` and `${secretsmanager:<id>}` references in the strings of
+/// `value`, keyed like written, e.g. `ssm:/app/prod/db_url`
+///
+/// Values not cached are fetched in as few requests as the APIs allow; any reference
+/// that can't be resolved fails the whole load. `${secretsmanager:<id>#<key>}` takes one
+/// key of a JSON secret.
+pub(crate) fn resolve(value: &Value) -> Result<HashMap<String, String>> {
+    let mut written = HashSet::new();
+    scan(value, &mut written);
+    if written.is_empty() {
+        return Ok(HashMap::new());
+    }
+
+    let references = written
+        .iter()
+        .map(|text| parse(text))
+        .collect::<HashSet<_>>();
+    let fetched = fetch_missing(references);
+    health::record_provider("aws", fetched.as_ref().err().map(|e| format!("{e:#}")));
+    let values = fetched?;
+
+    written
+        .into_iter()
+        .map(|text| {
+            let value = match parse(&text) {
+                Reference::Secret(id) => {
+                    let (_, key) = split_key(&text);
+                    let secret = values
+                        .get(&Reference::Secret(id.clone()))
+                        .cloned()
+                        .unwrap_or_default();
+
+                    match key {
+                        Some(key) => secret_key(&id, &secret, key)?,
+                        None => secret,
+                    }
+                }
+                reference => values.get(&reference).cloned().unwrap_or_default(),
+            };
+
+            Ok((text, value))
+        })
+        .collect()
+}
+
+// Every `ssm:...` and `secretsmanager:...` between `${` and `}`, escaped ones left out
+fn scan(value: &Value, references: &mut HashSet<String>) {
+    match value {
+        Value::String(text) => {
+            let mut rest = text.as_str();
+
+            while let Some(start) = rest.find("${") {
+                let escaped = rest[..start].ends_with('\\') && !rest[..start].ends_with("\\\\");
+                rest = &rest[start + 2..];
+                let Some(end) = rest.find('}') else {
+                    break;
+                };
+
+                let inner = &rest[..end];
+                if !escaped && (inner.starts_with("ssm:") || inner.starts_with("secretsmanager:")) {
+                    references.insert(inner.to_string());
+                }
+            }
+        }
+        Value::Mapping(mapping) => mapping.values().for_each(|v| scan(v, references)),
+        Value::Sequence(sequence) => sequence.iter().for_each(|v| scan(v, references)),
+        Value::Tagged(tagged) => scan(&tagged.value, references),
+        _ => {}
+    }
+}
+
+fn parse(text: &str) -> Reference {
+    match text.strip_prefix("ssm:") {
+        Some(name) => Reference::Ssm(name.to_string()),
+        None => Reference::Secret(split_key(text).0.to_string()),
+    }
+}
+
+// `secretsmanager:<id>#<key>` into the id and the key
+fn split_key(text: &str) -> (&str, Option<&str>) {
+    let id = text.strip_prefix("secretsmanager:").unwrap_or(text);
+
+    match id.split_once('#') {
+        Some((id, key)) => (id, Some(key)),
+        None => (id, None),
+    }
+}
+
+fn secret_key(id: &str, secret: &str, key: &str) -> Result<String> {
+    let secret: Value = serde_yaml::from_str(secret)
+        .map_err(|_| anyhow!("secret {id} is not JSON, can't take its `{key}`"))?;
+
+    match secret.get(key) {
+        Some(Value::String(value)) => Ok(value.clone()),
+        Some(Value::Number(value)) => Ok(value.to_string()),
+        Some(Value::Bool(value)) => Ok(value.to_string()),
+        _ => bail!("secret {id} has no `{key}`"),
+    }
+}
+
+// Values of the `references`, those out of the cache fetched and cached
+fn fetch_missing(references: HashSet<Reference>) -> Result<HashMap<Reference, String>> {
+    let ttl = *CACHE_TTL.lock().unwrap();
+    let mut values = HashMap::new();
+    let mut missing = vec![];
+
+    {
+        let cache = CACHE.lock().unwrap();
+        for reference in references {
+            match cache.get(&reference) {
+                Some((value, at)) if at.elapsed() < ttl => {
+                    values.insert(reference, value.clone());
+                }
+                _ => missing.push(reference),
+            }
+        }
+    }
+
+    let (mut parameters, mut secrets) = (vec![], vec![]);
+    for reference in missing {
+        match reference {
+            Reference::Ssm(name) => parameters.push(name),
+            Reference::Secret(id) => secrets.push(id),
+        }
+    }
+    // Same batches for the same references
+    parameters.sort();
+    secrets.sort();
+
+    let mut fetched = HashMap::new();
+    for batch in parameters.chunks(SSM_BATCH) {
+        fetched.extend(get_parameters(batch)?);
+    }
+    for batch in secrets.chunks(SECRETS_BATCH) {
+        fetched.extend(get_secrets(batch)?);
+    }
+
+    if let Some(id) = secrets
+        .iter()
+        .find(|id| !fetched.contains_key(&Reference::Secret(id.to_string())))
+    {
+        bail!("no secret {id} in Secrets Manager");
+    }
+
+    let now = Instant::now();
+    let mut cache = CACHE.lock().unwrap();
+    for (reference, value) in fetched {
+        cache.insert(reference.clone(), (value.clone(), now));
+        values.insert(reference, value);
+    }
+
+    Ok(values)
+}
+
+fn get_parameters(names: &[String]) -> Result<Vec<(Reference, String)>> {
+    let body = Value::Mapping(Mapping::from_iter([
+        (
+            Value::from("Names"),
+            Value::Sequence(
+                names
+                    .iter()
+                    .map(|name| Value::from(name.as_str()))
+                    .collect(),
+            ),
+        ),
+        (Value::from("WithDecryption"), Value::from(true)),
+    ]));
+    let response = call("ssm", "AmazonSSM.GetParameters", &json::to_string(&body))?;
+
+    let invalid = strings(response.get("InvalidParameters"));
+    if !invalid.is_empty() {
+        bail!("no SSM parameter {}", invalid.join(", "));
+    }
+
+    debug!("Read {} SSM parameters", names.len());
+
+    let parameters = response
+        .get("Parameters")
+        .and_then(Value::as_sequence)
+        .ok_or(anyhow!("malformed SSM response, no `Parameters`"))?;
+    parameters
+        .iter()
+        .map(|parameter| {
+            let field = |name| parameter.get(name).and_then(Value::as_str);
+            let name = field("Name").ok_or(anyhow!("malformed SSM response, no `Name`"))?;
+            // Answered under the bare name, the selector apart
+            let name = match field("Selector") {
+                Some(selector) => format!("{name}{selector}"),
+                None => name.to_string(),
+            };
+
+            Ok((
+                Reference::Ssm(name),
+                field("Value").unwrap_or_default().to_string(),
+            ))
+        })
+        .collect()
+}
+
+fn get_secrets(ids: &[String]) -> Result<Vec<(Reference, String)>> {
+    let body = Value::Mapping(Mapping::from_iter([(
+        Value::from("SecretIdList"),
+        Value::Sequence(ids.iter().map(|id| Value::from(id.as_str())).collect()),
+    )]));
+    let response = call(
+        "secretsmanager",
+        "secretsmanager.BatchGetSecretValue",
+        &json::to_string(&body),
+    )?;
+
+    if let Some(Value::Sequence(errors)) = response.get("Errors") {
+        if let Some(error) = errors.first() {
+            let field = |name| error.get(name).and_then(Value::as_str).unwrap_or_default();
+            bail!(
+                "can't read secret {}: {} {}",
+                field("SecretId"),
+                field("ErrorCode"),
+                field("Message")
+            );
+        }
+    }
+
+    debug!("Read {} Secrets Manager secrets", ids.len());
+
+    let secrets = response
+        .get("SecretValues")
+        .and_then(Value::as_sequence)
+        .ok_or(anyhow!(
+            "malformed Secrets Manager response, no `SecretValues`"
+        ))?;
+
+    // Answered by name and ARN, whichever the id was
+    Ok(ids
+        .iter()
+        .filter_map(|id| {
+            let secret = secrets.iter().find(|secret| {
+                [secret.get("Name"), secret.get("ARN")]
+                    .into_iter()
+                    .any(|field| field.and_then(Value::as_str) == Some(id))
+            })?;
+            let value = secret
+                .get("SecretString")
+                .and_then(Value::as_str)
+                .unwrap_or_default();
+
+            Some((Reference::Secret(id.clone()), value.to_string()))
+        })
+        .collect())
+}
+
+fn strings(value: Option<&Value>) -> Vec<String> {
+    value
+        .and_then(Value::as_sequence)
+        .map(|items| {
+            items
+                .iter()
+                .filter_map(|item| Some(item.as_str()?.to_string()))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+// One signed call to the JSON API of `service`
+fn call(service: &str, target: &str, body: &str) -> Result<Value> {
+    let var = |name| {
+        env_overlay::var(name)
+            .ok()
+            .filter(|value: &String| !value.is_empty())
+    };
+    let region = var("AWS_REGION")
+        .or_else(|| var("AWS_DEFAULT_REGION"))
+        .ok_or(anyhow!("no AWS region, set AWS_REGION"))?;
+    let (Some(key_id), Some(secret_key)) = (var("AWS_ACCESS_KEY_ID"), var("AWS_SECRET_ACCESS_KEY"))
+    else {
+        bail!("no AWS credentials, set AWS_ACCESS_KEY_ID and AWS_SECRET_ACCESS_KEY");
+    };
+    let session_token = var("AWS_SESSION_TOKEN");
+
+    // The endpoint of the service, then the one for all of them, as the AWS SDKs take them
+    let endpoint = match service {
+        "ssm" => var("AWS_ENDPOINT_URL_SSM"),
+        _ => var("AWS_ENDPOINT_URL_SECRETS_MANAGER"),
+    }
+    .or_else(|| var("AWS_ENDPOINT_URL"))
+    .unwrap_or_else(|| format!("https://{service}.{region}.amazonaws.com"));
+    let url = format!("{}/", endpoint.trim_end_matches('/'));
+    // Signed as the client sends it, without the port
+    let host = endpoint
+        .split_once("://")
+        .map_or(endpoint.as_str(), |(_, rest)| rest)
+        .split(['/', '?'])
+        .next()
+        .unwrap_or_default();
+    let host = match host.rsplit_once(':') {
+        Some((host, port)) if port.chars().all(|c| c.is_ascii_digit()) => host,
+        _ => host,
+    };
+
+    let amz_date = schedule::now_timestamp().replace(['-', ':'], "");
+    let date = &amz_date[..8];
+    let content_type = "application/x-amz-json-1.1";
+
+    let mut headers = vec![
+        ("content-type", content_type),
+        ("host", host),
+        ("x-amz-date", amz_date.as_str()),
+    ];
+    if let Some(token) = &session_token {
+        headers.push(("x-amz-security-token", token));
+    }
+    headers.push(("x-amz-target", target));
+
+    let signed = headers
+        .iter()
+        .map(|(name, _)| *name)
+        .collect::<Vec<_>>()
+        .join(";");
+    let canonical = format!(
+        "POST\n/\n\n{}\n{signed}\n{}",
+        headers
+            .iter()
+            .map(|(name, value)| format!("{name}:{}\n", value.trim()))
+            .collect::<String>(),
+        hex(&sha256(body.as_bytes()))
+    );
+    let scope = format!("{date}/{region}/{service}/aws4_request");
+    let to_sign = format!(
+        "AWS4-HMAC-SHA256\n{amz_date}\n{scope}\n{}",
+        hex(&sha256(canonical.as_bytes()))
+    );
+    let key = [date, &region, service, "aws4_request"]
+        .iter()
+        .fold(format!("AWS4{secret_key}").into_bytes(), |key, part| {
+            hmac(&key, part.as_bytes()).to_vec()
+        });
+    let authorization = format!(
+        "AWS4-HMAC-SHA256 Credential={key_id}/{scope}, SignedHeaders={signed}, Signature={}",
+        hex(&hmac(&key, to_sign.as_bytes()))
+    );
+
+    // `host` is sent by the client itself
+    let mut sent = headers
+        .into_iter()
+        .filter(|(name, _)| *name != "host")
+        .collect::<Vec<_>>();
+    sent.push(("authorization", &authorization));
+
+    let response = http::request("POST", &url, &sent, body, &UrlOptions::default())?;
+    let status = response.status;
+    let text = response.text()?;
+    let response: Value =
+        serde_yaml::from_str(&text).context(format!("malformed {service} response"))?;
+
+    if status != 200 {
+        let field = |name| response.get(name).and_then(Value::as_str);
+        bail!(
+            "{service} answered {status}: {}",
+            field("message")
+                .or_else(|| field("Message"))
+                .or_else(|| field("__type"))
+                .unwrap_or(text.trim())
+        );
+    }
+
+    Ok(response)
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+fn hmac(key: &[u8], message: &[u8]) -> [u8; 32] {
+    let mut block = [0u8; 64];
+    if key.len() > 64 {
+        block[..32].copy_from_slice(&sha256(key));
+    } else {
+        block[..key.len()].copy_from_slice(key);
+    }
+
+    let pad = |byte: u8| block.iter().map(|b| b ^ byte).collect::<Vec<_>>();
+    let inner = sha256(&[pad(0x36).as_slice(), message].concat());
+
+    sha256(&[pad(0x5c).as_slice(), &inner].concat())
+}
+
+const K: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+// SHA-256, for the request signatures only
+fn sha256(data: &[u8]) -> [u8; 32] {
+    let mut state: [u32; 8] = [
+        0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab,
+        0x5be0cd19,
+    ];
+
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&(data.len() as u64 * 8).to_be_bytes());
+
+    for block in message.chunks(64) {
+        let mut w = [0u32; 64];
+        for (i, word) in block.chunks(4).enumerate() {
+            w[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+        for i in 16..64 {
+            let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+            let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+            w[i] = w[i - 16]
+                .wrapping_add(s0)
+                .wrapping_add(w[i - 7])
+                .wrapping_add(s1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = state;
+        for i in 0..64 {
+            let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+            let ch = (e & f) ^ (!e & g);
+            let t1 = h
+                .wrapping_add(s1)
+                .wrapping_add(ch)
+                .wrapping_add(K[i])
+                .wrapping_add(w[i]);
+            let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+            let maj = (a & b) ^ (a & c) ^ (b & c);
+            let t2 = s0.wrapping_add(maj);
+
+            h = g;
+            g = f;
+            f = e;
+            e = d.wrapping_add(t1);
+            d = c;
+            c = b;
+            b = a;
+            a = t1.wrapping_add(t2);
+        }
+
+        for (word, added) in state.iter_mut().zip([a, b, c, d, e, f, g, h]) {
+            *word = word.wrapping_add(added);
+        }
+    }
+
+    let mut digest = [0u8; 32];
+    for (bytes, word) in digest.chunks_mut(4).zip(state) {
+        bytes.copy_from_slice(&word.to_be_bytes());
+    }
+
+    digest
+}
@@ -0,0 +1,416 @@
+use std::{
+    collections::HashMap,
+    env, fs,
+    hash::{DefaultHasher, Hash, Hasher},
+    path::{Path, PathBuf},
+    sync::{Arc, LazyLock, Mutex},
+};
+
+use serde_yaml::{
+    value::{Tag, TaggedValue},
+    Mapping, Number, Value,
+};
+use tracing::trace;
+
+use crate::{format::Format, UnconfigError};
+
+pub(crate) type SourceKey = (u64, usize);
+
+/// Parsed sources keyed by the hash and length of their text
+static PARSED: LazyLock<Mutex<Trees>> = LazyLock::new(Default::default);
+
+// Distinct sources kept at once, a file edited while watched would add one per version
+const CAPACITY: usize = 64;
+
+/// Trees by the source they come from, the least recently used dropped past `CAPACITY`
+#[derive(Default)]
+pub(crate) struct Trees {
+    entries: HashMap<SourceKey, (Arc<Value>, u64)>,
+    clock: u64,
+}
+
+impl Trees {
+    pub(crate) fn get(&mut self, key: &SourceKey) -> Option<Arc<Value>> {
+        self.clock += 1;
+        let (value, used) = self.entries.get_mut(key)?;
+        *used = self.clock;
+
+        Some(value.clone())
+    }
+
+    pub(crate) fn insert(&mut self, key: SourceKey, value: Arc<Value>) {
+        if self.entries.len() >= CAPACITY && !self.entries.contains_key(&key) {
+            let oldest = self.entries.iter().min_by_key(|(_, (_, used))| *used);
+
+            if let Some(&oldest) = oldest.map(|(key, _)| key) {
+                self.entries.remove(&oldest);
+            }
+        }
+
+        self.clock += 1;
+        self.entries.insert(key, (value, self.clock));
+    }
+}
+
+const MAGIC: &[u8; 8] = b"UNCFG\0\0\x01";
+
+// Nesting of cached trees, as deep as the YAML parser goes
+const MAX_DEPTH: usize = 128;
+
+pub(crate) fn source_key(content: &str, format: Format) -> SourceKey {
+    let mut hasher = DefaultHasher::new();
+    content.hash(&mut hasher);
+    format.hash(&mut hasher);
+
+    (hasher.finish(), content.len())
+}
+
+/// Parse `content` once per distinct source text and share the resulting tree
+///
+/// Every `#[configurable]` struct reads the same files, so without this the whole
+/// document would be parsed again for each of them. `source` names it in errors.
+pub(crate) fn parse(
+    source: &str,
+    content: &str,
+    format: Format,
+) -> Result<Arc<Value>, UnconfigError> {
+    let key = source_key(content, format);
+
+    if let Some(value) = PARSED.lock().unwrap().get(&key) {
+        return Ok(value);
+    }
+
+    let value: Arc<Value> = Arc::new(format.parse(source, content)?);
+    PARSED.lock().unwrap().insert(key, value.clone());
+
+    Ok(value)
+}
+
+/// Same as [`parse`], but with `CACHE_CONFIG=1` the parsed tree is also persisted
+/// next to `path` and reused by later processes while the source is unchanged, the format
+/// is told by the extension of `path` or the magic bytes of `content`
+///
+/// Only parsing is skipped: variables are still expanded on every load, since the
+/// environment may differ between runs.
+pub(crate) fn parse_file(path: &Path, content: &str) -> Result<Arc<Value>, UnconfigError> {
+    let format = Format::of_file(path, content);
+    let source = path.display().to_string();
+
+    if !matches!(env::var("CACHE_CONFIG").as_deref(), Ok("1")) {
+        return parse(&source, content, format);
+    }
+
+    let key = source_key(content, format);
+
+    if let Some(value) = PARSED.lock().unwrap().get(&key) {
+        return Ok(value);
+    }
+
+    let cache_path = binary_cache_path(path);
+    let value = match read_binary_cache(&cache_path, key) {
+        Some(value) => {
+            trace!("Using binary config cache {}", cache_path.display());
+            Arc::new(value)
+        }
+        None => {
+            let value: Arc<Value> = Arc::new(format.parse(&source, content)?);
+
+            if let Err(e) = write_binary_cache(&cache_path, key, &value) {
+                trace!(
+                    "Failed to write binary config cache {}: {e}",
+                    cache_path.display()
+                );
+            }
+
+            value
+        }
+    };
+    PARSED.lock().unwrap().insert(key, value.clone());
+
+    Ok(value)
+}
+
+// `dir/config.yml` is cached as `dir/.config.yml.cache`
+fn binary_cache_path(path: &Path) -> PathBuf {
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+
+    path.with_file_name(format!(".{file_name}.cache"))
+}
+
+fn read_binary_cache(cache_path: &Path, key: SourceKey) -> Option<Value> {
+    let bytes = fs::read(cache_path).ok()?;
+    let mut reader = Reader(bytes.strip_prefix(MAGIC)?);
+
+    if reader.u64()? != key.0 || reader.u64()? != key.1 as u64 {
+        return None;
+    }
+
+    let value = reader.value(0)?;
+
+    reader.0.is_empty().then_some(value)
+}
+
+fn write_binary_cache(cache_path: &Path, key: SourceKey, value: &Value) -> std::io::Result<()> {
+    let mut bytes = MAGIC.to_vec();
+    bytes.extend(key.0.to_le_bytes());
+    bytes.extend((key.1 as u64).to_le_bytes());
+    encode(value, &mut bytes);
+
+    // Write through a temporary file, so a concurrent reader never sees half a cache
+    let tmp_path = cache_path.with_extension(format!("tmp{}", std::process::id()));
+    fs::write(&tmp_path, bytes)?;
+    fs::rename(&tmp_path, cache_path)
+}
+
+fn encode(value: &Value, out: &mut Vec<u8>) {
+    fn encode_str(text: &str, out: &mut Vec<u8>) {
+        out.extend((text.len() as u64).to_le_bytes());
+        out.extend(text.as_bytes());
+    }
+
+    match value {
+        Value::Null => out.push(0),
+        Value::Bool(v) => out.extend([1, *v as u8]),
+        Value::Number(n) => {
+            if let Some(v) = n.as_u64() {
+                out.push(2);
+                out.extend(v.to_le_bytes());
+            } else if let Some(v) = n.as_i64() {
+                out.push(3);
+                out.extend(v.to_le_bytes());
+            } else {
+                out.push(4);
+                out.extend(n.as_f64().unwrap_or_default().to_le_bytes());
+            }
+        }
+        Value::String(text) => {
+            out.push(5);
+            encode_str(text, out);
+        }
+        Value::Sequence(seq) => {
+            out.push(6);
+            out.extend((seq.len() as u64).to_le_bytes());
+            seq.iter().for_each(|v| encode(v, out));
+        }
+        Value::Mapping(mapping) => {
+            out.push(7);
+            out.extend((mapping.len() as u64).to_le_bytes());
+            mapping.iter().for_each(|(k, v)| {
+                encode(k, out);
+                encode(v, out);
+            });
+        }
+        Value::Tagged(tagged) => {
+            out.push(8);
+            encode_str(&tagged.tag.to_string(), out);
+            encode(&tagged.value, out);
+        }
+    }
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl Reader<'_> {
+    fn take(&mut self, len: usize) -> Option<&[u8]> {
+        if self.0.len() < len {
+            return None;
+        }
+
+        let (head, tail) = self.0.split_at(len);
+        self.0 = tail;
+
+        Some(head)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        self.take(1).map(|b| b[0])
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        self.take(8)?.try_into().ok().map(u64::from_le_bytes)
+    }
+
+    fn len(&mut self) -> Option<usize> {
+        let len = usize::try_from(self.u64()?).ok()?;
+
+        // Every element takes at least one byte, anything longer is a corrupted cache
+        (len <= self.0.len()).then_some(len)
+    }
+
+    fn string(&mut self) -> Option<String> {
+        let len = self.len()?;
+
+        String::from_utf8(self.take(len)?.to_vec()).ok()
+    }
+
+    // A corrupted cache reads as `None`, never panics
+    fn value(&mut self, depth: usize) -> Option<Value> {
+        if depth > MAX_DEPTH {
+            return None;
+        }
+
+        let value = match self.u8()? {
+            0 => Value::Null,
+            1 => Value::Bool(self.u8()? != 0),
+            2 => Value::Number(self.u64()?.into()),
+            3 => Value::Number((self.u64()? as i64).into()),
+            4 => Value::Number(Number::from(f64::from_bits(self.u64()?))),
+            5 => Value::String(self.string()?),
+            6 => {
+                let len = self.len()?;
+                Value::Sequence(
+                    (0..len)
+                        .map(|_| self.value(depth + 1))
+                        .collect::<Option<_>>()?,
+                )
+            }
+            7 => {
+                let len = self.len()?;
+                let mut mapping = Mapping::with_capacity(len);
+
+                for _ in 0..len {
+                    mapping.insert(self.value(depth + 1)?, self.value(depth + 1)?);
+                }
+
+                Value::Mapping(mapping)
+            }
+            8 => {
+                // `Tag::new` panics on an empty tag
+                let tag = self
+                    .string()
+                    .filter(|tag| !tag.trim_start_matches('!').is_empty())?;
+                let tag = Tag::new(tag);
+                let value = self.value(depth + 1)?;
+
+                Value::Tagged(Box::new(TaggedValue { tag, value }))
+            }
+            _ => return None,
+        };
+
+        Some(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: SourceKey = (42, 7);
+
+    fn encoded(value: &Value) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend(KEY.0.to_le_bytes());
+        bytes.extend((KEY.1 as u64).to_le_bytes());
+        encode(value, &mut bytes);
+
+        bytes
+    }
+
+    fn decoded(bytes: &[u8]) -> Option<Value> {
+        let mut reader = Reader(bytes.strip_prefix(MAGIC)?);
+
+        if reader.u64()? != KEY.0 || reader.u64()? != KEY.1 as u64 {
+            return None;
+        }
+        let value = reader.value(0)?;
+
+        reader.0.is_empty().then_some(value)
+    }
+
+    fn sample() -> Value {
+        serde_yaml::from_str(
+            r#"
+null: ~
+bool: true
+unsigned: 18446744073709551615
+negative: -3
+float: 1.5
+string: "text ✓"
+empty: ""
+sequence: [1, [2, {a: b}], []]
+mapping: {1: one, ~: null, nested: {deep: [x]}}
+tagged: !eval "base * 2"
+"#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn round_trip() {
+        let value = sample();
+
+        assert_eq!(decoded(&encoded(&value)), Some(value));
+    }
+
+    #[test]
+    fn round_trip_file() {
+        let path = std::env::temp_dir().join(format!("unconfig-cache-{}", std::process::id()));
+        let value = sample();
+
+        write_binary_cache(&path, KEY, &value).unwrap();
+        assert_eq!(read_binary_cache(&path, KEY), Some(value));
+        assert_eq!(read_binary_cache(&path, (KEY.0, KEY.1 + 1)), None);
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn truncated() {
+        let bytes = encoded(&sample());
+
+        for len in 0..bytes.len() {
+            assert_eq!(decoded(&bytes[..len]), None, "{len} bytes");
+        }
+
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        assert_eq!(decoded(&trailing), None);
+    }
+
+    #[test]
+    fn corrupted() {
+        // Every byte flipped in turn, none may panic
+        let bytes = encoded(&sample());
+        for i in MAGIC.len()..bytes.len() {
+            let mut corrupted = bytes.clone();
+            corrupted[i] ^= 0xff;
+            let _ = decoded(&corrupted);
+        }
+
+        let mut empty_tag = encoded(&Value::Null);
+        empty_tag.pop();
+        empty_tag.push(8);
+        empty_tag.extend(0u64.to_le_bytes());
+        empty_tag.push(0);
+        assert_eq!(decoded(&empty_tag), None);
+
+        let mut bang_tag = encoded(&Value::Null);
+        bang_tag.pop();
+        bang_tag.push(8);
+        bang_tag.extend(1u64.to_le_bytes());
+        bang_tag.extend([b'!', 0]);
+        assert_eq!(decoded(&bang_tag), None);
+
+        let mut unknown = encoded(&Value::Null);
+        *unknown.last_mut().unwrap() = 9;
+        assert_eq!(decoded(&unknown), None);
+    }
+
+    #[test]
+    fn nested_too_deep() {
+        let mut nested = encoded(&Value::Null);
+        nested.pop();
+        for _ in 0..100_000 {
+            nested.push(6);
+            nested.extend(1u64.to_le_bytes());
+        }
+        nested.push(0);
+
+        assert_eq!(decoded(&nested), None);
+
+        let mut value = Value::Null;
+        for _ in 0..MAX_DEPTH {
+            value = Value::Sequence(vec![value]);
+        }
+        assert_eq!(decoded(&encoded(&value)), Some(value));
+    }
+}
@@ -0,0 +1,109 @@
+//! Simulated load failures, for testing how an application degrades without touching
+//! the filesystem or the network
+//!
+//! Only built with the `chaos` feature, meant for `[dev-dependencies]`:
+//!
+//! ```no_run
+//! use unconfig::{chaos::{self, Fault}, Config};
+//! # #[derive(serde::Deserialize)] struct Settings {}
+//!
+//! let _fault = chaos::inject("*/config.yml", Fault::PermissionDenied);
+//! assert!(Settings::load_path("config.yml").is_err());
+//! // Loads normally again once `_fault` is dropped
+//! ```
+//!
+//! Faults apply to config files and URLs matching their pattern, from any thread, so
+//! tests running in parallel should fault distinct files.
+
+use std::{
+    io,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        LazyLock, Mutex,
+    },
+    thread,
+    time::Duration,
+};
+
+use crate::overlay::glob_match;
+
+static FAULTS: LazyLock<Mutex<Vec<(u64, String, Fault)>>> = LazyLock::new(Default::default);
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+// Text no format parses
+const MALFORMED: &str = "chaos: [";
+
+/// What reading a faulted source does
+#[derive(Debug, Clone, PartialEq)]
+pub enum Fault {
+    /// Fails as if the file didn't exist
+    Missing,
+    /// Fails as if the file couldn't be read
+    PermissionDenied,
+    /// Returns text that doesn't parse
+    Malformed,
+    /// Reads normally after the delay, e.g. a slow remote provider
+    Slow(Duration),
+}
+
+/// Removes its fault when dropped
+#[must_use = "the fault is removed when this is dropped"]
+pub struct Injected {
+    id: u64,
+}
+
+impl Drop for Injected {
+    fn drop(&mut self) {
+        FAULTS.lock().unwrap().retain(|(id, _, _)| *id != self.id);
+    }
+}
+
+/// Fault every config source matching `pattern` until the returned guard is dropped
+///
+/// The pattern is matched against the full path of files and the URL of remote
+/// configs, `*` matches any run of characters and `?` exactly one. The latest fault
+/// injected for a source wins.
+pub fn inject(pattern: &str, fault: Fault) -> Injected {
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    FAULTS
+        .lock()
+        .unwrap()
+        .push((id, pattern.to_string(), fault));
+
+    Injected { id }
+}
+
+/// Remove every fault, also those whose guards are still alive
+pub fn clear() {
+    FAULTS.lock().unwrap().clear();
+}
+
+// The fault of `source`, `Some` text to read instead of the real content
+pub(crate) fn apply(source: &str) -> io::Result<Option<String>> {
+    let fault = FAULTS
+        .lock()
+        .unwrap()
+        .iter()
+        .rev()
+        .find(|(_, pattern, _)| glob_match(pattern, source))
+        .map(|(_, _, fault)| fault.clone());
+
+    match fault {
+        Some(Fault::Missing) => Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            "no such file (injected)",
+        )),
+        Some(Fault::PermissionDenied) => Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "permission denied (injected)",
+        )),
+        Some(Fault::Malformed) => Ok(Some(MALFORMED.to_string())),
+        Some(Fault::Slow(delay)) => {
+            // Not under the lock, other sources load meanwhile
+            thread::sleep(delay);
+
+            Ok(None)
+        }
+        None => Ok(None),
+    }
+}
@@ -0,0 +1,250 @@
+use std::fmt;
+
+use indexmap::IndexMap;
+use serde::{Deserialize, Serialize};
+use tracing::{Event, Subscriber};
+use tracing_subscriber::{
+    field::RecordFields,
+    fmt::{
+        format::{FmtSpan, Writer},
+        time, FmtContext, FormatEvent, FormatFields, MakeWriter,
+    },
+    registry::LookupSpan,
+    Layer,
+};
+
+use crate::{
+    sink::{JsonFormat, SinkFormat},
+    trace_id::TraceIdFormat,
+    LoggerParams,
+};
+
+/// Console output options, under `console` in the logger params
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct ConsoleParams {
+    /// Cut the fields of an event (the message included) after this many characters
+    pub max_message_length: Option<usize>,
+    /// Write embedded newlines as `\n`, so every event stays on one line
+    #[serde(default)]
+    pub fold_newlines: bool,
+    /// Multi-line human readable events instead of one line per event
+    #[serde(default)]
+    pub pretty: bool,
+    /// Styles for the events of some modules, e.g. `payments::fraud: bold red`
+    ///
+    /// A rule applies to the module and its submodules, the longest matching one wins.
+    #[serde(default)]
+    pub highlight: IndexMap<String, Style>,
+}
+
+/// Space separated ANSI attributes and colors, e.g. `bold red` or `underline on_yellow`
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(try_from = "String", into = "String")]
+pub struct Style {
+    text: String,
+    codes: String,
+}
+
+impl TryFrom<String> for Style {
+    type Error = String;
+
+    fn try_from(text: String) -> Result<Self, Self::Error> {
+        const COLORS: [&str; 8] = [
+            "black", "red", "green", "yellow", "blue", "magenta", "cyan", "white",
+        ];
+
+        let codes = text
+            .split_whitespace()
+            .map(|word| {
+                let code = match word {
+                    "bold" => 1,
+                    "dim" => 2,
+                    "italic" => 3,
+                    "underline" => 4,
+                    "blink" => 5,
+                    "reverse" => 7,
+                    _ => match word.strip_prefix("on_") {
+                        Some(color) => COLORS.iter().position(|c| *c == color).map(|i| 40 + i),
+                        None => COLORS.iter().position(|c| *c == word).map(|i| 30 + i),
+                    }
+                    .ok_or_else(|| format!("unknown style `{word}`"))?,
+                };
+
+                Ok(code.to_string())
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+
+        Ok(Self {
+            codes: format!("\x1b[{}m", codes.join(";")),
+            text,
+        })
+    }
+}
+
+impl From<Style> for String {
+    fn from(style: Style) -> Self {
+        style.text
+    }
+}
+
+/// Format of the console when no `sinks` are configured
+pub(crate) fn default_format(params: &LoggerParams) -> SinkFormat {
+    match &params.console {
+        Some(console) if console.pretty => SinkFormat::Pretty,
+        _ => SinkFormat::Text,
+    }
+}
+
+/// Console sink with the `console` options applied
+pub(crate) fn layer<S, W>(
+    params: &LoggerParams,
+    make_writer: W,
+    line_numbers: bool,
+    format: SinkFormat,
+) -> Box<dyn Layer<S> + Send + Sync>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    W: for<'w> MakeWriter<'w> + Send + Sync + 'static,
+{
+    let console = params.console.clone().unwrap_or_default();
+    let span_events = if params.span_timings {
+        FmtSpan::CLOSE | FmtSpan::ENTER
+    } else {
+        FmtSpan::NONE
+    };
+
+    let layer = tracing_subscriber::fmt::layer()
+        .with_thread_names(true)
+        .with_span_events(span_events)
+        .with_line_number(line_numbers)
+        .with_timer(time::time())
+        .with_writer(make_writer);
+
+    match format {
+        SinkFormat::Text => layer
+            .map_event_format(|inner| Highlight::new(TraceIdFormat(inner), &console))
+            .map_fmt_fields(|inner| ConsoleFields::new(inner, &console))
+            .boxed(),
+        SinkFormat::Pretty => layer
+            .pretty()
+            .map_event_format(|inner| Highlight::new(TraceIdFormat(inner), &console))
+            .map_fmt_fields(|inner| ConsoleFields::new(inner, &console))
+            .boxed(),
+        SinkFormat::Json => layer.with_ansi(false).event_format(JsonFormat).boxed(),
+    }
+}
+
+// Renders the events of highlighted modules in their style
+struct Highlight<F> {
+    inner: F,
+    rules: Vec<(String, Style)>,
+}
+
+impl<F> Highlight<F> {
+    fn new(inner: F, console: &ConsoleParams) -> Self {
+        let mut rules = console
+            .highlight
+            .iter()
+            .map(|(target, style)| (target.clone(), style.clone()))
+            .collect::<Vec<_>>();
+        rules.sort_by_key(|(target, _)| std::cmp::Reverse(target.len()));
+
+        Self { inner, rules }
+    }
+
+    fn style(&self, target: &str) -> Option<&Style> {
+        self.rules
+            .iter()
+            .find(|(module, _)| {
+                target
+                    .strip_prefix(module.as_str())
+                    .is_some_and(|rest| rest.is_empty() || rest.starts_with("::"))
+            })
+            .map(|(_, style)| style)
+    }
+}
+
+impl<S, N, F> FormatEvent<S, N> for Highlight<F>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+    F: FormatEvent<S, N>,
+{
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, N>,
+        mut writer: Writer<'_>,
+        event: &Event<'_>,
+    ) -> fmt::Result {
+        let style = match self.style(event.metadata().target()) {
+            Some(style) if writer.has_ansi_escapes() => style,
+            _ => return self.inner.format_event(ctx, writer, event),
+        };
+
+        // Rendered without the default colors, the whole event takes the style
+        let mut buf = String::new();
+        self.inner.format_event(ctx, Writer::new(&mut buf), event)?;
+
+        let line = buf.trim_end_matches('\n');
+        let newlines = &buf[line.len()..];
+
+        write!(writer, "{}{line}\x1b[0m{newlines}", style.codes)
+    }
+}
+
+// Truncates and folds the formatted fields of events and spans
+struct ConsoleFields<N> {
+    inner: N,
+    max_length: Option<usize>,
+    fold_newlines: bool,
+}
+
+impl<N> ConsoleFields<N> {
+    fn new(inner: N, console: &ConsoleParams) -> Self {
+        Self {
+            inner,
+            max_length: console.max_message_length,
+            fold_newlines: console.fold_newlines,
+        }
+    }
+}
+
+impl<'writer, N> FormatFields<'writer> for ConsoleFields<N>
+where
+    N: for<'a> FormatFields<'a>,
+{
+    fn format_fields<R: RecordFields>(
+        &self,
+        mut writer: Writer<'writer>,
+        fields: R,
+    ) -> fmt::Result {
+        if self.max_length.is_none() && !self.fold_newlines {
+            return self.inner.format_fields(writer, fields);
+        }
+
+        let mut buf = String::new();
+        self.inner.format_fields(Writer::new(&mut buf), fields)?;
+
+        if self.fold_newlines {
+            buf = buf.replace('\n', "\\n");
+        }
+
+        match self.max_length {
+            Some(max_length) if buf.chars().count() > max_length => {
+                let cut = buf
+                    .char_indices()
+                    .nth(max_length)
+                    .map_or(buf.len(), |(index, _)| index);
+
+                write!(
+                    writer,
+                    "{}… ({} more characters)",
+                    &buf[..cut],
+                    buf[cut..].chars().count()
+                )
+            }
+            _ => writer.write_str(&buf),
+        }
+    }
+}
@@ -0,0 +1,98 @@
+use std::{io, path::Path, sync::Arc, time::Duration};
+
+use anyhow::{bail, Result};
+use serde_yaml::Value;
+use tracing::debug_span;
+
+use crate::{cache, env_overlay, format::Format, health, http, limits, UnconfigError, UrlOptions};
+
+/// Address of the Consul agent, `http://127.0.0.1:8500` when unset
+pub const CONSUL_ADDR_VAR: &str = "CONSUL_HTTP_ADDR";
+/// ACL token sent with every request, if set
+pub const CONSUL_TOKEN_VAR: &str = "CONSUL_HTTP_TOKEN";
+
+const DEFAULT_ADDR: &str = "http://127.0.0.1:8500";
+// Longest a watch query is held open by Consul
+const WAIT: Duration = Duration::from_secs(30);
+
+/// `consul:app/config.yml`, how the value of `key` is named in errors, events and health
+pub(crate) fn source(key: &str) -> String {
+    format!("consul:{key}")
+}
+
+/// Parsed value of `key`, in the format told by its extension like a file, and the
+/// index it was read at
+pub(crate) fn read(key: &str) -> Result<(Arc<Value>, u64), UnconfigError> {
+    let source = source(key);
+    let fetched = debug_span!("config_read", source).in_scope(|| get(key, None));
+    health::record_provider("consul", fetched.as_ref().err().map(|e| format!("{e:#}")));
+
+    let (content, index) =
+        fetched.map_err(|e| UnconfigError::io(&source, io::Error::other(format!("{e:#}"))))?;
+    let content = content.ok_or_else(|| {
+        UnconfigError::io(
+            &source,
+            io::Error::new(io::ErrorKind::NotFound, "no such key"),
+        )
+    })?;
+    limits::limits()
+        .check_file_size(&source, content.len() as u64)
+        .map_err(UnconfigError::validation)?;
+
+    let format = Format::of_path(Path::new(key));
+    let params =
+        debug_span!("config_parse", source).in_scope(|| cache::parse(&source, &content, format))?;
+
+    Ok((params, index))
+}
+
+/// Index of `key` once it changes past `index`, or the same one when Consul ends the
+/// wait first
+pub(crate) fn wait(key: &str, index: u64) -> Result<u64> {
+    get(key, Some(index)).map(|(_, index)| index)
+}
+
+// Value of `key`, `None` when it's missing, and the `X-Consul-Index` of the answer. With
+// an `index`, a blocking query that Consul answers once the key changes past it
+fn get(key: &str, index: Option<u64>) -> Result<(Option<String>, u64)> {
+    let addr = env_overlay::var(CONSUL_ADDR_VAR).unwrap_or_else(|_| DEFAULT_ADDR.to_string());
+    // A bare `host:port`, as the Consul CLI takes it
+    let addr = if addr.contains("://") {
+        addr
+    } else {
+        format!("http://{addr}")
+    };
+    let mut url = format!(
+        "{}/v1/kv/{}?raw",
+        addr.trim_end_matches('/'),
+        key.trim_start_matches('/')
+    );
+    let mut options = UrlOptions::default();
+
+    if let Some(index) = index {
+        url += &format!("&index={index}&wait={}s", WAIT.as_secs());
+        // Consul adds up to 1/16 of the wait as jitter
+        options.timeout = WAIT * 2;
+    }
+
+    let token = env_overlay::var(CONSUL_TOKEN_VAR).unwrap_or_default();
+    let mut headers = vec![];
+    if !token.is_empty() {
+        headers.push(("X-Consul-Token", token.as_str()));
+    }
+
+    let response = http::request("GET", &url, &headers, "", &options)?;
+    let index = response
+        .header("X-Consul-Index")
+        .and_then(|index| index.parse().ok())
+        .unwrap_or_default();
+
+    match response.status {
+        200 => Ok((Some(response.text()?), index)),
+        404 => Ok((None, index)),
+        status => bail!(
+            "Consul answered {status}: {}",
+            response.text().unwrap_or_default().trim()
+        ),
+    }
+}
@@ -0,0 +1,58 @@
+use std::any::{type_name, Any};
+
+use indexmap::IndexMap;
+use thiserror::Error;
+
+/// Stored fields of a `#[configurable]` struct, taken by name by the `TryFrom` that
+/// `from = Old` generates on the new struct
+///
+/// Fields only the old struct has are dropped, fields only the new one has stay unset.
+#[doc(hidden)]
+pub struct Fields {
+    from: &'static str,
+    values: IndexMap<&'static str, Box<dyn Any>>,
+}
+
+impl Fields {
+    pub fn new(from: &'static str) -> Self {
+        Self {
+            from,
+            values: IndexMap::new(),
+        }
+    }
+
+    pub fn insert<T: Any>(&mut self, name: &'static str, value: Option<T>) {
+        self.values.insert(name, Box::new(value));
+    }
+
+    pub fn take<T: Any>(
+        &mut self,
+        to: &'static str,
+        name: &'static str,
+    ) -> Result<Option<T>, ConvertError> {
+        let Some(value) = self.values.shift_remove(name) else {
+            return Ok(None);
+        };
+
+        value
+            .downcast::<Option<T>>()
+            .map(|value| *value)
+            .map_err(|_| ConvertError {
+                from: self.from,
+                to,
+                field: name,
+                expected: type_name::<T>(),
+            })
+    }
+}
+
+/// Field shared by two `#[configurable]` structs with different types, merge strategy
+/// included
+#[derive(Error, Debug, Clone, PartialEq)]
+#[error("cannot convert {from} into {to}: field `{field}` is not of type `{expected}`")]
+pub struct ConvertError {
+    pub from: &'static str,
+    pub to: &'static str,
+    pub field: &'static str,
+    pub expected: &'static str,
+}
@@ -0,0 +1,185 @@
+use std::{
+    collections::VecDeque,
+    fmt::{self, Write as _},
+    fs::OpenOptions,
+    io::Write as _,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+use serde::{Deserialize, Serialize};
+use tracing::{
+    field::{Field, Visit},
+    Event, Level, Subscriber,
+};
+use tracing_subscriber::{layer::Context, Layer};
+
+use crate::{sink, LoggerError, LoggerParams};
+
+/// Keeps the latest events in memory and writes them to a file on panic, under
+/// `crash_dump` in the logger params
+///
+/// ```yaml
+/// logger:
+///   default_level: debug
+///   sinks:
+///     - kind: console
+///       level: info
+///   crash_dump:
+///     path: logs/crash.log
+///     trigger_level: error
+/// ```
+///
+/// A relative `path` is taken from `log_dir`. The buffer sees every event down to its own
+/// `level`, including those `default_level`, `filter` and the sinks filter out. Dumps are
+/// appended to the file, each after a `---` header line.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct CrashDumpParams {
+    pub path: PathBuf,
+    /// Events kept, 1000 by default
+    pub capacity: Option<usize>,
+    /// Least severe level kept, `debug` by default
+    pub level: Option<String>,
+    /// Also dump when an event at this level or above is logged, e.g. `error`
+    pub trigger_level: Option<String>,
+}
+
+struct State {
+    path: PathBuf,
+    capacity: usize,
+    level: Level,
+    trigger_level: Option<Level>,
+    events: Mutex<VecDeque<String>>,
+}
+
+impl State {
+    fn dump(&self, reason: &str) {
+        // Panicking while the buffer is taken would deadlock here
+        let Ok(events) = self.events.try_lock() else {
+            return;
+        };
+
+        if let Some(dir) = self.path.parent() {
+            let _ = std::fs::create_dir_all(dir);
+        }
+
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path);
+        let _ = file.and_then(|mut file| {
+            writeln!(file, "--- {} {reason} ---", sink::timestamp())?;
+
+            events.iter().try_for_each(|line| writeln!(file, "{line}"))
+        });
+    }
+}
+
+/// Ring buffer layer of the `crash_dump` params, also dumping it on panic
+#[derive(Clone)]
+pub(crate) struct CrashBuffer(Arc<State>);
+
+impl CrashBuffer {
+    pub(crate) fn new(params: &LoggerParams) -> Result<Option<Self>, LoggerError> {
+        let Some(crash_dump) = &params.crash_dump else {
+            return Ok(None);
+        };
+
+        let level = |level: &str| level.parse::<Level>().map_err(|_| LoggerError::Filter);
+        let state = Arc::new(State {
+            path: params.file_path(&crash_dump.path)?,
+            capacity: crash_dump.capacity.unwrap_or(1000),
+            level: level(crash_dump.level.as_deref().unwrap_or("debug"))?,
+            trigger_level: crash_dump.trigger_level.as_deref().map(level).transpose()?,
+            events: Mutex::default(),
+        });
+
+        let hook_state = state.clone();
+        let previous = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            hook_state.dump(&format!("panic: {info}").replace('\n', " "));
+            previous(info);
+        }));
+
+        Ok(Some(Self(state)))
+    }
+}
+
+impl<S: Subscriber> Layer<S> for CrashBuffer {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        let metadata = event.metadata();
+
+        if *metadata.level() > self.0.level {
+            return;
+        }
+
+        let mut text = Text::default();
+        event.record(&mut text);
+        let line = format!(
+            "{} {:>5} {}: {}",
+            sink::timestamp(),
+            metadata.level(),
+            metadata.target(),
+            text.0
+        );
+
+        {
+            let mut events = self.0.events.lock().unwrap();
+
+            if events.len() >= self.0.capacity {
+                events.pop_front();
+            }
+
+            events.push_back(line);
+        }
+
+        if self
+            .0
+            .trigger_level
+            .is_some_and(|trigger_level| *metadata.level() <= trigger_level)
+        {
+            self.0.dump(&format!("{} event", metadata.level()));
+        }
+    }
+}
+
+// Message first, then the other fields as `name=value`
+#[derive(Default)]
+struct Text(String);
+
+impl Visit for Text {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if field.name() == "message" {
+            self.0.insert_str(0, &format!("{value:?}"));
+        } else {
+            let _ = write!(self.0, " {}={value:?}", field.name());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tracing::{debug, error};
+
+    use crate::{Logger, UpperLoggerParams};
+
+    #[test]
+    fn keeps_filtered_events() {
+        let path = std::env::temp_dir().join(format!("unconfig-crash-{}.log", std::process::id()));
+        let params: UpperLoggerParams = serde_yaml::from_str(&format!(
+            "logger:\n  default_level: info\n  crash_dump:\n    path: {}\n    trigger_level: error",
+            path.display()
+        ))
+        .unwrap();
+        let _logger = Logger::init(&params).unwrap();
+
+        debug!("below the default level");
+        error!("dumping");
+
+        let dump = std::fs::read_to_string(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+        assert!(dump.contains("below the default level"), "{dump}");
+        assert!(dump.contains("dumping"), "{dump}");
+    }
+}
@@ -0,0 +1,126 @@
+use std::{
+    collections::HashMap,
+    sync::{LazyLock, Mutex},
+};
+
+use anyhow::{anyhow, Result};
+use serde_yaml::Value;
+use tracing::warn;
+
+use crate::{schedule, UnconfigError};
+
+// Deprecated top-level sections by name
+static DEPRECATED: LazyLock<Mutex<HashMap<String, Deprecation>>> = LazyLock::new(Default::default);
+
+/// Why a section is deprecated and when it stops loading, see [`deprecate_section`]
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Deprecation {
+    /// What to do instead, e.g. "moved to `network`"
+    pub note: String,
+    /// `2027-01-31` (or an RFC 3339 time), or a version such as `2.0.0` compared with
+    /// `version`. Without one the section only warns
+    pub sunset: Option<String>,
+    /// Version of the application, `env!("CARGO_PKG_VERSION")`
+    pub version: Option<String>,
+}
+
+impl Deprecation {
+    // Whether the sunset has passed
+    fn sunset_passed(&self) -> Result<bool> {
+        let Some(sunset) = &self.sunset else {
+            return Ok(false);
+        };
+
+        if let Some(sunset) = schedule::parse_timestamp(sunset) {
+            return Ok(schedule::now() >= sunset);
+        }
+
+        let Some(sunset_version) = version(sunset) else {
+            return Err(anyhow!(
+                "invalid sunset `{sunset}`, expected a date or a version"
+            ));
+        };
+        let Some(current) = self.version.as_deref() else {
+            return Err(anyhow!(
+                "sunset version {sunset} needs the version of the application"
+            ));
+        };
+        let current = version(current).ok_or_else(|| anyhow!("invalid version `{current}`"))?;
+
+        Ok(current >= sunset_version)
+    }
+}
+
+/// Deprecate the top-level `section` of every config loaded from now on
+///
+/// Sources with the section warn until the sunset of `deprecation` and fail the load
+/// from then on, so that it can be dropped from a whole fleet before the code reading it
+/// is. Embedded configs are exempt, they ship with that code. `#[configurable]` structs
+/// register theirs with `deprecated = "note"` and `sunset = "2027-01-31"`, or a version
+/// of the crate.
+pub fn deprecate_section(section: &str, deprecation: Deprecation) -> Result<(), UnconfigError> {
+    deprecation
+        .sunset_passed()
+        .map_err(|e| UnconfigError::Validation(format!("section `{section}`: {e}")))?;
+
+    DEPRECATED
+        .lock()
+        .unwrap()
+        .insert(section.to_string(), deprecation);
+
+    Ok(())
+}
+
+/// Warn about the deprecated sections of `params`, or fail on the first past its sunset
+pub(crate) fn check(source: &str, params: &Value) -> Result<()> {
+    let Some(mapping) = params.as_mapping() else {
+        return Ok(());
+    };
+
+    let deprecated = DEPRECATED.lock().unwrap();
+    if deprecated.is_empty() {
+        return Ok(());
+    }
+
+    for section in mapping.keys().filter_map(Value::as_str) {
+        let Some(deprecation) = deprecated.get(section) else {
+            continue;
+        };
+        let note = match deprecation.note.as_str() {
+            "" => String::new(),
+            note => format!(": {note}"),
+        };
+
+        match &deprecation.sunset {
+            Some(sunset) if deprecation.sunset_passed()? => {
+                return Err(UnconfigError::Sunset {
+                    file: source.to_string(),
+                    section: section.to_string(),
+                    sunset: sunset.clone(),
+                    note: deprecation.note.clone(),
+                }
+                .into());
+            }
+            Some(sunset) => {
+                warn!("{source}: section `{section}` is deprecated and stops loading at {sunset}{note}")
+            }
+            None => warn!("{source}: section `{section}` is deprecated{note}"),
+        }
+    }
+
+    Ok(())
+}
+
+// Numbers of a `1.2.3` version, missing ones are zero and a pre-release or build
+// suffix is ignored
+fn version(text: &str) -> Option<[u64; 3]> {
+    let text = text.trim().trim_start_matches('v');
+    let text = text.split(['-', '+']).next()?;
+
+    let mut version = [0; 3];
+    for (index, part) in text.split('.').enumerate() {
+        *version.get_mut(index)? = part.parse().ok()?;
+    }
+
+    Some(version)
+}
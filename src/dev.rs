@@ -0,0 +1,39 @@
+//! Helpers for authoring configs, not meant for production code
+
+use std::{fmt::Debug, fs, path::Path, thread, time::Duration};
+
+use serde::de::DeserializeOwned;
+
+use crate::{full_path, Config};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Load the config at `path` as `T` and pretty-print the result, again on every change
+///
+/// Each load runs the whole pipeline (overlays, substitution, validation), so the output
+/// is exactly what the application would get, or the error it would fail with. The file
+/// is polled, so this works on any filesystem; it never returns.
+pub fn watch_and_print<T: DeserializeOwned + Debug>(path: impl AsRef<Path>) -> ! {
+    let path = path.as_ref();
+    let mut last = None;
+
+    loop {
+        // Size too, since a quick edit may keep the modification time
+        let stamp = full_path(path)
+            .ok()
+            .and_then(|path| fs::metadata(path).ok())
+            .map(|meta| (meta.modified().ok(), meta.len()));
+
+        if last.as_ref() != Some(&stamp) {
+            last = Some(stamp);
+            println!("--- {} ---", path.display());
+
+            match T::load_path(path) {
+                Ok(config) => println!("{config:#?}"),
+                Err(e) => println!("error: {e:?}"),
+            }
+        }
+
+        thread::sleep(POLL_INTERVAL);
+    }
+}
@@ -0,0 +1,85 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use serde_yaml::{Mapping, Value};
+use tracing::debug_span;
+
+use crate::{coerce, full_path, limits, overlay, read_file, UnconfigError};
+
+/// Tree of the files under `path`, as mounted from a Kubernetes ConfigMap or Secret
+///
+/// Each file is a key named after it, its content the value, typed like an environment
+/// variable and without the trailing newline. Dots in a name and subdirectories nest the
+/// key, so `server.port` and `server/port` are both `server.port`. Files with a config
+/// extension, e.g. `config.yml`, are parsed and merged in at their level instead. Hidden
+/// names are skipped, which leaves out the `..data` links Kubernetes swaps on updates.
+pub(crate) fn read(path: impl AsRef<Path>) -> Result<(PathBuf, Value), UnconfigError> {
+    let dir = full_path(path)?;
+    let source = dir.display().to_string();
+    let tree = debug_span!("config_read", source).in_scope(|| read_dir(&dir))?;
+
+    Ok((dir, tree))
+}
+
+fn read_dir(dir: &Path) -> Result<Value, UnconfigError> {
+    let mut entries = fs::read_dir(dir)
+        .and_then(|entries| {
+            entries
+                .map(|entry| entry.map(|entry| entry.path()))
+                .collect::<io::Result<Vec<_>>>()
+        })
+        .map_err(|e| UnconfigError::io(dir, e))?;
+    // Later names win on conflicts, whatever order the filesystem lists them in
+    entries.sort();
+
+    let mut tree = Value::Mapping(Mapping::new());
+
+    for path in entries {
+        let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+            continue;
+        };
+        if name.starts_with('.') {
+            continue;
+        }
+
+        // Through the symlinks Kubernetes mounts every key as
+        let meta = fs::metadata(&path).map_err(|e| UnconfigError::io(&path, e))?;
+        let layer = if meta.is_dir() {
+            nest([name].into_iter(), read_dir(&path)?)
+        } else if is_document(&path) {
+            read_file(&path)?.1.as_ref().clone()
+        } else {
+            limits::limits()
+                .check_file_size(&path.display().to_string(), meta.len())
+                .map_err(UnconfigError::validation)?;
+
+            let content = fs::read_to_string(&path).map_err(|e| UnconfigError::io(&path, e))?;
+            let content = content
+                .strip_suffix('\n')
+                .map(|content| content.strip_suffix('\r').unwrap_or(content))
+                .unwrap_or(&content);
+
+            nest(name.split('.'), coerce(content.to_string()))
+        };
+
+        overlay::deep_merge(&mut tree, layer);
+    }
+
+    Ok(tree)
+}
+
+// `value` under the `levels`, outermost first
+fn nest<'a>(levels: impl DoubleEndedIterator<Item = &'a str>, value: Value) -> Value {
+    levels.rev().fold(value, |acc, level| {
+        Value::Mapping(Mapping::from_iter([(level.into(), acc)]))
+    })
+}
+
+fn is_document(path: &Path) -> bool {
+    let extension = path.extension().and_then(|extension| extension.to_str());
+
+    matches!(extension, Some("yml" | "yaml" | "json"))
+        || (cfg!(feature = "toml") && extension == Some("toml"))
+}
@@ -0,0 +1,159 @@
+use std::path::Path;
+
+use anyhow::{anyhow, Context, Result};
+use serde::de::DeserializeOwned;
+use serde_yaml::Value;
+use tracing::debug_span;
+
+use crate::{
+    cache, drift, env_overlay, expand, format::Format, read_path, resolve_document, schedule,
+    Difference, EMBEDDED_SOURCE,
+};
+
+/// A loaded config without a static type, for code that can't know the struct
+/// (plugins, scripting layers)
+///
+/// Goes through the same pipeline as [`crate::Config`]: overlays, `${...}` substitution,
+/// scheduled values, limits and the env policy. Values are addressed by dotted paths with
+/// `[i]` for sequence items, e.g. `server.endpoints[0].port`.
+#[derive(Debug, Clone)]
+pub struct Document {
+    source: String,
+    value: Value,
+    // Dotted paths of values changed by substitution, with their text as written
+    substituted: Vec<(String, Value)>,
+}
+
+/// Where a value of a [`Document`] or a `#[configurable]` config came from
+#[derive(Debug, Clone, PartialEq)]
+pub struct Provenance {
+    /// File path, `embedded` for strings, `environment` for variables
+    pub source: String,
+    /// The value as written, if `${...}` substitution replaced it
+    pub substituted_from: Option<Value>,
+}
+
+impl Document {
+    pub fn load_str(src: &str) -> Result<Self> {
+        let source = EMBEDDED_SOURCE;
+        let params = debug_span!("config_parse", source)
+            .in_scope(|| cache::parse(source, src, Format::of_text(src)))?;
+
+        Self::resolve(source, None, resolve_document(&params))
+    }
+
+    pub fn load_path<S: AsRef<Path>>(path: S) -> Result<Self> {
+        let (path, params) = read_path(path)?;
+
+        Self::resolve(
+            &path.display().to_string(),
+            Some(&path),
+            resolve_document(&params),
+        )
+    }
+
+    pub fn load_env<S: AsRef<Path>>(env: &'static str, alt_path: S) -> Result<Self> {
+        if let Ok(env_var_path) = env_overlay::var(env) {
+            Self::load_path(env_var_path)
+        } else {
+            Self::load_path(alt_path)
+        }
+    }
+
+    fn resolve(source: &str, origin: Option<&Path>, raw: Value) -> Result<Self> {
+        let mut value = raw.clone();
+        expand(source, origin, &mut value)?;
+
+        let substituted = drift::diff(&raw, &value, &[])
+            .differences
+            .into_iter()
+            .filter_map(|difference| match difference {
+                Difference::Changed { path, expected, .. } => Some((path, expected)),
+                _ => None,
+            })
+            .collect();
+
+        schedule::activate(&mut value)?;
+        #[cfg(feature = "eval")]
+        crate::eval::evaluate(&mut value, None)?;
+
+        Ok(Self {
+            source: source.to_string(),
+            value,
+            substituted,
+        })
+    }
+
+    /// Deserialize the value at `path` as `T`
+    pub fn get<T: DeserializeOwned>(&self, path: &str) -> Result<T> {
+        let value = self
+            .get_value(path)
+            .ok_or(anyhow!("{}: no value at `{path}`", self.source))?;
+
+        serde_yaml::from_value(value.clone()).context(format!("{}: invalid `{path}`", self.source))
+    }
+
+    /// The raw value at `path`
+    pub fn get_value(&self, path: &str) -> Option<&Value> {
+        lookup(&self.value, path)
+    }
+
+    /// Whether there is a value at `path`
+    pub fn contains(&self, path: &str) -> bool {
+        self.get_value(path).is_some()
+    }
+
+    /// Where the value at `path` came from
+    pub fn provenance(&self, path: &str) -> Option<Provenance> {
+        self.get_value(path)?;
+
+        Some(Provenance {
+            source: self.source.clone(),
+            substituted_from: self
+                .substituted
+                .iter()
+                .find(|(substituted, _)| substituted == path)
+                .map(|(_, raw)| raw.clone()),
+        })
+    }
+
+    /// File path, `embedded` for strings
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    /// The whole resolved tree
+    pub fn value(&self) -> &Value {
+        &self.value
+    }
+}
+
+// Follow `a.b[0].c` through mappings and sequences
+pub(crate) fn lookup<'a>(mut value: &'a Value, path: &str) -> Option<&'a Value> {
+    if path.is_empty() {
+        return Some(value);
+    }
+
+    for segment in path.split('.') {
+        let (key, mut indices) = match segment.find('[') {
+            Some(index) => segment.split_at(index),
+            None => (segment, ""),
+        };
+
+        if !key.is_empty() {
+            value = value.as_mapping()?.get(key)?;
+        }
+
+        while let Some(rest) = indices.strip_prefix('[') {
+            let (index, tail) = rest.split_once(']')?;
+            value = value.as_sequence()?.get(index.parse::<usize>().ok()?)?;
+            indices = tail;
+        }
+
+        if !indices.is_empty() {
+            return None;
+        }
+    }
+
+    Some(value)
+}
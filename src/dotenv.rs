@@ -0,0 +1,248 @@
+use std::{
+    collections::HashMap,
+    env, fs, io,
+    path::{Path, PathBuf},
+    sync::{Arc, LazyLock, Mutex},
+};
+
+use tracing::{debug, warn};
+
+use crate::env_overlay;
+
+/// Variable selecting the `.env.<mode>` files, e.g. `APP_ENV=production`
+pub const DOTENV_MODE_VAR: &str = "APP_ENV";
+
+// Variables of the files of each directory and mode, read once
+static LOADED: LazyLock<Mutex<HashMap<Key, Arc<Vars>>>> = LazyLock::new(Default::default);
+
+// Working directory and mode
+type Key = (PathBuf, Option<String>);
+
+// Value of each variable and the file it came from
+type Vars = HashMap<String, (String, PathBuf)>;
+
+/// `.env` files of the working directory, lowest precedence first
+///
+/// The files, each read only when it exists, are
+///
+/// 1. `.env`
+/// 2. `.env.local`
+/// 3. `.env.<mode>`, with the mode named by [`DOTENV_MODE_VAR`]
+/// 4. `.env.<mode>.local`
+///
+/// A variable of a later file replaces the one of an earlier file. Variables of the
+/// process, and of an [`EnvOverlay`](crate::EnvOverlay), take precedence over all of
+/// them, so the files only fill in what the environment leaves unset. They are seen
+/// wherever the loaders read the environment: `${...}` substitution, `env_prefix` and the
+/// other variable sources. The files are read once for each mode.
+pub fn dotenv_files() -> Vec<PathBuf> {
+    let Ok(dir) = env::current_dir() else {
+        return vec![];
+    };
+
+    let mut names = vec![".env".to_string(), ".env.local".to_string()];
+    if let Some(mode) = mode() {
+        names.push(format!(".env.{mode}"));
+        names.push(format!(".env.{mode}.local"));
+    }
+
+    names.into_iter().map(|name| dir.join(name)).collect()
+}
+
+/// File that supplies `name`, `None` when no `.env` file sets it or the environment
+/// does, see [`dotenv_files`]
+pub fn dotenv_source(name: &str) -> Option<PathBuf> {
+    if !supplied(name) {
+        return None;
+    }
+
+    loaded().get(name).map(|(_, file)| file.clone())
+}
+
+/// Each variable supplied by a `.env` file and that file, by name, see [`dotenv_files`]
+pub fn dotenv_sources() -> Vec<(String, PathBuf)> {
+    let mut sources = loaded()
+        .iter()
+        .filter(|(name, _)| supplied(name))
+        .map(|(name, (_, file))| (name.clone(), file.clone()))
+        .collect::<Vec<_>>();
+    sources.sort();
+
+    sources
+}
+
+/// Value of `name` in the `.env` files, whether the environment sets it or not
+pub(crate) fn var(name: &str) -> Option<String> {
+    loaded().get(name).map(|(value, _)| value.clone())
+}
+
+/// Every variable of the `.env` files, whether the environment sets it or not
+pub(crate) fn vars() -> Vec<(String, String)> {
+    loaded()
+        .iter()
+        .map(|(name, (value, _))| (name.clone(), value.clone()))
+        .collect()
+}
+
+// Set by the files alone, not the environment nor hidden by an overlay
+fn supplied(name: &str) -> bool {
+    env_overlay::os_var(name).is_err() && env_overlay::var(name).is_ok()
+}
+
+// Mode of the process or overlay only, the files can't pick their own
+fn mode() -> Option<String> {
+    let mode = env_overlay::os_var(DOTENV_MODE_VAR).ok()?;
+
+    // A name, not a path that could point elsewhere
+    (!mode.is_empty()
+        && mode
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_'))
+    .then_some(mode)
+}
+
+fn loaded() -> Arc<Vars> {
+    let Ok(dir) = env::current_dir() else {
+        return Arc::default();
+    };
+
+    LOADED
+        .lock()
+        .unwrap()
+        .entry((dir, mode()))
+        .or_insert_with(|| Arc::new(read_files()))
+        .clone()
+}
+
+fn read_files() -> Vars {
+    let mut vars = Vars::new();
+
+    if let (Ok(mode), None) = (env_overlay::os_var(DOTENV_MODE_VAR), mode()) {
+        warn!("Ignoring {DOTENV_MODE_VAR} `{mode}`, expected letters, digits, `-` or `_`");
+    }
+
+    for file in dotenv_files() {
+        let content = match fs::read_to_string(&file) {
+            Ok(content) => content,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => {
+                warn!("Failed to read {}: {e}", file.display());
+                continue;
+            }
+        };
+
+        debug!("Read environment variables from {}", file.display());
+        for (name, value) in parse(&file, &content) {
+            vars.insert(name, (value, file.clone()));
+        }
+    }
+
+    vars
+}
+
+// `NAME=value` lines, optionally `export`ed. Values may be single quoted, taken as they
+// are, or double quoted, with `\n`, `\t`, `\"` and `\\` escapes, and span lines when
+// quoted. `#` starts a comment on its own line or after an unquoted value.
+fn parse(file: &Path, content: &str) -> Vec<(String, String)> {
+    let mut vars = vec![];
+    let mut lines = content.lines().enumerate();
+
+    while let Some((number, line)) = lines.next() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let line = line.strip_prefix("export ").unwrap_or(line);
+        let Some((name, value)) = line.split_once('=') else {
+            warn!("{}:{}: expected `NAME=value`", file.display(), number + 1);
+            continue;
+        };
+        let name = name.trim();
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            warn!(
+                "{}:{}: invalid variable name `{name}`",
+                file.display(),
+                number + 1
+            );
+            continue;
+        }
+
+        let value = value.trim_start();
+        let value = match value.chars().next() {
+            Some(quote @ ('\'' | '"')) => {
+                let mut text = value[1..].to_string();
+                // Continued on the next lines until the closing quote
+                let closed = loop {
+                    if let Some(end) = closing(&text, quote) {
+                        text.truncate(end);
+                        break true;
+                    }
+
+                    match lines.next() {
+                        Some((_, line)) => {
+                            text.push('\n');
+                            text.push_str(line);
+                        }
+                        None => break false,
+                    }
+                };
+                if !closed {
+                    warn!("{}:{}: unterminated quote", file.display(), number + 1);
+                    continue;
+                }
+
+                if quote == '"' {
+                    unescape(&text)
+                } else {
+                    text
+                }
+            }
+            _ => match value.find(" #") {
+                Some(comment) => value[..comment].trim_end().to_string(),
+                None => value.trim_end().to_string(),
+            },
+        };
+
+        vars.push((name.to_string(), value));
+    }
+
+    vars
+}
+
+// Index of the quote ending `text`, skipping escaped ones in double quotes
+fn closing(text: &str, quote: char) -> Option<usize> {
+    let mut escaped = false;
+
+    for (index, c) in text.char_indices() {
+        match c {
+            '\\' if quote == '"' && !escaped => escaped = true,
+            c if c == quote && !escaped => return Some(index),
+            _ => escaped = false,
+        }
+    }
+
+    None
+}
+
+fn unescape(text: &str) -> String {
+    let mut unescaped = String::with_capacity(text.len());
+    let mut chars = text.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+
+        match chars.next() {
+            Some('n') => unescaped.push('\n'),
+            Some('t') => unescaped.push('\t'),
+            Some('r') => unescaped.push('\r'),
+            Some(c) => unescaped.push(c),
+            None => unescaped.push('\\'),
+        }
+    }
+
+    unescaped
+}
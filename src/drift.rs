@@ -0,0 +1,169 @@
+use std::{fmt, fs, path::Path};
+
+use anyhow::{Context, Result};
+use serde_yaml::Value;
+
+use crate::{cache, format::Format, overlay::glob_match};
+
+/// One key that differs between a config and its reference
+#[derive(Debug, Clone, PartialEq)]
+pub enum Difference {
+    /// Present in the reference only
+    Missing { path: String },
+    /// Present in the config only
+    Unexpected { path: String },
+    /// Present in both with different values
+    Changed {
+        path: String,
+        expected: Value,
+        actual: Value,
+    },
+}
+
+impl fmt::Display for Difference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let inline = |value: &Value| {
+            serde_yaml::to_string(value)
+                .map(|v| v.trim_end().replace('\n', " "))
+                .unwrap_or_default()
+        };
+
+        match self {
+            Self::Missing { path } => write!(f, "{path}: missing"),
+            Self::Unexpected { path } => write!(f, "{path}: not in reference"),
+            Self::Changed {
+                path,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "{path}: expected {}, got {}",
+                inline(expected),
+                inline(actual)
+            ),
+        }
+    }
+}
+
+/// Differences between a config file and its golden reference
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Drift {
+    pub differences: Vec<Difference>,
+}
+
+impl Drift {
+    pub fn is_empty(&self) -> bool {
+        self.differences.is_empty()
+    }
+}
+
+impl fmt::Display for Drift {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_empty() {
+            return write!(f, "no drift");
+        }
+
+        for difference in &self.differences {
+            writeln!(f, "{difference}")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Compare the file at `path` with the reference file at `reference`
+///
+/// Files are compared as written, before overlays and substitution, so the result doesn't
+/// depend on the host environment. Keys are addressed by dotted paths with `[i]` for
+/// sequence items (`server.endpoints[0].url`), `ignore` holds glob patterns over them
+/// for keys expected to differ per host (`server.host`, `hosts.*`).
+pub fn drift<P: AsRef<Path>, R: AsRef<Path>>(
+    path: P,
+    reference: R,
+    ignore: &[&str],
+) -> Result<Drift> {
+    let read = |path: &Path| -> Result<_> {
+        let content = fs::read_to_string(path)
+            .context(format!("failed to read config file: {}", path.display()))?;
+
+        Ok(cache::parse(
+            &path.display().to_string(),
+            &content,
+            Format::of_path(path),
+        )?)
+    };
+
+    let actual = read(path.as_ref())?;
+    let expected = read(reference.as_ref())?;
+
+    Ok(diff(&expected, &actual, ignore))
+}
+
+/// Differences between two already loaded values, see [`drift`]
+pub(crate) fn diff(expected: &Value, actual: &Value, ignore: &[&str]) -> Drift {
+    let mut drift = Drift::default();
+    compare(String::new(), expected, actual, ignore, &mut drift);
+
+    drift
+}
+
+fn compare(path: String, expected: &Value, actual: &Value, ignore: &[&str], drift: &mut Drift) {
+    if !path.is_empty() && is_ignored(ignore, &path) {
+        return;
+    }
+
+    let key_path = |key: &Value| {
+        let key = match key {
+            Value::String(key) => key.clone(),
+            other => serde_yaml::to_string(other)
+                .map(|k| k.trim_end().to_string())
+                .unwrap_or_default(),
+        };
+
+        if path.is_empty() {
+            key
+        } else {
+            format!("{path}.{key}")
+        }
+    };
+
+    match (expected, actual) {
+        (Value::Mapping(expected), Value::Mapping(actual)) => {
+            for (k, v) in expected {
+                match actual.get(k) {
+                    Some(actual_v) => compare(key_path(k), v, actual_v, ignore, drift),
+                    None => {
+                        let path = key_path(k);
+
+                        if !is_ignored(ignore, &path) {
+                            drift.differences.push(Difference::Missing { path });
+                        }
+                    }
+                }
+            }
+
+            for k in actual.keys().filter(|k| !expected.contains_key(*k)) {
+                let path = key_path(k);
+
+                if !is_ignored(ignore, &path) {
+                    drift.differences.push(Difference::Unexpected { path });
+                }
+            }
+        }
+        (Value::Sequence(expected), Value::Sequence(actual)) if expected.len() == actual.len() => {
+            for (i, (expected, actual)) in expected.iter().zip(actual).enumerate() {
+                compare(format!("{path}[{i}]"), expected, actual, ignore, drift);
+            }
+        }
+        (expected, actual) if expected != actual => drift.differences.push(Difference::Changed {
+            path,
+            expected: expected.clone(),
+            actual: actual.clone(),
+        }),
+        _ => {}
+    }
+}
+
+fn is_ignored(ignore: &[&str], path: &str) -> bool {
+    ignore.iter().any(|pattern| glob_match(pattern, path))
+}
@@ -0,0 +1,166 @@
+use std::{cell::RefCell, collections::HashMap, env, sync::Arc};
+
+use crate::dotenv;
+
+thread_local! {
+    // Variables set or unset on this thread, `None` hiding the process one
+    static OVERLAY: RefCell<Option<Arc<Vars>>> = const { RefCell::new(None) };
+}
+
+type Vars = HashMap<String, Option<String>>;
+
+/// Environment variables seen by the loaders of this thread only, over the process ones
+///
+/// Config tests that set variables with `std::env::set_var` race each other when run in
+/// parallel; an overlay keeps them apart without touching the process environment.
+/// Overlays nest, the inner one winning, and threads spawned with
+/// [`spawn_traced`](crate::spawn_traced), e.g. by [`AsyncConfig`](crate::AsyncConfig),
+/// inherit the overlay of their parent.
+///
+/// ```no_run
+/// # use unconfig::{Config, EnvOverlay};
+/// # #[derive(serde::Deserialize)] struct Settings {}
+/// let settings = EnvOverlay::new()
+///     .set("APP__PORT", "8081")
+///     .unset("APP__HOST")
+///     .run(|| Settings::load_prefixed("app"));
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct EnvOverlay {
+    vars: Vars,
+}
+
+impl EnvOverlay {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.vars.insert(name.into(), Some(value.into()));
+        self
+    }
+
+    /// Hide `name` even if the process has it
+    pub fn unset(mut self, name: impl Into<String>) -> Self {
+        self.vars.insert(name.into(), None);
+        self
+    }
+
+    /// `f` with the overlay in place
+    pub fn run<R>(self, f: impl FnOnce() -> R) -> R {
+        let _guard = self.enter();
+
+        f()
+    }
+
+    /// Put the overlay in place until the guard is dropped, e.g. for a whole test
+    pub fn enter(self) -> EnvOverlayGuard {
+        let previous = current();
+        let mut vars = previous.as_deref().cloned().unwrap_or_default();
+        vars.extend(self.vars);
+
+        OVERLAY.set(Some(Arc::new(vars)));
+
+        EnvOverlayGuard { previous }
+    }
+}
+
+/// Restores the overlay that was in place before [`EnvOverlay::enter`]
+#[must_use = "the overlay is removed when the guard is dropped"]
+pub struct EnvOverlayGuard {
+    previous: Option<Arc<Vars>>,
+}
+
+impl Drop for EnvOverlayGuard {
+    fn drop(&mut self) {
+        OVERLAY.set(self.previous.take());
+    }
+}
+
+/// `f` with `vars` set on this thread only, see [`EnvOverlay`]
+pub fn with_env<R>(vars: &[(&str, &str)], f: impl FnOnce() -> R) -> R {
+    vars.iter()
+        .fold(EnvOverlay::new(), |overlay, (name, value)| {
+            overlay.set(*name, *value)
+        })
+        .run(f)
+}
+
+/// `std::env::var` through the overlay of this thread, then the `.env` files
+pub(crate) fn var(name: &str) -> Result<String, env::VarError> {
+    match current().as_deref().and_then(|vars| vars.get(name)) {
+        Some(Some(value)) => Ok(value.clone()),
+        Some(None) => Err(env::VarError::NotPresent),
+        None => env::var(name).or_else(|e| dotenv::var(name).ok_or(e)),
+    }
+}
+
+/// [`var`] for the code `#[configurable]` generates, e.g. the path watched for
+/// `#[configurable("${CONFIG}")]`
+#[doc(hidden)]
+pub fn overlay_var(name: &str) -> Result<String, env::VarError> {
+    var(name)
+}
+
+/// Same as [`var`], without the `.env` files
+pub(crate) fn os_var(name: &str) -> Result<String, env::VarError> {
+    match current().as_deref().and_then(|vars| vars.get(name)) {
+        Some(Some(value)) => Ok(value.clone()),
+        Some(None) => Err(env::VarError::NotPresent),
+        None => env::var(name),
+    }
+}
+
+/// `std::env::vars` through the overlay of this thread, variables that aren't valid
+/// Unicode left out, then those only the `.env` files set
+pub(crate) fn vars() -> Vec<(String, String)> {
+    let overlay = current();
+    let mut vars = os_vars(overlay.as_deref());
+    let mut files = dotenv::vars()
+        .into_iter()
+        .filter(|(name, _)| {
+            !overlay.as_ref().is_some_and(|vars| vars.contains_key(name))
+                && env::var_os(name).is_none()
+        })
+        .collect::<Vec<_>>();
+    files.sort();
+    vars.extend(files);
+
+    vars
+}
+
+fn os_vars(overlay: Option<&Vars>) -> Vec<(String, String)> {
+    let vars = env::vars_os()
+        .filter_map(|(name, value)| Some((name.into_string().ok()?, value.into_string().ok()?)));
+    let Some(overlay) = overlay else {
+        return vars.collect();
+    };
+
+    // The process ones the overlay leaves alone, in their order, then those it sets
+    let mut vars = vars
+        .filter(|(name, _)| !overlay.contains_key(name))
+        .collect::<Vec<_>>();
+    let mut added = overlay
+        .iter()
+        .filter_map(|(name, value)| Some((name.clone(), value.clone()?)))
+        .collect::<Vec<_>>();
+    added.sort();
+    vars.extend(added);
+
+    vars
+}
+
+/// `f` for a thread about to be spawned, run with the overlay of the current one
+pub(crate) fn inherit<R>(f: impl FnOnce() -> R) -> impl FnOnce() -> R {
+    let overlay = current();
+
+    move || {
+        OVERLAY.set(overlay);
+
+        f()
+    }
+}
+
+fn current() -> Option<Arc<Vars>> {
+    OVERLAY.with_borrow(Clone::clone)
+}
@@ -0,0 +1,94 @@
+use std::io;
+
+use thiserror::Error;
+
+use crate::Violation;
+
+/// Why a config failed to load, returned by [`crate::Config`] and the loaders built on it
+///
+/// Match on the variant to tell a missing file from a broken one or from a config that
+/// parses but doesn't fit its struct:
+///
+/// ```no_run
+/// # use unconfig::{Config, UnconfigError};
+/// # #[derive(serde::Deserialize)] struct Settings {}
+/// match Settings::load_path("config.yml") {
+///     Ok(settings) => {}
+///     Err(UnconfigError::Io { source, .. }) if source.kind() == std::io::ErrorKind::NotFound => {}
+///     Err(e) => panic!("{e}"),
+/// }
+/// ```
+#[derive(Error, Debug)]
+#[non_exhaustive]
+pub enum UnconfigError {
+    /// The config file or URL couldn't be read
+    #[error("failed to read config {path}: {source}")]
+    Io {
+        path: String,
+        #[source]
+        source: io::Error,
+    },
+    /// The text isn't valid in its format, `line` and `column` count from 1 and are 0 when
+    /// the parser doesn't tell
+    #[error("failed to parse config {file}: {message}")]
+    Parse {
+        file: String,
+        line: usize,
+        column: usize,
+        message: String,
+    },
+    /// A variable the config requires is not set, e.g. by `${VAR:?message}`. `message` is
+    /// the one the config gives, if any
+    #[error("{file}: environment variable `{var}` is not set{}", suffix(.message))]
+    EnvMissing {
+        file: String,
+        var: String,
+        message: String,
+    },
+    /// A section deprecated by [`crate::deprecate_section`] is past its sunset, `note` says
+    /// what to do instead, if anything
+    #[error("{file}: section `{section}` was removed at {sunset}{}", suffix(.note))]
+    Sunset {
+        file: String,
+        section: String,
+        sunset: String,
+        note: String,
+    },
+    /// Merging the layers of a `#[configurable]` struct left an invalid value, e.g. a
+    /// `#[unconfig(merge = "deep")]` field no layer completes
+    #[error("invalid {field} after merging config layers: {message}")]
+    Merge { field: String, message: String },
+    /// The config was read but rejected: it doesn't deserialize into its type, breaks the
+    /// [`crate::Limits`] or the env policy, or has an invalid computed value
+    #[error("{0}")]
+    Validation(String),
+    /// Fields breaking their `#[validate(...)]` rules or `#[required]` and unset, all of them
+    #[error("invalid config:{}", .0.iter().map(|violation| format!("\n  {violation}")).collect::<String>())]
+    Violations(Vec<Violation>),
+}
+
+// `: message` after the error, when there is one
+fn suffix(message: &str) -> String {
+    if message.is_empty() {
+        return String::new();
+    }
+
+    format!(": {message}")
+}
+
+impl UnconfigError {
+    // Checks still report through `anyhow`, typed errors among them are kept as they are
+    pub(crate) fn validation(e: anyhow::Error) -> Self {
+        match e.downcast::<Self>() {
+            Ok(e) => e,
+            Err(e) => Self::Validation(format!("{e:#}")),
+        }
+    }
+
+    pub(crate) fn io(path: impl AsRef<std::path::Path>, source: io::Error) -> Self {
+        Self::Io {
+            path: path.as_ref().display().to_string(),
+            source,
+        }
+    }
+}
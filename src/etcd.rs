@@ -0,0 +1,327 @@
+use std::{
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+    io,
+    path::Path,
+    sync::{Arc, LazyLock, Mutex},
+};
+
+use anyhow::{anyhow, bail, Context, Result};
+use serde_yaml::{Mapping, Value};
+use tracing::{debug, debug_span};
+
+use crate::{
+    cache, env_overlay, format::Format, health, http, json, limits, overlay, UnconfigError,
+    UrlOptions,
+};
+
+/// Comma separated etcd endpoints, tried in order, `http://127.0.0.1:2379` when unset
+pub const ETCD_ENDPOINTS_VAR: &str = "ETCDCTL_ENDPOINTS";
+/// `name:password` of the etcd user to authenticate as, if auth is enabled
+pub const ETCD_USER_VAR: &str = "ETCDCTL_USER";
+
+const DEFAULT_ENDPOINT: &str = "http://127.0.0.1:2379";
+
+// Token of the last authentication, kept until etcd rejects it
+static AUTH: LazyLock<Mutex<Option<String>>> = LazyLock::new(Default::default);
+
+/// `etcd:app/config/`, how the keys under `prefix` are named in errors, events and health
+pub(crate) fn source(prefix: &str) -> String {
+    format!("etcd:{prefix}")
+}
+
+/// Tree of the keys under `prefix` and the digest of their revisions, see [`revisions`]
+///
+/// Each key is a path below the prefix, its `/` separated parts the levels of the tree,
+/// and its value is parsed in the format its extension names, YAML without one. A key
+/// equal to the prefix holds a whole document, under the other keys. Levels keyed `0`,
+/// `1`, ... become sequences like with prefixed environment variables.
+pub(crate) fn read(prefix: &str) -> Result<(Arc<Value>, u64), UnconfigError> {
+    let source = source(prefix);
+    let fetched = debug_span!("config_read", source).in_scope(|| range(prefix, false));
+    health::record_provider("etcd", fetched.as_ref().err().map(|e| format!("{e:#}")));
+
+    let kvs =
+        fetched.map_err(|e| UnconfigError::io(&source, io::Error::other(format!("{e:#}"))))?;
+    if kvs.is_empty() {
+        return Err(UnconfigError::io(
+            &source,
+            io::Error::new(io::ErrorKind::NotFound, "no keys under the prefix"),
+        ));
+    }
+    limits::limits()
+        .check_file_size(&source, kvs.iter().map(|kv| kv.value.len() as u64).sum())
+        .map_err(UnconfigError::validation)?;
+
+    let mut tree = Value::Mapping(Mapping::new());
+    let mut document = None;
+
+    debug_span!("config_parse", source).in_scope(|| {
+        for kv in &kvs {
+            let key = &kv.key[prefix.len()..];
+            let value = cache::parse(
+                &format!("{source}{key}"),
+                &kv.value,
+                Format::of_path(Path::new(key)),
+            )?;
+
+            let mut levels = key.split('/').filter(|level| !level.is_empty()).rev();
+            let Some(last) = levels.next() else {
+                document = Some(value);
+                continue;
+            };
+            let layer = levels.fold(
+                Value::Mapping(Mapping::from_iter([(last.into(), value.as_ref().clone())])),
+                |acc, level| Value::Mapping(Mapping::from_iter([(level.into(), acc)])),
+            );
+
+            overlay::deep_merge(&mut tree, layer);
+        }
+
+        Ok::<_, UnconfigError>(())
+    })?;
+    crate::sequences(&mut tree);
+
+    let params = match document {
+        Some(document) => {
+            let mut params = document.as_ref().clone();
+            overlay::deep_merge(&mut params, tree);
+
+            params
+        }
+        None => tree,
+    };
+
+    Ok((Arc::new(params), digest(&kvs)))
+}
+
+/// Digest of the keys under `prefix` and their modification revisions, which changes
+/// whenever one of them is put or deleted
+pub(crate) fn revisions(prefix: &str) -> Result<u64> {
+    range(prefix, true).map(|kvs| digest(&kvs))
+}
+
+struct KeyValue {
+    key: String,
+    value: String,
+    mod_revision: String,
+}
+
+fn digest(kvs: &[KeyValue]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    for kv in kvs {
+        (&kv.key, &kv.mod_revision).hash(&mut hasher);
+    }
+
+    hasher.finish()
+}
+
+// The keys under `prefix` in key order, without their values when `keys_only`
+fn range(prefix: &str, keys_only: bool) -> Result<Vec<KeyValue>> {
+    let mut body = Mapping::from_iter([
+        (Value::from("key"), Value::from(encode(prefix.as_bytes()))),
+        (
+            Value::from("range_end"),
+            Value::from(encode(&range_end(prefix.as_bytes()))),
+        ),
+    ]);
+    if keys_only {
+        body.insert(Value::from("keys_only"), Value::from(true));
+    }
+
+    let response = call("/v3/kv/range", &json::to_string(&Value::Mapping(body)))?;
+    let response: Value = serde_yaml::from_str(&response).context("malformed etcd response")?;
+
+    let Some(kvs) = response.get("kvs") else {
+        // Left out when no key matches
+        return Ok(vec![]);
+    };
+    let kvs = kvs.as_sequence().ok_or(anyhow!(
+        "malformed etcd response, expected a `kvs` sequence"
+    ))?;
+
+    kvs.iter()
+        .map(|kv| {
+            let field = |name| kv.get(name).and_then(Value::as_str).unwrap_or_default();
+            let text = |name| {
+                String::from_utf8(decode(field(name))?)
+                    .map_err(|_| anyhow!("etcd {name} is not valid UTF-8"))
+            };
+
+            Ok(KeyValue {
+                key: text("key")?,
+                value: text("value")?,
+                // Sent as a string, like every 64-bit integer of the gateway
+                mod_revision: match kv.get("mod_revision") {
+                    Some(Value::Number(revision)) => revision.to_string(),
+                    _ => field("mod_revision").to_string(),
+                },
+            })
+        })
+        .collect()
+}
+
+// End of the range of keys starting with `prefix`: its last byte that isn't 0xff
+// incremented, the rest dropped
+fn range_end(prefix: &[u8]) -> Vec<u8> {
+    let mut end = prefix.to_vec();
+
+    while let Some(last) = end.pop() {
+        if last < 0xff {
+            end.push(last + 1);
+            return end;
+        }
+    }
+
+    // Every key, for an empty prefix
+    vec![0]
+}
+
+// POST `body` to the JSON gateway of the first endpoint that answers, authenticated
+// when a user is set
+fn call(path: &str, body: &str) -> Result<String> {
+    let endpoints = env_overlay::var(ETCD_ENDPOINTS_VAR)
+        .ok()
+        .filter(|endpoints| !endpoints.trim().is_empty())
+        .unwrap_or_else(|| DEFAULT_ENDPOINT.to_string());
+    let mut failure = None;
+
+    for endpoint in endpoints.split(',').map(str::trim) {
+        // A bare `host:port`, as etcdctl takes it
+        let endpoint = if endpoint.contains("://") {
+            endpoint.to_string()
+        } else {
+            format!("http://{endpoint}")
+        };
+        let url = format!("{}{path}", endpoint.trim_end_matches('/'));
+
+        match authorized(&endpoint, &url, body) {
+            Ok(response) => return Ok(response),
+            Err(e) => failure = Some(e),
+        }
+    }
+
+    Err(failure.unwrap_or_else(|| anyhow!("no etcd endpoint in {ETCD_ENDPOINTS_VAR}")))
+}
+
+fn authorized(endpoint: &str, url: &str, body: &str) -> Result<String> {
+    let token = auth_token(endpoint, false)?;
+    let (mut status, mut response) = post(url, token.as_deref(), body)?;
+
+    // An expired token, authenticate again once
+    if status == 401 && token.is_some() {
+        (status, response) = post(url, auth_token(endpoint, true)?.as_deref(), body)?;
+    }
+
+    match status {
+        200 => Ok(response),
+        status => bail!("etcd answered {status}: {}", error(&response)),
+    }
+}
+
+// Token of the `ETCDCTL_USER`, `None` without one
+fn auth_token(endpoint: &str, renew: bool) -> Result<Option<String>> {
+    let Ok(user) = env_overlay::var(ETCD_USER_VAR) else {
+        return Ok(None);
+    };
+
+    let mut auth = AUTH.lock().unwrap();
+    if let (Some(token), false) = (&*auth, renew) {
+        return Ok(Some(token.clone()));
+    }
+
+    let (name, password) = user
+        .split_once(':')
+        .ok_or(anyhow!("{ETCD_USER_VAR} is not `name:password`"))?;
+    let body = json::to_string(&Value::Mapping(Mapping::from_iter([
+        (Value::from("name"), Value::from(name)),
+        (Value::from("password"), Value::from(password)),
+    ])));
+
+    let url = format!("{}/v3/auth/authenticate", endpoint.trim_end_matches('/'));
+    let (status, response) = post(&url, None, &body)?;
+    if status != 200 {
+        bail!(
+            "etcd authentication failed with {status}: {}",
+            error(&response)
+        );
+    }
+
+    let response: Value = serde_yaml::from_str(&response).context("malformed etcd response")?;
+    let token = response
+        .get("token")
+        .and_then(Value::as_str)
+        .ok_or(anyhow!(
+            "malformed etcd authentication response, no `token`"
+        ))?
+        .to_string();
+
+    debug!("Authenticated to etcd as {name}");
+    *auth = Some(token.clone());
+
+    Ok(Some(token))
+}
+
+fn post(url: &str, token: Option<&str>, body: &str) -> Result<(u16, String)> {
+    let mut headers = vec![];
+    if let Some(token) = token {
+        headers.push(("Authorization", token));
+    }
+
+    let response = http::request("POST", url, &headers, body, &UrlOptions::default())?;
+
+    Ok((response.status, response.text()?))
+}
+
+// The `message` etcd answers with, or the whole body
+fn error(body: &str) -> String {
+    serde_yaml::from_str::<Value>(body)
+        .ok()
+        .and_then(|body| Some(body.get("message")?.as_str()?.to_string()))
+        .unwrap_or_else(|| body.trim().to_string())
+}
+
+const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+// Standard padded base64, which the gateway takes keys and values in
+fn encode(bytes: &[u8]) -> String {
+    let mut text = String::with_capacity(bytes.len().div_ceil(3) * 4);
+
+    for chunk in bytes.chunks(3) {
+        let group = chunk.iter().enumerate().fold(0u32, |group, (i, byte)| {
+            group | (*byte as u32) << (16 - 8 * i)
+        });
+
+        for i in 0..4 {
+            if i <= chunk.len() {
+                text.push(ALPHABET[(group >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                text.push('=');
+            }
+        }
+    }
+
+    text
+}
+
+fn decode(text: &str) -> Result<Vec<u8>> {
+    let mut bytes = Vec::with_capacity(text.len() / 4 * 3);
+    let mut group = 0u32;
+    let mut bits = 0;
+
+    for c in text.trim_end_matches('=').bytes() {
+        let sextet = ALPHABET
+            .iter()
+            .position(|a| *a == c)
+            .ok_or(anyhow!("malformed base64 in etcd response"))?;
+        group = (group << 6 | sextet as u32) & 0xff_ffff;
+        bits += 6;
+
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((group >> bits) as u8);
+        }
+    }
+
+    Ok(bytes)
+}
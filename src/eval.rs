@@ -0,0 +1,619 @@
+use std::{
+    cell::RefCell,
+    collections::{hash_map::Entry, HashMap},
+};
+
+use anyhow::{anyhow, Result};
+use serde_yaml::{Number, Value};
+
+use crate::{extract_section, pipeline::Scope};
+
+const EVAL_TAG: &str = "eval";
+
+/// Replace every `!eval "expression"` with its result
+///
+/// Expressions are arithmetic over numbers and strings: `+ - * / %`, parentheses,
+/// numeric and quoted string literals, and references to other values by path
+/// (`base_workers * 2`, `server.port + 1`, `"http://" + host`, `hosts[0]`). Keys that
+/// aren't identifiers are quoted in brackets: `limits["max-conns"] - 1`.
+///
+/// A reference is looked up next to the expression first, then in each enclosing
+/// mapping up to the root. When a single section is loaded, the other sections of its
+/// `scope` come last, expanded and activated like the section itself. Expressions may
+/// reference each other as long as they don't form a cycle.
+pub(crate) fn evaluate(root: &mut Value, scope: Option<&Scope>) -> Result<()> {
+    let mut pending = vec![];
+    collect(root, &mut vec![], &mut pending);
+    let sections = RefCell::new(HashMap::new());
+
+    while !pending.is_empty() {
+        let mut deferred = vec![];
+
+        for (path, expression) in pending.iter() {
+            match Parser::new(expression, root, path, scope, &sections).run() {
+                Ok(result) => *at(root, path) = result,
+                Err(Error::Pending) => deferred.push((path.clone(), expression.clone())),
+                Err(Error::Invalid(msg)) => {
+                    return Err(anyhow!(
+                        "invalid !eval `{expression}` at `{}`: {msg}",
+                        display(path)
+                    ))
+                }
+            }
+        }
+
+        if deferred.len() == pending.len() {
+            let paths = deferred
+                .iter()
+                .map(|(path, _)| display(path))
+                .collect::<Vec<_>>()
+                .join(", ");
+
+            return Err(anyhow!("!eval expressions reference each other: {paths}"));
+        }
+
+        pending = deferred;
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, Clone)]
+enum Segment {
+    Key(Value),
+    Index(usize),
+}
+
+type Pending = Vec<(Vec<Segment>, String)>;
+
+fn eval_expression(value: &Value) -> Option<&str> {
+    match value {
+        Value::Tagged(tagged) if tagged.tag == EVAL_TAG => tagged.value.as_str(),
+        _ => None,
+    }
+}
+
+fn collect(value: &Value, path: &mut Vec<Segment>, pending: &mut Pending) {
+    if let Some(expression) = eval_expression(value) {
+        pending.push((path.clone(), expression.to_string()));
+        return;
+    }
+
+    match value {
+        Value::Mapping(mapping) => {
+            for (k, v) in mapping {
+                path.push(Segment::Key(k.clone()));
+                collect(v, path, pending);
+                path.pop();
+            }
+        }
+        Value::Sequence(seq) => {
+            for (i, v) in seq.iter().enumerate() {
+                path.push(Segment::Index(i));
+                collect(v, path, pending);
+                path.pop();
+            }
+        }
+        _ => {}
+    }
+}
+
+fn at<'a>(mut value: &'a mut Value, path: &[Segment]) -> &'a mut Value {
+    for segment in path {
+        value = match (segment, value) {
+            (Segment::Key(k), Value::Mapping(mapping)) => mapping.get_mut(k).unwrap(),
+            (Segment::Index(i), Value::Sequence(seq)) => &mut seq[*i],
+            _ => unreachable!("collected paths point into the tree"),
+        };
+    }
+
+    value
+}
+
+fn display(path: &[Segment]) -> String {
+    path.iter()
+        .fold(String::new(), |acc, segment| match segment {
+            Segment::Key(Value::String(k)) if acc.is_empty() => k.clone(),
+            Segment::Key(Value::String(k)) => format!("{acc}.{k}"),
+            Segment::Key(k) => format!("{acc}.{k:?}"),
+            Segment::Index(i) => format!("{acc}[{i}]"),
+        })
+}
+
+enum Error {
+    // References an expression that isn't evaluated yet
+    Pending,
+    Invalid(String),
+}
+
+impl From<String> for Error {
+    fn from(msg: String) -> Self {
+        Self::Invalid(msg)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Operand {
+    Int(i64),
+    Float(f64),
+    Str(String),
+}
+
+impl Operand {
+    fn from_value(value: &Value) -> Result<Self, Error> {
+        if eval_expression(value).is_some() {
+            return Err(Error::Pending);
+        }
+
+        match value {
+            Value::Number(n) => match n.as_i64() {
+                Some(v) => Ok(Self::Int(v)),
+                None => Ok(Self::Float(n.as_f64().unwrap_or_default())),
+            },
+            Value::String(text) => Ok(Self::Str(text.clone())),
+            other => Err(format!("{other:?} is neither a number nor a string").into()),
+        }
+    }
+
+    fn into_value(self) -> Value {
+        match self {
+            Self::Int(v) => Value::Number(v.into()),
+            Self::Float(v) => Value::Number(Number::from(v)),
+            Self::Str(v) => Value::String(v),
+        }
+    }
+
+    fn as_f64(&self) -> Option<f64> {
+        match self {
+            Self::Int(v) => Some(*v as f64),
+            Self::Float(v) => Some(*v),
+            Self::Str(_) => None,
+        }
+    }
+
+    fn apply(self, op: char, rhs: Self) -> Result<Self, Error> {
+        use Operand::*;
+
+        let result = match (self, rhs) {
+            (Str(a), Str(b)) if op == '+' => Str(a + &b),
+            (Str(a), b) if op == '+' => Str(format!("{a}{}", b.display())),
+            (a, Str(b)) if op == '+' => Str(format!("{}{b}", a.display())),
+            (Int(_), Int(0)) if op == '/' || op == '%' => {
+                return Err("division by zero".to_string().into())
+            }
+            (Int(a), Int(b)) => {
+                let result = match op {
+                    '+' => a.checked_add(b),
+                    '-' => a.checked_sub(b),
+                    '*' => a.checked_mul(b),
+                    '/' => a.checked_div(b),
+                    _ => a.checked_rem(b),
+                };
+
+                Int(result.ok_or("integer overflow".to_string())?)
+            }
+            (a, b) => match (a.as_f64(), b.as_f64()) {
+                (Some(a), Some(b)) => Float(match op {
+                    '+' => a + b,
+                    '-' => a - b,
+                    '*' => a * b,
+                    '/' => a / b,
+                    _ => a % b,
+                }),
+                _ => return Err(format!("`{op}` is not defined for strings").into()),
+            },
+        };
+
+        Ok(result)
+    }
+
+    fn display(&self) -> String {
+        match self {
+            Self::Int(v) => v.to_string(),
+            Self::Float(v) => v.to_string(),
+            Self::Str(v) => v.clone(),
+        }
+    }
+}
+
+// Recursive descent over the expression text, evaluating while parsing
+struct Parser<'a> {
+    text: &'a str,
+    pos: usize,
+    root: &'a Value,
+    path: &'a [Segment],
+    // Open parentheses and unary minuses
+    depth: usize,
+    scope: Option<&'a Scope<'a>>,
+    // Other sections of the scope resolved so far
+    sections: &'a RefCell<HashMap<String, Value>>,
+}
+
+thread_local! {
+    // Sections being resolved for the expressions of others, outermost first
+    static RESOLVING: RefCell<Vec<String>> = const { RefCell::new(vec![]) };
+}
+
+// Step of a reference: `.key`, `["key"]` or `[0]`
+#[derive(Debug, Clone, PartialEq)]
+enum Part {
+    Key(String),
+    Index(usize),
+}
+
+// More would only come from a generated or malicious config
+const MAX_DEPTH: usize = 64;
+
+impl<'a> Parser<'a> {
+    fn new(
+        text: &'a str,
+        root: &'a Value,
+        path: &'a [Segment],
+        scope: Option<&'a Scope<'a>>,
+        sections: &'a RefCell<HashMap<String, Value>>,
+    ) -> Self {
+        Self {
+            text,
+            pos: 0,
+            root,
+            path,
+            depth: 0,
+            scope,
+            sections,
+        }
+    }
+
+    fn nested(&mut self, parse: fn(&mut Self) -> Result<Operand, Error>) -> Result<Operand, Error> {
+        if self.depth >= MAX_DEPTH {
+            return Err(format!("nested deeper than {MAX_DEPTH} levels").into());
+        }
+
+        self.depth += 1;
+        let operand = parse(self);
+        self.depth -= 1;
+
+        operand
+    }
+
+    fn run(mut self) -> Result<Value, Error> {
+        let result = self.sum()?;
+
+        self.skip_whitespace();
+        if self.pos < self.text.len() {
+            return Err(format!("unexpected `{}`", &self.text[self.pos..]).into());
+        }
+
+        Ok(result.into_value())
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.text[self.pos..].chars().next()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(c) = self.peek().filter(|c| c.is_whitespace()) {
+            self.pos += c.len_utf8();
+        }
+    }
+
+    fn operator(&mut self, ops: &[char]) -> Option<char> {
+        self.skip_whitespace();
+
+        let op = self.peek().filter(|c| ops.contains(c))?;
+        self.pos += 1;
+
+        Some(op)
+    }
+
+    fn sum(&mut self) -> Result<Operand, Error> {
+        let mut acc = self.product()?;
+
+        while let Some(op) = self.operator(&['+', '-']) {
+            acc = acc.apply(op, self.product()?)?;
+        }
+
+        Ok(acc)
+    }
+
+    fn product(&mut self) -> Result<Operand, Error> {
+        let mut acc = self.unary()?;
+
+        while let Some(op) = self.operator(&['*', '/', '%']) {
+            acc = acc.apply(op, self.unary()?)?;
+        }
+
+        Ok(acc)
+    }
+
+    fn unary(&mut self) -> Result<Operand, Error> {
+        if self.operator(&['-']).is_some() {
+            return Operand::Int(0).apply('-', self.nested(Self::unary)?);
+        }
+
+        self.atom()
+    }
+
+    fn atom(&mut self) -> Result<Operand, Error> {
+        self.skip_whitespace();
+
+        let rest = &self.text[self.pos..];
+
+        match self.peek() {
+            Some('(') => {
+                self.pos += 1;
+                let inner = self.nested(Self::sum)?;
+
+                if self.operator(&[')']).is_none() {
+                    return Err("missing `)`".to_string().into());
+                }
+
+                Ok(inner)
+            }
+            Some('"') => Ok(Operand::Str(self.quoted()?)),
+            Some(c) if c.is_ascii_digit() || c == '.' => {
+                let len = rest
+                    .find(|c: char| !(c.is_ascii_digit() || c == '.' || c == '_'))
+                    .unwrap_or(rest.len());
+                let literal = rest[..len].replace('_', "");
+                self.pos += len;
+
+                match literal.parse::<i64>() {
+                    Ok(v) => Ok(Operand::Int(v)),
+                    Err(_) => literal
+                        .parse::<f64>()
+                        .map(Operand::Float)
+                        .map_err(|_| format!("invalid number `{literal}`").into()),
+                }
+            }
+            Some(c) if c.is_alphabetic() || c == '_' || c == '[' => {
+                let start = self.pos;
+                let parts = self.parts()?;
+
+                self.reference(&self.text[start..self.pos], &parts)
+            }
+            Some(c) => Err(format!("unexpected `{c}`").into()),
+            None => Err("unexpected end of expression".to_string().into()),
+        }
+    }
+
+    // `"text"` or `'text'` at the current position
+    fn quoted(&mut self) -> Result<String, Error> {
+        let rest = &self.text[self.pos..];
+        let quote = rest.chars().next().unwrap_or('"');
+        let len = rest[1..]
+            .find(quote)
+            .ok_or("unterminated string".to_string())?;
+        self.pos += len + 2;
+
+        Ok(rest[1..len + 1].to_string())
+    }
+
+    fn identifier(&mut self) -> &'a str {
+        let rest = &self.text[self.pos..];
+        let len = rest
+            .find(|c: char| !(c.is_alphanumeric() || c == '_'))
+            .unwrap_or(rest.len());
+        self.pos += len;
+
+        &rest[..len]
+    }
+
+    // `server.port`, `hosts[0]`, `limits["max-conns"]`
+    fn parts(&mut self) -> Result<Vec<Part>, Error> {
+        let mut parts = vec![];
+        if self.peek() != Some('[') {
+            parts.push(Part::Key(self.identifier().to_string()));
+        }
+
+        loop {
+            match self.peek() {
+                Some('.') => {
+                    self.pos += 1;
+                    match self.identifier() {
+                        "" => return Err("expected a key after `.`".to_string().into()),
+                        key => parts.push(Part::Key(key.to_string())),
+                    }
+                }
+                Some('[') => {
+                    self.pos += 1;
+                    let part = match self.peek() {
+                        Some('"' | '\'') => Part::Key(self.quoted()?),
+                        _ => {
+                            let index = self.identifier();
+                            Part::Index(index.parse().map_err(|_| {
+                                format!("expected an index or a quoted key, not `{index}`")
+                            })?)
+                        }
+                    };
+
+                    if self.peek() != Some(']') {
+                        return Err("missing `]`".to_string().into());
+                    }
+                    self.pos += 1;
+                    parts.push(part);
+                }
+                _ => return Ok(parts),
+            }
+        }
+    }
+
+    // Innermost enclosing mapping wins, then the other sections of the scope
+    fn reference(&self, name: &str, parts: &[Part]) -> Result<Operand, Error> {
+        for depth in (0..self.path.len()).rev() {
+            let mut scope = self.root;
+
+            for segment in &self.path[..depth] {
+                scope = match (segment, scope) {
+                    (Segment::Key(k), Value::Mapping(mapping)) => &mapping[k],
+                    (Segment::Index(i), Value::Sequence(seq)) => &seq[*i],
+                    _ => unreachable!("collected paths point into the tree"),
+                };
+            }
+
+            if scope.is_mapping() {
+                if let Some(value) = lookup(scope, parts) {
+                    return Operand::from_value(value);
+                }
+            }
+        }
+
+        if let (Some(scope), Some(Part::Key(section))) = (self.scope, parts.first()) {
+            if self.root.get(section).is_none() && scope.document.get(section).is_some() {
+                let value = self.section(scope, section)?;
+                if let Some(value) = lookup(&value, parts) {
+                    return Operand::from_value(value);
+                }
+            }
+        }
+
+        Err(format!("unknown value `{name}`").into())
+    }
+
+    // Another top-level section, expanded and activated on first use
+    fn section(&self, scope: &Scope, section: &str) -> Result<Value, Error> {
+        if let Some(value) = self.sections.borrow().get(section) {
+            return Ok(value.clone());
+        }
+
+        let cycle = RESOLVING.with_borrow_mut(|resolving| {
+            let cycle = resolving.iter().any(|resolved| resolved == section);
+            resolving.push(section.to_string());
+
+            cycle.then(|| resolving.join(" -> "))
+        });
+        let value = match cycle {
+            Some(cycle) => Err(format!("sections reference each other: {cycle}").into()),
+            None => {
+                let mut value = extract_section(scope.document, section);
+
+                let activated = crate::expand(scope.source, scope.origin, &mut value)
+                    .map_err(|e| format!("{e:#}"))
+                    .and_then(|_| {
+                        crate::pipeline::activate_within(&mut value, Some(scope))
+                            .map_err(|e| e.to_string())
+                    });
+
+                activated.map(|_| value).map_err(Error::Invalid)
+            }
+        };
+        RESOLVING.with_borrow_mut(|resolving| resolving.pop());
+
+        let value = value?;
+        if let Entry::Vacant(entry) = self.sections.borrow_mut().entry(section.to_string()) {
+            entry.insert(value.clone());
+        }
+
+        Ok(value)
+    }
+}
+
+fn lookup<'v>(mut value: &'v Value, parts: &[Part]) -> Option<&'v Value> {
+    for part in parts {
+        value = match part {
+            Part::Key(key) => value.as_mapping()?.get(key.as_str())?,
+            Part::Index(index) => value.as_sequence()?.get(*index)?,
+        };
+    }
+
+    Some(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval(yaml: &str, path: &str) -> Result<Value> {
+        let mut root = serde_yaml::from_str(yaml)?;
+        evaluate(&mut root, None)?;
+
+        Ok(crate::document::lookup(&root, path).cloned().unwrap())
+    }
+
+    fn expression(text: &str) -> Result<Value> {
+        eval(&format!("base: 4\nvalue: !eval {text:?}"), "value")
+    }
+
+    #[test]
+    fn precedence() {
+        assert_eq!(expression("2 + 3 * 4").unwrap(), Value::from(14));
+        assert_eq!(expression("(2 + 3) * 4").unwrap(), Value::from(20));
+        assert_eq!(expression("10 - 4 - 3").unwrap(), Value::from(3));
+        assert_eq!(expression("7 % 4 * 2").unwrap(), Value::from(6));
+        assert_eq!(expression("1 + 2.5").unwrap(), Value::from(3.5));
+        assert_eq!(expression("\"v\" + 1 + 2").unwrap(), Value::from("v12"));
+    }
+
+    #[test]
+    fn unary_minus() {
+        assert_eq!(expression("-2 * 3").unwrap(), Value::from(-6));
+        assert_eq!(expression("--1").unwrap(), Value::from(1));
+        assert_eq!(expression("base-1").unwrap(), Value::from(3));
+        assert_eq!(expression("base--1").unwrap(), Value::from(5));
+        assert_eq!(expression("-base").unwrap(), Value::from(-4));
+    }
+
+    #[test]
+    fn overflow() {
+        let error = expression("9223372036854775807 + 1").unwrap_err();
+        assert!(error.to_string().contains("integer overflow"), "{error}");
+
+        let error = expression("1 / 0").unwrap_err();
+        assert!(error.to_string().contains("division by zero"), "{error}");
+
+        let error = expression(&"-".repeat(MAX_DEPTH + 1)).unwrap_err();
+        assert!(error.to_string().contains("nested deeper"), "{error}");
+    }
+
+    #[test]
+    fn cycles() {
+        let yaml = "a: !eval b + 1\nb: !eval c + 1\nc: 1";
+        assert_eq!(eval(yaml, "a").unwrap(), Value::from(3));
+
+        let error = eval("a: !eval b\nb: !eval a", "a").unwrap_err();
+        assert!(
+            error.to_string().contains("reference each other"),
+            "{error}"
+        );
+    }
+
+    #[test]
+    fn paths() {
+        let yaml = r#"
+limits:
+  max-conns: 10
+  hosts: [a, b]
+server:
+  conns: !eval limits["max-conns"] - 1
+  host: !eval limits.hosts[1] + ":80"
+  scoped: !eval "['max-conns']"
+  max-conns: 3
+"#;
+        assert_eq!(eval(yaml, "server.conns").unwrap(), Value::from(9));
+        assert_eq!(eval(yaml, "server.host").unwrap(), Value::from("b:80"));
+        assert_eq!(eval(yaml, "server.scoped").unwrap(), Value::from(3));
+
+        let error = eval("a: 1\nb: !eval a-c", "b").unwrap_err();
+        assert!(error.to_string().contains("unknown value `c`"), "{error}");
+    }
+
+    #[test]
+    fn other_sections() {
+        let document = serde_yaml::from_str(
+            "base: 4\nlimits: {conns: !eval base * 10}\nserver: {w: !eval base * 2, limit: !eval limits.conns}",
+        )
+        .unwrap();
+        let loaded: Value =
+            crate::pipeline::load_section("test", None, &document, "server").unwrap();
+        assert_eq!(loaded["server"]["w"], Value::from(8));
+        assert_eq!(loaded["server"]["limit"], Value::from(40));
+        assert!(loaded.get("limits").is_none());
+
+        let document = serde_yaml::from_str("a: {x: !eval b.x}\nb: {x: !eval a.x}").unwrap();
+        let error =
+            crate::pipeline::load_section::<Value>("test", None, &document, "a").unwrap_err();
+        assert!(
+            error.to_string().contains("sections reference each other"),
+            "{error}"
+        );
+    }
+}
@@ -0,0 +1,161 @@
+use tracing::Subscriber;
+use tracing_subscriber::registry::LookupSpan;
+
+use crate::{sink::BoxedLayer, LoggerError};
+
+/// Layer reporting events to the Windows Event Log, under the executable's name
+#[cfg(windows)]
+pub(crate) fn layer<S>() -> Result<BoxedLayer<S>, LoggerError>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    use tracing_subscriber::Layer;
+
+    let log = windows::EventLog::register(&crate::preset::app_name())?;
+
+    // The Event Log keeps its own time
+    Ok(tracing_subscriber::fmt::layer()
+        .with_ansi(false)
+        .without_time()
+        .with_writer(log)
+        .boxed())
+}
+
+#[cfg(not(windows))]
+pub(crate) fn layer<S>() -> Result<BoxedLayer<S>, LoggerError>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    Err(LoggerError::Params(
+        "the `event_log` sink is only available on Windows".to_string(),
+    ))
+}
+
+#[cfg(windows)]
+mod windows {
+    use std::{
+        ffi::c_void,
+        io::{self, Write},
+        iter, ptr,
+    };
+
+    use tracing::{Level, Metadata};
+    use tracing_subscriber::fmt::MakeWriter;
+
+    const EVENTLOG_ERROR_TYPE: u16 = 0x0001;
+    const EVENTLOG_WARNING_TYPE: u16 = 0x0002;
+    const EVENTLOG_INFORMATION_TYPE: u16 = 0x0004;
+
+    #[link(name = "advapi32")]
+    extern "system" {
+        fn RegisterEventSourceW(server: *const u16, source: *const u16) -> *mut c_void;
+        fn DeregisterEventSource(log: *mut c_void) -> i32;
+        fn ReportEventW(
+            log: *mut c_void,
+            kind: u16,
+            category: u16,
+            event_id: u32,
+            user_sid: *mut c_void,
+            strings: u16,
+            data_size: u32,
+            string_ptrs: *const *const u16,
+            data: *mut c_void,
+        ) -> i32;
+    }
+
+    // Event source handle, which the Event Log API lets any thread use
+    pub(super) struct EventLog(usize);
+
+    impl EventLog {
+        pub(super) fn register(source: &str) -> io::Result<Self> {
+            let source = wide(source);
+            // SAFETY: `source` is NUL terminated and outlives the call
+            let handle = unsafe { RegisterEventSourceW(ptr::null(), source.as_ptr()) };
+
+            if handle.is_null() {
+                return Err(io::Error::last_os_error());
+            }
+
+            Ok(Self(handle as usize))
+        }
+    }
+
+    impl Drop for EventLog {
+        fn drop(&mut self) {
+            // SAFETY: the handle was registered and isn't used after this
+            unsafe { DeregisterEventSource(self.0 as *mut c_void) };
+        }
+    }
+
+    impl<'a> MakeWriter<'a> for EventLog {
+        type Writer = Entry<'a>;
+
+        fn make_writer(&'a self) -> Entry<'a> {
+            Entry {
+                log: self,
+                kind: EVENTLOG_INFORMATION_TYPE,
+                text: vec![],
+            }
+        }
+
+        fn make_writer_for(&'a self, meta: &Metadata<'_>) -> Entry<'a> {
+            let kind = match *meta.level() {
+                Level::ERROR => EVENTLOG_ERROR_TYPE,
+                Level::WARN => EVENTLOG_WARNING_TYPE,
+                _ => EVENTLOG_INFORMATION_TYPE,
+            };
+
+            Entry {
+                kind,
+                ..self.make_writer()
+            }
+        }
+    }
+
+    // One event, reported once it's fully written
+    pub(super) struct Entry<'a> {
+        log: &'a EventLog,
+        kind: u16,
+        text: Vec<u8>,
+    }
+
+    impl Write for Entry<'_> {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.text.extend_from_slice(buf);
+
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Drop for Entry<'_> {
+        fn drop(&mut self) {
+            let text = String::from_utf8_lossy(&self.text);
+            let text = wide(text.trim_end());
+            let strings = [text.as_ptr()];
+
+            // SAFETY: the handle is registered until the `EventLog` drops, after its
+            // entries, and `strings` holds one NUL terminated string
+            unsafe {
+                ReportEventW(
+                    self.log.0 as *mut c_void,
+                    self.kind,
+                    0,
+                    0,
+                    ptr::null_mut(),
+                    1,
+                    0,
+                    strings.as_ptr(),
+                    ptr::null_mut(),
+                );
+            }
+        }
+    }
+
+    fn wide(text: &str) -> Vec<u16> {
+        text.encode_utf16().chain(iter::once(0)).collect()
+    }
+}
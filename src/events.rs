@@ -0,0 +1,130 @@
+use std::{
+    sync::{
+        mpsc::{self, Receiver, Sender},
+        LazyLock, Mutex,
+    },
+    thread,
+    time::Duration,
+};
+
+use crate::{health, Drift};
+
+// How often log sinks are checked for dropped lines while anyone subscribes
+const SINK_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+static SUBSCRIBERS: LazyLock<Mutex<Subscribers>> = LazyLock::new(Default::default);
+
+#[derive(Default)]
+struct Subscribers {
+    senders: Vec<Sender<Event>>,
+    // Whether the sink checking thread runs, it ends once no subscriber is left when an
+    // event is published
+    checking_sinks: bool,
+}
+
+/// Config lifecycle event, see [`events`]
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub enum Event {
+    /// A `#[configurable]` struct was initialized or the logger installed, `source` is
+    /// the struct name or `logger`
+    Loaded { source: String },
+    /// `source` was reloaded or replaced, `diff` goes from the previous version to the
+    /// new one: of the watched file as written for a `ConfigWatcher`, of the applied
+    /// params for the logger
+    ReloadSucceeded { source: String, diff: Drift },
+    /// Reloading `source` failed, the previous config stays in effect
+    ReloadFailed { source: String, error: String },
+    /// The log file `name` lost lines since the last check because its writer fell behind,
+    /// `dropped_lines` is the total so far
+    SinkDegraded { name: String, dropped_lines: usize },
+}
+
+/// Every config lifecycle event from now on, in the order they happened
+///
+/// Each call subscribes another receiver, all of them get every event. Events are sent
+/// from the thread they happen on and never block it, a receiver that's not read just
+/// buffers them. Dropping the receiver unsubscribes.
+///
+/// ```no_run
+/// for event in unconfig::events() {
+///     if let unconfig::Event::ReloadFailed { source, error } = event {
+///         eprintln!("{source}: {error}");
+///     }
+/// }
+/// ```
+pub fn events() -> Receiver<Event> {
+    let (sender, receiver) = mpsc::channel();
+    let mut subscribers = SUBSCRIBERS.lock().unwrap();
+    subscribers.senders.push(sender);
+
+    if !subscribers.checking_sinks {
+        subscribers.checking_sinks = true;
+        check_sinks();
+    }
+
+    receiver
+}
+
+// Send `event` to every subscriber, forgetting those that are gone
+pub(crate) fn publish(event: Event) {
+    SUBSCRIBERS
+        .lock()
+        .unwrap()
+        .senders
+        .retain(|sender| sender.send(event.clone()).is_ok());
+}
+
+/// [`Event::Loaded`] of a `#[configurable]` struct, sent by its generated `init()`
+#[doc(hidden)]
+pub fn publish_loaded(source: &str) {
+    publish(Event::Loaded {
+        source: source.to_string(),
+    });
+}
+
+// Dropped line counters only grow, a sink degrades whenever its counter moved
+fn check_sinks() {
+    let mut dropped = drops();
+
+    thread::Builder::new()
+        .name("config-events".to_string())
+        .spawn(move || loop {
+            thread::sleep(SINK_CHECK_INTERVAL);
+
+            let current = drops();
+
+            for (name, dropped_lines) in &current {
+                let before = dropped
+                    .iter()
+                    .find(|(before, _)| before == name)
+                    .map_or(0, |(_, dropped_lines)| *dropped_lines);
+
+                if *dropped_lines > before {
+                    publish(Event::SinkDegraded {
+                        name: name.clone(),
+                        dropped_lines: *dropped_lines,
+                    });
+                }
+            }
+
+            dropped = current;
+
+            let mut subscribers = SUBSCRIBERS.lock().unwrap();
+
+            if subscribers.senders.is_empty() {
+                subscribers.checking_sinks = false;
+
+                return;
+            }
+        })
+        .expect("failed to spawn the config events thread");
+}
+
+fn drops() -> Vec<(String, usize)> {
+    health::health()
+        .sinks
+        .into_iter()
+        .map(|sink| (sink.name, sink.dropped_lines))
+        .collect()
+}
@@ -0,0 +1,331 @@
+use std::{
+    env,
+    io::Read,
+    process::{Command, Stdio},
+    sync::OnceLock,
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, bail, Result};
+use tracing::debug;
+
+use crate::{closing_brace, env_overlay, overlay::glob_match};
+
+static EXEC_POLICY: OnceLock<ExecPolicy> = OnceLock::new();
+
+/// Which programs `${exec:command}` values may run
+///
+/// Patterns are globs (`*`, `?`) matched against the program as written in the value,
+/// e.g. `vault` or `/usr/local/bin/op`. None are allowed by default, and a value running
+/// any other program fails the load.
+///
+/// Unless [`set_exec_policy`] is called, the patterns are read from `CONFIG_EXEC_ALLOW`
+/// (comma separated) and the timeout, in seconds, from `CONFIG_EXEC_TIMEOUT`.
+#[derive(Debug, Clone)]
+pub struct ExecPolicy {
+    allow: Vec<String>,
+    timeout: Duration,
+}
+
+impl Default for ExecPolicy {
+    fn default() -> Self {
+        Self {
+            allow: vec![],
+            timeout: Duration::from_secs(30),
+        }
+    }
+}
+
+impl ExecPolicy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn allow(mut self, pattern: impl Into<String>) -> Self {
+        self.allow.push(pattern.into());
+        self
+    }
+
+    /// Time a command may run before it is killed and the load fails
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn permits(&self, program: &str) -> bool {
+        self.allow
+            .iter()
+            .any(|pattern| glob_match(pattern, program))
+    }
+
+    fn from_env() -> Self {
+        let mut policy = Self::default();
+
+        if let Ok(allow) = env::var("CONFIG_EXEC_ALLOW") {
+            policy.allow = allow
+                .split(',')
+                .map(str::trim)
+                .filter(|p| !p.is_empty())
+                .map(String::from)
+                .collect();
+        }
+        if let Some(timeout) = env::var("CONFIG_EXEC_TIMEOUT")
+            .ok()
+            .and_then(|timeout| timeout.parse().ok())
+        {
+            policy.timeout = Duration::from_secs(timeout);
+        }
+
+        policy
+    }
+}
+
+/// Install the policy for every following load
+///
+/// Has to run before the first config is loaded, afterwards the policy is fixed and the
+/// rejected one is handed back.
+pub fn set_exec_policy(policy: ExecPolicy) -> Result<(), ExecPolicy> {
+    EXEC_POLICY.set(policy)
+}
+
+fn exec_policy() -> &'static ExecPolicy {
+    EXEC_POLICY.get_or_init(ExecPolicy::from_env)
+}
+
+/// Standard output of the command `words`, without its trailing newline
+///
+/// The words come from [`split`], with their `${...}` references substituted one word at
+/// a time, and run without a shell, so pipes, redirections and `$VAR`s are passed on as
+/// they are. It sees the environment the loader does.
+pub(crate) fn run(words: &[String]) -> Result<String> {
+    run_with(exec_policy(), words)
+}
+
+fn run_with(policy: &ExecPolicy, words: &[String]) -> Result<String> {
+    let Some((program, args)) = words.split_first() else {
+        bail!("empty command");
+    };
+
+    if !policy.permits(program) {
+        bail!("`{program}` is not allowed by the exec policy, see `CONFIG_EXEC_ALLOW`");
+    }
+
+    debug!("Running `{program}` for a config value");
+    let mut child = Command::new(program)
+        .args(args)
+        .env_clear()
+        .envs(env_overlay::vars())
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| anyhow!("`{program}`: {e}"))?;
+
+    // Drained while waiting, so a chatty command can't block on a full pipe
+    let stdout = child.stdout.take().map(drain);
+    let stderr = child.stderr.take().map(drain);
+
+    let deadline = Instant::now() + policy.timeout;
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if Instant::now() >= deadline {
+            let _ = child.kill();
+            let _ = child.wait();
+            bail!("`{program}` timed out after {:?}", policy.timeout);
+        }
+
+        thread::sleep(Duration::from_millis(10));
+    };
+
+    let output = |reader: Option<thread::JoinHandle<Vec<u8>>>| {
+        reader
+            .and_then(|reader| reader.join().ok())
+            .unwrap_or_default()
+    };
+    let (stdout, stderr) = (output(stdout), output(stderr));
+
+    if !status.success() {
+        let stderr = String::from_utf8_lossy(&stderr);
+
+        match stderr.trim() {
+            "" => bail!("`{program}` failed with {status}"),
+            stderr => bail!("`{program}` failed with {status}: {stderr}"),
+        }
+    }
+
+    let stdout = String::from_utf8(stdout).map_err(|_| anyhow!("`{program}`: invalid UTF-8"))?;
+    let stdout = stdout.strip_suffix('\n').unwrap_or(&stdout);
+
+    Ok(stdout.strip_suffix('\r').unwrap_or(stdout).to_string())
+}
+
+fn drain(mut reader: impl Read + Send + 'static) -> thread::JoinHandle<Vec<u8>> {
+    thread::spawn(move || {
+        let mut buf = vec![];
+        let _ = reader.read_to_end(&mut buf);
+
+        buf
+    })
+}
+
+// Words of `command`: separated by whitespace, single quotes taken as they are, double
+// quotes and bare words with `\` escaping the next character. `${...}` references are
+// kept whole within their word, spaces included, to be substituted into it afterwards,
+// so that a substituted value can't add arguments
+pub(crate) fn split(command: &str) -> Result<Vec<String>> {
+    let mut words = vec![];
+    let mut word = None::<String>;
+    let mut quote = None;
+    let mut rest = command;
+
+    while let Some(c) = rest.chars().next() {
+        rest = &rest[c.len_utf8()..];
+
+        match (quote, c) {
+            (None | Some('"'), '$') if rest.starts_with('{') => {
+                let word = word.get_or_insert_with(String::new);
+                word.push('$');
+
+                if let Some(end) = closing_brace(&rest[1..]) {
+                    word.push_str(&rest[..end + 2]);
+                    rest = &rest[end + 2..];
+                }
+            }
+            (None, c) if c.is_whitespace() => words.extend(word.take()),
+            (None, '\'' | '"') => {
+                word.get_or_insert_with(String::new);
+                quote = Some(c);
+            }
+            (Some(open), c) if c == open => quote = None,
+            (None | Some('"'), '\\') => {
+                let word = word.get_or_insert_with(String::new);
+                // An escaped reference stays escaped for the substitution
+                if rest.starts_with("${") {
+                    word.push('\\');
+                }
+                if let Some(c) = rest.chars().next() {
+                    word.push(c);
+                    rest = &rest[c.len_utf8()..];
+                }
+            }
+            (_, c) => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+
+    if quote.is_some() {
+        bail!("unterminated quote in `{command}`");
+    }
+    words.extend(word);
+
+    Ok(words)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn words(command: &str) -> Vec<String> {
+        split(command).unwrap()
+    }
+
+    #[test]
+    fn splits() {
+        assert_eq!(
+            words("  vault  kv get\tsecret "),
+            ["vault", "kv", "get", "secret"]
+        );
+        assert_eq!(
+            words(r#"echo 'a "b"' "c \"d\" 'e'" f\ g"#),
+            ["echo", "a \"b\"", "c \"d\" 'e'", "f g"]
+        );
+        assert_eq!(words(r#"echo a'b'"c" '' """#), ["echo", "abc", "", ""]);
+        assert_eq!(
+            words(r"echo 'C:\dir' C:\\dir"),
+            ["echo", r"C:\dir", r"C:\dir"]
+        );
+        assert!(words("").is_empty());
+    }
+
+    #[test]
+    fn splits_references() {
+        assert_eq!(
+            words("op read ${VAULT_PATH:secret/app db} --field=${FIELD}"),
+            [
+                "op",
+                "read",
+                "${VAULT_PATH:secret/app db}",
+                "--field=${FIELD}"
+            ]
+        );
+        assert_eq!(words("get ${A:${B:x y}}"), ["get", "${A:${B:x y}}"]);
+        assert_eq!(
+            words(r#"get "pre ${A:b c} post""#),
+            ["get", "pre ${A:b c} post"]
+        );
+        assert_eq!(words(r"get \${A} \$B"), ["get", r"\${A}", "$B"]);
+        // Not a reference without its closing brace
+        assert_eq!(words("get ${A b"), ["get", "${A", "b"]);
+    }
+
+    #[test]
+    fn rejects_unterminated_quotes() {
+        for command in ["echo 'a", r#"echo "a"#, r#"echo "a\""#] {
+            let e = split(command).unwrap_err();
+            assert!(e.to_string().contains("unterminated quote"), "{e}");
+        }
+    }
+
+    #[test]
+    fn runs() {
+        let policy = ExecPolicy::new().allow("echo").allow("s?");
+
+        assert_eq!(
+            run_with(&policy, &words("echo 'a  b' c")).unwrap(),
+            "a  b c"
+        );
+        assert_eq!(
+            run_with(&policy, &words("sh -c 'printf \"x\\r\\n\"'")).unwrap(),
+            "x"
+        );
+
+        let e = run_with(&policy, &words("sh -c 'echo oops >&2; exit 3'")).unwrap_err();
+        assert!(e.to_string().contains("exit status: 3: oops"), "{e}");
+
+        assert!(run_with(&policy, &[]).is_err());
+    }
+
+    #[test]
+    fn rejects_disallowed() {
+        let policy = ExecPolicy::new().allow("/usr/bin/*");
+
+        assert!(run_with(&policy, &words("/usr/bin/env true")).is_ok());
+        for command in ["env true", "/usr/local/bin/env true", "./usr/bin/env"] {
+            let e = run_with(&policy, &words(command)).unwrap_err();
+            assert!(
+                e.to_string().contains("is not allowed by the exec policy"),
+                "{e}"
+            );
+        }
+
+        let e = run_with(&ExecPolicy::default(), &words("echo hi")).unwrap_err();
+        assert!(e.to_string().contains("`echo` is not allowed"), "{e}");
+    }
+
+    #[test]
+    fn times_out() {
+        let policy = ExecPolicy::new()
+            .allow("sleep")
+            .timeout(Duration::from_millis(100));
+
+        let started = Instant::now();
+        let e = run_with(&policy, &words("sleep 10")).unwrap_err();
+        assert!(
+            e.to_string().contains("`sleep` timed out after 100ms"),
+            "{e}"
+        );
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+}
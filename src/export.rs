@@ -0,0 +1,137 @@
+use serde::Serialize;
+use serde_yaml::Value;
+
+use crate::{secret, UnconfigError};
+
+/// Which keys [`Config::export_env_with`](crate::Config::export_env_with) exports
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ExportOptions {
+    /// Dotted paths of the keys exported with everything under them, e.g. `database` or
+    /// `server.port`. Every key when empty
+    pub keys: Vec<String>,
+    /// Dotted paths of the `Secret` values exported as they are, the other secrets are
+    /// left out
+    pub secrets: Vec<String>,
+}
+
+/// `config` as `PREFIX__KEY__NESTED_KEY` variables, sorted by name
+pub(crate) fn vars<T: Serialize>(
+    config: &T,
+    prefix: &str,
+    options: &ExportOptions,
+) -> Result<Vec<(String, String)>, UnconfigError> {
+    let exposed = secret::exposing(|| serde_yaml::to_value(config))
+        .map_err(|e| UnconfigError::Validation(e.to_string()))?;
+    // Where the two differ are the secrets
+    let masked = secret::masking(|| serde_yaml::to_value(config))
+        .map_err(|e| UnconfigError::Validation(e.to_string()))?;
+
+    let mut vars = vec![];
+    flatten(&mut vec![], &exposed, Some(&masked), options, &mut vars);
+
+    let mut vars = vars
+        .into_iter()
+        .map(|(path, value)| {
+            let name = [prefix.to_string()]
+                .into_iter()
+                .chain(path)
+                .collect::<Vec<_>>()
+                .join("__")
+                .to_uppercase();
+
+            (name, value)
+        })
+        .collect::<Vec<_>>();
+    vars.sort();
+
+    Ok(vars)
+}
+
+// Leaves of `value` at `path` with their paths. `masked` is the same value as serialized
+// normally, `None` under an exported secret
+fn flatten(
+    path: &mut Vec<String>,
+    value: &Value,
+    masked: Option<&Value>,
+    options: &ExportOptions,
+    vars: &mut Vec<(Vec<String>, String)>,
+) {
+    let masked = match masked {
+        Some(masked) if masked != value && masked.is_string() => {
+            if !selected(path, &options.secrets) {
+                return;
+            }
+
+            None
+        }
+        masked => masked,
+    };
+
+    let mut nested = |path: &mut Vec<String>, key: String, value, masked| {
+        path.push(key);
+        flatten(path, value, masked, options, vars);
+        path.pop();
+    };
+
+    match value {
+        Value::Mapping(mapping) => {
+            for (key, value) in mapping {
+                let masked = masked.map(|masked| masked.get(key).unwrap_or(&Value::Null));
+                let Some(key) = scalar(key) else {
+                    continue;
+                };
+
+                nested(path, key, value, masked);
+            }
+        }
+        Value::Sequence(sequence) => {
+            for (index, value) in sequence.iter().enumerate() {
+                let masked = masked.map(|masked| masked.get(index).unwrap_or(&Value::Null));
+
+                nested(path, index.to_string(), value, masked);
+            }
+        }
+        // An enum variant with data, one level named after the variant
+        Value::Tagged(tagged) => {
+            let masked = masked.map(|masked| match masked {
+                Value::Tagged(masked) => &masked.value,
+                masked => masked,
+            });
+            let variant = tagged.tag.to_string();
+            let variant = variant.trim_start_matches('!').to_string();
+
+            nested(path, variant, &tagged.value, masked);
+        }
+        value => {
+            let Some(value) = scalar(value) else {
+                return;
+            };
+
+            if options.keys.is_empty() || selected(path, &options.keys) {
+                vars.push((path.clone(), value));
+            }
+        }
+    }
+}
+
+// Whether `path` is one of `paths` or under one of them
+fn selected(path: &[String], paths: &[String]) -> bool {
+    paths.iter().any(|selected| {
+        let selected = selected.split('.').collect::<Vec<_>>();
+
+        path.len() >= selected.len()
+            && path
+                .iter()
+                .zip(&selected)
+                .all(|(key, selected)| key == selected)
+    })
+}
+
+fn scalar(value: &Value) -> Option<String> {
+    match value {
+        Value::String(string) => Some(string.clone()),
+        Value::Bool(bool) => Some(bool.to_string()),
+        Value::Number(number) => Some(number.to_string()),
+        _ => None,
+    }
+}
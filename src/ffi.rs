@@ -0,0 +1,134 @@
+//! C API over the configs the application exposes, for C and C++ code linked into the
+//! same binary
+//!
+//! Only built with the `ffi` feature. Rust code decides what's visible:
+//!
+//! ```no_run
+//! # #[derive(serde::Serialize)] struct User { name: String }
+//! # let user = User { name: String::new() };
+//! unconfig::ffi::expose("user", &user);
+//! ```
+//!
+//! C code then reads it with `unconfig_get_str("user.name")`, declared with the rest of
+//! the API in `include/unconfig.h`. Values are read from a copy taken when they're
+//! exposed, so `Secret` fields read as `***`.
+
+use std::{
+    ffi::{c_char, c_void, CStr, CString},
+    ptr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        LazyLock, Mutex, RwLock,
+    },
+};
+
+use indexmap::IndexMap;
+use serde::Serialize;
+use serde_yaml::Value;
+use tracing::warn;
+
+use crate::{document::lookup, ConfigWatcher};
+
+/// Called with the new generation and the `user_data` it was registered with
+pub type ReloadCallback = extern "C" fn(generation: u64, user_data: *mut c_void);
+
+static SECTIONS: LazyLock<RwLock<IndexMap<String, Value>>> = LazyLock::new(Default::default);
+static GENERATION: AtomicU64 = AtomicU64::new(0);
+// `user_data` as an address, only ever handed back to C
+static CALLBACKS: Mutex<Vec<(ReloadCallback, usize)>> = Mutex::new(vec![]);
+
+/// Make `config` readable from C under `section`, replacing what was there
+///
+/// Bumps the generation and calls the registered reload callbacks.
+pub fn expose<T: Serialize>(section: &str, config: &T) {
+    let value = match serde_yaml::to_value(config) {
+        Ok(value) => value,
+        Err(e) => {
+            warn!("Failed to expose {section} over FFI: {e}");
+            return;
+        }
+    };
+
+    SECTIONS.write().unwrap().insert(section.to_string(), value);
+    let generation = GENERATION.fetch_add(1, Ordering::SeqCst) + 1;
+
+    // Not under the lock, a callback may register another
+    let callbacks = CALLBACKS.lock().unwrap().clone();
+
+    for (callback, user_data) in callbacks {
+        callback(generation, user_data as *mut c_void);
+    }
+}
+
+/// [`expose`] the current value of `watcher`, and every value it reloads
+pub fn expose_watched<T: Serialize + Send + Sync + 'static>(
+    section: &str,
+    watcher: &ConfigWatcher<T>,
+) {
+    expose(section, &*watcher.load());
+
+    let section = section.to_string();
+    watcher.on_change(move |_, new| expose(&section, new));
+}
+
+/// Value at a dotted path such as `user.name` or `server.hosts[0]`, as a string
+///
+/// Null when nothing is exposed at `path` or the value isn't a scalar. The string is
+/// owned by the caller and freed with [`unconfig_free_str`].
+///
+/// # Safety
+///
+/// `path` must be a valid nul-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn unconfig_get_str(path: *const c_char) -> *mut c_char {
+    if path.is_null() {
+        return ptr::null_mut();
+    }
+
+    // SAFETY: guaranteed by the caller
+    let Ok(path) = unsafe { CStr::from_ptr(path) }.to_str() else {
+        return ptr::null_mut();
+    };
+    let (section, rest) = path.split_once('.').unwrap_or((path, ""));
+
+    let sections = SECTIONS.read().unwrap();
+    let text = match sections.get(section).and_then(|value| lookup(value, rest)) {
+        Some(Value::String(text)) => text.clone(),
+        Some(Value::Number(number)) => number.to_string(),
+        Some(Value::Bool(flag)) => flag.to_string(),
+        _ => return ptr::null_mut(),
+    };
+
+    CString::new(text).map_or(ptr::null_mut(), CString::into_raw)
+}
+
+/// Free a string returned by [`unconfig_get_str`], null is ignored
+///
+/// # Safety
+///
+/// `text` must come from [`unconfig_get_str`] and not be freed already.
+#[no_mangle]
+pub unsafe extern "C" fn unconfig_free_str(text: *mut c_char) {
+    if !text.is_null() {
+        // SAFETY: allocated by `CString::into_raw`, guaranteed by the caller
+        drop(unsafe { CString::from_raw(text) });
+    }
+}
+
+/// Number of times a config was exposed, changes whenever a value may have
+#[no_mangle]
+pub extern "C" fn unconfig_generation() -> u64 {
+    GENERATION.load(Ordering::SeqCst)
+}
+
+/// Call `callback` after every change of the exposed configs
+///
+/// Callbacks run on the thread that exposed the change, the watcher's own one for
+/// [`expose_watched`] configs. `user_data` is passed back as is.
+#[no_mangle]
+pub extern "C" fn unconfig_on_reload(callback: ReloadCallback, user_data: *mut c_void) {
+    CALLBACKS
+        .lock()
+        .unwrap()
+        .push((callback, user_data as usize));
+}
@@ -0,0 +1,320 @@
+use std::{
+    cell::Cell,
+    fmt,
+    path::Path,
+    sync::{Arc, RwLock},
+};
+
+use serde_yaml::Value;
+
+use crate::{limits, render, RenderOptions, UnconfigError};
+
+static REGISTRY: FormatRegistry = FormatRegistry {
+    formats: RwLock::new(vec![]),
+};
+
+thread_local! {
+    // Format of every source loaded on this thread, set by `format = "..."`
+    static FORCED: Cell<Option<Format>> = const { Cell::new(None) };
+}
+
+type Parser = dyn Fn(&str) -> anyhow::Result<Value> + Send + Sync;
+
+/// Config formats of the embedder, parsed by their own parsers into the same tree as
+/// YAML
+///
+/// ```no_run
+/// # use unconfig::{CustomFormat, FormatRegistry};
+/// # fn parse_myconf(text: &str) -> anyhow::Result<serde_yaml::Value> { todo!() }
+/// FormatRegistry::global().register(
+///     CustomFormat::new("myconf", parse_myconf)
+///         .extension("conf")
+///         .magic(b"%myconf"),
+/// );
+/// ```
+///
+/// Files with a registered extension, or starting with registered magic bytes, are read
+/// with that format by `load_path` and the other loaders, before the built-in formats.
+/// Embedded text is told by its magic bytes, and `#[configurable(..., format =
+/// "custom:myconf")]` reads every file of a struct with the format. Register formats
+/// before the first config is loaded.
+pub struct FormatRegistry {
+    formats: RwLock<Vec<Arc<CustomFormat>>>,
+}
+
+/// A format of a [`FormatRegistry`]
+pub struct CustomFormat {
+    name: &'static str,
+    extensions: Vec<String>,
+    magic: Vec<Vec<u8>>,
+    parse: Box<Parser>,
+}
+
+impl fmt::Debug for CustomFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CustomFormat")
+            .field("name", &self.name)
+            .field("extensions", &self.extensions)
+            .field("magic", &self.magic)
+            .finish_non_exhaustive()
+    }
+}
+
+impl CustomFormat {
+    /// Format named `name`, its text parsed by `parse`
+    pub fn new(
+        name: &str,
+        parse: impl Fn(&str) -> anyhow::Result<Value> + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            // Formats are few and registered once, their names live as long as the tree
+            // keys they end up in
+            name: Box::leak(name.to_string().into_boxed_str()),
+            extensions: vec![],
+            magic: vec![],
+            parse: Box::new(parse),
+        }
+    }
+
+    /// Files ending in `.extension` are in this format
+    pub fn extension(mut self, extension: &str) -> Self {
+        self.extensions
+            .push(extension.trim_start_matches('.').to_string());
+        self
+    }
+
+    /// Text starting with `magic` is in this format
+    pub fn magic(mut self, magic: &[u8]) -> Self {
+        self.magic.push(magic.to_vec());
+        self
+    }
+
+    pub fn name(&self) -> &str {
+        self.name
+    }
+}
+
+impl FormatRegistry {
+    /// The registry every loader reads with
+    pub fn global() -> &'static Self {
+        &REGISTRY
+    }
+
+    /// Add `format`, replacing the one of the same name
+    pub fn register(&self, format: CustomFormat) -> &Self {
+        let mut formats = self.formats.write().unwrap();
+        formats.retain(|registered| registered.name != format.name);
+        formats.push(Arc::new(format));
+
+        self
+    }
+
+    /// Names of the registered formats
+    pub fn names(&self) -> Vec<&'static str> {
+        let formats = self.formats.read().unwrap();
+
+        formats.iter().map(|format| format.name).collect()
+    }
+
+    fn find(&self, matches: impl Fn(&CustomFormat) -> bool) -> Option<Arc<CustomFormat>> {
+        let formats = self.formats.read().unwrap();
+
+        formats.iter().find(|format| matches(format)).cloned()
+    }
+}
+
+/// Sources loaded by the current thread until the guard is dropped are in the format
+/// `name`: `yaml`, `json`, `toml` or `custom:<name>` of the [`FormatRegistry`]
+#[doc(hidden)]
+pub fn force_format(name: &str) -> Result<FormatGuard, UnconfigError> {
+    let format = match name {
+        "yaml" | "yml" => Format::Yaml,
+        "json" => Format::Json,
+        #[cfg(feature = "toml")]
+        "toml" => Format::Toml,
+        _ => {
+            let custom = name.strip_prefix("custom:").unwrap_or(name);
+            let format = REGISTRY
+                .find(|format| format.name == custom)
+                .ok_or_else(|| {
+                    UnconfigError::Validation(format!("format `{name}` is not registered"))
+                })?;
+
+            Format::Custom(format.name)
+        }
+    };
+
+    Ok(FormatGuard(FORCED.replace(Some(format))))
+}
+
+/// Restores the format of the thread when dropped, see [`force_format`]
+#[doc(hidden)]
+pub struct FormatGuard(Option<Format>);
+
+impl Drop for FormatGuard {
+    fn drop(&mut self) {
+        FORCED.set(self.0);
+    }
+}
+
+/// Syntax of a config source, all of them are parsed into the same tree
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) enum Format {
+    Yaml,
+    Json,
+    #[cfg(feature = "toml")]
+    Toml,
+    /// Registered in the [`FormatRegistry`] under this name
+    Custom(&'static str),
+}
+
+impl Format {
+    /// By file extension, YAML unless another format is known for it
+    pub(crate) fn of_path(path: &Path) -> Self {
+        if let Some(format) = FORCED.get() {
+            return format;
+        }
+
+        let extension = path.extension().and_then(|extension| extension.to_str());
+        if let Some(format) = extension.and_then(|extension| {
+            REGISTRY.find(|format| format.extensions.iter().any(|known| known == extension))
+        }) {
+            return Self::Custom(format.name);
+        }
+
+        match extension {
+            #[cfg(feature = "toml")]
+            Some("toml") => Self::Toml,
+            Some("json") => Self::Json,
+            _ => Self::Yaml,
+        }
+    }
+
+    /// By file extension, or by the magic bytes of `content` for an extension no format
+    /// claims
+    pub(crate) fn of_file(path: &Path, content: &str) -> Self {
+        match Self::of_path(path) {
+            format if FORCED.get().is_some() => format,
+            format @ Self::Custom(_) => format,
+            format => Self::of_magic(content).unwrap_or(format),
+        }
+    }
+
+    // Custom format whose magic bytes `content` starts with
+    fn of_magic(content: &str) -> Option<Self> {
+        REGISTRY
+            .find(|format| {
+                format
+                    .magic
+                    .iter()
+                    .any(|magic| content.as_bytes().starts_with(magic))
+            })
+            .map(|format| Self::Custom(format.name))
+    }
+
+    /// Embedded text is TOML when its first statement is a `[table]` header or a
+    /// `key = value` pair, which YAML would read as a plain string. Embedded JSON is read
+    /// as YAML, which it is a subset of. Magic bytes of a custom format come first.
+    pub(crate) fn of_text(content: &str) -> Self {
+        if let Some(format) = FORCED.get().or_else(|| Self::of_magic(content)) {
+            return format;
+        }
+
+        #[cfg(feature = "toml")]
+        {
+            let first = content
+                .lines()
+                .map(str::trim)
+                .find(|line| !line.is_empty() && !line.starts_with('#'));
+
+            if let Some(line) = first {
+                let table = line.starts_with('[') && line.ends_with(']') && !line.contains(',');
+                let pair = line.split_once('=').is_some_and(|(key, _)| {
+                    !key.trim().is_empty()
+                        && key.trim().chars().all(|c| {
+                            c.is_ascii_alphanumeric()
+                                || matches!(c, '_' | '-' | '.' | '"' | '\'' | ' ')
+                        })
+                });
+
+                if table || pair {
+                    return Self::Toml;
+                }
+            }
+        }
+
+        let _ = content;
+
+        Self::Yaml
+    }
+
+    /// `source` names the text in errors, e.g. its file
+    pub(crate) fn parse(self, source: &str, content: &str) -> Result<Value, UnconfigError> {
+        match self {
+            // JSON documents are YAML documents as well, only the error says otherwise
+            Self::Yaml | Self::Json => {
+                limits::limits()
+                    .check_flow_depth(content)
+                    .map_err(UnconfigError::validation)?;
+
+                serde_yaml::from_str(content).map_err(|e| {
+                    let (line, column) = e
+                        .location()
+                        .map_or((0, 0), |location| (location.line(), location.column()));
+                    let message = match self {
+                        Self::Json => format!("invalid JSON, {e}"),
+                        _ => e.to_string(),
+                    };
+
+                    UnconfigError::Parse {
+                        file: source.to_string(),
+                        line,
+                        column,
+                        message,
+                    }
+                })
+            }
+            #[cfg(feature = "toml")]
+            Self::Toml => crate::toml::parse(source, content),
+            Self::Custom(name) => {
+                let format = REGISTRY.find(|format| format.name == name).ok_or_else(|| {
+                    UnconfigError::Validation(format!(
+                        "{source}: format `{name}` is not registered"
+                    ))
+                })?;
+
+                (format.parse)(content).map_err(|e| match e.downcast::<UnconfigError>() {
+                    Ok(e) => e,
+                    Err(e) => UnconfigError::Parse {
+                        file: source.to_string(),
+                        line: 0,
+                        column: 0,
+                        message: format!("invalid {name}, {e:#}"),
+                    },
+                })
+            }
+        }
+    }
+
+    /// Text of a config written back, `source` names it in errors
+    pub(crate) fn render(
+        self,
+        source: &str,
+        value: &Value,
+        options: &RenderOptions,
+    ) -> Result<String, UnconfigError> {
+        let _ = source;
+
+        match self {
+            Self::Yaml => Ok(render::yaml(value, options)),
+            Self::Json => Ok(render::json(value, options)),
+            #[cfg(feature = "toml")]
+            Self::Toml => Err(UnconfigError::Validation(format!(
+                "{source}: configs can't be saved as TOML, save them as YAML or JSON"
+            ))),
+            Self::Custom(name) => Err(UnconfigError::Validation(format!(
+                "{source}: configs can't be saved as {name}, save them as YAML or JSON"
+            ))),
+        }
+    }
+}
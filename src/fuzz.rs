@@ -0,0 +1,28 @@
+//! Entry points for fuzzing, driven by the cargo-fuzz targets in `fuzz/`
+//!
+//! Inputs that aren't valid configs are expected to fail with an error, a panic, a hang
+//! or an allocation beyond [`crate::Limits`] is a bug.
+
+use crate::{format::Format, load, resolve_document, Expansion};
+
+/// Parse `data` as a config of any enabled format and run it through the whole pipeline
+/// into an untyped tree
+pub fn load_arbitrary(data: &[u8]) {
+    let Ok(text) = std::str::from_utf8(data) else {
+        return;
+    };
+
+    // Not through the parse cache, which would keep every input
+    let Ok(params) = Format::of_text(text).parse("fuzz", text) else {
+        return;
+    };
+
+    let _ = load::<serde_yaml::Value>("fuzz", None, resolve_document(&params));
+}
+
+/// `${...}` substitution of a single value
+pub fn substitute_arbitrary(data: &[u8]) {
+    let text = String::from_utf8_lossy(data);
+
+    crate::subst_env_variable("FUZZ", &text, &mut Expansion::new(None));
+}
@@ -0,0 +1,101 @@
+use std::{
+    sync::{LazyLock, Mutex, OnceLock},
+    time::Duration,
+};
+
+use indexmap::IndexMap;
+
+use crate::histogram;
+
+/// Receives every exported config value, e.g. to forward it to a metrics crate:
+/// `set_gauge_recorder(|name, value| metrics::gauge!(name).set(value))`
+pub type GaugeRecorder = fn(&str, f64);
+
+static GAUGES: LazyLock<Mutex<IndexMap<String, f64>>> = LazyLock::new(Default::default);
+static RECORDER: OnceLock<GaugeRecorder> = OnceLock::new();
+
+/// Forward gauges to `recorder` as well, including the ones exported before this call
+///
+/// The rejected recorder is handed back when one is already set.
+pub fn set_gauge_recorder(recorder: GaugeRecorder) -> Result<(), GaugeRecorder> {
+    RECORDER.set(recorder)?;
+
+    for (name, value) in GAUGES.lock().unwrap().iter() {
+        recorder(name, *value);
+    }
+
+    Ok(())
+}
+
+/// Set the gauge `name`, done for `#[unconfig(metric = "name")]` fields on every load
+pub fn set_gauge(name: &str, value: f64) {
+    GAUGES.lock().unwrap().insert(name.to_string(), value);
+
+    if let Some(recorder) = RECORDER.get() {
+        recorder(name, value);
+    }
+}
+
+/// Current gauges in the order they were first set
+pub fn gauges() -> Vec<(String, f64)> {
+    GAUGES
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|(name, value)| (name.clone(), *value))
+        .collect()
+}
+
+/// Current gauges in the Prometheus text format, for a `/metrics` endpoint
+///
+/// Span durations collected with `span_histograms` follow as the
+/// `span_duration_seconds` summary.
+pub fn render_gauges() -> String {
+    let gauges = GAUGES
+        .lock()
+        .unwrap()
+        .iter()
+        .fold(String::new(), |acc, (name, value)| {
+            format!("{acc}# TYPE {name} gauge\n{name} {value}\n")
+        });
+
+    gauges + &histogram::render()
+}
+
+/// Config values that can be exported as a gauge
+pub trait GaugeValue {
+    fn gauge_value(&self) -> f64;
+}
+
+macro_rules! gauge_value {
+    ($($ty:ty),*) => {
+        $(
+            impl GaugeValue for $ty {
+                fn gauge_value(&self) -> f64 {
+                    *self as f64
+                }
+            }
+        )*
+    };
+}
+
+gauge_value!(u8, u16, u32, u64, usize, i8, i16, i32, i64, isize, f32, f64);
+
+impl GaugeValue for bool {
+    fn gauge_value(&self) -> f64 {
+        u8::from(*self).into()
+    }
+}
+
+// Seconds, as Prometheus expects
+impl GaugeValue for Duration {
+    fn gauge_value(&self) -> f64 {
+        self.as_secs_f64()
+    }
+}
+
+impl<T: GaugeValue> GaugeValue for Option<T> {
+    fn gauge_value(&self) -> f64 {
+        self.as_ref().map_or(0., GaugeValue::gauge_value)
+    }
+}
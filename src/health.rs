@@ -0,0 +1,126 @@
+use std::sync::{LazyLock, Mutex};
+
+use serde::Serialize;
+use tracing_appender::non_blocking::ErrorCounter;
+
+use crate::{json, schedule};
+
+static STATE: LazyLock<Mutex<State>> = LazyLock::new(Default::default);
+
+#[derive(Default)]
+struct State {
+    generation: u64,
+    last_reload: Option<Reload>,
+    providers: Vec<Provider>,
+    sinks: Vec<(String, ErrorCounter)>,
+}
+
+/// Snapshot of config and logger state for health and readiness endpoints
+#[derive(Debug, Clone, Serialize)]
+pub struct Health {
+    /// Successful reloads so far
+    pub generation: u64,
+    pub last_reload: Option<Reload>,
+    /// Remote config sources, by the result of their last fetch
+    pub providers: Vec<Provider>,
+    /// Log files written in the background
+    pub sinks: Vec<Sink>,
+}
+
+/// Outcome of the latest reload
+#[derive(Debug, Clone, Serialize)]
+pub struct Reload {
+    /// RFC 3339 time of the reload
+    pub timestamp: String,
+    pub source: String,
+    /// Why the reload failed, the previous config stays in effect then
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Provider {
+    pub name: String,
+    pub connected: bool,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Sink {
+    pub name: String,
+    /// Lines lost because the writer fell behind
+    pub dropped_lines: usize,
+}
+
+impl Health {
+    /// Whether the last reload succeeded and every provider is reachable
+    pub fn is_healthy(&self) -> bool {
+        self.last_reload
+            .as_ref()
+            .is_none_or(|reload| reload.error.is_none())
+            && self.providers.iter().all(|provider| provider.connected)
+    }
+
+    /// The snapshot as a JSON object
+    pub fn to_json(&self) -> String {
+        serde_yaml::to_value(self)
+            .map(|value| json::to_string(&value))
+            .unwrap_or_default()
+    }
+}
+
+/// Current config and logger state
+pub fn health() -> Health {
+    let state = STATE.lock().unwrap();
+
+    Health {
+        generation: state.generation,
+        last_reload: state.last_reload.clone(),
+        providers: state.providers.clone(),
+        sinks: state
+            .sinks
+            .iter()
+            .map(|(name, counter)| Sink {
+                name: name.clone(),
+                dropped_lines: counter.dropped_lines(),
+            })
+            .collect(),
+    }
+}
+
+pub(crate) fn record_reload(source: &str, error: Option<String>) {
+    let mut state = STATE.lock().unwrap();
+
+    if error.is_none() {
+        state.generation += 1;
+    }
+
+    state.last_reload = Some(Reload {
+        timestamp: schedule::now_timestamp(),
+        source: source.to_string(),
+        error,
+    });
+}
+
+#[allow(dead_code)]
+pub(crate) fn record_provider(name: &str, error: Option<String>) {
+    let mut state = STATE.lock().unwrap();
+    let provider = Provider {
+        name: name.to_string(),
+        connected: error.is_none(),
+        error,
+    };
+
+    match state.providers.iter_mut().find(|p| p.name == name) {
+        Some(existing) => *existing = provider,
+        None => state.providers.push(provider),
+    }
+}
+
+pub(crate) fn register_sink(name: String, counter: ErrorCounter) {
+    STATE.lock().unwrap().sinks.push((name, counter));
+}
+
+// Config generation in effect now, for log correlation
+pub(crate) fn generation() -> u64 {
+    STATE.lock().unwrap().generation
+}
@@ -0,0 +1,186 @@
+use std::{
+    fmt::Write,
+    sync::{LazyLock, Mutex},
+    time::{Duration, Instant},
+};
+
+use indexmap::IndexMap;
+use tracing::{span, Subscriber};
+use tracing_subscriber::{layer::Context, registry::LookupSpan, Layer};
+
+// Buckets per doubling, i.e. values are kept within about 19% of their size
+const SUB_BUCKETS: f64 = 4.;
+const QUANTILES: [f64; 4] = [0.5, 0.9, 0.99, 0.999];
+
+static HISTOGRAMS: LazyLock<Mutex<IndexMap<&'static str, Histogram>>> =
+    LazyLock::new(Default::default);
+
+/// Distribution of close durations of one span name
+#[derive(Debug, Clone, Default)]
+struct Histogram {
+    // Count per logarithmic bucket of nanoseconds
+    buckets: Vec<u64>,
+    count: u64,
+    sum: Duration,
+    min: Duration,
+    max: Duration,
+}
+
+impl Histogram {
+    fn record(&mut self, duration: Duration) {
+        let nanos = duration.as_nanos().max(1) as f64;
+        let index = (nanos.log2() * SUB_BUCKETS) as usize;
+
+        if self.buckets.len() <= index {
+            self.buckets.resize(index + 1, 0);
+        }
+
+        self.buckets[index] += 1;
+        self.min = if self.count == 0 {
+            duration
+        } else {
+            self.min.min(duration)
+        };
+        self.max = self.max.max(duration);
+        self.sum += duration;
+        self.count += 1;
+    }
+
+    fn quantile(&self, quantile: f64) -> Duration {
+        let rank = (quantile * self.count as f64).ceil().max(1.) as u64;
+        let mut seen = 0;
+
+        for (index, count) in self.buckets.iter().enumerate() {
+            seen += count;
+
+            if seen >= rank {
+                // Upper edge of the bucket, clamped to what was actually seen
+                let nanos = 2f64.powf((index + 1) as f64 / SUB_BUCKETS);
+
+                return Duration::from_nanos(nanos as u64).clamp(self.min, self.max);
+            }
+        }
+
+        self.max
+    }
+}
+
+/// Summary of the close durations of one span name
+#[derive(Debug, Clone)]
+pub struct SpanTimings {
+    pub name: &'static str,
+    pub count: u64,
+    pub total: Duration,
+    pub mean: Duration,
+    pub min: Duration,
+    pub max: Duration,
+    /// Estimated durations at the 50th, 90th, 99th and 99.9th percentile
+    pub quantiles: Vec<(f64, Duration)>,
+}
+
+/// Span durations recorded so far, with `span_histograms` enabled in the logger params
+pub fn span_timings() -> Vec<SpanTimings> {
+    HISTOGRAMS
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|(name, histogram)| SpanTimings {
+            name,
+            count: histogram.count,
+            total: histogram.sum,
+            mean: histogram.sum / histogram.count.max(1) as u32,
+            min: histogram.min,
+            max: histogram.max,
+            quantiles: QUANTILES
+                .iter()
+                .map(|q| (*q, histogram.quantile(*q)))
+                .collect(),
+        })
+        .collect()
+}
+
+// Prometheus summaries for `render_gauges`
+pub(crate) fn render() -> String {
+    let timings = span_timings();
+
+    if timings.is_empty() {
+        return String::new();
+    }
+
+    let mut out = String::from("# TYPE span_duration_seconds summary\n");
+
+    for timing in timings {
+        let name = timing.name;
+
+        for (quantile, duration) in &timing.quantiles {
+            let _ = writeln!(
+                out,
+                "span_duration_seconds{{span=\"{name}\",quantile=\"{quantile}\"}} {}",
+                duration.as_secs_f64()
+            );
+        }
+
+        let _ = writeln!(
+            out,
+            "span_duration_seconds_sum{{span=\"{name}\"}} {}\nspan_duration_seconds_count{{span=\"{name}\"}} {}",
+            timing.total.as_secs_f64(),
+            timing.count
+        );
+    }
+
+    out
+}
+
+/// Log the recorded distributions, done when the logger shuts down
+pub(crate) fn summarize() {
+    for timing in span_timings() {
+        let quantiles = timing
+            .quantiles
+            .iter()
+            .map(|(q, d)| format!("p{}={d:?}", q * 100.))
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        tracing::info!(
+            "span {}: count={} mean={:?} min={:?} max={:?} {quantiles}",
+            timing.name,
+            timing.count,
+            timing.mean,
+            timing.min,
+            timing.max
+        );
+    }
+}
+
+/// Records how long each span lived, from creation to close
+#[derive(Clone, Copy)]
+pub(crate) struct SpanHistograms;
+
+struct Opened(Instant);
+
+impl<S> Layer<S> for SpanHistograms
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, _attrs: &span::Attributes<'_>, id: &span::Id, ctx: Context<'_, S>) {
+        if let Some(span) = ctx.span(id) {
+            span.extensions_mut().insert(Opened(Instant::now()));
+        }
+    }
+
+    fn on_close(&self, id: span::Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(&id) else {
+            return;
+        };
+        let Some(opened) = span.extensions().get::<Opened>().map(|opened| opened.0) else {
+            return;
+        };
+
+        HISTOGRAMS
+            .lock()
+            .unwrap()
+            .entry(span.name())
+            .or_default()
+            .record(opened.elapsed());
+    }
+}
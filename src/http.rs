@@ -0,0 +1,502 @@
+use std::{
+    io::{Read, Write},
+    net::{TcpStream, ToSocketAddrs},
+    path::Path,
+    process::{Command, Stdio},
+    thread,
+    time::Duration,
+};
+
+use anyhow::{anyhow, bail, Context, Result};
+use tracing::warn;
+
+use crate::{env_overlay, format::Format, limits};
+
+/// Binary fetching `https://` URLs, `curl` on the `PATH` by default
+///
+/// TLS is left to it, so its CA bundle, client certificates and proxy variables apply
+/// (`CURL_CA_BUNDLE`, `HTTPS_PROXY`), the loader's overlay included.
+pub const CURL_BIN_VAR: &str = "CURL_BIN";
+
+/// How [`Config::load_url_with`](crate::Config::load_url_with) fetches a config
+#[derive(Debug, Clone)]
+pub struct UrlOptions {
+    /// Connecting, sending and each read, not the whole download
+    pub timeout: Duration,
+    /// Further attempts after a connection error or a 5xx response
+    pub retries: u32,
+    /// Wait before the first retry, doubled for each next one
+    pub retry_delay: Duration,
+}
+
+impl Default for UrlOptions {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_secs(10),
+            retries: 3,
+            retry_delay: Duration::from_millis(500),
+        }
+    }
+}
+
+/// Body of `url` and its format, told by the `Content-Type` or else by the extension
+pub(crate) fn fetch(url: &str, options: &UrlOptions) -> Result<(String, Format)> {
+    let target = Target::parse(url)?;
+    let mut delay = options.retry_delay;
+    let mut attempt = 0;
+
+    loop {
+        match send(&target, options, "GET", &[], "") {
+            Ok(response) if response.status < 300 => {
+                limits::limits().check_file_size(url, response.body.len() as u64)?;
+
+                let format = response
+                    .format()
+                    .unwrap_or_else(|| Format::of_path(Path::new(target.file())));
+                let body = String::from_utf8(response.body)
+                    .context(format!("{url}: config is not valid UTF-8"))?;
+
+                return Ok((body, format));
+            }
+            Ok(response) if response.status < 500 => {
+                bail!("{url}: server answered {}", response.status)
+            }
+            Ok(response) if attempt < options.retries => {
+                warn!(
+                    "{url}: server answered {}, retrying in {delay:?}",
+                    response.status
+                )
+            }
+            Ok(response) => bail!("{url}: server answered {}", response.status),
+            Err(e) if attempt < options.retries => warn!("{url}: {e:#}, retrying in {delay:?}"),
+            Err(e) => return Err(e.context(format!("failed to fetch config: {url}"))),
+        }
+
+        thread::sleep(delay);
+        delay *= 2;
+        attempt += 1;
+    }
+}
+
+struct Target<'a> {
+    url: &'a str,
+    // `https://`, fetched with curl
+    tls: bool,
+    host: &'a str,
+    port: u16,
+    // With the query, `/` when the URL has none
+    path: &'a str,
+}
+
+impl<'a> Target<'a> {
+    fn parse(url: &'a str) -> Result<Self> {
+        let (rest, tls) = if let Some(rest) = url.strip_prefix("http://") {
+            (rest, false)
+        } else if let Some(rest) = url.strip_prefix("https://") {
+            (rest, true)
+        } else {
+            bail!("{url}: expected an http:// or https:// URL")
+        };
+
+        let (authority, path) = match rest.find('/') {
+            Some(index) => rest.split_at(index),
+            None => (rest, "/"),
+        };
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) if !port.contains(']') => (
+                host,
+                port.parse().map_err(|_| anyhow!("{url}: invalid port"))?,
+            ),
+            _ => (authority, if tls { 443 } else { 80 }),
+        };
+
+        if host.is_empty() {
+            bail!("{url}: no host");
+        }
+
+        Ok(Self {
+            url,
+            tls,
+            host,
+            port,
+            path,
+        })
+    }
+
+    fn file(&self) -> &'a str {
+        self.path.split(['?', '#']).next().unwrap_or_default()
+    }
+}
+
+pub(crate) struct Response {
+    pub(crate) status: u16,
+    headers: Vec<(String, String)>,
+    pub(crate) body: Vec<u8>,
+}
+
+impl Response {
+    /// First header named `name`, in any case
+    pub(crate) fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    #[allow(dead_code)]
+    pub(crate) fn text(self) -> Result<String> {
+        String::from_utf8(self.body).context("body is not UTF-8")
+    }
+
+    fn format(&self) -> Option<Format> {
+        let content_type = self.header("content-type")?.to_ascii_lowercase();
+
+        if content_type.contains("json") {
+            Some(Format::Json)
+        } else if content_type.contains("yaml") {
+            Some(Format::Yaml)
+        } else {
+            #[cfg(feature = "toml")]
+            if content_type.contains("toml") {
+                return Some(Format::Toml);
+            }
+
+            None
+        }
+    }
+}
+
+/// One `method` request to `url` with `headers` and `body`, not retried
+#[allow(dead_code)]
+pub(crate) fn request(
+    method: &str,
+    url: &str,
+    headers: &[(&str, &str)],
+    body: &str,
+    options: &UrlOptions,
+) -> Result<Response> {
+    send(&Target::parse(url)?, options, method, headers, body).context(format!("{method} {url}"))
+}
+
+// One request over a fresh connection, read until the server closes it
+fn send(
+    target: &Target,
+    options: &UrlOptions,
+    method: &str,
+    headers: &[(&str, &str)],
+    body: &str,
+) -> Result<Response> {
+    if target.tls {
+        return send_curl(target.url, options, method, headers, body);
+    }
+
+    let address = (target.host.trim_matches(['[', ']']), target.port)
+        .to_socket_addrs()?
+        .next()
+        .ok_or(anyhow!("{} has no address", target.host))?;
+    let mut stream = TcpStream::connect_timeout(&address, options.timeout)?;
+    stream.set_read_timeout(Some(options.timeout))?;
+    stream.set_write_timeout(Some(options.timeout))?;
+
+    write!(
+        stream,
+        "{method} {} HTTP/1.1\r\nHost: {}\r\nAccept: application/yaml, application/json, */*;q=0.5\r\nUser-Agent: unconfig\r\nConnection: close\r\n",
+        target.path, target.host
+    )?;
+    for (name, value) in headers {
+        write!(stream, "{name}: {value}\r\n")?;
+    }
+    if !body.is_empty() {
+        // JSON unless the caller says otherwise
+        if !headers
+            .iter()
+            .any(|(name, _)| name.eq_ignore_ascii_case("content-type"))
+        {
+            write!(stream, "Content-Type: application/json\r\n")?;
+        }
+        write!(stream, "Content-Length: {}\r\n", body.len())?;
+    }
+    write!(stream, "\r\n{body}")?;
+
+    let mut raw = vec![];
+    stream.read_to_end(&mut raw)?;
+
+    response(raw, true)
+}
+
+// Same as `send` through curl, which decodes the body. Everything goes through its config
+// on stdin, so that tokens in headers don't show in the process list
+fn send_curl(
+    url: &str,
+    options: &UrlOptions,
+    method: &str,
+    headers: &[(&str, &str)],
+    body: &str,
+) -> Result<Response> {
+    let bin = env_overlay::var(CURL_BIN_VAR).unwrap_or_else(|_| "curl".to_string());
+    let config = curl_config(url, options, method, headers, body);
+
+    let mut child = Command::new(&bin)
+        .args(["--config", "-"])
+        .env_clear()
+        .envs(env_overlay::vars())
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("failed to run {bin}"))?;
+    child
+        .stdin
+        .take()
+        .ok_or(anyhow!("{bin} has no stdin"))?
+        .write_all(config.as_bytes())?;
+    let output = child.wait_with_output()?;
+
+    if !output.status.success() {
+        bail!(
+            "{bin} failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+
+    response(output.stdout, false)
+}
+
+fn curl_config(
+    url: &str,
+    options: &UrlOptions,
+    method: &str,
+    headers: &[(&str, &str)],
+    body: &str,
+) -> String {
+    let quoted = |text: &str| {
+        let escaped = text
+            .replace('\\', "\\\\")
+            .replace('"', "\\\"")
+            .replace('\n', "\\n")
+            .replace('\r', "\\r")
+            .replace('\t', "\\t");
+
+        format!("\"{escaped}\"")
+    };
+    let seconds = options.timeout.as_secs().max(1);
+
+    // Headers included in the output but not a proxy's `CONNECT` ones, no progress, and a
+    // stalled download failing like a read timing out
+    let mut config = format!(
+        "url = {}\nrequest = {}\ninclude\nsuppress-connect-headers\nsilent\nshow-error\nconnect-timeout = {seconds}\nspeed-limit = 1\nspeed-time = {seconds}\n",
+        quoted(url),
+        quoted(method)
+    );
+    let mut headers = headers.to_vec();
+    headers.extend([
+        ("Accept", "application/yaml, application/json, */*;q=0.5"),
+        ("User-Agent", "unconfig"),
+        // No `100 Continue` response ahead of the real one
+        ("Expect", ""),
+    ]);
+    if !body.is_empty()
+        && !headers
+            .iter()
+            .any(|(name, _)| name.eq_ignore_ascii_case("content-type"))
+    {
+        headers.push(("Content-Type", "application/json"));
+    }
+    for (name, value) in headers {
+        // `Name:` removes a header curl adds by itself
+        let header = match value {
+            "" => format!("{name}:"),
+            value => format!("{name}: {value}"),
+        };
+        config += &format!("header = {}\n", quoted(&header));
+    }
+    if !body.is_empty() {
+        config += &format!("data-binary = {}\n", quoted(body));
+    }
+
+    config
+}
+
+// Status, headers and body of a raw response, `chunked` ones decoded when `dechunk`
+fn response(mut raw: Vec<u8>, dechunk: bool) -> Result<Response> {
+    let head_end = raw
+        .windows(4)
+        .position(|window| window == b"\r\n\r\n")
+        .ok_or(anyhow!("malformed HTTP response"))?;
+    let head = std::str::from_utf8(&raw[..head_end]).context("malformed HTTP response")?;
+    let mut lines = head.split("\r\n");
+
+    let status = lines
+        .next()
+        .and_then(|line| line.split(' ').nth(1))
+        .and_then(|status| status.parse().ok())
+        .ok_or(anyhow!("malformed HTTP status line"))?;
+    let headers = lines
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, value)| (name.trim().to_string(), value.trim().to_string()))
+        .collect::<Vec<_>>();
+    let chunked = headers.iter().any(|(name, value)| {
+        name.eq_ignore_ascii_case("transfer-encoding") && value.eq_ignore_ascii_case("chunked")
+    });
+
+    let body = raw.split_off(head_end + 4);
+    let body = match chunked && dechunk {
+        true => decode_chunked(&body)?,
+        false => body,
+    };
+
+    Ok(Response {
+        status,
+        headers,
+        body,
+    })
+}
+
+// `Transfer-Encoding: chunked` bodies, trailers are ignored
+fn decode_chunked(mut raw: &[u8]) -> Result<Vec<u8>> {
+    let mut body = vec![];
+
+    loop {
+        let line_end = raw
+            .windows(2)
+            .position(|window| window == b"\r\n")
+            .ok_or(anyhow!("malformed chunked body"))?;
+        let size = std::str::from_utf8(&raw[..line_end])?;
+        let size = usize::from_str_radix(size.split(';').next().unwrap_or_default().trim(), 16)
+            .context("malformed chunk size")?;
+
+        if size == 0 {
+            return Ok(body);
+        }
+
+        let chunk = raw
+            .get(line_end + 2..line_end + 2 + size)
+            .ok_or(anyhow!("truncated chunked body"))?;
+        body.extend_from_slice(chunk);
+        raw = raw.get(line_end + 4 + size..).unwrap_or_default();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+
+    use super::*;
+
+    #[test]
+    fn targets() {
+        let target = Target::parse("https://config.internal/app/config.yml?v=2").unwrap();
+        assert!(target.tls);
+        assert_eq!((target.host, target.port), ("config.internal", 443));
+        assert_eq!(target.path, "/app/config.yml?v=2");
+        assert_eq!(target.file(), "/app/config.yml");
+
+        let target = Target::parse("http://[::1]:8080").unwrap();
+        assert!(!target.tls);
+        assert_eq!(
+            (target.host, target.port, target.path),
+            ("[::1]", 8080, "/")
+        );
+
+        assert!(Target::parse("ftp://config.internal").is_err());
+        assert!(Target::parse("https://:443/").is_err());
+        assert!(Target::parse("http://config.internal:port").is_err());
+    }
+
+    #[test]
+    fn curl_config_quotes() {
+        let options = UrlOptions {
+            timeout: Duration::from_millis(200),
+            ..UrlOptions::default()
+        };
+        let config = curl_config(
+            "https://vault.internal/v1/kv",
+            &options,
+            "PUT",
+            &[("X-Vault-Token", "a\"b\\c")],
+            "{\"key\":\n1}",
+        );
+
+        assert!(config.contains("url = \"https://vault.internal/v1/kv\"\n"));
+        assert!(config.contains("request = \"PUT\"\n"));
+        assert!(config.contains("connect-timeout = 1\n"));
+        assert!(config.contains("header = \"X-Vault-Token: a\\\"b\\\\c\"\n"));
+        assert!(config.contains("header = \"Expect:\"\n"));
+        assert!(config.contains("header = \"Content-Type: application/json\"\n"));
+        assert!(config.contains("data-binary = \"{\\\"key\\\":\\n1}\"\n"));
+    }
+
+    #[test]
+    fn responses() {
+        let raw = b"HTTP/1.1 200 OK\r\nContent-Type: application/json\r\n\r\n{}".to_vec();
+        let parsed = response(raw, true).unwrap();
+        assert_eq!(parsed.status, 200);
+        assert_eq!(parsed.format(), Some(Format::Json));
+        assert_eq!(parsed.body, b"{}");
+
+        let raw = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nab:\r\n2;x=y\r\n 1\r\n0\r\n\r\n";
+        assert_eq!(response(raw.to_vec(), true).unwrap().body, b"ab: 1");
+        // curl decodes the body itself
+        assert_eq!(
+            response(
+                b"HTTP/2 204\r\nTransfer-Encoding: chunked\r\n\r\nab: 1".to_vec(),
+                false
+            )
+            .unwrap()
+            .body,
+            b"ab: 1"
+        );
+
+        assert!(response(b"HTTP/1.1 200 OK\r\n".to_vec(), true).is_err());
+        assert!(response(b"HTTP/1.1 OK\r\n\r\n".to_vec(), true).is_err());
+        assert!(response(
+            b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\nzz\r\n".to_vec(),
+            true
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn curl_request() {
+        if Command::new("curl").arg("--version").output().is_err() {
+            return;
+        }
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/config", listener.local_addr().unwrap());
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = vec![];
+            let mut buffer = [0; 1024];
+            while !String::from_utf8_lossy(&request).ends_with("{\"a\":1}") {
+                let read = stream.read(&mut buffer).unwrap();
+                request.extend_from_slice(&buffer[..read]);
+            }
+            stream
+                .write_all(
+                    b"HTTP/1.1 200 OK\r\nContent-Type: application/yaml\r\nTransfer-Encoding: chunked\r\n\r\n5\r\na: 1\n\r\n0\r\n\r\n",
+                )
+                .unwrap();
+
+            String::from_utf8(request).unwrap()
+        });
+
+        let response = send_curl(
+            &url,
+            &UrlOptions::default(),
+            "POST",
+            &[("Authorization", "Bearer token")],
+            "{\"a\":1}",
+        )
+        .unwrap();
+        assert_eq!(response.status, 200);
+        assert_eq!(response.format(), Some(Format::Yaml));
+        assert_eq!(response.body, b"a: 1\n");
+
+        let request = server.join().unwrap();
+        assert!(request.starts_with("POST /config HTTP/1.1\r\n"));
+        assert!(request.contains("\r\nAuthorization: Bearer token\r\n"));
+        assert!(request.contains("\r\nContent-Type: application/json\r\n"));
+        assert!(!request.contains("\r\nExpect:"));
+    }
+}
@@ -0,0 +1,146 @@
+use std::{
+    cell::RefCell,
+    fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use anyhow::{anyhow, Result};
+use serde_yaml::Value;
+
+use crate::{overlay::deep_merge, read_file};
+
+const INCLUDE_TAG: &str = "include";
+/// Top-level key listing files the rest of the document is merged over
+const INCLUDE_KEY: &str = "include";
+
+thread_local! {
+    // Files being resolved, outermost first
+    static INCLUDING: RefCell<Vec<PathBuf>> = const { RefCell::new(vec![]) };
+}
+
+/// Replace the includes of the file at `path` with the files they name
+///
+/// `!include other.yml` is replaced by the whole of `other.yml`, wherever it appears.
+/// `include: [db.yml, http.yml]` at the top level merges the files in order, and the
+/// including document over them. Relative paths resolve against the including file,
+/// includes may include others as long as they don't form a cycle.
+///
+/// Only files read at runtime are resolved, not the ones embedded at compile time, and
+/// only the including file is watched for changes.
+pub(crate) fn resolve(path: &Path, value: Arc<Value>) -> Result<Arc<Value>> {
+    if !has_includes(&value) {
+        return Ok(value);
+    }
+
+    // `a/../b.yml` and `b.yml` are the same file
+    let key = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    let cycle = INCLUDING.with_borrow_mut(|including| {
+        let cycle = including.contains(&key);
+        including.push(key);
+
+        cycle
+    });
+    let resolved = if cycle {
+        let chain = INCLUDING.with_borrow(|including| {
+            including
+                .iter()
+                .map(|included| included.display().to_string())
+                .collect::<Vec<_>>()
+                .join(" -> ")
+        });
+
+        Err(anyhow!("config files include each other: {chain}"))
+    } else {
+        let mut value = Value::clone(&value);
+
+        resolve_document(path, &mut value).map(|_| Arc::new(value))
+    };
+    INCLUDING.with_borrow_mut(Vec::pop);
+
+    resolved
+}
+
+fn resolve_document(path: &Path, value: &mut Value) -> Result<()> {
+    let dir = path.parent().unwrap_or(Path::new(""));
+    resolve_tags(dir, value)?;
+
+    let Some(includes) = value
+        .as_mapping_mut()
+        .and_then(|mapping| mapping.remove(INCLUDE_KEY))
+    else {
+        return Ok(());
+    };
+    let includes = match includes {
+        Value::String(include) => vec![include],
+        Value::Sequence(includes) => includes
+            .into_iter()
+            .map(|include| match include {
+                Value::String(include) => Ok(include),
+                other => Err(anyhow!(
+                    "`{INCLUDE_KEY}` entries must be paths, not {other:?}"
+                )),
+            })
+            .collect::<Result<_>>()?,
+        other => return Err(anyhow!("`{INCLUDE_KEY}` must list paths, not {other:?}")),
+    };
+
+    let mut merged = Value::Mapping(Default::default());
+
+    for include in includes {
+        deep_merge(&mut merged, included(dir, &include)?);
+    }
+
+    deep_merge(&mut merged, std::mem::take(value));
+    *value = merged;
+
+    Ok(())
+}
+
+fn resolve_tags(dir: &Path, value: &mut Value) -> Result<()> {
+    match value {
+        Value::Tagged(tagged) if tagged.tag == INCLUDE_TAG => {
+            let Some(include) = tagged.value.as_str() else {
+                return Err(anyhow!(
+                    "!{INCLUDE_TAG} takes a path, not {:?}",
+                    tagged.value
+                ));
+            };
+            *value = included(dir, include)?;
+        }
+        Value::Tagged(tagged) => resolve_tags(dir, &mut tagged.value)?,
+        Value::Mapping(mapping) => {
+            for (_, v) in mapping {
+                resolve_tags(dir, v)?;
+            }
+        }
+        Value::Sequence(seq) => {
+            for v in seq {
+                resolve_tags(dir, v)?;
+            }
+        }
+        _ => {}
+    }
+
+    Ok(())
+}
+
+// Read like any other config file, resolving its own includes
+fn included(dir: &Path, include: &str) -> Result<Value> {
+    let (_, value) = read_file(dir.join(include))?;
+
+    Ok(Value::clone(&value))
+}
+
+fn has_includes(value: &Value) -> bool {
+    fn has_tags(value: &Value) -> bool {
+        match value {
+            Value::Tagged(tagged) => tagged.tag == INCLUDE_TAG || has_tags(&tagged.value),
+            Value::Mapping(mapping) => mapping.values().any(has_tags),
+            Value::Sequence(seq) => seq.iter().any(has_tags),
+            _ => false,
+        }
+    }
+
+    value.get(INCLUDE_KEY).is_some() || has_tags(value)
+}
@@ -0,0 +1,134 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        mpsc::{self, RecvTimeoutError},
+        LazyLock, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use serde_yaml::{Mapping, Value};
+use tracing::info;
+
+use crate::{json, provenance};
+
+// How long the last `init_within` of each config took
+static ELAPSED: LazyLock<Mutex<HashMap<&'static str, Duration>>> = LazyLock::new(Default::default);
+
+/// How one config of `#[config]` was initialized, see [`init_report`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConfigInit {
+    pub name: &'static str,
+    /// Sources merged, in order: `embedded`, files, `environment`, ...
+    pub sources: Vec<String>,
+    pub elapsed: Duration,
+    /// Whether a source merged over the first one, e.g. a runtime file or variables
+    pub overridden: bool,
+}
+
+/// Load a config of `#[config]` on another thread, panicking with the config's name if
+/// it takes longer than `timeout` instead of blocking its first access indefinitely
+///
+/// The load keeps running after the timeout, a hung source only holds its own thread.
+#[doc(hidden)]
+pub fn init_within<T: Send + 'static>(
+    config: &'static str,
+    timeout: Option<Duration>,
+    init: fn() -> T,
+) -> T {
+    let timed = move || {
+        let started = Instant::now();
+        let value = init();
+        ELAPSED.lock().unwrap().insert(config, started.elapsed());
+
+        value
+    };
+
+    let Some(timeout) = timeout else {
+        return timed();
+    };
+
+    let (sender, receiver) = mpsc::channel();
+    crate::spawn_traced(move || {
+        let _ = sender.send(timed());
+    });
+
+    match receiver.recv_timeout(timeout) {
+        Ok(config) => config,
+        Err(RecvTimeoutError::Timeout) => {
+            panic!("{config} didn't load within {timeout:?}, one of its sources may be hung")
+        }
+        // The loading thread panicked and reported why
+        Err(RecvTimeoutError::Disconnected) => panic!("{config} failed to load"),
+    }
+}
+
+/// How each of `configs` was last initialized, in that order
+pub fn init_report(configs: &[&'static str]) -> Vec<ConfigInit> {
+    let elapsed = ELAPSED.lock().unwrap();
+
+    configs
+        .iter()
+        .map(|&name| {
+            let sources = provenance::sources_of(name);
+
+            ConfigInit {
+                name,
+                overridden: sources.len() > 1,
+                sources,
+                elapsed: elapsed.get(name).copied().unwrap_or_default(),
+            }
+        })
+        .collect()
+}
+
+/// One event summing up the configs `#[config]` just initialized in `elapsed`
+///
+/// The message reads as a line per config, the `configs` field holds the same as JSON.
+#[doc(hidden)]
+pub fn report_init(configs: &[&'static str], elapsed: Duration) {
+    let report = init_report(configs);
+    let lines = report
+        .iter()
+        .map(|config| {
+            let overridden = if config.overridden {
+                "overridden"
+            } else {
+                "no overrides"
+            };
+
+            format!(
+                "\n  {}: {:?} from {}, {overridden}",
+                config.name,
+                config.elapsed,
+                config.sources.join(" then ")
+            )
+        })
+        .collect::<String>();
+    let json = json::to_string(&Value::Sequence(
+        report
+            .iter()
+            .map(|config| {
+                Value::Mapping(Mapping::from_iter([
+                    ("name".into(), config.name.into()),
+                    (
+                        "sources".into(),
+                        Value::Sequence(config.sources.iter().map(|s| s.as_str().into()).collect()),
+                    ),
+                    (
+                        "elapsed_ms".into(),
+                        (config.elapsed.as_secs_f64() * 1000.0).into(),
+                    ),
+                    ("overridden".into(), config.overridden.into()),
+                ]))
+            })
+            .collect(),
+    ));
+
+    info!(
+        configs = %json,
+        elapsed_ms = elapsed.as_secs_f64() * 1000.0,
+        "Initialized {} configs in {elapsed:?}:{lines}",
+        report.len()
+    );
+}
@@ -0,0 +1,84 @@
+use std::fmt::Write;
+
+use serde_yaml::Value;
+
+/// Render a value as compact single-line JSON
+///
+/// Non-string mapping keys are written as their YAML text, tags are dropped and
+/// non-finite floats become `null`.
+pub(crate) fn to_string(value: &Value) -> String {
+    let mut out = String::new();
+    write_value(&mut out, value);
+
+    out
+}
+
+fn write_value(out: &mut String, value: &Value) {
+    match value {
+        Value::Null => out.push_str("null"),
+        Value::Bool(v) => out.push_str(if *v { "true" } else { "false" }),
+        Value::Number(n) => match n.as_f64() {
+            Some(f) if n.is_f64() && !f.is_finite() => out.push_str("null"),
+            _ => {
+                let _ = write!(out, "{n}");
+            }
+        },
+        Value::String(text) => write_str(out, text),
+        Value::Sequence(seq) => {
+            out.push('[');
+
+            for (i, v) in seq.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+
+                write_value(out, v);
+            }
+
+            out.push(']');
+        }
+        Value::Mapping(mapping) => {
+            out.push('{');
+
+            for (i, (k, v)) in mapping.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+
+                match k {
+                    Value::String(k) => write_str(out, k),
+                    other => write_str(
+                        out,
+                        serde_yaml::to_string(other).unwrap_or_default().trim_end(),
+                    ),
+                }
+
+                out.push(':');
+                write_value(out, v);
+            }
+
+            out.push('}');
+        }
+        Value::Tagged(tagged) => write_value(out, &tagged.value),
+    }
+}
+
+pub(crate) fn write_str(out: &mut String, text: &str) {
+    out.push('"');
+
+    for c in text.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+
+    out.push('"');
+}
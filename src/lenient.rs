@@ -0,0 +1,586 @@
+use std::{cell::Cell, fmt};
+
+use serde::de::{
+    self, DeserializeSeed, Deserializer, EnumAccess, MapAccess, SeqAccess, VariantAccess, Visitor,
+};
+
+/// Deserializer converting scalars by the type they are deserialized into
+///
+/// Values substituted from the environment arrive as whatever they looked like, so this
+/// takes a string where a number or bool is expected and parses it, bools as
+/// `true`/`yes`/`on`/`1` and their opposites in any case, and matches enum variants
+/// ignoring case, `-` and `_`, so `fast` is `Mode::Fast` and `read-only` is
+/// `Access::ReadOnly`. Newtype wrappers, options, sequences and maps are converted
+/// through to their contents.
+///
+/// Under [`strict_types`] scalars convert no more: a string where a number or bool is
+/// expected, or a number or bool where a string is, fails with what to write instead.
+pub(crate) struct Lenient<D>(pub(crate) D);
+
+thread_local! {
+    // Whether the configs deserialized on this thread must have the types of their fields
+    static STRICT: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Configs deserialized by the current thread until the guard is dropped must have the
+/// types of their fields: `port: "8080"` no longer reads as a number, `name: 8080` no
+/// longer as a string
+///
+/// Integers still read as floats, and map keys and enum variants convert as before.
+#[doc(hidden)]
+pub fn strict_types() -> StrictTypesGuard {
+    StrictTypesGuard(STRICT.replace(true))
+}
+
+/// Restores the typing of the thread when dropped, see [`strict_types`]
+#[doc(hidden)]
+pub struct StrictTypesGuard(bool);
+
+impl Drop for StrictTypesGuard {
+    fn drop(&mut self) {
+        STRICT.set(self.0);
+    }
+}
+
+// What the visitor was asked for, which decides how a string converts
+#[derive(Clone, Copy)]
+enum Target {
+    Any,
+    Bool,
+    Signed,
+    Unsigned,
+    Float,
+    // A string, only converted through `deserialize_any` in strict mode
+    Str,
+}
+
+impl Target {
+    // Hint of a `${...}` reference substituting a value of this type
+    fn hint(self) -> &'static str {
+        match self {
+            Self::Bool => "bool",
+            Self::Signed | Self::Unsigned => "int",
+            Self::Float => "float",
+            Self::Any | Self::Str => "str",
+        }
+    }
+}
+
+struct Wrap<V> {
+    visitor: V,
+    target: Target,
+}
+
+impl<V> Wrap<V> {
+    fn new(visitor: V, target: Target) -> Self {
+        Self { visitor, target }
+    }
+}
+
+macro_rules! forward {
+    ($($method:ident)*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+                self.0.$method(Wrap::new(visitor, Target::Any))
+            }
+        )*
+    };
+}
+
+// Through `deserialize_any`, which hands quoted strings to the visitor where the typed
+// methods would fail
+macro_rules! convert {
+    ($($method:ident => $target:ident,)*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+                self.0.deserialize_any(Wrap::new(visitor, Target::$target))
+            }
+        )*
+    };
+}
+
+impl<'de, D: Deserializer<'de>> Deserializer<'de> for Lenient<D> {
+    type Error = D::Error;
+
+    forward! {
+        deserialize_any deserialize_char deserialize_bytes deserialize_byte_buf deserialize_option deserialize_unit deserialize_seq
+        deserialize_map deserialize_identifier deserialize_ignored_any
+    }
+
+    convert! {
+        deserialize_bool => Bool,
+        deserialize_i8 => Signed,
+        deserialize_i16 => Signed,
+        deserialize_i32 => Signed,
+        deserialize_i64 => Signed,
+        deserialize_i128 => Signed,
+        deserialize_u8 => Unsigned,
+        deserialize_u16 => Unsigned,
+        deserialize_u32 => Unsigned,
+        deserialize_u64 => Unsigned,
+        deserialize_u128 => Unsigned,
+        deserialize_f32 => Float,
+        deserialize_f64 => Float,
+    }
+
+    fn deserialize_str<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        match STRICT.get() {
+            true => self.0.deserialize_any(Wrap::new(visitor, Target::Str)),
+            false => self.0.deserialize_str(Wrap::new(visitor, Target::Any)),
+        }
+    }
+
+    fn deserialize_string<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        match STRICT.get() {
+            true => self.0.deserialize_any(Wrap::new(visitor, Target::Str)),
+            false => self.0.deserialize_string(Wrap::new(visitor, Target::Any)),
+        }
+    }
+
+    fn deserialize_unit_struct<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        self.0
+            .deserialize_unit_struct(name, Wrap::new(visitor, Target::Any))
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        self.0
+            .deserialize_newtype_struct(name, Wrap::new(visitor, Target::Any))
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(
+        self,
+        len: usize,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        self.0
+            .deserialize_tuple(len, Wrap::new(visitor, Target::Any))
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        len: usize,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        self.0
+            .deserialize_tuple_struct(name, len, Wrap::new(visitor, Target::Any))
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        self.0
+            .deserialize_struct(name, fields, Wrap::new(visitor, Target::Any))
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        self.0
+            .deserialize_enum(name, variants, Variants { visitor, variants })
+    }
+
+    fn is_human_readable(&self) -> bool {
+        self.0.is_human_readable()
+    }
+}
+
+impl<'de, V: Visitor<'de>> Visitor<'de> for Wrap<V> {
+    type Value = V::Value;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        self.visitor.expecting(formatter)
+    }
+
+    fn visit_bool<E: de::Error>(self, v: bool) -> Result<Self::Value, E> {
+        if let Target::Str = self.target {
+            return Err(self.not_a_string(format!("the bool {v}")));
+        }
+
+        self.visitor.visit_bool(v)
+    }
+
+    fn visit_i64<E: de::Error>(self, v: i64) -> Result<Self::Value, E> {
+        if let Target::Str = self.target {
+            return Err(self.not_a_string(format!("the number {v}")));
+        }
+
+        self.visitor.visit_i64(v)
+    }
+
+    fn visit_i128<E: de::Error>(self, v: i128) -> Result<Self::Value, E> {
+        self.visitor.visit_i128(v)
+    }
+
+    fn visit_u64<E: de::Error>(self, v: u64) -> Result<Self::Value, E> {
+        if let Target::Str = self.target {
+            return Err(self.not_a_string(format!("the number {v}")));
+        }
+
+        self.visitor.visit_u64(v)
+    }
+
+    fn visit_u128<E: de::Error>(self, v: u128) -> Result<Self::Value, E> {
+        self.visitor.visit_u128(v)
+    }
+
+    fn visit_f64<E: de::Error>(self, v: f64) -> Result<Self::Value, E> {
+        if let Target::Str = self.target {
+            return Err(self.not_a_string(format!("the number {v}")));
+        }
+
+        self.visitor.visit_f64(v)
+    }
+
+    fn visit_char<E: de::Error>(self, v: char) -> Result<Self::Value, E> {
+        self.visitor.visit_char(v)
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<Self::Value, E> {
+        let text = v.trim();
+
+        if !matches!(self.target, Target::Any | Target::Str) && STRICT.get() {
+            return Err(E::custom(format_args!(
+                "expected {}, got the string {v:?} (strict types: write it unquoted, or substitute it with `${{VAR|{}}}`)",
+                Expecting(&self.visitor),
+                self.target.hint()
+            )));
+        }
+
+        match self.target {
+            Target::Bool => match text.to_ascii_lowercase().as_str() {
+                "true" | "yes" | "on" | "1" => self.visitor.visit_bool(true),
+                "false" | "no" | "off" | "0" => self.visitor.visit_bool(false),
+                _ => self.visitor.visit_str(v),
+            },
+            Target::Signed => match text.parse() {
+                Ok(number) => self.visitor.visit_i64(number),
+                Err(_) => self.visitor.visit_str(v),
+            },
+            Target::Unsigned => match text.parse() {
+                Ok(number) => self.visitor.visit_u64(number),
+                Err(_) => self.visitor.visit_str(v),
+            },
+            Target::Float => match text.parse() {
+                Ok(number) => self.visitor.visit_f64(number),
+                Err(_) => self.visitor.visit_str(v),
+            },
+            Target::Any | Target::Str => self.visitor.visit_str(v),
+        }
+    }
+
+    fn visit_borrowed_str<E: de::Error>(self, v: &'de str) -> Result<Self::Value, E> {
+        match self.target {
+            Target::Any | Target::Str => self.visitor.visit_borrowed_str(v),
+            _ => self.visit_str(v),
+        }
+    }
+
+    fn visit_string<E: de::Error>(self, v: String) -> Result<Self::Value, E> {
+        match self.target {
+            Target::Any | Target::Str => self.visitor.visit_string(v),
+            _ => self.visit_str(&v),
+        }
+    }
+
+    fn visit_bytes<E: de::Error>(self, v: &[u8]) -> Result<Self::Value, E> {
+        self.visitor.visit_bytes(v)
+    }
+
+    fn visit_borrowed_bytes<E: de::Error>(self, v: &'de [u8]) -> Result<Self::Value, E> {
+        self.visitor.visit_borrowed_bytes(v)
+    }
+
+    fn visit_byte_buf<E: de::Error>(self, v: Vec<u8>) -> Result<Self::Value, E> {
+        self.visitor.visit_byte_buf(v)
+    }
+
+    fn visit_none<E: de::Error>(self) -> Result<Self::Value, E> {
+        self.visitor.visit_none()
+    }
+
+    fn visit_some<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        self.visitor.visit_some(Lenient(deserializer))
+    }
+
+    fn visit_unit<E: de::Error>(self) -> Result<Self::Value, E> {
+        self.visitor.visit_unit()
+    }
+
+    fn visit_newtype_struct<D: Deserializer<'de>>(
+        self,
+        deserializer: D,
+    ) -> Result<Self::Value, D::Error> {
+        self.visitor.visit_newtype_struct(Lenient(deserializer))
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, seq: A) -> Result<Self::Value, A::Error> {
+        self.visitor.visit_seq(Seq(seq))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<Self::Value, A::Error> {
+        self.visitor.visit_map(Map(map))
+    }
+
+    fn visit_enum<A: EnumAccess<'de>>(self, data: A) -> Result<Self::Value, A::Error> {
+        self.visitor.visit_enum(Enum {
+            data,
+            variants: &[],
+        })
+    }
+}
+
+impl<'de, V: Visitor<'de>> Wrap<V> {
+    // A number or bool, described by `got`, where a string is expected
+    fn not_a_string<E: de::Error>(&self, got: String) -> E {
+        E::custom(format_args!(
+            "expected {}, got {got} (strict types: quote it, or substitute it with `${{VAR|str}}`)",
+            Expecting(&self.visitor)
+        ))
+    }
+}
+
+// What a visitor expects, as its error messages put it
+struct Expecting<'a, V>(&'a V);
+
+impl<'de, V: Visitor<'de>> fmt::Display for Expecting<'_, V> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.expecting(f)
+    }
+}
+
+// A seed deserializing its value leniently
+struct Seed<S>(S);
+
+impl<'de, S: DeserializeSeed<'de>> DeserializeSeed<'de> for Seed<S> {
+    type Value = S::Value;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        self.0.deserialize(Lenient(deserializer))
+    }
+}
+
+struct Seq<A>(A);
+
+impl<'de, A: SeqAccess<'de>> SeqAccess<'de> for Seq<A> {
+    type Error = A::Error;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(
+        &mut self,
+        seed: T,
+    ) -> Result<Option<T::Value>, Self::Error> {
+        self.0.next_element_seed(Seed(seed))
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        self.0.size_hint()
+    }
+}
+
+struct Map<A>(A);
+
+impl<'de, A: MapAccess<'de>> MapAccess<'de> for Map<A> {
+    type Error = A::Error;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, Self::Error> {
+        // Keys convert in strict mode too, `8080: http` is a fine `HashMap<String, _>`
+        let strict = STRICT.replace(false);
+        let key = self.0.next_key_seed(Seed(seed));
+        STRICT.set(strict);
+
+        key
+    }
+
+    fn next_value_seed<T: DeserializeSeed<'de>>(
+        &mut self,
+        seed: T,
+    ) -> Result<T::Value, Self::Error> {
+        self.0.next_value_seed(Seed(seed))
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        self.0.size_hint()
+    }
+}
+
+// The visitor of an enum, which knows its variants
+struct Variants<V> {
+    visitor: V,
+    variants: &'static [&'static str],
+}
+
+impl<'de, V: Visitor<'de>> Visitor<'de> for Variants<V> {
+    type Value = V::Value;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        self.visitor.expecting(formatter)
+    }
+
+    fn visit_enum<A: EnumAccess<'de>>(self, data: A) -> Result<Self::Value, A::Error> {
+        self.visitor.visit_enum(Enum {
+            data,
+            variants: self.variants,
+        })
+    }
+}
+
+struct Enum<A> {
+    data: A,
+    variants: &'static [&'static str],
+}
+
+impl<'de, A: EnumAccess<'de>> EnumAccess<'de> for Enum<A> {
+    type Error = A::Error;
+    type Variant = Variant<A::Variant>;
+
+    fn variant_seed<S: DeserializeSeed<'de>>(
+        self,
+        seed: S,
+    ) -> Result<(S::Value, Self::Variant), Self::Error> {
+        let (value, variant) = self.data.variant_seed(Name {
+            seed,
+            variants: self.variants,
+        })?;
+
+        Ok((value, Variant(variant)))
+    }
+}
+
+struct Variant<A>(A);
+
+impl<'de, A: VariantAccess<'de>> VariantAccess<'de> for Variant<A> {
+    type Error = A::Error;
+
+    fn unit_variant(self) -> Result<(), Self::Error> {
+        self.0.unit_variant()
+    }
+
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(
+        self,
+        seed: T,
+    ) -> Result<T::Value, Self::Error> {
+        self.0.newtype_variant_seed(Seed(seed))
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(
+        self,
+        len: usize,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        self.0.tuple_variant(len, Wrap::new(visitor, Target::Any))
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        self.0
+            .struct_variant(fields, Wrap::new(visitor, Target::Any))
+    }
+}
+
+// Seed of a variant name, given the declared name it matches
+struct Name<S> {
+    seed: S,
+    variants: &'static [&'static str],
+}
+
+impl<'de, S: DeserializeSeed<'de>> DeserializeSeed<'de> for Name<S> {
+    type Value = S::Value;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        self.seed.deserialize(NameDeserializer {
+            deserializer,
+            variants: self.variants,
+        })
+    }
+}
+
+struct NameDeserializer<D> {
+    deserializer: D,
+    variants: &'static [&'static str],
+}
+
+impl<'de, D: Deserializer<'de>> Deserializer<'de> for NameDeserializer<D> {
+    type Error = D::Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        self.deserializer.deserialize_identifier(NameVisitor {
+            visitor,
+            variants: self.variants,
+        })
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string bytes byte_buf
+        option unit unit_struct newtype_struct seq tuple tuple_struct map struct enum
+        identifier ignored_any
+    }
+}
+
+struct NameVisitor<V> {
+    visitor: V,
+    variants: &'static [&'static str],
+}
+
+impl<'de, V: Visitor<'de>> Visitor<'de> for NameVisitor<V> {
+    type Value = V::Value;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        self.visitor.expecting(formatter)
+    }
+
+    fn visit_u64<E: de::Error>(self, v: u64) -> Result<Self::Value, E> {
+        self.visitor.visit_u64(v)
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<Self::Value, E> {
+        self.visitor.visit_str(variant(v, self.variants))
+    }
+
+    fn visit_bytes<E: de::Error>(self, v: &[u8]) -> Result<Self::Value, E> {
+        self.visitor.visit_bytes(v)
+    }
+}
+
+// The declared variant `name` stands for: itself when declared, else the only one equal
+// to it ignoring case, `-` and `_`
+fn variant<'a>(name: &'a str, variants: &'static [&'static str]) -> &'a str {
+    if variants.contains(&name) {
+        return name;
+    }
+
+    let normalized = |name: &str| {
+        name.chars()
+            .filter(|c| !matches!(c, '-' | '_'))
+            .flat_map(char::to_lowercase)
+            .collect::<String>()
+    };
+    let wanted = normalized(name);
+    let mut matching = variants
+        .iter()
+        .filter(|variant| normalized(variant) == wanted);
+
+    match (matching.next(), matching.next()) {
+        (Some(variant), None) => variant,
+        _ => name,
+    }
+}
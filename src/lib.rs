@@ -1,111 +1,1080 @@
+pub mod admin;
+#[cfg(feature = "async")]
+mod async_config;
+mod audit;
+#[cfg(feature = "aws")]
+mod aws;
+mod cache;
+#[cfg(feature = "chaos")]
+pub mod chaos;
+mod console;
+#[cfg(feature = "consul")]
+mod consul;
+mod convert;
+mod crash;
+mod deprecation;
+pub mod dev;
+mod dir;
+mod document;
+mod dotenv;
+mod drift;
+mod env_overlay;
+mod error;
+#[cfg(feature = "etcd")]
+mod etcd;
+#[cfg(feature = "eval")]
+mod eval;
+mod event_log;
+mod events;
+mod exec;
+mod export;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+mod format;
+pub mod fuzz;
+mod gauge;
+mod health;
+mod histogram;
+#[cfg(feature = "http")]
+mod http;
+mod include;
+mod init;
+mod json;
+mod lenient;
+mod limits;
 mod logger;
+mod merge;
+#[cfg(all(feature = "mmap", unix, target_pointer_width = "64"))]
+mod mmap;
+mod overlay;
+mod patch;
+pub mod pipeline;
+mod policy;
+mod preset;
+mod profile;
+mod provenance;
+#[cfg(feature = "python")]
+pub mod python;
+mod render;
+mod rlimit;
+mod save;
+mod schedule;
+mod secret;
+mod secrets_file;
+mod sink;
+#[cfg(feature = "sops")]
+mod sops;
+mod spawn;
+mod startup;
+mod template;
+#[cfg(feature = "toml")]
+mod toml;
+mod trace_id;
+mod validate;
+#[cfg(feature = "vault")]
+mod vault;
+mod watch;
 
 // Reimport
+pub use ::anyhow;
 pub use serde;
+pub use tracing;
+
+/// Map for config fields whose key order matters, e.g. middleware chains
+///
+/// Loading keeps the order of the file through every stage, so entries come out in the
+/// order they were written, overlays appending new keys after existing ones.
+pub use indexmap::IndexMap;
 
 // Own
+#[cfg(feature = "async")]
+pub use async_config::AsyncConfig;
+pub use audit::{set_audit_path, AuditEntry};
+#[cfg(feature = "aws")]
+pub use aws::set_aws_cache_ttl;
+pub use console::{ConsoleParams, Style};
+#[cfg(feature = "consul")]
+pub use consul::{CONSUL_ADDR_VAR, CONSUL_TOKEN_VAR};
+pub use convert::{ConvertError, Fields};
+pub use crash::CrashDumpParams;
+pub use deprecation::{deprecate_section, Deprecation};
 pub use derive_macro::*;
+pub use document::{Document, Provenance};
+pub use dotenv::{dotenv_files, dotenv_source, dotenv_sources, DOTENV_MODE_VAR};
+pub use drift::{drift, Difference, Drift};
+pub use env_overlay::{overlay_var, with_env, EnvOverlay, EnvOverlayGuard};
+pub use error::UnconfigError;
+#[cfg(feature = "etcd")]
+pub use etcd::{ETCD_ENDPOINTS_VAR, ETCD_USER_VAR};
+pub use events::{events, publish_loaded, Event};
+pub use exec::{set_exec_policy, ExecPolicy};
+pub use export::ExportOptions;
+pub use format::{force_format, CustomFormat, FormatGuard, FormatRegistry};
+pub use gauge::{gauges, render_gauges, set_gauge, set_gauge_recorder, GaugeRecorder, GaugeValue};
+pub use health::{health, Health, Provider, Reload, Sink};
+pub use histogram::{span_timings, SpanTimings};
+#[cfg(feature = "http")]
+pub use http::{UrlOptions, CURL_BIN_VAR};
+pub use init::{init_report, init_within, report_init, ConfigInit};
+pub use lenient::{strict_types, StrictTypesGuard};
+pub use limits::{set_limits, Limits};
 pub use logger::*;
+pub use merge::*;
+pub use patch::{Operation, Patch};
+pub use policy::{set_env_policy, EnvPolicy};
+pub use preset::LoggerPreset;
+pub use profile::{load_profile, profile, profile_path, PROFILE_VAR};
+pub use provenance::{provenance_of, track_provenance, Tracking};
+pub use render::{Quoting, RenderOptions};
+pub use rlimit::{apply_resource_limits, ResourceLimits, Rlimit};
+pub use secret::{skip_secret, Secret, SecretString};
+pub use secrets_file::SECRETS_FILE;
+pub use sink::{SinkFormat, SinkId, SinkKind, SinkParams};
+#[cfg(feature = "sops")]
+pub use sops::SOPS_BIN_VAR;
+pub use spawn::{spawn_traced, traced};
+pub use startup::StartupBuffer;
+pub use validate::{Validate, Validator, Violation};
+#[cfg(feature = "vault")]
+pub use vault::{
+    load_vault, VAULT_ADDR_VAR, VAULT_NAMESPACE_VAR, VAULT_ROLE_ID_VAR, VAULT_SECRET_ID_VAR,
+    VAULT_TOKEN_VAR,
+};
+pub use watch::ConfigWatcher;
 
-use std::{env, fs::File, io::BufReader, path::Path, str::FromStr};
+use std::{
+    env,
+    fs::File,
+    io,
+    marker::PhantomData,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::Arc,
+};
 
-use anyhow::{anyhow, Context, Result};
-use serde::de::DeserializeOwned;
-use tracing::trace;
+use anyhow::{anyhow, Result};
+use serde::{de::DeserializeOwned, Serialize};
+use tracing::{debug_span, warn};
+
+use format::Format;
+use pipeline::{load, load_section};
 
 pub trait Config {
-    fn load_str(src: &'static str) -> Result<Self>
+    fn load_str(src: &'static str) -> Result<Self, UnconfigError>
+    where
+        Self: Sized + DeserializeOwned;
+    // Relative paths are looked up in the current directory, `exe:config.yml` next to the
+    // running binary
+    fn load_path<S: AsRef<Path>>(path: S) -> Result<Self, UnconfigError>
+    where
+        Self: Sized + DeserializeOwned;
+    fn load_env<S: AsRef<Path>>(env: &'static str, alt_path: S) -> Result<Self, UnconfigError>
+    where
+        Self: Sized + DeserializeOwned;
+
+    // Same as above, but only the top-level `section` of the source is expanded and deserialized
+    fn load_str_section(src: &'static str, section: &str) -> Result<Self, UnconfigError>
+    where
+        Self: Sized + DeserializeOwned;
+    fn load_path_section<S: AsRef<Path>>(path: S, section: &str) -> Result<Self, UnconfigError>
+    where
+        Self: Sized + DeserializeOwned;
+    fn load_env_section<S: AsRef<Path>>(
+        env: &'static str,
+        alt_path: S,
+        section: &str,
+    ) -> Result<Self, UnconfigError>
+    where
+        Self: Sized + DeserializeOwned;
+
+    // Tree of the files under a directory, one key per file, as Kubernetes mounts a
+    // ConfigMap or Secret
+    fn load_dir<S: AsRef<Path>>(path: S) -> Result<Self, UnconfigError>
+    where
+        Self: Sized + DeserializeOwned;
+    fn load_dir_section<S: AsRef<Path>>(path: S, section: &str) -> Result<Self, UnconfigError>
     where
         Self: Sized + DeserializeOwned;
-    fn load_path<S: AsRef<Path>>(path: S) -> Result<Self>
+
+    // Builds `section` only from `SECTION_FIELD` environment variables, without any file
+    fn load_vars_section(section: &str, fields: &[&str]) -> Result<Self, UnconfigError>
+    where
+        Self: Sized + DeserializeOwned;
+
+    // Builds the whole config from `PREFIX__KEY__NESTED_KEY` environment variables
+    fn load_prefixed(prefix: &str) -> Result<Self, UnconfigError>
     where
         Self: Sized + DeserializeOwned;
-    fn load_env<S: AsRef<Path>>(env: &'static str, alt_path: S) -> Result<Self>
+    fn load_prefixed_section(prefix: &str, section: &str) -> Result<Self, UnconfigError>
     where
         Self: Sized + DeserializeOwned;
+
+    // Fetched from a config server over HTTP, or HTTPS with `curl` (see `CURL_BIN_VAR`),
+    // YAML unless the response says otherwise
+    #[cfg(feature = "http")]
+    fn load_url(url: &str) -> Result<Self, UnconfigError>
+    where
+        Self: Sized + DeserializeOwned;
+    #[cfg(feature = "http")]
+    fn load_url_with(url: &str, options: &UrlOptions) -> Result<Self, UnconfigError>
+    where
+        Self: Sized + DeserializeOwned;
+
+    // Value of a Consul KV key, in the format of its extension and YAML without one. The
+    // agent is `CONSUL_HTTP_ADDR`, with the `CONSUL_HTTP_TOKEN` ACL token
+    #[cfg(feature = "consul")]
+    fn load_consul(key: &str) -> Result<Self, UnconfigError>
+    where
+        Self: Sized + DeserializeOwned;
+    #[cfg(feature = "consul")]
+    fn load_consul_section(key: &str, section: &str) -> Result<Self, UnconfigError>
+    where
+        Self: Sized + DeserializeOwned;
+
+    // Keys under an etcd prefix as a tree, one level per `/` of the key below the prefix.
+    // The endpoints are `ETCDCTL_ENDPOINTS`, with the `ETCDCTL_USER` user
+    #[cfg(feature = "etcd")]
+    fn load_etcd(prefix: &str) -> Result<Self, UnconfigError>
+    where
+        Self: Sized + DeserializeOwned;
+    #[cfg(feature = "etcd")]
+    fn load_etcd_section(prefix: &str, section: &str) -> Result<Self, UnconfigError>
+    where
+        Self: Sized + DeserializeOwned;
+
+    // Written back as YAML, or JSON for `.json` paths, unset `#[configurable]` fields and
+    // secrets left out
+    fn save_str(&self) -> Result<String, UnconfigError>
+    where
+        Self: Serialize;
+    fn save_path<S: AsRef<Path>>(&self, path: S) -> Result<(), UnconfigError>
+    where
+        Self: Serialize;
+
+    // Same as above, but nested under the top-level `section`. The other sections of the
+    // file are kept as written, though without their comments
+    fn save_str_section(&self, section: &str) -> Result<String, UnconfigError>
+    where
+        Self: Serialize;
+    fn save_path_section<S: AsRef<Path>>(
+        &self,
+        path: S,
+        section: &str,
+    ) -> Result<(), UnconfigError>
+    where
+        Self: Serialize;
+
+    // All of the saves above, styled by `options` instead of the defaults
+    fn save_str_with(
+        &self,
+        section: Option<&str>,
+        options: &RenderOptions,
+    ) -> Result<String, UnconfigError>
+    where
+        Self: Serialize;
+    fn save_path_with<S: AsRef<Path>>(
+        &self,
+        path: S,
+        section: Option<&str>,
+        options: &RenderOptions,
+    ) -> Result<(), UnconfigError>
+    where
+        Self: Serialize;
+
+    // The config as loaded, every layer merged and every `${...}` substituted, with
+    // `Secret` values masked, e.g. to print at startup
+    fn dump_effective(&self) -> Result<String, UnconfigError>
+    where
+        Self: Serialize;
+
+    // A copy of the config with `patch` applied, without `${...}` substitution or
+    // validation
+    fn apply_patch(&self, patch: &Patch) -> Result<Self, UnconfigError>
+    where
+        Self: Sized + Serialize + DeserializeOwned;
+
+    // Flattened into `PREFIX__KEY__NESTED_KEY` variables for a child process, which reads
+    // them back with `load_prefixed`. Secrets are left out
+    fn export_env(&self, prefix: &str) -> Result<Vec<(String, String)>, UnconfigError>
+    where
+        Self: Serialize;
+    // Same as above, only the keys and secrets `options` selects
+    fn export_env_with(
+        &self,
+        prefix: &str,
+        options: &ExportOptions,
+    ) -> Result<Vec<(String, String)>, UnconfigError>
+    where
+        Self: Serialize;
 }
 
 impl<T: Sized + DeserializeOwned> Config for T {
-    fn load_env<S: AsRef<Path>>(env: &'static str, alt_path: S) -> Result<Self>
+    fn load_env<S: AsRef<Path>>(env: &'static str, alt_path: S) -> Result<Self, UnconfigError>
     where
         Self: Sized + DeserializeOwned,
     {
-        if let Ok(env_var_path) = env::var(env) {
+        if let Ok(env_var_path) = env_overlay::var(env) {
             Self::load_path(env_var_path)
         } else {
             Self::load_path(alt_path)
         }
     }
 
-    fn load_path<S: AsRef<Path>>(path: S) -> Result<Self>
+    fn load_path<S: AsRef<Path>>(path: S) -> Result<Self, UnconfigError>
+    where
+        Self: Sized + DeserializeOwned,
+    {
+        let (path, params) = read_path(path)?;
+
+        load(
+            &path.display().to_string(),
+            Some(&path),
+            resolve_document(&params),
+        )
+    }
+
+    fn load_str(src: &'static str) -> Result<Self, UnconfigError>
+    where
+        Self: Sized + DeserializeOwned,
+    {
+        let source = EMBEDDED_SOURCE;
+        let params = debug_span!("config_parse", source)
+            .in_scope(|| cache::parse(source, src, Format::of_text(src)))?;
+
+        load(source, None, resolve_document(&params))
+    }
+
+    fn load_env_section<S: AsRef<Path>>(
+        env: &'static str,
+        alt_path: S,
+        section: &str,
+    ) -> Result<Self, UnconfigError>
+    where
+        Self: Sized + DeserializeOwned,
+    {
+        if let Ok(env_var_path) = env_overlay::var(env) {
+            Self::load_path_section(env_var_path, section)
+        } else {
+            Self::load_path_section(alt_path, section)
+        }
+    }
+
+    fn load_path_section<S: AsRef<Path>>(path: S, section: &str) -> Result<Self, UnconfigError>
+    where
+        Self: Sized + DeserializeOwned,
+    {
+        let (path, params) = read_path(path)?;
+
+        load_section(&path.display().to_string(), Some(&path), &params, section)
+    }
+
+    fn load_str_section(src: &'static str, section: &str) -> Result<Self, UnconfigError>
+    where
+        Self: Sized + DeserializeOwned,
+    {
+        let source = EMBEDDED_SOURCE;
+        let params = debug_span!("config_parse", source)
+            .in_scope(|| cache::parse(source, src, Format::of_text(src)))?;
+
+        load_section(source, None, &params, section)
+    }
+
+    fn load_dir<S: AsRef<Path>>(path: S) -> Result<Self, UnconfigError>
+    where
+        Self: Sized + DeserializeOwned,
+    {
+        let (dir, params) = dir::read(path)?;
+
+        load(&dir.display().to_string(), None, resolve_document(&params))
+    }
+
+    fn load_dir_section<S: AsRef<Path>>(path: S, section: &str) -> Result<Self, UnconfigError>
+    where
+        Self: Sized + DeserializeOwned,
+    {
+        let (dir, params) = dir::read(path)?;
+
+        load_section(&dir.display().to_string(), None, &params, section)
+    }
+
+    fn load_vars_section(section: &str, fields: &[&str]) -> Result<Self, UnconfigError>
+    where
+        Self: Sized + DeserializeOwned,
+    {
+        let mut mapping = serde_yaml::Mapping::new();
+
+        for field in fields {
+            let var = format!("{section}_{field}").to_uppercase();
+
+            if !policy::env_policy().permits(&var) {
+                continue;
+            }
+
+            if let Ok(value) = env_overlay::var(&var) {
+                mapping.insert((*field).into(), coerce(value));
+            }
+        }
+
+        let mut params = serde_yaml::Mapping::new();
+        params.insert(section.into(), serde_yaml::Value::Mapping(mapping));
+
+        load("environment", None, serde_yaml::Value::Mapping(params))
+    }
+
+    fn load_prefixed(prefix: &str) -> Result<Self, UnconfigError>
+    where
+        Self: Sized + DeserializeOwned,
+    {
+        load("environment", None, prefixed_vars(prefix))
+    }
+
+    fn load_prefixed_section(prefix: &str, section: &str) -> Result<Self, UnconfigError>
+    where
+        Self: Sized + DeserializeOwned,
+    {
+        load_section("environment", None, &prefixed_vars(prefix), section)
+    }
+
+    #[cfg(feature = "http")]
+    fn load_url(url: &str) -> Result<Self, UnconfigError>
+    where
+        Self: Sized + DeserializeOwned,
+    {
+        Self::load_url_with(url, &UrlOptions::default())
+    }
+
+    #[cfg(feature = "http")]
+    fn load_url_with(url: &str, options: &UrlOptions) -> Result<Self, UnconfigError>
+    where
+        Self: Sized + DeserializeOwned,
+    {
+        let source = url;
+        let (content, format) = debug_span!("config_read", source).in_scope(|| {
+            #[cfg(feature = "chaos")]
+            if let Some(content) = chaos::apply(url).map_err(|e| UnconfigError::io(url, e))? {
+                return Ok((content, Format::Yaml));
+            }
+
+            http::fetch(url, options)
+                .map_err(|e| UnconfigError::io(url, io::Error::other(format!("{e:#}"))))
+        })?;
+        let params = debug_span!("config_parse", source)
+            .in_scope(|| cache::parse(source, &content, format))?;
+
+        load(source, None, resolve_document(&params))
+    }
+
+    #[cfg(feature = "consul")]
+    fn load_consul(key: &str) -> Result<Self, UnconfigError>
     where
         Self: Sized + DeserializeOwned,
     {
-        let full_path = env::current_dir()?.join(
-            &path
-                .as_ref()
-                .file_name()
-                .ok_or(anyhow!("File name is not set"))?,
-        );
+        let (params, _) = consul::read(key)?;
+
+        load(&consul::source(key), None, resolve_document(&params))
+    }
 
-        let path_display = full_path.display();
-        let file = File::open(&full_path)
-            .context(format!("failed to open config file: {path_display}"))?;
-        let reader = BufReader::new(file);
+    #[cfg(feature = "consul")]
+    fn load_consul_section(key: &str, section: &str) -> Result<Self, UnconfigError>
+    where
+        Self: Sized + DeserializeOwned,
+    {
+        let (params, _) = consul::read(key)?;
 
-        load(serde_yaml::from_reader(reader)?)
+        load_section(&consul::source(key), None, &params, section)
     }
 
-    fn load_str(src: &'static str) -> Result<Self>
+    #[cfg(feature = "etcd")]
+    fn load_etcd(prefix: &str) -> Result<Self, UnconfigError>
     where
         Self: Sized + DeserializeOwned,
     {
-        load(serde_yaml::from_str(src)?)
+        let (params, _) = etcd::read(prefix)?;
+
+        load(&etcd::source(prefix), None, resolve_document(&params))
+    }
+
+    #[cfg(feature = "etcd")]
+    fn load_etcd_section(prefix: &str, section: &str) -> Result<Self, UnconfigError>
+    where
+        Self: Sized + DeserializeOwned,
+    {
+        let (params, _) = etcd::read(prefix)?;
+
+        load_section(&etcd::source(prefix), None, &params, section)
+    }
+
+    fn save_str(&self) -> Result<String, UnconfigError>
+    where
+        Self: Serialize,
+    {
+        self.save_str_with(None, &RenderOptions::default())
+    }
+
+    fn save_path<S: AsRef<Path>>(&self, path: S) -> Result<(), UnconfigError>
+    where
+        Self: Serialize,
+    {
+        self.save_path_with(path, None, &RenderOptions::default())
+    }
+
+    fn save_str_section(&self, section: &str) -> Result<String, UnconfigError>
+    where
+        Self: Serialize,
+    {
+        self.save_str_with(Some(section), &RenderOptions::default())
+    }
+
+    fn save_path_section<S: AsRef<Path>>(&self, path: S, section: &str) -> Result<(), UnconfigError>
+    where
+        Self: Serialize,
+    {
+        self.save_path_with(path, Some(section), &RenderOptions::default())
+    }
+
+    fn save_str_with(
+        &self,
+        section: Option<&str>,
+        options: &RenderOptions,
+    ) -> Result<String, UnconfigError>
+    where
+        Self: Serialize,
+    {
+        let value = save::to_value(self)?;
+        let value = match section {
+            Some(section) => save::nested(section, value),
+            None => value,
+        };
+
+        Format::Yaml.render("config", &value, options)
+    }
+
+    fn save_path_with<S: AsRef<Path>>(
+        &self,
+        path: S,
+        section: Option<&str>,
+        options: &RenderOptions,
+    ) -> Result<(), UnconfigError>
+    where
+        Self: Serialize,
+    {
+        save::write(path.as_ref(), section, save::to_value(self)?, options)
+    }
+
+    fn dump_effective(&self) -> Result<String, UnconfigError>
+    where
+        Self: Serialize,
+    {
+        let mut value =
+            serde_yaml::to_value(self).map_err(|e| UnconfigError::Validation(e.to_string()))?;
+        secrets_file::mask(&mut value, true);
+
+        Format::Yaml.render("config", &value, &RenderOptions::default())
+    }
+
+    fn apply_patch(&self, patch: &Patch) -> Result<Self, UnconfigError>
+    where
+        Self: Sized + Serialize + DeserializeOwned,
+    {
+        let mut value = secret::exposing(|| serde_yaml::to_value(self))
+            .map_err(|e| UnconfigError::Validation(e.to_string()))?;
+        patch::apply(&mut value, patch).map_err(UnconfigError::validation)?;
+
+        pipeline::deserialize("patch", &value)
+    }
+
+    fn export_env(&self, prefix: &str) -> Result<Vec<(String, String)>, UnconfigError>
+    where
+        Self: Serialize,
+    {
+        self.export_env_with(prefix, &ExportOptions::default())
+    }
+
+    fn export_env_with(
+        &self,
+        prefix: &str,
+        options: &ExportOptions,
+    ) -> Result<Vec<(String, String)>, UnconfigError>
+    where
+        Self: Serialize,
+    {
+        export::vars(self, prefix, options)
     }
 }
 
-fn load<T: Sized + DeserializeOwned>(mut params: serde_yaml::Value) -> Result<T> {
-    expand_variables(String::new(), &mut params);
+/// Sources stacked in an explicit order, each one overriding the ones before it key by
+/// key at any depth
+///
+/// ```no_run
+/// # use unconfig::ConfigBuilder;
+/// # #[derive(serde::Deserialize)] struct Settings {}
+/// let settings: Settings = ConfigBuilder::new()
+///     .embedded(include_str!("../config.yml"))
+///     .optional_file("local.yml")
+///     .env("APP")
+///     .overrides(std::env::args().skip(1))
+///     .build()?;
+/// # Ok::<_, anyhow::Error>(())
+/// ```
+///
+/// The merged tree goes through the same pipeline as [`Config`]: overlays, `${...}`
+/// substitution, scheduled values and limits.
+pub struct ConfigBuilder<T> {
+    sources: Vec<Source>,
+    section: Option<String>,
+    _config: PhantomData<fn() -> T>,
+}
 
-    let config = serde_yaml::to_string(&params)?;
-    let params: Result<T, serde_yaml::Error> = serde_yaml::from_str(&config);
+enum Source {
+    Embedded(String),
+    File {
+        path: PathBuf,
+        required: bool,
+    },
+    Dir {
+        path: PathBuf,
+        required: bool,
+    },
+    Env(String),
+    Prefixed(String),
+    Set(String, String),
+    #[cfg(feature = "etcd")]
+    Etcd(String),
+}
 
-    if let Ok("1") = env::var("DEBUG_CONFIG").as_deref() {
-        trace!("Full processed config:\n{config}");
+impl<T: DeserializeOwned> Default for ConfigBuilder<T> {
+    fn default() -> Self {
+        Self::new()
     }
+}
 
-    if let Err(e) = &params {
-        if let Some(location) = e.location() {
-            let start = location.line().saturating_sub(5);
-            let end = location.line() + 5;
-            let mut msg = format!(
-                "{e}\nRelevant part of the config (set DEBUG_CONFIG=1 to print full config):\n",
-            );
+impl<T: DeserializeOwned> ConfigBuilder<T> {
+    pub fn new() -> Self {
+        Self {
+            sources: vec![],
+            section: None,
+            _config: PhantomData,
+        }
+    }
 
-            for (index, line) in config.lines().enumerate().skip(start).take(end - start) {
-                let tag0 = if index + 1 == location.line() {
-                    "\x1b[31;1m"
-                } else {
-                    ""
-                };
+    /// Config text, YAML or any other enabled format
+    pub fn embedded(mut self, src: &str) -> Self {
+        self.sources.push(Source::Embedded(src.to_string()));
+        self
+    }
 
-                let tag1 = if index + 1 == location.line() {
-                    "\x1b[0m"
-                } else {
-                    ""
-                };
+    /// Config file, looked up like [`Config::load_path`] does, failing the build when missing
+    pub fn file<S: AsRef<Path>>(mut self, path: S) -> Self {
+        self.sources.push(Source::File {
+            path: path.as_ref().to_path_buf(),
+            required: true,
+        });
+        self
+    }
+
+    /// Same as [`Self::file`], but skipped when the file doesn't exist
+    pub fn optional_file<S: AsRef<Path>>(mut self, path: S) -> Self {
+        self.sources.push(Source::File {
+            path: path.as_ref().to_path_buf(),
+            required: false,
+        });
+        self
+    }
+
+    /// Directory of files, one key per file, as a Kubernetes ConfigMap or Secret is
+    /// mounted, see [`Config::load_dir`], failing the build when missing
+    pub fn dir<S: AsRef<Path>>(mut self, path: S) -> Self {
+        self.sources.push(Source::Dir {
+            path: path.as_ref().to_path_buf(),
+            required: true,
+        });
+        self
+    }
+
+    /// Same as [`Self::dir`], but skipped when the directory doesn't exist
+    pub fn optional_dir<S: AsRef<Path>>(mut self, path: S) -> Self {
+        self.sources.push(Source::Dir {
+            path: path.as_ref().to_path_buf(),
+            required: false,
+        });
+        self
+    }
+
+    /// `PREFIX_KEY` environment variables, as the lowercase top-level `key`
+    pub fn env(mut self, prefix: &str) -> Self {
+        self.sources.push(Source::Env(prefix.to_string()));
+        self
+    }
+
+    /// `PREFIX__KEY__NESTED_KEY` environment variables, at any depth
+    pub fn prefixed(mut self, prefix: &str) -> Self {
+        self.sources.push(Source::Prefixed(prefix.to_string()));
+        self
+    }
+
+    /// Keys under an etcd prefix, see [`Config::load_etcd`], failing the build when there
+    /// are none
+    #[cfg(feature = "etcd")]
+    pub fn etcd(mut self, prefix: &str) -> Self {
+        self.sources.push(Source::Etcd(prefix.to_string()));
+        self
+    }
+
+    /// One value at a dotted path, e.g. `server.port`
+    pub fn set(mut self, path: &str, value: impl Into<String>) -> Self {
+        self.sources
+            .push(Source::Set(path.to_string(), value.into()));
+        self
+    }
+
+    /// `path=value` overrides, e.g. from the command line, anything else is ignored
+    pub fn overrides<I, S>(mut self, overrides: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        for item in overrides {
+            if let Some((path, value)) = item.as_ref().split_once('=') {
+                self = self.set(path.trim_start_matches('-'), value);
+            }
+        }
+
+        self
+    }
+
+    /// Only deserialize the top-level `section` of the merged sources
+    pub fn section(mut self, section: &str) -> Self {
+        self.section = Some(section.to_string());
+        self
+    }
+
+    pub fn build(self) -> Result<T, UnconfigError> {
+        let mut params = serde_yaml::Value::Mapping(Default::default());
+        // Relative paths in values resolve against the last file
+        let mut origin = None;
+
+        for source in self.sources {
+            let layer = match source {
+                Source::Embedded(src) => {
+                    let source = EMBEDDED_SOURCE;
+                    debug_span!("config_parse", source)
+                        .in_scope(|| cache::parse(source, &src, Format::of_text(&src)))?
+                        .as_ref()
+                        .clone()
+                }
+                Source::File { path, required } => {
+                    if !required && !full_path(&path)?.exists() {
+                        continue;
+                    }
+
+                    let (path, layer) = read_path(path)?;
+                    origin = Some(path);
+
+                    layer.as_ref().clone()
+                }
+                Source::Dir { path, required } => {
+                    if !required && !full_path(&path)?.exists() {
+                        continue;
+                    }
+
+                    dir::read(path)?.1
+                }
+                Source::Env(prefix) => {
+                    let prefix = format!("{}_", prefix.to_uppercase());
+                    let mut mapping = serde_yaml::Mapping::new();
+
+                    for (var, value) in env_overlay::vars() {
+                        let Some(key) = var.strip_prefix(&prefix) else {
+                            continue;
+                        };
+
+                        if policy::env_policy().permits(&var) {
+                            mapping.insert(key.to_lowercase().into(), coerce(value));
+                        }
+                    }
+
+                    serde_yaml::Value::Mapping(mapping)
+                }
+                Source::Prefixed(prefix) => prefixed_vars(&prefix),
+                #[cfg(feature = "etcd")]
+                Source::Etcd(prefix) => etcd::read(&prefix)?.0.as_ref().clone(),
+                Source::Set(path, value) => path.rsplit('.').fold(coerce(value), |acc, key| {
+                    let mut mapping = serde_yaml::Mapping::new();
+                    mapping.insert(key.into(), acc);
+
+                    serde_yaml::Value::Mapping(mapping)
+                }),
+            };
+
+            overlay::deep_merge(&mut params, layer);
+        }
+
+        match &self.section {
+            Some(section) => load_section("builder", origin.as_deref(), &params, section),
+            None => load("builder", origin.as_deref(), resolve_document(&params)),
+        }
+    }
+}
+
+/// Tree of the `PREFIX__KEY__NESTED_KEY` environment variables, keys lowercased
+///
+/// `__` separates levels, single underscores stay in the keys, so
+/// `APP__DATABASE__MAX_CONNECTIONS=10` is `database.max_connections`. Levels keyed
+/// `0`, `1`, ... become sequences: `APP__SERVERS__0__HOST`.
+fn prefixed_vars(prefix: &str) -> serde_yaml::Value {
+    let prefix = format!("{}__", prefix.to_uppercase());
+    let mut tree = serde_yaml::Value::Mapping(Default::default());
+    let mut vars = env_overlay::vars()
+        .into_iter()
+        .filter(|(var, _)| var.starts_with(&prefix) && policy::env_policy().permits(var))
+        .collect::<Vec<_>>();
+    // Stable order for the sequences below
+    vars.sort();
+
+    for (var, value) in vars {
+        let path = var[prefix.len()..].to_lowercase();
+        let layer = path.rsplit("__").fold(coerce(value), |acc, key| {
+            let mut mapping = serde_yaml::Mapping::new();
+            mapping.insert(key.into(), acc);
+
+            serde_yaml::Value::Mapping(mapping)
+        });
+
+        overlay::deep_merge(&mut tree, layer);
+    }
+
+    sequences(&mut tree);
+
+    tree
+}
+
+// Mappings keyed by every index from 0 into sequences, at any depth
+fn sequences(value: &mut serde_yaml::Value) {
+    let serde_yaml::Value::Mapping(mapping) = value else {
+        return;
+    };
+
+    mapping.values_mut().for_each(sequences);
+
+    let mut indexed = mapping
+        .iter()
+        .map(|(key, _)| key.as_str().and_then(|key| key.parse::<usize>().ok()))
+        .collect::<Option<Vec<_>>>()
+        .unwrap_or_default();
+    indexed.sort_unstable();
+
+    if !indexed.is_empty() && indexed.iter().enumerate().all(|(i, index)| i == *index) {
+        let mut items = std::mem::take(mapping)
+            .into_iter()
+            .map(|(key, value)| (key.as_str().and_then(|key| key.parse().ok()), value))
+            .collect::<Vec<(Option<usize>, _)>>();
+        items.sort_by_key(|(index, _)| *index);
+
+        *value = serde_yaml::Value::Sequence(items.into_iter().map(|(_, value)| value).collect());
+    }
+}
+
+// Paths under it are relative to the directory of the running binary, for deployments
+// whose current directory is unpredictable
+const EXE_PREFIX: &str = "exe:";
+
+// Relative config paths are looked up in the current directory, of the host the binary
+// runs on rather than the one it was built on, or next to the binary with `exe:`
+fn full_path<S: AsRef<Path>>(path: S) -> Result<PathBuf, UnconfigError> {
+    let path = path.as_ref();
 
-                let inc = index + 1;
-                msg += format!("{tag0}{inc:>3}: {line}{tag1}\n").as_str();
+    if path.file_name().is_none() {
+        return Err(UnconfigError::io(
+            path,
+            io::Error::new(io::ErrorKind::InvalidInput, "file name is not set"),
+        ));
+    }
+
+    if let Some(relative) = path.to_str().and_then(|path| path.strip_prefix(EXE_PREFIX)) {
+        let exe = env::current_exe().map_err(|e| UnconfigError::io(path, e))?;
+        let dir = exe.parent().unwrap_or(Path::new("/"));
+
+        return Ok(dir.join(relative));
+    }
+
+    if path.is_absolute() {
+        return Ok(path.to_path_buf());
+    }
+
+    Ok(env::current_dir()
+        .map_err(|e| UnconfigError::io(path, e))?
+        .join(path))
+}
+
+// A config file with the secrets files next to it merged in
+fn read_path<S: AsRef<Path>>(path: S) -> Result<(PathBuf, Arc<serde_yaml::Value>), UnconfigError> {
+    let (path, params) = read_file(path)?;
+    let params = secrets_file::overlay(&path, params)?;
+
+    Ok((path, params))
+}
+
+fn read_file<S: AsRef<Path>>(path: S) -> Result<(PathBuf, Arc<serde_yaml::Value>), UnconfigError> {
+    let full_path = full_path(path)?;
+    let source = full_path.display().to_string();
+
+    let content = debug_span!("config_read", source).in_scope(|| {
+        #[cfg(feature = "chaos")]
+        if let Some(content) =
+            chaos::apply(&source).map_err(|e| UnconfigError::io(&full_path, e))?
+        {
+            return Ok(Content::Owned(content));
+        }
+
+        let file = File::open(&full_path).map_err(|e| UnconfigError::io(&full_path, e))?;
+        let size = file
+            .metadata()
+            .map_err(|e| UnconfigError::io(&full_path, e))?
+            .len();
+        limits::limits()
+            .check_file_size(&source, size)
+            .map_err(UnconfigError::validation)?;
+
+        Content::read(file).map_err(|e| UnconfigError::io(&full_path, e))
+    })?;
+    let content = content
+        .as_str()
+        .map_err(|e| UnconfigError::io(&full_path, e))?;
+    let params =
+        debug_span!("config_parse", source).in_scope(|| cache::parse_file(&full_path, content))?;
+    #[cfg(feature = "sops")]
+    let params = sops::decrypt(&full_path, content, params)?;
+    let params = include::resolve(&full_path, params).map_err(UnconfigError::validation)?;
+
+    Ok((full_path, params))
+}
+
+// Raw text of a config file, mapped into memory when the `mmap` feature is enabled and
+// it is at least `MMAP_THRESHOLD` long
+enum Content {
+    Owned(String),
+    #[cfg(all(feature = "mmap", unix, target_pointer_width = "64"))]
+    Mapped(mmap::Mmap),
+}
+
+impl Content {
+    fn read(file: File) -> io::Result<Self> {
+        #[cfg(all(feature = "mmap", unix, target_pointer_width = "64"))]
+        if file.metadata()?.len() >= mmap::MMAP_THRESHOLD {
+            if let Ok(mapped) = mmap::Mmap::map(&file) {
+                return Ok(Self::Mapped(mapped));
             }
+        }
 
-            return Err(anyhow!("{msg}"));
+        io::read_to_string(file).map(Self::Owned)
+    }
+
+    fn as_str(&self) -> io::Result<&str> {
+        match self {
+            Self::Owned(content) => Ok(content),
+            #[cfg(all(feature = "mmap", unix, target_pointer_width = "64"))]
+            Self::Mapped(mapped) => std::str::from_utf8(mapped).map_err(|_| {
+                io::Error::new(io::ErrorKind::InvalidData, "config file is not valid UTF-8")
+            }),
         }
+    }
+}
 
-        return Err(anyhow!("{e} (set DEBUG_CONFIG=1 to print full config)"));
+// Whole document with the overlays for this machine merged in
+fn resolve_document(params: &serde_yaml::Value) -> serde_yaml::Value {
+    let mut document = params.clone();
+
+    for overlay in overlay::matching(params) {
+        overlay::deep_merge(&mut document, overlay.clone());
     }
 
-    Ok(params?)
+    if let serde_yaml::Value::Mapping(mapping) = &mut document {
+        for key in overlay::OVERLAY_KEYS {
+            mapping.shift_remove(key);
+        }
+    }
+
+    document
+}
+
+// Keep only the requested top-level key, so the rest of the tree is neither expanded nor deserialized
+fn extract_section(params: &serde_yaml::Value, section: &str) -> serde_yaml::Value {
+    let mut mapping = serde_yaml::Mapping::new();
+    let mut value = params.get(section).cloned();
+
+    for overlay in overlay::matching(params) {
+        if let Some(overlay) = overlay.get(section) {
+            match value.as_mut() {
+                Some(value) => overlay::deep_merge(value, overlay.clone()),
+                None => value = Some(overlay.clone()),
+            }
+        }
+    }
+
+    if let Some(value) = value {
+        mapping.insert(section.into(), value);
+    }
+    // For the `!use`s of the section, dropped once they are resolved
+    if let Some(templates) = params.get(template::TEMPLATES_KEY) {
+        mapping.insert(template::TEMPLATES_KEY.into(), templates.clone());
+    }
+
+    serde_yaml::Value::Mapping(mapping)
+}
+
+/// Source name of configs given as text
+pub(crate) const EMBEDDED_SOURCE: &str = "embedded";
+
+// Substitute variables, then check the result against the limits and the env policy
+fn expand(source: &str, origin: Option<&Path>, params: &mut serde_yaml::Value) -> Result<()> {
+    // Before too, so that oversized trees are rejected without walking them again
+    limits::limits().check_tree(source, params)?;
+    // Embedded configs ship with the code reading them
+    if source != EMBEDDED_SOURCE {
+        deprecation::check(source, params)?;
+    }
+    template::resolve(params).map_err(|e| e.context(format!("{source}: templates")))?;
+
+    let mut expansion = Expansion::new(origin);
+    #[cfg(feature = "aws")]
+    {
+        expansion.aws =
+            aws::resolve(params).map_err(|e| e.context(format!("{source}: AWS references")))?;
+    }
+    debug_span!("config_expand", source)
+        .in_scope(|| expand_variables(String::new(), params, &mut expansion));
+
+    limits::limits().check_tree(source, params)?;
+
+    if !expansion.denied.is_empty() {
+        let denied = expansion.denied.join(", ");
+
+        if policy::env_policy().is_strict() {
+            return Err(anyhow!(
+                "{source}: environment variables are not allowed by the policy: {denied}"
+            ));
+        }
+
+        warn!("{source}: ignored environment variables not allowed by the policy: {denied}");
+    }
+
+    if let Some((var, message)) = expansion.missing.into_iter().next() {
+        return Err(UnconfigError::EnvMissing {
+            file: source.to_string(),
+            var,
+            message,
+        }
+        .into());
+    }
+
+    if !expansion.failed.is_empty() {
+        return Err(anyhow!(
+            "{source}: failed to substitute variables: {}",
+            expansion.failed.join(", ")
+        ));
+    }
+
+    Ok(())
 }
 
 /// This function is used for scan every config's string parameter and replace environment variables inside
@@ -114,6 +1083,20 @@ fn load<T: Sized + DeserializeOwned>(mut params: serde_yaml::Value) -> Result<T>
 ///
 /// * `/mypath/${ENV_VAR_NAME}/bla/bla`
 /// * `My name is ${APP_NAME}. I have version ${APP_VERSION}`
+/// * `${ssm:/app/prod/db_url}`, `${secretsmanager:app/db#password}` with the `aws` feature
+/// * `${exec:vault read -field=password secret/db}`, the output of a command the
+///   [`ExecPolicy`] allows
+/// * `${PORT:-8080}`, the default for an unset or empty variable
+/// * `${DATABASE_URL:?set it to the primary}`, failing the load when unset or empty
+/// * `${PORT|int:-8080}`, `${RATIO|float}`, `${DEBUG|bool:-false}`, `${TAG|str}`, read
+///   as that type and failing the load when they aren't. Values without a hint stay
+///   strings, which the field's type converts when deserialized
+/// * `${PREFIX:${FALLBACK_PREFIX:/srv}}/data`, references nested in defaults, messages,
+///   commands and names (`${${ENV}_URL}`)
+///
+/// A variable whose value has references of its own is substituted too, up to
+/// `CONFIG_MAX_EXPANSION_DEPTH` levels, and variables referencing each other fail the
+/// load.
 ///
 /// # String examples without replacement
 ///
@@ -121,106 +1104,500 @@ fn load<T: Sized + DeserializeOwned>(mut params: serde_yaml::Value) -> Result<T>
 /// * `My name is \${WHAT_IS_MY_NAME}`
 ///
 /// Be aware: in `yml` files you must use `\\` for a single backslash. So every backslash in these examples actually must be doubled.
-fn subst_env_variable(env_path: &str, value: &str) -> String {
-    let path_var = match env::var(env_path) {
+fn subst_env_variable(env_path: &str, value: &str, expansion: &mut Expansion) -> String {
+    match expansion.implicit_var(env_path) {
         // If env_path by full path of varialble was presented
         // Return it first
-        Ok(v) => v,
+        Some(v) => v,
         // Otherwise, we check the environment variables specified explicitly
-        Err(_) => {
-            let mut acc = String::with_capacity(value.len());
-            let mut split = value.split("${");
-
-            // split always has at least a single value
-            acc.push_str(split.next().unwrap_or_default());
-
-            split.for_each(|part| {
-                // check if `${` was prefixed with escaping slash `\`
-                if acc.ends_with("\\\\") {
-                    // if `${` was prefixed by double escaping char
-                    // then it is escaping char for escaping char => we must remove last one
-                    acc.pop();
-                } else if acc.ends_with('\\') {
-                    // if it was prefixed by `\`, then delete that escaping character
-                    acc.pop();
-
-                    // and skip all the logic of env variable replacement
+        None => expansion.substitute(value, 0),
+    }
+}
+
+// Length of the `${...}` content at the start of `text`, up to the `}` closing it
+fn closing_brace(text: &str) -> Option<usize> {
+    let mut depth = 0;
+    let mut escaped = false;
+    let mut chars = text.char_indices().peekable();
+
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '$' if !escaped && chars.next_if(|&(_, c)| c == '{').is_some() => depth += 1,
+            '}' if depth == 0 => return Some(i),
+            '}' => depth -= 1,
+            _ => {}
+        }
+        escaped = c == '\\' && !escaped;
+    }
+
+    None
+}
+
+// Whether `text` is a single `${...}` reference and nothing else
+fn is_sole_reference(text: &str) -> bool {
+    text.strip_prefix("${")
+        .and_then(closing_brace)
+        .is_some_and(|end| end + 3 == text.len())
+}
+
+// `name:content` of a reference, split at the first `:` outside of nested references
+fn split_reference(inner: &str) -> (&str, Option<&str>) {
+    let mut rest = inner;
+    let mut offset = 0;
+
+    while let Some(i) = rest.find([':', '$']) {
+        if rest[i..].starts_with(':') {
+            let i = offset + i;
+
+            return (&inner[..i], Some(&inner[i + 1..]));
+        }
+
+        // Skip a nested `${...}` as a whole
+        let skip = match rest[i + 1..].strip_prefix('{').and_then(closing_brace) {
+            Some(end) => i + end + 3,
+            None => i + 1,
+        };
+        offset += skip;
+        rest = &rest[skip..];
+    }
+
+    (inner, None)
+}
+
+const BUILTIN_PREFIX: &str = "unconfig";
+
+/// Type a reference is read as, `${PORT|int}` or `${PORT|int:-8080}`
+#[derive(Debug, Clone, Copy)]
+enum Hint {
+    Int,
+    Float,
+    Bool,
+    Str,
+}
+
+impl Hint {
+    // The name of a reference without its hint, `PORT` and `int` for `PORT|int`
+    fn split(name: &str) -> (&str, Option<Self>) {
+        let Some((rest, hint)) = name.rsplit_once('|') else {
+            return (name, None);
+        };
+
+        let hint = match hint {
+            "int" => Self::Int,
+            "float" => Self::Float,
+            "bool" => Self::Bool,
+            "str" => Self::Str,
+            _ => return (name, None),
+        };
+
+        (rest, Some(hint))
+    }
+
+    // An empty value is null, for optional fields
+    fn convert(self, text: &str) -> Result<serde_yaml::Value, String> {
+        use serde_yaml::{Number, Value};
+
+        if text.is_empty() && !matches!(self, Self::Str) {
+            return Ok(Value::Null);
+        }
+
+        let invalid = || format!("expected {}, got `{text}`", self.describe());
+        match self {
+            Self::Int => match (text.parse::<u64>(), text.parse::<i64>()) {
+                (Ok(n), _) => Ok(Value::Number(Number::from(n))),
+                (_, Ok(n)) => Ok(Value::Number(Number::from(n))),
+                _ => Err(invalid()),
+            },
+            Self::Float => text
+                .parse::<f64>()
+                .map(|n| Value::Number(Number::from(n)))
+                .map_err(|_| invalid()),
+            Self::Bool => match text.to_ascii_lowercase().as_str() {
+                "true" => Ok(Value::Bool(true)),
+                "false" => Ok(Value::Bool(false)),
+                _ => Err(invalid()),
+            },
+            Self::Str => Ok(Value::String(text.to_string())),
+        }
+    }
+
+    fn describe(self) -> &'static str {
+        match self {
+            Self::Int => "an integer",
+            Self::Float => "a number",
+            Self::Bool => "`true` or `false`",
+            Self::Str => "a string",
+        }
+    }
+}
+
+const EXEC_PREFIX: &str = "exec";
+
+/// Values of `${unconfig:<name>}` builtins
+///
+/// * `config_path` - the file the value was read from
+/// * `config_dir` - the directory of that file
+///
+/// Values that don't come from a file (embedded or environment) resolve both against the
+/// current directory, where the runtime file is looked up.
+fn builtin(name: &str, origin: Option<&Path>) -> Option<String> {
+    let cwd = env::current_dir().unwrap_or_default();
+
+    let path = match name {
+        "config_path" => origin.map(Path::to_path_buf).unwrap_or(cwd),
+        "config_dir" => origin
+            .and_then(Path::parent)
+            .map(Path::to_path_buf)
+            .unwrap_or(cwd),
+        _ => return None,
+    };
+
+    Some(path.display().to_string())
+}
+
+// Substituted values are plain strings, give them back the type they look like. Only
+// when written as the number would be, so text like `1.50` or `0123` stays as it is for
+// string fields, and number fields still parse it when deserialized
+fn coerce(v: String) -> serde_yaml::Value {
+    use serde_yaml::*;
+
+    let number = match (u64::from_str(&v), f64::from_str(&v)) {
+        (Ok(n), _) => Some(Number::from(n)),
+        (_, Ok(n)) => Some(Number::from(n)),
+        _ => None,
+    };
+    if let Some(number) = number.filter(|number| number.to_string() == v) {
+        return Value::Number(number);
+    }
+
+    if let Ok(v) = bool::from_str(&v) {
+        return Value::Bool(v);
+    }
+
+    Value::String(v)
+}
+
+// State of one expansion pass over a config
+struct Expansion<'a> {
+    // Where the values come from, for `${unconfig:...}` builtins
+    origin: Option<&'a Path>,
+    // Explicitly referenced variables rejected by the env policy
+    denied: Vec<String>,
+    // Output of each `${exec:...}` command, run once per pass
+    commands: std::collections::HashMap<Vec<String>, String>,
+    // Commands that couldn't run and references that couldn't be substituted, with the
+    // reason
+    failed: Vec<String>,
+    // Variables whose values are being substituted, outermost first
+    expanding: Vec<String>,
+    // Value of the last outermost reference with a type hint, as that type
+    typed: Option<serde_yaml::Value>,
+    // `${VAR:?message}` variables that are unset, with their messages
+    missing: Vec<(String, String)>,
+    // Values of the `${ssm:...}` and `${secretsmanager:...}` references, fetched first
+    #[cfg(feature = "aws")]
+    aws: std::collections::HashMap<String, String>,
+}
+
+impl<'a> Expansion<'a> {
+    fn new(origin: Option<&'a Path>) -> Self {
+        Self {
+            origin,
+            denied: vec![],
+            commands: Default::default(),
+            failed: vec![],
+            expanding: vec![],
+            typed: None,
+            missing: vec![],
+            #[cfg(feature = "aws")]
+            aws: Default::default(),
+        }
+    }
+
+    // `text` with its `${...}` references substituted, `depth` levels into other references
+    fn substitute(&mut self, text: &str, depth: usize) -> String {
+        let mut acc = String::with_capacity(text.len());
+        let mut rest = text;
+
+        while let Some(start) = rest.find("${") {
+            acc.push_str(&rest[..start]);
+            rest = &rest[start + 2..];
+
+            // check if `${` was prefixed with escaping slash `\`
+            if acc.ends_with("\\\\") {
+                // if `${` was prefixed by double escaping char
+                // then it is escaping char for escaping char => we must remove last one
+                acc.pop();
+            } else if acc.ends_with('\\') {
+                // if it was prefixed by `\`, then delete that escaping character
+                // and skip all the logic of env variable replacement
+                acc.pop();
+                acc.push_str("${");
+                continue;
+            }
+
+            let Some(end) = closing_brace(rest) else {
+                // if no closing bracket were found, then just appending raw content
+                acc.push_str("${");
+                continue;
+            };
+            let inner = &rest[..end];
+            rest = &rest[end + 1..];
+
+            match self.reference_value(inner, depth) {
+                Some(v) => acc.push_str(&v),
+                // Kept as written
+                None => {
                     acc.push_str("${");
-                    acc.push_str(part);
-                    return;
+                    acc.push_str(inner);
+                    acc.push('}');
                 }
+            }
+        }
+        acc.push_str(rest);
 
-                if let Some((varname, tail)) = part.split_once('}') {
-                    // trim ":" prefix
-                    let varname = varname.split_once(':');
+        acc
+    }
 
-                    if let Some((value, content)) = varname {
-                        match env::var(value) {
-                            Ok(v) => {
-                                acc.push_str(&v);
-                            }
-                            Err(_) => acc.push_str(content),
-                        }
-                    }
+    // Value of the `${inner}` reference, `None` to keep it as it is
+    fn reference_value(&mut self, inner: &str, depth: usize) -> Option<String> {
+        let max_depth = limits::limits().max_expansion_depth;
+        if depth >= max_depth {
+            self.fail(format!(
+                "`${{{inner}}}` is nested deeper than {max_depth} levels (CONFIG_MAX_EXPANSION_DEPTH)"
+            ));
 
-                    acc.push_str(tail);
-                } else {
-                    // if no closing bracket were found, then just appending raw content
-                    acc.push_str("${");
-                    acc.push_str(part);
+            return None;
+        }
+
+        let (name, content) = split_reference(inner);
+        let (name, hint) = Hint::split(name);
+        // `${${ENV}_URL}`, a name built from other variables
+        let name = match name.contains("${") {
+            true => self.substitute(name, depth + 1),
+            false => name.to_string(),
+        };
+
+        let v = self.untyped_value(&name, content, depth)?;
+        match hint {
+            Some(hint) => self.hinted(inner, hint, v, depth),
+            None => Some(v),
+        }
+    }
+
+    // Value of a `${name:content}` reference as text, `None` to keep it as it is
+    fn untyped_value(&mut self, name: &str, content: Option<&str>, depth: usize) -> Option<String> {
+        let Some(content) = content else {
+            return Some(self.var_value(name, depth).unwrap_or_default());
+        };
+
+        if name == BUILTIN_PREFIX {
+            // Unknown builtins are kept as they are
+            return builtin(content, self.origin);
+        }
+        if name == EXEC_PREFIX {
+            // Split before substituting, so that each value stays a single argument
+            let words = match exec::split(content) {
+                Ok(words) => words,
+                Err(e) => {
+                    self.fail(e.to_string());
+
+                    return Some(String::new());
                 }
+            };
+            let words = words
+                .iter()
+                .map(|word| self.substitute(word, depth + 1))
+                .collect::<Vec<_>>();
+
+            // A command that failed fails the whole load
+            return Some(self.exec(words).unwrap_or_default());
+        }
+        if let Some(v) = self.reference(name, content) {
+            return Some(v);
+        }
+
+        self.var_text(name, content, depth)
+    }
+
+    // Value of the variable `name` of a `${name:content}` reference
+    fn var_text(&mut self, name: &str, content: &str, depth: usize) -> Option<String> {
+        let v = self.var_value(name, depth);
+
+        if let Some(default) = content.strip_prefix('-') {
+            // `${VAR:-default}`, also for an empty variable
+            return Some(match v.filter(|v| !v.is_empty()) {
+                Some(v) => v,
+                None => self.substitute(default, depth + 1),
             });
+        }
+        if let Some(message) = content.strip_prefix('?') {
+            // `${VAR:?message}`, failing the load when unset or empty
+            return Some(match v.filter(|v| !v.is_empty()) {
+                Some(v) => v,
+                None => {
+                    let message = self.substitute(message, depth + 1);
+                    self.require(name, &message);
 
-            acc
+                    String::new()
+                }
+            });
         }
-    };
 
-    path_var
-}
+        Some(match v {
+            Some(v) => v,
+            None => self.substitute(content, depth + 1),
+        })
+    }
 
-fn expand_variables(env_path: String, value: &mut serde_yaml::Value) {
-    use serde_yaml::*;
+    // `v` checked against the `hint` of the `${inner}` reference
+    fn hinted(&mut self, inner: &str, hint: Hint, v: String, depth: usize) -> Option<String> {
+        match hint.convert(&v) {
+            Ok(value) => {
+                // Nested references don't type the value around them
+                if depth == 0 {
+                    self.typed = Some(value);
+                }
 
-    match value {
-        Value::String(text) => {
-            // Remove first dot symbol
-            let env_path = &env_path[1..];
-            let v = subst_env_variable(env_path, text.as_str());
+                Some(v)
+            }
+            Err(e) => {
+                self.fail(format!("`${{{inner}}}`: {e}"));
 
-            if v == *text {
-                return;
+                None
             }
+        }
+    }
 
-            if let Ok(v) = u64::from_str(&v) {
-                *value = Value::Number(v.into());
-                return;
+    // Variable referenced as `${NAME}`, with the references in its value substituted
+    fn var_value(&mut self, name: &str, depth: usize) -> Option<String> {
+        let v = self.var(name)?;
+        if !v.contains("${") {
+            return Some(v);
+        }
+
+        if self.expanding.iter().any(|expanding| expanding == name) {
+            self.fail(format!(
+                "variables reference each other: {} -> {name}",
+                self.expanding.join(" -> ")
+            ));
+
+            return None;
+        }
+
+        self.expanding.push(name.to_string());
+        let v = self.substitute(&v, depth + 1);
+        self.expanding.pop();
+
+        Some(v)
+    }
+
+    fn fail(&mut self, e: String) {
+        if !self.failed.contains(&e) {
+            self.failed.push(e);
+        }
+    }
+
+    // Value of an `${ssm:...}` or `${secretsmanager:...}` reference
+    fn reference(&self, kind: &str, name: &str) -> Option<String> {
+        #[cfg(feature = "aws")]
+        return self.aws.get(&format!("{kind}:{name}")).cloned();
+
+        #[cfg(not(feature = "aws"))]
+        {
+            let _ = (kind, name);
+
+            None
+        }
+    }
+
+    // Variable of a `${VAR:?message}` that is unset
+    fn require(&mut self, name: &str, message: &str) {
+        if !self.missing.iter().any(|(missing, _)| missing == name) {
+            self.missing.push((name.to_string(), message.to_string()));
+        }
+    }
+
+    // Output of an `${exec:...}` command
+    fn exec(&mut self, words: Vec<String>) -> Option<String> {
+        if let Some(output) = self.commands.get(&words) {
+            return Some(output.clone());
+        }
+
+        match exec::run(&words) {
+            Ok(output) => {
+                self.commands.insert(words, output.clone());
+
+                Some(output)
             }
+            Err(e) => {
+                self.fail(e.to_string());
 
-            if let Ok(v) = f64::from_str(&v) {
-                *value = Value::Number(v.into());
-                return;
+                None
             }
+        }
+    }
 
-            if let Ok(v) = bool::from_str(&v) {
-                *value = Value::Bool(v);
+    // Variable referenced as `${NAME}` in a value
+    fn var(&mut self, name: &str) -> Option<String> {
+        if !policy::env_policy().permits(name) {
+            if !self.denied.iter().any(|denied| denied == name) {
+                self.denied.push(name.to_string());
+            }
+
+            return None;
+        }
+
+        env_overlay::var(name).ok()
+    }
+
+    // Override looked up by the value's path (e.g. `USER_NAME`), forbidden ones are just skipped
+    fn implicit_var(&self, name: &str) -> Option<String> {
+        policy::env_policy()
+            .permits(name)
+            .then(|| env_overlay::var(name).ok())
+            .flatten()
+    }
+}
+
+fn expand_variables(env_path: String, value: &mut serde_yaml::Value, expansion: &mut Expansion) {
+    use serde_yaml::*;
+
+    match value {
+        Value::String(text) => {
+            // Remove first dot symbol
+            let env_path = env_path.get(1..).unwrap_or_default();
+            expansion.typed = None;
+            let v = subst_env_variable(env_path, text.as_str(), expansion);
+            let typed = expansion.typed.take();
+
+            if v == *text {
                 return;
             }
 
-            *text = v;
+            // Only a value that is a single reference takes the type of its hint, the
+            // rest stay strings for their fields to convert, so `1.10` isn't read as `1.1`
+            *value = match typed {
+                Some(typed) if is_sole_reference(text) => typed,
+                _ => Value::String(v),
+            };
         }
         Value::Mapping(mapping) => {
             for (k, v) in mapping {
-                let env_path = format!(
-                    "{}_{}",
-                    env_path.to_uppercase(),
-                    k.as_str().unwrap().to_uppercase()
-                );
-                expand_variables(env_path, v);
+                // Numeric keys are named as written, others can't be
+                let key = match k {
+                    Value::String(key) => key.clone(),
+                    Value::Number(key) => key.to_string(),
+                    Value::Bool(key) => key.to_string(),
+                    _ => String::new(),
+                };
+                let env_path = format!("{}_{}", env_path.to_uppercase(), key.to_uppercase());
+                expand_variables(env_path, v, expansion);
             }
         }
         Value::Sequence(seq) => {
             for v in seq {
-                expand_variables(env_path.clone(), v);
+                expand_variables(env_path.clone(), v, expansion);
             }
         }
         _ => {}
@@ -0,0 +1,177 @@
+use std::{env, sync::OnceLock};
+
+use anyhow::{anyhow, Result};
+use serde_yaml::Value;
+
+static LIMITS: OnceLock<Limits> = OnceLock::new();
+
+/// Upper bounds on what a single load may produce
+///
+/// Protect against configs that blow up in memory once aliases, overlays and
+/// substitutions are applied. Unless [`set_limits`] is called, every bound can be
+/// overridden with an environment variable, named next to each field.
+#[derive(Debug, Clone)]
+pub struct Limits {
+    /// Size of a config file in bytes (`CONFIG_MAX_FILE_SIZE`)
+    pub max_file_size: u64,
+    /// Number of values in a processed config (`CONFIG_MAX_NODES`)
+    pub max_nodes: usize,
+    /// Nesting depth of mappings and sequences (`CONFIG_MAX_DEPTH`)
+    pub max_depth: usize,
+    /// Length of a single string after substitution (`CONFIG_MAX_VALUE_LENGTH`)
+    pub max_value_length: usize,
+    /// Levels of `${...}` nested in one another or in the values of the variables they
+    /// reference (`CONFIG_MAX_EXPANSION_DEPTH`)
+    pub max_expansion_depth: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_file_size: 64 * 1024 * 1024,
+            max_nodes: 1_000_000,
+            max_depth: 64,
+            max_value_length: 1024 * 1024,
+            max_expansion_depth: 16,
+        }
+    }
+}
+
+impl Limits {
+    fn from_env() -> Self {
+        fn var<T: std::str::FromStr>(name: &str, default: T) -> T {
+            env::var(name)
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(default)
+        }
+
+        let default = Self::default();
+
+        Self {
+            max_file_size: var("CONFIG_MAX_FILE_SIZE", default.max_file_size),
+            max_nodes: var("CONFIG_MAX_NODES", default.max_nodes),
+            max_depth: var("CONFIG_MAX_DEPTH", default.max_depth),
+            max_value_length: var("CONFIG_MAX_VALUE_LENGTH", default.max_value_length),
+            max_expansion_depth: var("CONFIG_MAX_EXPANSION_DEPTH", default.max_expansion_depth),
+        }
+    }
+
+    pub(crate) fn check_file_size(&self, source: &str, size: u64) -> Result<()> {
+        if size > self.max_file_size {
+            return Err(anyhow!(
+                "{source}: config file is {size} bytes, the limit is {} (CONFIG_MAX_FILE_SIZE)",
+                self.max_file_size
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Nesting of `[...]` and `{...}` in YAML or JSON text, before it reaches the parser,
+    /// which takes quadratic time on deeply nested flow collections before its own
+    /// recursion limit kicks in
+    ///
+    /// Quoted strings and comments are skipped, and brackets only count where a flow
+    /// collection may start, not inside plain values such as `^[a-z`. Block nesting is
+    /// checked on the parsed tree.
+    pub(crate) fn check_flow_depth(&self, text: &str) -> Result<()> {
+        let mut depth = 0usize;
+        let mut quote = None;
+        let mut comment = false;
+        let mut escaped = false;
+        let mut previous = '\n';
+        // Last character outside of strings and comments that isn't whitespace
+        let mut token = '\n';
+
+        for c in text.chars() {
+            match (quote, c) {
+                _ if comment => comment = c != '\n',
+                (Some('"'), '\\') => escaped = !escaped,
+                (Some(open), _) if c == open && !escaped => quote = None,
+                (Some(_), _) => escaped = false,
+                (None, '"' | '\'') => quote = Some(c),
+                (None, '#') if previous.is_whitespace() => comment = true,
+                (None, '[' | '{') if matches!(token, '\n' | ':' | ',' | '[' | '{' | '-' | '?') => {
+                    depth += 1;
+
+                    if depth > self.max_depth {
+                        return Err(anyhow!(
+                            "config is nested deeper than {} levels (CONFIG_MAX_DEPTH)",
+                            self.max_depth
+                        ));
+                    }
+                }
+                (None, ']' | '}') => depth = depth.saturating_sub(1),
+                _ => {}
+            }
+
+            if quote.is_none() && !comment && (c == '\n' || !c.is_whitespace()) {
+                token = c;
+            }
+            previous = c;
+        }
+
+        Ok(())
+    }
+
+    /// Walk a processed config and fail on the first exceeded bound
+    pub(crate) fn check_tree(&self, source: &str, value: &Value) -> Result<()> {
+        let mut nodes = 0;
+
+        self.check_node(source, value, 0, &mut nodes)
+    }
+
+    fn check_node(
+        &self,
+        source: &str,
+        value: &Value,
+        depth: usize,
+        nodes: &mut usize,
+    ) -> Result<()> {
+        *nodes += 1;
+
+        if *nodes > self.max_nodes {
+            return Err(anyhow!(
+                "{source}: config has more than {} values (CONFIG_MAX_NODES)",
+                self.max_nodes
+            ));
+        }
+
+        if depth > self.max_depth {
+            return Err(anyhow!(
+                "{source}: config is nested deeper than {} levels (CONFIG_MAX_DEPTH)",
+                self.max_depth
+            ));
+        }
+
+        match value {
+            Value::String(text) if text.len() > self.max_value_length => Err(anyhow!(
+                "{source}: a value is {} bytes long, the limit is {} (CONFIG_MAX_VALUE_LENGTH)",
+                text.len(),
+                self.max_value_length
+            )),
+            Value::Sequence(seq) => seq
+                .iter()
+                .try_for_each(|v| self.check_node(source, v, depth + 1, nodes)),
+            Value::Mapping(mapping) => mapping.iter().try_for_each(|(k, v)| {
+                self.check_node(source, k, depth + 1, nodes)?;
+                self.check_node(source, v, depth + 1, nodes)
+            }),
+            Value::Tagged(tagged) => self.check_node(source, &tagged.value, depth, nodes),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Install the limits for every following load
+///
+/// Has to run before the first config is loaded, afterwards the limits are fixed and the
+/// rejected ones are handed back.
+pub fn set_limits(limits: Limits) -> Result<(), Limits> {
+    LIMITS.set(limits)
+}
+
+pub(crate) fn limits() -> &'static Limits {
+    LIMITS.get_or_init(Limits::from_env)
+}
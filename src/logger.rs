@@ -1,20 +1,45 @@
-use std::env::current_dir;
+use std::{
+    env::current_dir,
+    ffi::OsString,
+    io::Write,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex, Weak,
+    },
+    time::Duration,
+};
 
 use serde::{
     de::{Deserializer, MapAccess, Visitor},
-    Deserialize,
+    ser::{SerializeMap, Serializer},
+    Deserialize, Serialize,
 };
+use serde_yaml::Value;
 use thiserror::Error;
-use tracing::info;
+
+use crate::{
+    console::{self, ConsoleParams},
+    crash::{CrashBuffer, CrashDumpParams},
+    drift, events,
+    histogram::{self, SpanHistograms},
+    preset::LoggerPreset,
+    sink::{self, DynamicSinks, SinkId, SinkParams},
+    trace_id::{TraceIdFormat, TraceIds},
+    Event,
+};
+use tracing::{debug, info};
 use tracing_subscriber::{
     filter, filter::EnvFilter, fmt::format::FmtSpan, layer::SubscriberExt, prelude::*,
 };
 
+use tracing_appender::non_blocking::{NonBlocking, NonBlockingBuilder};
+
 type AppenderGuard = tracing_appender::non_blocking::WorkerGuard;
 type FilterReloadHandle =
     tracing_subscriber::reload::Handle<EnvFilter, tracing_subscriber::registry::Registry>;
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct UpperLoggerParams {
     pub logger: LoggerParams,
 }
@@ -28,9 +53,17 @@ impl UpperLoggerParams {
 }
 
 /// Logger parameters
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Serialize, Debug, Clone)]
 #[serde(deny_unknown_fields)]
 pub struct LoggerParams {
+    /// Canned params for a deployment target, the others are merged over them, see
+    /// [`LoggerPreset`]
+    pub preset: Option<LoggerPreset>,
+    /// Base directory of relative log file paths, the working directory by default
+    ///
+    /// Services started by systemd get theirs from `STATE_DIRECTORY` or `LOGS_DIRECTORY`,
+    /// e.g. `log_dir: ${LOGS_DIRECTORY:.}`.
+    pub log_dir: Option<PathBuf>,
     /// A path to a log file, including file name
     /// The file name part will be suffixed with the current date
     pub log_file_prefix: Option<std::path::PathBuf>,
@@ -47,26 +80,91 @@ pub struct LoggerParams {
 
     #[serde(default)]
     pub span_timings: bool,
+    /// With `span_timings`, also collect span durations into histograms, see
+    /// [`crate::span_timings`], and log a summary of them on shutdown
+    #[serde(default)]
+    pub span_histograms: bool,
+    /// Prefix events with the `trace_id` and `span_id` of their span, in every sink
+    ///
+    /// Ids come from `trace_id`/`span_id` or W3C `traceparent` span fields, so logs can be
+    /// joined with traces in the backend; spans without them start a new trace.
+    #[serde(default)]
+    pub trace_ids: bool,
+
+    /// Console output options
+    pub console: Option<ConsoleParams>,
+    /// Outputs with their own format and level, e.g. pretty console at `info` and JSON
+    /// file at `debug`, see [`SinkParams`]
+    ///
+    /// Replaces the `log_file_prefix` and `add_*` outputs when set.
+    pub sinks: Option<Vec<SinkParams>>,
+
+    /// Name of the thread writing log files, as shown by profilers
+    pub appender_thread_name: Option<String>,
+    /// Niceness of that thread (unix only), positive values deprioritize log writing
+    pub appender_nice: Option<i32>,
+    /// Latest events to write to a file on panic, see [`CrashDumpParams`]
+    pub crash_dump: Option<CrashDumpParams>,
+    /// How long the `#[logger]` function waits on exit for log files to be written, 5000
+    /// by default
+    pub shutdown_timeout_ms: Option<u64>,
 }
 
 impl LoggerParams {
     pub fn merge(self, rhs: Self) -> Self {
         Self {
+            preset: rhs.preset.or(self.preset),
+            log_dir: rhs.log_dir.or(self.log_dir),
             log_file_prefix: rhs.log_file_prefix.or(self.log_file_prefix),
             add_log_file_prefix: rhs.add_log_file_prefix.or(self.add_log_file_prefix),
             default_level: rhs.default_level,
             filter: rhs.filter,
             add_filter: rhs.add_filter.or(self.add_filter),
             span_timings: rhs.span_timings,
+            span_histograms: rhs.span_histograms,
+            trace_ids: rhs.trace_ids,
+            console: rhs.console.or(self.console),
+            sinks: rhs.sinks.or(self.sinks),
+            appender_thread_name: rhs.appender_thread_name.or(self.appender_thread_name),
+            appender_nice: rhs.appender_nice.or(self.appender_nice),
+            crash_dump: rhs.crash_dump.or(self.crash_dump),
+            shutdown_timeout_ms: rhs.shutdown_timeout_ms.or(self.shutdown_timeout_ms),
+        }
+    }
+
+    // These params merged over those of their preset
+    pub(crate) fn resolve_preset(&self) -> Self {
+        match self.preset {
+            Some(preset) => preset.params().merge(self.clone()),
+            None => self.clone(),
         }
     }
+
+    // Where a log file path points, relative to `log_dir`
+    pub(crate) fn file_path(&self, path: &Path) -> Result<PathBuf, LoggerError> {
+        let base = current_dir()?;
+
+        Ok(match &self.log_dir {
+            Some(log_dir) => base.join(log_dir).join(path),
+            None => base.join(path),
+        })
+    }
+
+    // Directory of a daily log file and its file name prefix
+    pub(crate) fn daily_file(&self, prefix: &Path) -> Result<(PathBuf, OsString), LoggerError> {
+        let path = self.file_path(prefix)?;
+        let file_name = path.file_name().ok_or(LoggerError::File)?.to_owned();
+        let dir = path.parent().ok_or(LoggerError::File)?.to_path_buf();
+
+        Ok((dir, file_name))
+    }
 }
 
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Clone)]
 pub struct LoggerFilter(Vec<(String, String)>);
 
 impl LoggerFilter {
-    fn as_slice(&self) -> &[(String, String)] {
+    pub(crate) fn as_slice(&self) -> &[(String, String)] {
         self.0.as_slice()
     }
 }
@@ -110,6 +208,21 @@ impl<'de> Visitor<'de> for LoggerFilterVisitor {
     }
 }
 
+impl Serialize for LoggerFilter {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut map = serializer.serialize_map(Some(self.0.len()))?;
+
+        for (key, value) in &self.0 {
+            map.serialize_entry(key, value)?;
+        }
+
+        map.end()
+    }
+}
+
 impl<'de> Deserialize<'de> for LoggerFilter {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
     where
@@ -120,9 +233,57 @@ impl<'de> Deserialize<'de> for LoggerFilter {
 }
 
 /// Logger initialization
+///
+/// Clones share the same logger, which stays installed while any of them is alive.
+#[derive(Clone)]
 pub struct Logger {
-    _guard: Option<Vec<AppenderGuard>>,
+    inner: Arc<LoggerInner>,
+}
+
+// The logger of this process while a handle to it is alive, the global subscriber can
+// only be installed once
+static ACTIVE: Mutex<Weak<LoggerInner>> = Mutex::new(Weak::new());
+
+struct LoggerInner {
+    // Taken on shutdown, dropping them flushes the files
+    guards: Mutex<Vec<AppenderGuard>>,
+    shutdown_timeout: Duration,
     filter_reload_handle: FilterReloadHandle,
+    dynamic: DynamicSinks,
+    // Successful reloads so far and the params they applied, for the audit trail
+    generation: AtomicU64,
+    applied: Mutex<Value>,
+    span_histograms: bool,
+    summarized: AtomicBool,
+}
+
+impl LoggerInner {
+    // Before the guards flush, so the summary still makes it into the files
+    fn summarize(&self) {
+        if self.span_histograms && !self.summarized.swap(true, Ordering::Relaxed) {
+            histogram::summarize();
+        }
+    }
+}
+
+impl Drop for LoggerInner {
+    fn drop(&mut self) {
+        self.summarize();
+    }
+}
+
+/// Shuts the logger down when dropped, see [`Logger::shutdown_guard`]
+pub struct ShutdownGuard {
+    logger: Logger,
+}
+
+impl Drop for ShutdownGuard {
+    fn drop(&mut self) {
+        // The logger can't report on itself anymore
+        if let Err(e) = self.logger.shutdown(self.logger.inner.shutdown_timeout) {
+            eprintln!("{e}");
+        }
+    }
 }
 
 /// Logger error
@@ -142,6 +303,14 @@ pub enum LoggerError {
         #[from]
         src: std::convert::Infallible,
     },
+    #[error(
+        "A global tracing subscriber is already installed and no logger handle is alive to reuse"
+    )]
+    AlreadyInitialized,
+    #[error("Invalid logger params: {0}")]
+    Params(String),
+    #[error("Log sinks didn't finish writing within {0:?}")]
+    ShutdownTimeout(Duration),
     #[error("Io error: {src}")]
     IO {
         #[from]
@@ -150,7 +319,7 @@ pub enum LoggerError {
 }
 
 impl Logger {
-    fn load_filter_info(
+    pub(crate) fn load_filter_info(
         default_level: &str,
         directives: &[(String, String)],
     ) -> Result<EnvFilter, LoggerError> {
@@ -164,26 +333,246 @@ impl Logger {
         Ok(filter)
     }
 
+    // Background writer for a log file
+    pub(crate) fn non_blocking<W: Write + Send + 'static>(
+        writer: W,
+        params: &LoggerParams,
+    ) -> (NonBlocking, AppenderGuard) {
+        let mut builder = NonBlockingBuilder::default();
+
+        if let Some(name) = &params.appender_thread_name {
+            builder = builder.thread_name(name);
+        }
+
+        match params.appender_nice {
+            Some(nice) => builder.finish(Niced {
+                writer,
+                nice,
+                applied: false,
+            }),
+            None => builder.finish(writer),
+        }
+    }
+
+    fn new(
+        guard: Option<Vec<AppenderGuard>>,
+        filter_reload_handle: FilterReloadHandle,
+        dynamic: DynamicSinks,
+        params: &UpperLoggerParams,
+    ) -> Self {
+        Self {
+            inner: Arc::new(LoggerInner {
+                guards: Mutex::new(guard.unwrap_or_default()),
+                shutdown_timeout: Duration::from_millis(
+                    params.logger.shutdown_timeout_ms.unwrap_or(5000),
+                ),
+                filter_reload_handle,
+                dynamic,
+                generation: AtomicU64::new(0),
+                applied: Mutex::new(serde_yaml::to_value(params).unwrap_or_default()),
+                span_histograms: Self::span_histograms(params).is_some(),
+                summarized: AtomicBool::new(false),
+            }),
+        }
+    }
+
     #[allow(dead_code)]
     pub fn reload(&self, params: &UpperLoggerParams) -> Result<(), LoggerError> {
-        let filter = Self::load_filter_info(
+        self.reload_as(params, None)
+    }
+
+    /// Same as [`Logger::reload`], with the `actor` that asked for it recorded in the
+    /// audit trail, see [`crate::set_audit_path`]
+    pub fn reload_as(
+        &self,
+        params: &UpperLoggerParams,
+        actor: Option<&str>,
+    ) -> Result<(), LoggerError> {
+        let reloaded = Self::load_filter_info(
             &params.logger.default_level,
             params.logger.filter.as_slice(),
-        )?;
+        )
+        .and_then(|filter| Ok(self.inner.filter_reload_handle.reload(filter)?));
+        crate::health::record_reload("logger", reloaded.as_ref().err().map(ToString::to_string));
 
-        self.filter_reload_handle.reload(filter)?;
+        if let Err(e) = reloaded {
+            events::publish(Event::ReloadFailed {
+                source: "logger".to_string(),
+                error: e.to_string(),
+            });
+
+            return Err(e);
+        }
+
+        let new = serde_yaml::to_value(params).unwrap_or_default();
+        let old = std::mem::replace(&mut *self.inner.applied.lock().unwrap(), new.clone());
+        let generation = self.inner.generation.fetch_add(1, Ordering::Relaxed) + 1;
+        crate::audit::record("logger", generation, &old, &new, actor);
+        events::publish(Event::ReloadSucceeded {
+            source: "logger".to_string(),
+            diff: drift::diff(&old, &new, &[]),
+        });
 
         Ok(())
     }
 
+    /// Change the level of `target`, or the default level without one, keeping the rest of
+    /// the parameters the logger was last installed or reloaded with
+    pub fn set_level(
+        &self,
+        target: Option<&str>,
+        level: &str,
+        actor: Option<&str>,
+    ) -> Result<(), LoggerError> {
+        level
+            .parse::<tracing_subscriber::filter::LevelFilter>()
+            .map_err(|_| LoggerError::Filter)?;
+
+        let applied = self.inner.applied.lock().unwrap().clone();
+        let mut params: UpperLoggerParams =
+            serde_yaml::from_value(applied).map_err(|e| LoggerError::Params(e.to_string()))?;
+
+        match target {
+            Some(target) => {
+                let filter = &mut params.logger.filter.0;
+
+                match filter.iter_mut().find(|(name, _)| name == target) {
+                    Some((_, current)) => *current = level.to_string(),
+                    None => filter.push((target.to_string(), level.to_string())),
+                }
+            }
+            None => params.logger.default_level = level.to_string(),
+        }
+
+        self.reload_as(&params, actor)
+    }
+
+    /// Start writing to another sink, e.g. a debug file while an incident is looked into
+    ///
+    /// The sink takes the console options and appender settings the logger was
+    /// installed or last reloaded with. It only sees events passing the logger's level and
+    /// filter.
+    pub fn add_sink(&self, sink: &SinkParams) -> Result<SinkId, LoggerError> {
+        let applied = self.inner.applied.lock().unwrap().clone();
+        let params = serde_yaml::from_value::<UpperLoggerParams>(applied)
+            .map_err(|e| LoggerError::Params(e.to_string()))?;
+
+        self.inner.dynamic.add(&params.logger, sink)
+    }
+
+    /// Stop writing to a sink added with [`Logger::add_sink`] after flushing it, false if
+    /// it was already removed
+    pub fn remove_sink(&self, id: SinkId) -> bool {
+        self.inner.dynamic.remove(id)
+    }
+
+    /// Write out everything logged so far and stop writing log files, waiting at most
+    /// `timeout` for it
+    ///
+    /// Events logged afterwards are lost for the files. Dropping the last handle does the
+    /// same without a bound, but handles kept in statics are never dropped.
+    pub fn shutdown(&self, timeout: Duration) -> Result<(), LoggerError> {
+        self.inner.summarize();
+
+        let guards = std::mem::take(&mut *self.inner.guards.lock().unwrap());
+
+        if guards.is_empty() {
+            return Ok(());
+        }
+
+        let (done, flushed) = std::sync::mpsc::channel();
+        std::thread::spawn(move || {
+            drop(guards);
+            let _ = done.send(());
+        });
+
+        flushed
+            .recv_timeout(timeout)
+            .map_err(|_| LoggerError::ShutdownTimeout(timeout))
+    }
+
+    /// Guard calling [`Logger::shutdown`] with the configured `shutdown_timeout_ms` when
+    /// it goes out of scope, `#[logger]` holds one for the function body
+    pub fn shutdown_guard(&self) -> ShutdownGuard {
+        ShutdownGuard {
+            logger: self.clone(),
+        }
+    }
+
+    /// Install the logger for this process
+    ///
+    /// Calling it again (another `#[logger]` function, tests) returns the logger already
+    /// installed, `params` are ignored then.
     pub fn init(params: &UpperLoggerParams) -> Result<Logger, LoggerError> {
-        if let Some(log_file_prefix) = params.logger.log_file_prefix.as_ref() {
-            let file_prefix = log_file_prefix.file_name().ok_or(LoggerError::File)?;
+        let mut active = ACTIVE.lock().unwrap();
+
+        if let Some(inner) = active.upgrade() {
+            debug!("Logger is already initialized, reusing it");
+
+            return Ok(Self { inner });
+        }
+
+        let logger = Self::install(params)?;
+        *active = Arc::downgrade(&logger.inner);
+        events::publish(Event::Loaded {
+            source: "logger".to_string(),
+        });
+
+        Ok(logger)
+    }
+
+    fn span_histograms(params: &UpperLoggerParams) -> Option<SpanHistograms> {
+        (params.logger.span_timings && params.logger.span_histograms).then_some(SpanHistograms)
+    }
+
+    fn install(params: &UpperLoggerParams) -> Result<Logger, LoggerError> {
+        let params = &UpperLoggerParams {
+            logger: params.logger.resolve_preset(),
+        };
+        let histograms = Self::span_histograms(params);
+        let crash_buffer = CrashBuffer::new(&params.logger)?;
+        let trace_ids = params.logger.trace_ids.then_some(TraceIds);
+        let dynamic = DynamicSinks::default();
+
+        if params.logger.sinks.is_some() {
+            let mut guards = vec![];
+            let sinks = sink::layers(&params.logger, &mut guards)?;
+            let count = sinks.len();
+
+            let filter = Self::load_filter_info(
+                &params.logger.default_level,
+                params.logger.filter.as_slice(),
+            )?;
+            let (filter, handle) = tracing_subscriber::reload::Layer::new(filter);
+
+            // Filtering the outputs only, the crash buffer keeps its own level
+            tracing_subscriber::registry()
+                .with(
+                    dynamic
+                        .clone()
+                        .and_then(sinks)
+                        .and_then(histograms)
+                        .with_filter(filter),
+                )
+                .with(crash_buffer.clone())
+                .with(trace_ids)
+                .try_init()
+                .map_err(|_| LoggerError::AlreadyInitialized)?;
+
+            info!("Start logging to {count} sinks");
+
+            return Ok(Self::new(Some(guards), handle, dynamic, params));
+        }
 
-            let dir = current_dir()?.join(log_file_prefix.parent().ok_or(LoggerError::File)?);
+        if let Some(log_file_prefix) = params.logger.log_file_prefix.as_ref() {
+            let (dir, file_prefix) = params.logger.daily_file(log_file_prefix)?;
             let daily_file = tracing_appender::rolling::daily(dir, file_prefix);
 
-            let (non_blocking, guard) = tracing_appender::non_blocking(daily_file);
+            let (non_blocking, guard) = Self::non_blocking(daily_file, &params.logger);
+            crate::health::register_sink(
+                log_file_prefix.display().to_string(),
+                non_blocking.error_counter(),
+            );
             let sub_daily = tracing_subscriber::fmt::layer()
                 .with_ansi(false)
                 .with_span_events(FmtSpan::NONE)
@@ -198,16 +587,19 @@ impl Logger {
             } else {
                 sub_daily
             };
+            let sub_daily = sub_daily.map_event_format(TraceIdFormat);
 
             if let Some(add_log_file_prefix) = &params.logger.add_log_file_prefix {
                 if let Some(add_filter) = &params.logger.add_filter {
-                    let dir_add =
-                        current_dir()?.join(add_log_file_prefix.parent().ok_or(LoggerError::File)?);
-                    let file_prefix_add =
-                        add_log_file_prefix.file_name().ok_or(LoggerError::File)?;
+                    let (dir_add, file_prefix_add) =
+                        params.logger.daily_file(add_log_file_prefix)?;
                     let daily_file_add = tracing_appender::rolling::daily(dir_add, file_prefix_add);
                     let (non_blocking_add, guard_add) =
-                        tracing_appender::non_blocking(daily_file_add);
+                        Self::non_blocking(daily_file_add, &params.logger);
+                    crate::health::register_sink(
+                        add_log_file_prefix.display().to_string(),
+                        non_blocking_add.error_counter(),
+                    );
 
                     let add_filter_clone = add_filter.clone();
                     let sub_daily_add = tracing_subscriber::fmt::layer()
@@ -216,6 +608,7 @@ impl Logger {
                         .with_thread_names(true)
                         .with_line_number(true)
                         .with_writer(non_blocking_add)
+                        .map_event_format(TraceIdFormat)
                         .with_filter(filter::filter_fn(move |metadata| {
                             add_filter_clone
                                 .iter()
@@ -229,21 +622,12 @@ impl Logger {
                     }));
                     let add_filter_clone = add_filter.clone();
 
-                    let sub_stderr_x = tracing_subscriber::fmt::layer()
-                        .with_thread_names(true)
-                        .with_span_events(FmtSpan::NONE)
-                        .with_line_number(true)
-                        .with_timer(tracing_subscriber::fmt::time::time())
-                        .with_writer(std::io::stderr);
-
-                    let sub_stderr_x = if params.logger.span_timings {
-                        sub_stderr_x
-                            .with_span_events(FmtSpan::CLOSE | FmtSpan::ENTER)
-                            .with_timer(tracing_subscriber::fmt::time::time())
-                    } else {
-                        sub_stderr_x
-                    };
-
+                    let sub_stderr_x = console::layer(
+                        &params.logger,
+                        std::io::stderr,
+                        true,
+                        console::default_format(&params.logger),
+                    );
                     let sub_stderr_x =
                         sub_stderr_x.with_filter(filter::filter_fn(move |metadata| {
                             add_filter_clone
@@ -259,16 +643,26 @@ impl Logger {
                     let (filter, handle) = tracing_subscriber::reload::Layer::new(filter);
 
                     tracing_subscriber::registry()
-                        .with(filter)
-                        .with(sub_daily)
-                        .with(sub_daily_add)
-                        .with(sub_stderr_x)
-                        .init();
-
-                    return Ok(Self {
-                        _guard: Some(vec![guard, guard_add]),
-                        filter_reload_handle: handle,
-                    });
+                        .with(
+                            dynamic
+                                .clone()
+                                .and_then(sub_daily)
+                                .and_then(sub_daily_add)
+                                .and_then(sub_stderr_x)
+                                .and_then(histograms)
+                                .with_filter(filter),
+                        )
+                        .with(crash_buffer.clone())
+                        .with(trace_ids)
+                        .try_init()
+                        .map_err(|_| LoggerError::AlreadyInitialized)?;
+
+                    return Ok(Self::new(
+                        Some(vec![guard, guard_add]),
+                        handle,
+                        dynamic,
+                        params,
+                    ));
                 }
             }
 
@@ -279,30 +673,28 @@ impl Logger {
             let (filter, handle) = tracing_subscriber::reload::Layer::new(filter);
 
             tracing_subscriber::registry()
-                .with(filter)
-                .with(sub_daily)
-                .init();
+                .with(
+                    dynamic
+                        .clone()
+                        .and_then(sub_daily)
+                        .and_then(histograms)
+                        .with_filter(filter),
+                )
+                .with(crash_buffer.clone())
+                .with(trace_ids)
+                .try_init()
+                .map_err(|_| LoggerError::AlreadyInitialized)?;
 
             info!("Started logging to file {}", log_file_prefix.display());
 
-            Ok(Self {
-                _guard: Some(vec![guard]),
-                filter_reload_handle: handle,
-            })
+            Ok(Self::new(Some(vec![guard]), handle, dynamic, params))
         } else {
-            let writer = tracing_subscriber::fmt::layer()
-                .with_thread_names(true)
-                .with_span_events(FmtSpan::NONE)
-                .with_timer(tracing_subscriber::fmt::time::time())
-                .with_writer(std::io::stdout);
-
-            let writer = if params.logger.span_timings {
-                writer
-                    .with_span_events(FmtSpan::CLOSE | FmtSpan::ENTER)
-                    .with_timer(tracing_subscriber::fmt::time::time())
-            } else {
-                writer
-            };
+            let writer = console::layer(
+                &params.logger,
+                std::io::stdout,
+                false,
+                console::default_format(&params.logger),
+            );
 
             let filter = Self::load_filter_info(
                 &params.logger.default_level,
@@ -311,16 +703,66 @@ impl Logger {
             let (filter, handle) = tracing_subscriber::reload::Layer::new(filter);
 
             tracing_subscriber::registry()
-                .with(filter)
-                .with(writer)
-                .init();
+                .with(
+                    dynamic
+                        .clone()
+                        .and_then(writer)
+                        .and_then(histograms)
+                        .with_filter(filter),
+                )
+                .with(crash_buffer.clone())
+                .with(trace_ids)
+                .try_init()
+                .map_err(|_| LoggerError::AlreadyInitialized)?;
 
             info!("Start logging: ");
 
-            Ok(Self {
-                _guard: None,
-                filter_reload_handle: handle,
-            })
+            Ok(Self::new(None, handle, dynamic, params))
         }
     }
 }
+
+// Lowers the priority of the appender thread, from there since the first write runs on it
+struct Niced<W> {
+    writer: W,
+    nice: i32,
+    applied: bool,
+}
+
+impl<W: Write> Write for Niced<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if !self.applied {
+            self.applied = true;
+            set_thread_nice(self.nice);
+        }
+
+        self.writer.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+#[cfg(unix)]
+fn set_thread_nice(nice: i32) {
+    use std::ffi::c_int;
+
+    const PRIO_PROCESS: c_int = 0;
+
+    extern "C" {
+        fn setpriority(which: c_int, who: u32, prio: c_int) -> c_int;
+    }
+
+    // On Linux `who = 0` is the calling thread, not the whole process
+    // SAFETY: plain syscall without pointers
+    if unsafe { setpriority(PRIO_PROCESS, 0, nice) } != 0 {
+        eprintln!(
+            "Failed to set log appender niceness to {nice}: {}",
+            std::io::Error::last_os_error()
+        );
+    }
+}
+
+#[cfg(not(unix))]
+fn set_thread_nice(_nice: i32) {}
@@ -0,0 +1,343 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+    hash::{BuildHasher, Hash},
+    path::PathBuf,
+    time::Duration,
+};
+
+use indexmap::IndexMap;
+use serde::{de::DeserializeOwned, Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::overlay;
+
+/// How two layers of a config value combine, `rhs` being the later one, e.g. the runtime
+/// file over the compile time one
+///
+/// `#[configurable]` structs implement it field by field, and `#[unconfig(merge = "custom")]`
+/// fields are merged with their type's implementation. Implementations are expected to
+/// follow two laws, which the ones here do:
+///
+/// * associativity: `a.merge(b).merge(c) == a.merge(b.merge(c))`, so any number of layers
+///   gives the same result however they're grouped;
+/// * identity: an empty value (`None`, an empty map) changes nothing on either side.
+///
+/// For [`Deep`] values associativity only holds while the layers agree on which values are
+/// mappings: a scalar replacing a mapping and a mapping replacing it again keeps just the
+/// last mapping when merged in order, but the keys of both when the last two are merged
+/// first. Layers are always merged in order when loading.
+pub trait Merge {
+    fn merge(self, rhs: Self) -> Self;
+}
+
+/// Both present values are merged, otherwise the present one is kept
+impl<T: Merge> Merge for Option<T> {
+    fn merge(self, rhs: Self) -> Self {
+        match (self, rhs) {
+            (Some(lhs), Some(rhs)) => Some(lhs.merge(rhs)),
+            (lhs, rhs) => rhs.or(lhs),
+        }
+    }
+}
+
+macro_rules! replace {
+    ($($ty:ty),*) => {
+        $(
+            impl Merge for $ty {
+                fn merge(self, rhs: Self) -> Self {
+                    rhs
+                }
+            }
+        )*
+    };
+}
+
+// Scalars are replaced by the later layer
+replace!(
+    bool, char, String, PathBuf, Duration, u8, u16, u32, u64, u128, usize, i8, i16, i32, i64, i128,
+    isize, f32, f64
+);
+
+/// Replaced as a whole, items aren't matched up between layers
+impl<T> Merge for Vec<T> {
+    fn merge(self, rhs: Self) -> Self {
+        rhs
+    }
+}
+
+/// Key by key, values under the same key are merged, new keys come last
+impl<K: Hash + Eq, V: Merge, S: BuildHasher> Merge for IndexMap<K, V, S> {
+    fn merge(mut self, rhs: Self) -> Self {
+        for (key, value) in rhs {
+            match self.get_index_of(&key) {
+                Some(index) => {
+                    let (key, lhs) = self.shift_remove_index(index).unwrap();
+                    self.shift_insert(index, key, lhs.merge(value));
+                }
+                None => {
+                    self.insert(key, value);
+                }
+            }
+        }
+
+        self
+    }
+}
+
+/// Key by key, values under the same key are merged
+impl<K: Hash + Eq, V: Merge, S: BuildHasher> Merge for HashMap<K, V, S> {
+    fn merge(mut self, rhs: Self) -> Self {
+        for (key, value) in rhs {
+            let value = match self.remove(&key) {
+                Some(lhs) => lhs.merge(value),
+                None => value,
+            };
+
+            self.insert(key, value);
+        }
+
+        self
+    }
+}
+
+/// Key by key, values under the same key are merged
+impl<K: Ord, V: Merge> Merge for BTreeMap<K, V> {
+    fn merge(mut self, rhs: Self) -> Self {
+        for (key, value) in rhs {
+            let value = match self.remove(&key) {
+                Some(lhs) => lhs.merge(value),
+                None => value,
+            };
+
+            self.insert(key, value);
+        }
+
+        self
+    }
+}
+
+/// Storage of a `#[unconfig(merge = "deep")]` field
+///
+/// Keeps the raw value next to the deserialized one, so that a runtime layer can be
+/// merged into the compile time one key by key at any depth (see `overlay::deep_merge`)
+/// instead of replacing the whole field. A single layer may therefore be incomplete:
+/// only the merged value has to deserialize, its error is kept until then.
+pub struct Deep<T> {
+    value: Result<T, String>,
+    raw: serde_yaml::Value,
+}
+
+impl<T: DeserializeOwned> Deep<T> {
+    fn from_raw(raw: serde_yaml::Value) -> Self {
+        let value = T::deserialize(raw.clone()).map_err(|e| e.to_string());
+
+        Self { value, raw }
+    }
+
+    /// Field set in code, as a setter generated with `setters = true` does
+    pub fn new(value: T) -> Self
+    where
+        T: Serialize,
+    {
+        Self {
+            raw: serde_yaml::to_value(&value).unwrap_or_default(),
+            value: Ok(value),
+        }
+    }
+
+    pub fn get(&self) -> Option<&T> {
+        self.value.as_ref().ok()
+    }
+
+    pub fn error(&self) -> Option<&str> {
+        self.value.as_ref().err().map(String::as_str)
+    }
+
+    pub fn into_inner(self) -> Option<T> {
+        self.value.ok()
+    }
+}
+
+/// Key by key at any depth, see [`Deep`]
+impl<T: DeserializeOwned> Merge for Deep<T> {
+    fn merge(self, rhs: Self) -> Self {
+        let mut raw = self.raw;
+        overlay::deep_merge(&mut raw, rhs.raw);
+
+        Self::from_raw(raw)
+    }
+}
+
+impl<'de, T: DeserializeOwned> Deserialize<'de> for Deep<T> {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        serde_yaml::Value::deserialize(deserializer).map(Self::from_raw)
+    }
+}
+
+/// As merged, including keys the value's type doesn't know
+impl<T> Serialize for Deep<T> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        self.raw.serialize(serializer)
+    }
+}
+
+impl<T: Clone> Clone for Deep<T> {
+    fn clone(&self) -> Self {
+        Self {
+            value: self.value.clone(),
+            raw: self.raw.clone(),
+        }
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for Deep<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.value {
+            Ok(value) => value.fmt(f),
+            Err(e) => write!(f, "<invalid: {e}>"),
+        }
+    }
+}
+
+impl<T: PartialEq> PartialEq for Deep<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.value == other.value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fmt::Debug;
+
+    use super::*;
+
+    // Checks the laws of `Merge` over every combination of values of `domain`, compared
+    // by `key`
+    fn laws<T: Merge + Clone, K: PartialEq + Debug>(domain: &[T], empty: T, key: impl Fn(T) -> K) {
+        for a in domain {
+            assert_eq!(key(empty.clone().merge(a.clone())), key(a.clone()));
+            assert_eq!(key(a.clone().merge(empty.clone())), key(a.clone()));
+
+            for b in domain {
+                for c in domain {
+                    let left = a.clone().merge(b.clone()).merge(c.clone());
+                    let right = a.clone().merge(b.clone().merge(c.clone()));
+
+                    assert_eq!(key(left), key(right));
+                }
+            }
+        }
+    }
+
+    // Every list of entries with distinct `keys`, in every order
+    fn entries<K: Clone + PartialEq, V: Clone>(keys: &[K], values: &[V]) -> Vec<Vec<(K, V)>> {
+        let mut all = vec![vec![]];
+        let mut longest = vec![vec![]];
+
+        for _ in keys {
+            longest = longest
+                .iter()
+                .flat_map(|list: &Vec<(K, V)>| {
+                    let unused = keys
+                        .iter()
+                        .filter(|key| list.iter().all(|(k, _)| k != *key));
+
+                    unused.flat_map(move |key| {
+                        values.iter().map(move |value| {
+                            let mut list = list.clone();
+                            list.push((key.clone(), value.clone()));
+                            list
+                        })
+                    })
+                })
+                .collect();
+            all.extend(longest.iter().cloned());
+        }
+
+        all
+    }
+
+    const VALUES: [Option<u8>; 3] = [None, Some(1), Some(2)];
+
+    #[test]
+    fn option() {
+        laws(&VALUES, None, |v| v);
+
+        let nested = [None, Some(None), Some(Some(1)), Some(Some(2))];
+        laws(&nested, None, |v| v);
+    }
+
+    #[test]
+    fn index_map() {
+        let domain = entries(&[0, 1], &VALUES)
+            .into_iter()
+            .map(IndexMap::<u8, _>::from_iter)
+            .collect::<Vec<_>>();
+
+        // In order too, which the map's own equality ignores
+        laws(&domain, IndexMap::new(), |map| {
+            map.into_iter().collect::<Vec<_>>()
+        });
+    }
+
+    #[test]
+    fn hash_map() {
+        let domain = entries(&[0, 1], &VALUES)
+            .into_iter()
+            .map(HashMap::<u8, _>::from_iter)
+            .collect::<Vec<_>>();
+
+        laws(&domain, HashMap::new(), |map| map);
+    }
+
+    #[test]
+    fn btree_map() {
+        let domain = entries(&[0, 1], &VALUES)
+            .into_iter()
+            .map(BTreeMap::<u8, _>::from_iter)
+            .collect::<Vec<_>>();
+        laws(&domain, BTreeMap::new(), |map| map);
+
+        let inner = entries(&[0, 1], &[1u8, 2])
+            .into_iter()
+            .map(BTreeMap::<u8, _>::from_iter)
+            .collect::<Vec<_>>();
+        let nested = entries(&[0u8], &inner)
+            .into_iter()
+            .map(BTreeMap::from_iter)
+            .collect::<Vec<_>>();
+        laws(&nested, BTreeMap::new(), |map| map);
+    }
+
+    #[test]
+    fn deep() {
+        // Layers agreeing on which values are mappings: `a` always is one, `b` never
+        let inner = entries(&["x", "y"], &[1, 2])
+            .into_iter()
+            .map(|entries| serde_yaml::to_value(BTreeMap::from_iter(entries)).unwrap())
+            .collect::<Vec<_>>();
+        let values = entries(&["a"], &inner)
+            .into_iter()
+            .chain(entries(&["b"], &[1.into(), 2.into()]))
+            .chain(
+                inner
+                    .iter()
+                    .map(|inner| vec![("a", inner.clone()), ("b", 1.into())]),
+            )
+            .map(|entries| serde_yaml::to_value(IndexMap::<_, _>::from_iter(entries)).unwrap())
+            .collect::<Vec<_>>();
+        let domain = values
+            .into_iter()
+            .map(Deep::<serde_yaml::Value>::from_raw)
+            .collect::<Vec<_>>();
+
+        let empty = Deep::from_raw(serde_yaml::Value::Mapping(Default::default()));
+        laws(&domain, empty, |deep| serde_yaml::to_value(&deep).unwrap());
+    }
+}
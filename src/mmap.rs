@@ -0,0 +1,85 @@
+use std::{
+    ffi::{c_int, c_void},
+    fs::File,
+    io,
+    ops::Deref,
+    os::fd::AsRawFd,
+    ptr, slice,
+};
+
+const PROT_READ: c_int = 1;
+const MAP_PRIVATE: c_int = 2;
+
+extern "C" {
+    fn mmap(
+        addr: *mut c_void,
+        len: usize,
+        prot: c_int,
+        flags: c_int,
+        fd: c_int,
+        offset: i64,
+    ) -> *mut c_void;
+    fn munmap(addr: *mut c_void, len: usize) -> c_int;
+}
+
+/// Size from which config files are mapped rather than read, smaller ones copy faster
+/// than they map and don't risk what a mapping does
+pub(crate) const MMAP_THRESHOLD: u64 = 32 << 20;
+
+/// Read-only private mapping of a whole file
+///
+/// The file must not change while it is mapped: truncating it makes reading the lost
+/// pages raise `SIGBUS`, and writing to it in place may change pages not copied yet,
+/// under the text parsed from them. Replace mapped files by renaming over them.
+pub(crate) struct Mmap {
+    ptr: *mut c_void,
+    len: usize,
+}
+
+impl Mmap {
+    pub(crate) fn map(file: &File) -> io::Result<Self> {
+        let len = usize::try_from(file.metadata()?.len())
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "file is too large"))?;
+
+        // Empty files can't be mapped, let the caller fall back to a plain read
+        if len == 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "file is empty"));
+        }
+
+        // SAFETY: a fresh read-only private mapping that is unmapped exactly once in `Drop`
+        let ptr = unsafe {
+            mmap(
+                ptr::null_mut(),
+                len,
+                PROT_READ,
+                MAP_PRIVATE,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+
+        if ptr as isize == -1 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(Self { ptr, len })
+    }
+}
+
+impl Deref for Mmap {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        // SAFETY: `ptr` points to `len` readable bytes for as long as `self` is alive
+        unsafe { slice::from_raw_parts(self.ptr as *const u8, self.len) }
+    }
+}
+
+impl Drop for Mmap {
+    fn drop(&mut self) {
+        // SAFETY: `ptr` and `len` come from a successful `mmap` call
+        unsafe {
+            munmap(self.ptr, self.len);
+        }
+    }
+}
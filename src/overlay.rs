@@ -0,0 +1,151 @@
+use std::{
+    env::{
+        self,
+        consts::{ARCH, FAMILY, OS},
+    },
+    sync::LazyLock,
+};
+
+use serde_yaml::Value;
+
+use crate::env_overlay;
+
+/// Top-level key holding per-platform overlays
+pub(crate) const PLATFORM_KEY: &str = "platform";
+/// Top-level key holding per-host overlays
+pub(crate) const HOSTS_KEY: &str = "hosts";
+
+/// Top-level keys that only carry overlays and never reach deserialization
+pub(crate) const OVERLAY_KEYS: [&str; 2] = [PLATFORM_KEY, HOSTS_KEY];
+
+static HOSTNAME: LazyLock<Option<String>> = LazyLock::new(hostname);
+
+/// Overlays that apply to this machine, least specific first
+///
+/// Entries of `platform:` are matched by target family (`unix`, `windows`), OS
+/// (`linux`, `macos`, `windows`, ...), architecture (`x86_64`, `aarch64`, ...) and
+/// `os-arch` pairs such as `linux-aarch64`, each one merged over the previous.
+///
+/// Entries of `hosts:` come after them. Their keys are glob patterns (`*`, `?`) matched
+/// against the local hostname and against the `ROLE` environment variable. Patterns
+/// apply in file order, an exact name match always applies last.
+pub(crate) fn matching(params: &Value) -> Vec<&Value> {
+    let mut overlays = vec![];
+
+    if let Some(platform) = params.get(PLATFORM_KEY) {
+        overlays.extend(
+            [
+                FAMILY.to_string(),
+                OS.to_string(),
+                ARCH.to_string(),
+                format!("{OS}-{ARCH}"),
+            ]
+            .iter()
+            .filter_map(|key| platform.get(key.as_str())),
+        );
+    }
+
+    if let Some(Value::Mapping(hosts)) = params.get(HOSTS_KEY) {
+        let role = env_overlay::var("ROLE").ok();
+        let names = [HOSTNAME.as_deref(), role.as_deref()];
+        let names = names.iter().flatten();
+
+        let mut exact = vec![];
+
+        for (pattern, overlay) in hosts {
+            let Some(pattern) = pattern.as_str() else {
+                continue;
+            };
+
+            if names.clone().any(|name| *name == pattern) {
+                exact.push(overlay);
+            } else if names.clone().any(|name| glob_match(pattern, name)) {
+                overlays.push(overlay);
+            }
+        }
+
+        overlays.extend(exact);
+    }
+
+    overlays
+}
+
+/// Recursively merge `overlay` into `base`
+///
+/// Mappings are merged key by key, any other value in `overlay` replaces the one in `base`.
+pub(crate) fn deep_merge(base: &mut Value, overlay: Value) {
+    match (base, overlay) {
+        (Value::Mapping(base), Value::Mapping(overlay)) => {
+            for (k, v) in overlay {
+                match base.get_mut(&k) {
+                    Some(base_v) => deep_merge(base_v, v),
+                    None => {
+                        base.insert(k, v);
+                    }
+                }
+            }
+        }
+        (base, overlay) => *base = overlay,
+    }
+}
+
+// `*` matches any run of characters, `?` exactly one
+pub(crate) fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+
+    let (mut p, mut t) = (0, 0);
+    let mut backtrack = None;
+
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, t));
+                p += 1;
+            }
+            Some(c) if *c == '?' || *c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match backtrack {
+                // Let the last `*` swallow one more character
+                Some((star_p, star_t)) => {
+                    backtrack = Some((star_p, star_t + 1));
+                    p = star_p + 1;
+                    t = star_t + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+#[cfg(unix)]
+fn hostname() -> Option<String> {
+    use std::ffi::{c_char, c_int, CStr};
+
+    extern "C" {
+        fn gethostname(name: *mut c_char, len: usize) -> c_int;
+    }
+
+    let mut buf = [0 as c_char; 256];
+
+    // SAFETY: the buffer is valid for its whole length and stays NUL terminated
+    if unsafe { gethostname(buf.as_mut_ptr(), buf.len() - 1) } != 0 {
+        return env::var("HOSTNAME").ok();
+    }
+
+    // SAFETY: the last byte of `buf` is never written, so a terminator is always present
+    let name = unsafe { CStr::from_ptr(buf.as_ptr()) };
+
+    Some(name.to_string_lossy().into_owned())
+}
+
+#[cfg(not(unix))]
+fn hostname() -> Option<String> {
+    env::var("COMPUTERNAME")
+        .or_else(|_| env::var("HOSTNAME"))
+        .ok()
+}
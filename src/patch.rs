@@ -0,0 +1,210 @@
+use anyhow::{anyhow, bail, Result};
+use serde::{Deserialize, Serialize};
+use serde_yaml::{Mapping, Value};
+
+/// A change to a config, applied with [`Config::apply_patch`](crate::Config::apply_patch)
+/// or through the admin API
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Patch {
+    /// RFC 6902 operations, e.g. `[{"op": "replace", "path": "/port", "value": 8081}]`
+    Json(Vec<Operation>),
+    /// RFC 7386 merge patch: mappings merged key by key, `null` removing the key, any
+    /// other value replacing the one in the config
+    Merge(Value),
+}
+
+/// One RFC 6902 operation, `path` and `from` are JSON pointers such as `/hosts/0`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+pub enum Operation {
+    Add {
+        path: String,
+        value: Value,
+    },
+    Remove {
+        path: String,
+    },
+    Replace {
+        path: String,
+        value: Value,
+    },
+    Move {
+        from: String,
+        path: String,
+    },
+    Copy {
+        from: String,
+        path: String,
+    },
+    /// Fails the whole patch unless the value at `path` equals `value`
+    Test {
+        path: String,
+        value: Value,
+    },
+}
+
+impl From<Value> for Patch {
+    /// An array of operations is a JSON Patch, anything else a merge patch
+    fn from(value: Value) -> Self {
+        match serde_yaml::from_value(value.clone()) {
+            Ok(operations) if value.is_sequence() => Self::Json(operations),
+            _ => Self::Merge(value),
+        }
+    }
+}
+
+/// Apply `patch` to `target`, which is left unchanged if any operation fails
+pub(crate) fn apply(target: &mut Value, patch: &Patch) -> Result<()> {
+    match patch {
+        Patch::Merge(patch) => merge(target, patch),
+        Patch::Json(operations) => {
+            let mut patched = target.clone();
+
+            for (index, operation) in operations.iter().enumerate() {
+                run(&mut patched, operation).map_err(|e| anyhow!("operation {index}: {e}"))?;
+            }
+
+            *target = patched;
+        }
+    }
+
+    Ok(())
+}
+
+fn merge(target: &mut Value, patch: &Value) {
+    let Value::Mapping(patch) = patch else {
+        *target = patch.clone();
+        return;
+    };
+
+    if !target.is_mapping() {
+        *target = Value::Mapping(Mapping::new());
+    }
+
+    let Value::Mapping(target) = target else {
+        return;
+    };
+
+    for (k, v) in patch {
+        if v.is_null() {
+            target.remove(k);
+        } else {
+            merge(target.entry(k.clone()).or_insert(Value::Null), v);
+        }
+    }
+}
+
+fn run(target: &mut Value, operation: &Operation) -> Result<()> {
+    match operation {
+        Operation::Add { path, value } => add(target, path, value.clone()),
+        Operation::Remove { path } => remove(target, path).map(|_| ()),
+        Operation::Replace { path, value } => {
+            *get_mut(target, path)? = value.clone();
+
+            Ok(())
+        }
+        Operation::Move { from, path } => {
+            if path.starts_with(&format!("{from}/")) {
+                bail!("can't move `{from}` into itself");
+            }
+
+            let value = remove(target, from)?;
+            add(target, path, value)
+        }
+        Operation::Copy { from, path } => {
+            let value = get_mut(target, from)?.clone();
+            add(target, path, value)
+        }
+        Operation::Test { path, value } => {
+            if get_mut(target, path)? != value {
+                bail!("`{path}` doesn't hold the tested value");
+            }
+
+            Ok(())
+        }
+    }
+}
+
+// `/a/b~1c` as `["a", "b/c"]`
+fn tokens(pointer: &str) -> Result<Vec<String>> {
+    if pointer.is_empty() {
+        return Ok(vec![]);
+    }
+
+    let Some(pointer) = pointer.strip_prefix('/') else {
+        bail!("`{pointer}` is not a JSON pointer, it must start with `/`");
+    };
+
+    Ok(pointer
+        .split('/')
+        .map(|token| token.replace("~1", "/").replace("~0", "~"))
+        .collect())
+}
+
+fn child<'a>(value: &'a mut Value, token: &str, pointer: &str) -> Result<&'a mut Value> {
+    let child = match value {
+        Value::Mapping(mapping) => mapping.get_mut(token),
+        Value::Sequence(seq) => token.parse::<usize>().ok().and_then(|i| seq.get_mut(i)),
+        _ => None,
+    };
+
+    child.ok_or_else(|| anyhow!("nothing at `{pointer}`"))
+}
+
+fn get_mut<'a>(value: &'a mut Value, pointer: &str) -> Result<&'a mut Value> {
+    tokens(pointer)?
+        .iter()
+        .try_fold(value, |value, token| child(value, token, pointer))
+}
+
+// The parent of `pointer` and its last token, the root has none
+fn parent<'a>(value: &'a mut Value, pointer: &str) -> Result<(&'a mut Value, Option<String>)> {
+    let mut tokens = tokens(pointer)?;
+    let last = tokens.pop();
+    let parent = tokens
+        .iter()
+        .try_fold(value, |value, token| child(value, token, pointer))?;
+
+    Ok((parent, last))
+}
+
+fn add(target: &mut Value, pointer: &str, value: Value) -> Result<()> {
+    let (parent, last) = parent(target, pointer)?;
+    let Some(last) = last else {
+        *parent = value;
+        return Ok(());
+    };
+
+    match parent {
+        Value::Mapping(mapping) => {
+            mapping.insert(Value::String(last), value);
+        }
+        Value::Sequence(seq) if last == "-" => seq.push(value),
+        Value::Sequence(seq) => match last.parse::<usize>() {
+            Ok(index) if index <= seq.len() => seq.insert(index, value),
+            _ => bail!("`{pointer}` is out of its sequence"),
+        },
+        _ => bail!("the parent of `{pointer}` is not a mapping or a sequence"),
+    }
+
+    Ok(())
+}
+
+fn remove(target: &mut Value, pointer: &str) -> Result<Value> {
+    let (parent, last) = parent(target, pointer)?;
+    let Some(last) = last else {
+        return Ok(std::mem::take(parent));
+    };
+
+    let removed = match parent {
+        Value::Mapping(mapping) => mapping.remove(last.as_str()),
+        Value::Sequence(seq) => match last.parse::<usize>() {
+            Ok(index) if index < seq.len() => Some(seq.remove(index)),
+            _ => None,
+        },
+        _ => None,
+    };
+
+    removed.ok_or_else(|| anyhow!("nothing at `{pointer}`"))
+}
@@ -0,0 +1,182 @@
+//! Stages of loading a config, for loaders of sources unconfig doesn't read itself, e.g.
+//! a config stored in a database
+//!
+//! [`Config`](crate::Config) runs them in this order on every source:
+//!
+//! 1. [`parse`] the text into a tree,
+//! 2. apply its [`overlays`], or only those of one [`section`],
+//! 3. [`expand`] `${...}` references, checking limits and the env policy,
+//! 4. [`activate`] scheduled and computed values,
+//! 5. [`deserialize`] into the config type, with an excerpt of the config on errors.
+//!
+//! [`load`] runs 3 to 5, [`load_section`] 2 to 5 for one section. `source` names the
+//! config in errors and traces.
+
+use std::{env, path::Path};
+
+use serde::de::DeserializeOwned;
+use serde_yaml::Value;
+use tracing::{debug_span, trace};
+
+use crate::{
+    extract_section, format::Format, lenient::Lenient, provenance, resolve_document, schedule,
+    secret, secrets_file, UnconfigError,
+};
+
+type Result<T> = std::result::Result<T, UnconfigError>;
+
+/// Parse config text, YAML unless it looks like another enabled format
+pub fn parse(source: &str, text: &str) -> Result<Value> {
+    Format::of_text(text).parse(source, text)
+}
+
+/// The document with the overlays matching this host and environment merged in
+pub fn overlays(value: &Value) -> Value {
+    resolve_document(value)
+}
+
+/// Only the top-level `section`, with its part of the matching overlays
+pub fn section(value: &Value, section: &str) -> Value {
+    extract_section(value, section)
+}
+
+/// Substitute `${...}` references, `origin` is the file `${unconfig:config_path}` and
+/// relative paths resolve against
+pub fn expand(source: &str, origin: Option<&Path>, value: &mut Value) -> Result<()> {
+    crate::expand(source, origin, value).map_err(UnconfigError::validation)
+}
+
+/// Resolve scheduled values and, with the `eval` feature, `!eval` expressions
+pub fn activate(value: &mut Value) -> Result<()> {
+    activate_within(value, None)
+}
+
+// `activate` a section of `scope`, whose other sections `!eval` expressions may reference
+pub(crate) fn activate_within(value: &mut Value, scope: Option<&Scope>) -> Result<()> {
+    schedule::activate(value).map_err(UnconfigError::validation)?;
+    #[cfg(feature = "eval")]
+    crate::eval::evaluate(value, scope).map_err(UnconfigError::validation)?;
+    #[cfg(not(feature = "eval"))]
+    let _ = scope;
+
+    Ok(())
+}
+
+// The document a section was extracted from, resolving its other sections on demand
+#[cfg_attr(not(feature = "eval"), allow(dead_code))]
+pub(crate) struct Scope<'a> {
+    pub(crate) source: &'a str,
+    pub(crate) origin: Option<&'a Path>,
+    pub(crate) document: &'a Value,
+}
+
+/// A substituted string as the scalar it reads as exactly: number, bool, or the string
+/// itself, e.g. for `1.50` or `0123`, which the field's type converts when deserialized
+pub fn coerce(text: impl Into<String>) -> Value {
+    crate::coerce(text.into())
+}
+
+/// Deserialize a processed config, errors show the lines around the failing value
+///
+/// Scalars convert by the type of their field: strings parse into numbers and bools, and
+/// enum variants match ignoring case, `-` and `_`.
+pub fn deserialize<T: DeserializeOwned>(source: &str, value: &Value) -> Result<T> {
+    let config =
+        serde_yaml::to_string(value).map_err(|e| UnconfigError::Validation(e.to_string()))?;
+    let (params, secrets): (std::result::Result<T, serde_yaml::Error>, _) =
+        debug_span!("config_validate", source).in_scope(|| {
+            secret::recording(|| {
+                T::deserialize(Lenient(serde_yaml::Deserializer::from_str(&config)))
+            })
+        });
+
+    // Printed without the values of the `Secret`s just read, nor those of secrets files
+    let mut masked = value.clone();
+    secret::mask(&mut masked, &secrets);
+    secrets_file::mask(&mut masked, false);
+    let config = match &masked == value {
+        true => config,
+        false => serde_yaml::to_string(&masked).unwrap_or(config),
+    };
+
+    if let Ok("1") = env::var("DEBUG_CONFIG").as_deref() {
+        trace!("Full processed config:\n{config}");
+    }
+
+    params.map_err(|e| {
+        UnconfigError::Validation(match e.location() {
+            Some(location) => format!(
+                "{e}\nRelevant part of the config (set DEBUG_CONFIG=1 to print full config):\n{}",
+                excerpt(&config, location.line())
+            ),
+            None => format!("{e} (set DEBUG_CONFIG=1 to print full config)"),
+        })
+    })
+}
+
+/// Numbered lines of `text` around `line`, counted from 1, which is highlighted
+pub fn excerpt(text: &str, line: usize) -> String {
+    let start = line.saturating_sub(5);
+    let end = line + 5;
+    let mut excerpt = String::new();
+
+    for (index, content) in text.lines().enumerate().skip(start).take(end - start) {
+        let number = index + 1;
+
+        if number == line {
+            excerpt += &format!("\x1b[31;1m{number:>3}: {content}\x1b[0m\n");
+        } else {
+            excerpt += &format!("{number:>3}: {content}\n");
+        }
+    }
+
+    excerpt
+}
+
+/// Expand, activate and deserialize a parsed tree
+pub fn load<T: DeserializeOwned>(source: &str, origin: Option<&Path>, value: Value) -> Result<T> {
+    load_within(source, origin, value, None)
+}
+
+/// [`load`] the top-level `section` of a parsed document, with its part of the matching
+/// overlays
+///
+/// `!eval` expressions of the section may reference values of the other sections, which
+/// are expanded and activated for it.
+pub fn load_section<T: DeserializeOwned>(
+    source: &str,
+    origin: Option<&Path>,
+    document: &Value,
+    section: &str,
+) -> Result<T> {
+    let scope = Scope {
+        source,
+        origin,
+        document,
+    };
+
+    load_within(
+        source,
+        origin,
+        extract_section(document, section),
+        Some(&scope),
+    )
+}
+
+fn load_within<T: DeserializeOwned>(
+    source: &str,
+    origin: Option<&Path>,
+    mut value: Value,
+    scope: Option<&Scope>,
+) -> Result<T> {
+    let raw = provenance::recording().then(|| value.clone());
+    expand(source, origin, &mut value)?;
+    activate_within(&mut value, scope)?;
+
+    let config = deserialize(source, &value)?;
+    if let Some(raw) = raw {
+        provenance::record(source, raw, &value);
+    }
+
+    Ok(config)
+}
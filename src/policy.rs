@@ -0,0 +1,85 @@
+use std::{env, sync::OnceLock};
+
+use crate::overlay::glob_match;
+
+static ENV_POLICY: OnceLock<EnvPolicy> = OnceLock::new();
+
+/// Which environment variables may be substituted into configs
+///
+/// Patterns are globs (`*`, `?`). A variable is permitted when it matches no `deny`
+/// pattern and, if any `allow` patterns are set, at least one of them. A forbidden
+/// variable is treated as unset, and in strict mode referencing one fails the load.
+///
+/// Unless [`set_env_policy`] is called, the policy is read from `CONFIG_ENV_ALLOW` and
+/// `CONFIG_ENV_DENY` (comma separated patterns) and `CONFIG_ENV_STRICT=1`.
+#[derive(Debug, Clone, Default)]
+pub struct EnvPolicy {
+    allow: Vec<String>,
+    deny: Vec<String>,
+    strict: bool,
+}
+
+impl EnvPolicy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn allow(mut self, pattern: impl Into<String>) -> Self {
+        self.allow.push(pattern.into());
+        self
+    }
+
+    pub fn deny(mut self, pattern: impl Into<String>) -> Self {
+        self.deny.push(pattern.into());
+        self
+    }
+
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    pub fn is_strict(&self) -> bool {
+        self.strict
+    }
+
+    pub fn permits(&self, name: &str) -> bool {
+        if self.deny.iter().any(|pattern| glob_match(pattern, name)) {
+            return false;
+        }
+
+        self.allow.is_empty() || self.allow.iter().any(|pattern| glob_match(pattern, name))
+    }
+
+    fn from_env() -> Self {
+        let patterns = |var| {
+            env::var(var)
+                .map(|v| {
+                    v.split(',')
+                        .map(str::trim)
+                        .filter(|p| !p.is_empty())
+                        .map(String::from)
+                        .collect()
+                })
+                .unwrap_or_default()
+        };
+
+        Self {
+            allow: patterns("CONFIG_ENV_ALLOW"),
+            deny: patterns("CONFIG_ENV_DENY"),
+            strict: matches!(env::var("CONFIG_ENV_STRICT").as_deref(), Ok("1")),
+        }
+    }
+}
+
+/// Install the policy for every following load
+///
+/// Has to run before the first config is loaded, afterwards the policy is fixed and the
+/// rejected one is handed back.
+pub fn set_env_policy(policy: EnvPolicy) -> Result<(), EnvPolicy> {
+    ENV_POLICY.set(policy)
+}
+
+pub(crate) fn env_policy() -> &'static EnvPolicy {
+    ENV_POLICY.get_or_init(EnvPolicy::from_env)
+}
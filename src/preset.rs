@@ -0,0 +1,95 @@
+use std::{env, path::PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{LoggerFilter, LoggerParams, SinkFormat, SinkKind, SinkParams};
+
+/// Canned logger params for a deployment target, chosen with `preset` in the logger params
+///
+/// ```yaml
+/// logger:
+///   preset: container
+///   default_level: info
+/// ```
+///
+/// The rest of the params are merged over the preset's, so any of them, e.g. `sinks`,
+/// can still be set to replace what the preset picked.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum LoggerPreset {
+    /// JSON lines on stdout for the log collector, no files
+    Container,
+    /// Warnings and errors to the Windows Event Log, everything to a daily file under
+    /// `%ProgramData%\<app>\logs`
+    ///
+    /// The Event Log source is named after the executable. Off Windows only the file is
+    /// written, under `logs` in the working directory.
+    WindowsService,
+}
+
+impl LoggerPreset {
+    pub fn params(self) -> LoggerParams {
+        let sinks = match self {
+            Self::Container => vec![sink(SinkKind::Console, SinkFormat::Json)],
+            Self::WindowsService => {
+                let app = app_name();
+                let dir = match env::var_os("ProgramData").filter(|dir| !dir.is_empty()) {
+                    Some(dir) => PathBuf::from(dir).join(&app).join("logs"),
+                    None => PathBuf::from("logs"),
+                };
+
+                let file = SinkParams {
+                    path: Some(dir.join(format!("{app}.log"))),
+                    ..sink(SinkKind::File, SinkFormat::Text)
+                };
+                let event_log = SinkParams {
+                    level: Some("warn".to_string()),
+                    ..sink(SinkKind::EventLog, SinkFormat::Text)
+                };
+
+                if cfg!(windows) {
+                    vec![event_log, file]
+                } else {
+                    vec![file]
+                }
+            }
+        };
+
+        LoggerParams {
+            preset: Some(self),
+            log_dir: None,
+            log_file_prefix: None,
+            add_log_file_prefix: None,
+            default_level: "info".to_string(),
+            filter: LoggerFilter::default(),
+            add_filter: None,
+            span_timings: false,
+            span_histograms: false,
+            trace_ids: false,
+            console: None,
+            sinks: Some(sinks),
+            appender_thread_name: None,
+            appender_nice: None,
+            crash_dump: None,
+            shutdown_timeout_ms: None,
+        }
+    }
+}
+
+fn sink(kind: SinkKind, format: SinkFormat) -> SinkParams {
+    SinkParams {
+        kind,
+        path: None,
+        format,
+        level: None,
+        filter: LoggerFilter::default(),
+    }
+}
+
+/// File name of the executable without its extension
+pub(crate) fn app_name() -> String {
+    env::current_exe()
+        .ok()
+        .and_then(|exe| Some(exe.file_stem()?.to_string_lossy().into_owned()))
+        .unwrap_or_else(|| env!("CARGO_PKG_NAME").to_string())
+}
@@ -0,0 +1,71 @@
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
+
+use serde::de::DeserializeOwned;
+use tracing::debug;
+
+use crate::{env_overlay, Config, UnconfigError};
+
+/// Variable selecting the profile, e.g. `UNCONFIG_PROFILE=prod`
+pub const PROFILE_VAR: &str = "UNCONFIG_PROFILE";
+
+/// Profile selected by [`PROFILE_VAR`], if any
+///
+/// `#[configurable]` structs merge `config.<profile>.yml`, next to their runtime file,
+/// over it, and the environment variables of `env_prefix` over both. Only the runtime
+/// file itself is watched for changes.
+pub fn profile() -> Option<String> {
+    env_overlay::var(PROFILE_VAR)
+        .ok()
+        .filter(|profile| !profile.is_empty())
+}
+
+/// `config.prod.yml` for `config.yml` and the `prod` profile
+pub fn profile_path(path: impl AsRef<Path>, profile: &str) -> PathBuf {
+    let path = path.as_ref();
+    let stem = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy())
+        .unwrap_or_default();
+    let name = match path.extension() {
+        Some(extension) => format!("{stem}.{profile}.{}", extension.to_string_lossy()),
+        None => format!("{stem}.{profile}"),
+    };
+
+    path.with_file_name(name)
+}
+
+/// `section` of the profile file of `path`, `None` without a profile or a file for it
+#[doc(hidden)]
+pub fn load_profile<T: DeserializeOwned>(
+    path: impl AsRef<Path>,
+    section: &str,
+) -> Result<Option<T>, UnconfigError> {
+    let Some(profile) = profile() else {
+        return Ok(None);
+    };
+
+    // A name, not a path that could point elsewhere
+    if !profile
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        return Err(UnconfigError::Validation(format!(
+            "invalid {PROFILE_VAR} `{profile}`, expected letters, digits, `-` or `_`"
+        )));
+    }
+
+    let path = profile_path(path, &profile);
+
+    match T::load_path_section(&path, section) {
+        Ok(config) => Ok(Some(config)),
+        Err(UnconfigError::Io { source, .. }) if source.kind() == io::ErrorKind::NotFound => {
+            debug!("No {} for the {profile} profile", path.display());
+
+            Ok(None)
+        }
+        Err(e) => Err(e),
+    }
+}
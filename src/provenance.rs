@@ -0,0 +1,174 @@
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    sync::{LazyLock, Mutex},
+};
+
+use indexmap::IndexMap;
+use serde_yaml::Value;
+
+use crate::Provenance;
+
+// The sources of the configs being loaded on this thread, innermost last
+thread_local! {
+    static RECORDING: RefCell<Vec<Vec<Layer>>> = const { RefCell::new(vec![]) };
+}
+
+static PROVENANCE: LazyLock<Mutex<HashMap<&'static str, IndexMap<String, Provenance>>>> =
+    LazyLock::new(Default::default);
+// The sources of the last load of each config, in merge order
+static SOURCES: LazyLock<Mutex<HashMap<&'static str, Vec<String>>>> =
+    LazyLock::new(Default::default);
+
+// One source as written and as loaded
+struct Layer {
+    source: String,
+    raw: Value,
+    expanded: Value,
+}
+
+/// Records the sources loaded until [`Tracking::finish`], in the order they're merged
+///
+/// Used by the generated `init()` and `reload()`, dropping it unfinished discards them.
+#[doc(hidden)]
+#[must_use = "sources are only recorded until this is dropped"]
+pub struct Tracking(());
+
+#[doc(hidden)]
+pub fn track_provenance() -> Tracking {
+    RECORDING.with_borrow_mut(|recording| recording.push(vec![]));
+
+    Tracking(())
+}
+
+impl Tracking {
+    /// Attribute every value of `section` to the last source setting it
+    ///
+    /// A later source replaces a whole top-level field, except for the `deep` ones which
+    /// are merged key by key.
+    pub fn finish(self, config: &'static str, section: &str, deep: &[&str]) {
+        let layers = RECORDING.with_borrow_mut(|recording| recording.pop().unwrap_or_default());
+        std::mem::forget(self);
+
+        let mut provenance = IndexMap::new();
+        let mut sources = vec![];
+
+        for layer in layers {
+            if !sources.contains(&layer.source) {
+                sources.push(layer.source.clone());
+            }
+
+            let Some(Value::Mapping(fields)) = layer.expanded.get(section) else {
+                continue;
+            };
+            let raw = layer.raw.get(section);
+
+            for (field, value) in fields {
+                let Some(field) = field.as_str() else {
+                    continue;
+                };
+
+                if value.is_null() {
+                    continue;
+                }
+
+                if !deep.contains(&field) {
+                    provenance.retain(|path: &String, _| !is_within(path, field));
+                }
+
+                let raw = raw.and_then(|raw| raw.get(field));
+                leaves(
+                    field.to_string(),
+                    value,
+                    raw,
+                    &mut |path, substituted_from| {
+                        provenance.insert(
+                            path,
+                            Provenance {
+                                source: layer.source.clone(),
+                                substituted_from,
+                            },
+                        );
+                    },
+                );
+            }
+        }
+
+        PROVENANCE.lock().unwrap().insert(config, provenance);
+        SOURCES.lock().unwrap().insert(config, sources);
+    }
+}
+
+impl Drop for Tracking {
+    fn drop(&mut self) {
+        RECORDING.with_borrow_mut(|recording| recording.pop());
+    }
+}
+
+/// Key paths of the last loaded `config` and where their values came from
+#[doc(hidden)]
+pub fn provenance_of(config: &str) -> IndexMap<String, Provenance> {
+    PROVENANCE
+        .lock()
+        .unwrap()
+        .get(config)
+        .cloned()
+        .unwrap_or_default()
+}
+
+/// Sources the last load of `config` merged, in order
+pub(crate) fn sources_of(config: &str) -> Vec<String> {
+    SOURCES
+        .lock()
+        .unwrap()
+        .get(config)
+        .cloned()
+        .unwrap_or_default()
+}
+
+/// Whether a config is being loaded on this thread, see [`record`]
+pub(crate) fn recording() -> bool {
+    RECORDING.with_borrow(|recording| !recording.is_empty())
+}
+
+/// A source of the config being loaded on this thread, `raw` before substitution
+pub(crate) fn record(source: &str, raw: Value, expanded: &Value) {
+    RECORDING.with_borrow_mut(|recording| {
+        if let Some(layers) = recording.last_mut() {
+            layers.push(Layer {
+                source: source.to_string(),
+                raw,
+                expanded: expanded.clone(),
+            });
+        }
+    });
+}
+
+// Mappings are walked, any other value is one leaf
+fn leaves(
+    path: String,
+    value: &Value,
+    raw: Option<&Value>,
+    found: &mut impl FnMut(String, Option<Value>),
+) {
+    match value {
+        Value::Mapping(mapping) => {
+            for (k, v) in mapping {
+                let Some(k) = k.as_str() else {
+                    continue;
+                };
+
+                if !v.is_null() {
+                    let raw = raw.and_then(|raw| raw.get(k));
+                    leaves(format!("{path}.{k}"), v, raw, found);
+                }
+            }
+        }
+        value => found(path, raw.filter(|raw| *raw != value).cloned()),
+    }
+}
+
+fn is_within(path: &str, field: &str) -> bool {
+    path.strip_prefix(field)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with('.'))
+}
@@ -0,0 +1,101 @@
+//! Loader entry points for the Python bindings in `python/unconfig.py`
+//!
+//! Only built with the `python` feature. Python sidecars load, expand and merge configs
+//! through these, so `${...}` substitution, overlays, limits and the env policy behave
+//! exactly as in the Rust services. Results are JSON, freed with
+//! [`crate::ffi::unconfig_free_str`]. On errors they're null and
+//! [`unconfig_last_error`] tells why.
+
+use std::{
+    cell::RefCell,
+    ffi::{c_char, CStr, CString},
+    ptr,
+};
+
+use anyhow::{anyhow, Result};
+use serde_yaml::Value;
+
+use crate::{format::Format, json, overlay::deep_merge, Document, UnconfigError};
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+/// The config file at `path`, processed like [`Document::load_path`]
+///
+/// # Safety
+///
+/// `path` must be a valid nul-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn unconfig_load(path: *const c_char) -> *mut c_char {
+    // SAFETY: guaranteed by the caller
+    returned(unsafe { text(path) }.and_then(|path| Ok(Document::load_path(path)?.value().clone())))
+}
+
+/// Config text, processed like [`Document::load_str`]
+///
+/// # Safety
+///
+/// `config` must be a valid nul-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn unconfig_expand(config: *const c_char) -> *mut c_char {
+    // SAFETY: guaranteed by the caller
+    returned(
+        unsafe { text(config) }.and_then(|config| Ok(Document::load_str(config)?.value().clone())),
+    )
+}
+
+/// `overlay` merged over `base`, both YAML or JSON text: mappings key by key, other
+/// values of `overlay` replacing those of `base`
+///
+/// # Safety
+///
+/// `base` and `overlay` must be valid nul-terminated strings.
+#[no_mangle]
+pub unsafe extern "C" fn unconfig_merge(
+    base: *const c_char,
+    overlay: *const c_char,
+) -> *mut c_char {
+    // SAFETY: guaranteed by the caller
+    let merged = unsafe { text(base).and_then(|base| Ok((base, text(overlay)?))) }.and_then(
+        |(base, overlay)| {
+            let mut base = parse("base", base)?;
+            deep_merge(&mut base, parse("overlay", overlay)?);
+
+            Ok(base)
+        },
+    );
+
+    returned(merged)
+}
+
+/// Why the last call on this thread returned null, null if it didn't
+///
+/// Owned by unconfig, valid until the next call on this thread.
+#[no_mangle]
+pub extern "C" fn unconfig_last_error() -> *const c_char {
+    LAST_ERROR.with_borrow(|error| error.as_ref().map_or(ptr::null(), |error| error.as_ptr()))
+}
+
+// SAFETY: `text` must be null or a valid nul-terminated string
+unsafe fn text<'a>(text: *const c_char) -> Result<&'a str> {
+    if text.is_null() {
+        return Err(anyhow!("null string"));
+    }
+
+    Ok(unsafe { CStr::from_ptr(text) }.to_str()?)
+}
+
+fn parse(source: &str, text: &str) -> Result<Value> {
+    Ok(Format::of_text(text).parse(source, text)?)
+}
+
+fn returned(value: Result<Value>) -> *mut c_char {
+    let (json, error) = match value {
+        Ok(value) => (CString::new(json::to_string(&value)).ok(), None),
+        Err(e) => (None, Some(UnconfigError::validation(e).to_string())),
+    };
+    LAST_ERROR.set(error.and_then(|error| CString::new(error).ok()));
+
+    json.map_or(ptr::null_mut(), CString::into_raw)
+}
@@ -0,0 +1,230 @@
+use std::fmt::Write;
+
+use serde_yaml::Value;
+
+use crate::json;
+
+/// Style of the configs unconfig writes, e.g. with [`Config::save_path`](crate::Config::save_path)
+///
+/// The same tree always renders to the same text, so that a config saved again after a
+/// change only differs by that change.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RenderOptions {
+    /// Spaces per nesting level
+    pub indent: usize,
+    /// Mapping keys in sorted order, otherwise in the order of the config. Keep it on for
+    /// configs with `HashMap` fields, which have no order of their own
+    pub sort_keys: bool,
+    pub quoting: Quoting,
+}
+
+impl Default for RenderOptions {
+    fn default() -> Self {
+        Self {
+            indent: 2,
+            sort_keys: true,
+            quoting: Quoting::Minimal,
+        }
+    }
+}
+
+/// When YAML strings are quoted, always with double quotes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Quoting {
+    /// Only strings that would read back as something else, e.g. `"true"` or `"8080"`
+    Minimal,
+    /// Every string
+    Always,
+}
+
+/// `value` as block YAML
+pub(crate) fn yaml(value: &Value, options: &RenderOptions) -> String {
+    let mut out = String::new();
+
+    match value {
+        Value::Mapping(mapping) if !mapping.is_empty() => {
+            write_entries(&mut out, value, 0, options)
+        }
+        Value::Sequence(seq) if !seq.is_empty() => write_items(&mut out, value, 0, options),
+        other => {
+            write_node(&mut out, other, 0, options);
+            // Scalars are written after a key, without the space
+            out.remove(0);
+        }
+    }
+
+    out
+}
+
+/// `value` as indented JSON, tags dropped
+pub(crate) fn json(value: &Value, options: &RenderOptions) -> String {
+    let mut out = String::new();
+    write_json(&mut out, value, 0, options);
+    out.push('\n');
+
+    out
+}
+
+fn sorted<'a>(
+    entries: impl Iterator<Item = (&'a Value, &'a Value)>,
+    options: &RenderOptions,
+) -> Vec<(String, &'a Value)> {
+    let mut entries = entries
+        .map(|(k, v)| (key(k, options), v))
+        .collect::<Vec<_>>();
+
+    if options.sort_keys {
+        entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+    }
+
+    entries
+}
+
+// Whatever follows `key:` or `- `, up to the end of its last line
+fn write_node(out: &mut String, value: &Value, column: usize, options: &RenderOptions) {
+    match value {
+        Value::Mapping(mapping) if !mapping.is_empty() => {
+            out.push('\n');
+            write_entries(out, value, column + options.indent, options);
+        }
+        Value::Sequence(seq) if !seq.is_empty() => {
+            out.push('\n');
+            write_items(out, value, column + options.indent, options);
+        }
+        Value::Tagged(tagged) => {
+            let _ = write!(out, " {}", tagged.tag);
+            write_node(out, &tagged.value, column, options);
+        }
+        scalar => {
+            out.push(' ');
+            out.push_str(&self::scalar(scalar, options));
+            out.push('\n');
+        }
+    }
+}
+
+fn write_entries(out: &mut String, value: &Value, column: usize, options: &RenderOptions) {
+    let Value::Mapping(mapping) = value else {
+        return;
+    };
+
+    for (key, value) in sorted(mapping.iter(), options) {
+        let _ = write!(out, "{:column$}{key}:", "");
+        write_node(out, value, column, options);
+    }
+}
+
+fn write_items(out: &mut String, value: &Value, column: usize, options: &RenderOptions) {
+    let Value::Sequence(seq) = value else {
+        return;
+    };
+
+    for item in seq {
+        let _ = write!(out, "{:column$}-", "");
+
+        match item {
+            // The first entry on the line of the dash, the others aligned with it
+            Value::Mapping(mapping) if !mapping.is_empty() => {
+                let mut entries = String::new();
+                write_entries(&mut entries, item, column + 2, options);
+                out.push(' ');
+                out.push_str(&entries[column + 2..]);
+            }
+            _ => write_node(out, item, column, options),
+        }
+    }
+}
+
+fn key(key: &Value, options: &RenderOptions) -> String {
+    match key {
+        Value::String(_) | Value::Number(_) | Value::Bool(_) | Value::Null => scalar(key, options),
+        // Complex keys are written as their JSON text
+        other => {
+            let mut out = String::new();
+            json::write_str(&mut out, &json::to_string(other));
+
+            out
+        }
+    }
+}
+
+fn scalar(value: &Value, options: &RenderOptions) -> String {
+    match value {
+        Value::Null => "null".to_string(),
+        Value::Bool(flag) => flag.to_string(),
+        Value::Number(number) => number.to_string(),
+        Value::String(text) if options.quoting == Quoting::Minimal && is_plain(text) => {
+            text.clone()
+        }
+        Value::String(text) => {
+            let mut out = String::new();
+            json::write_str(&mut out, text);
+
+            out
+        }
+        Value::Mapping(_) => "{}".to_string(),
+        Value::Sequence(_) => "[]".to_string(),
+        Value::Tagged(tagged) => format!("{} {}", tagged.tag, scalar(&tagged.value, options)),
+    }
+}
+
+// Reads back as the same string when written without quotes
+fn is_plain(text: &str) -> bool {
+    !text.is_empty()
+        && text.trim() == text
+        && !text.contains(['\n', '\r', '\t', '"', '\''])
+        && !text.contains(": ")
+        && !text.contains(" #")
+        && !text.ends_with(':')
+        && !text.starts_with([
+            '-', '?', ':', ',', '[', ']', '{', '}', '#', '&', '*', '!', '|', '>', '%', '@', '`',
+        ])
+        && matches!(
+            serde_yaml::from_str::<Value>(text),
+            Ok(Value::String(parsed)) if parsed == text
+        )
+}
+
+fn write_json(out: &mut String, value: &Value, column: usize, options: &RenderOptions) {
+    let inner = column + options.indent;
+
+    match value {
+        Value::Mapping(mapping) if !mapping.is_empty() => {
+            out.push_str("{\n");
+
+            for (i, (key, value)) in sorted(mapping.iter(), options).into_iter().enumerate() {
+                if i > 0 {
+                    out.push_str(",\n");
+                }
+
+                let _ = write!(out, "{:inner$}", "");
+                // Quoted already unless plain
+                if key.starts_with('"') {
+                    out.push_str(&key);
+                } else {
+                    json::write_str(out, &key);
+                }
+                out.push_str(": ");
+                write_json(out, value, inner, options);
+            }
+
+            let _ = write!(out, "\n{:column$}}}", "");
+        }
+        Value::Sequence(seq) if !seq.is_empty() => {
+            out.push_str("[\n");
+
+            for (i, item) in seq.iter().enumerate() {
+                if i > 0 {
+                    out.push_str(",\n");
+                }
+
+                let _ = write!(out, "{:inner$}", "");
+                write_json(out, item, inner, options);
+            }
+
+            let _ = write!(out, "\n{:column$}]", "");
+        }
+        Value::Tagged(tagged) => write_json(out, &tagged.value, column, options),
+        scalar => out.push_str(&json::to_string(scalar)),
+    }
+}
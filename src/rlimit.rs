@@ -0,0 +1,226 @@
+use std::{fmt, io, path::Path};
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_yaml::Value;
+use tracing::info;
+
+use crate::{Config, UnconfigError};
+
+/// `limits` section of a config, applied to the process with [`ResourceLimits::apply`] or
+/// by `#[config(limits = "config.yml", ...)]` before any config is loaded
+///
+/// ```yaml
+/// limits:
+///   max_open_files: 65536
+///   core_dump_size: unlimited
+///   nice: 10
+/// ```
+///
+/// Unset limits are left as the process inherited them. Only Linux and macOS support
+/// them, elsewhere applying any fails.
+#[derive(Deserialize, Serialize, Debug, Clone, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ResourceLimits {
+    /// File descriptors the process may hold, `RLIMIT_NOFILE`
+    pub max_open_files: Option<Rlimit>,
+    /// Largest core dump written in bytes, `0` to write none, `RLIMIT_CORE`
+    pub core_dump_size: Option<Rlimit>,
+    /// Scheduling priority, from -20 (highest) to 19 (lowest)
+    pub nice: Option<i32>,
+}
+
+#[derive(Deserialize)]
+struct UpperResourceLimits {
+    #[serde(default)]
+    limits: ResourceLimits,
+}
+
+/// Value of a resource limit, a number or `unlimited`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rlimit {
+    Limited(u64),
+    Unlimited,
+}
+
+impl fmt::Display for Rlimit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Limited(value) => write!(f, "{value}"),
+            Self::Unlimited => f.write_str("unlimited"),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Rlimit {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        match Value::deserialize(deserializer)? {
+            Value::Number(number) => number.as_u64().map(Self::Limited).ok_or_else(|| {
+                serde::de::Error::custom(format!("expected a positive limit, got {number}"))
+            }),
+            Value::String(text) if text == "unlimited" => Ok(Self::Unlimited),
+            // Substituted from a variable
+            Value::String(text) => text.parse().map(Self::Limited).map_err(|_| {
+                serde::de::Error::custom(format!("expected a number or `unlimited`, got `{text}`"))
+            }),
+            other => Err(serde::de::Error::custom(format!(
+                "expected a number or `unlimited`, got {other:?}"
+            ))),
+        }
+    }
+}
+
+impl Serialize for Rlimit {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        match self {
+            Self::Limited(value) => serializer.serialize_u64(*value),
+            Self::Unlimited => serializer.serialize_str("unlimited"),
+        }
+    }
+}
+
+impl ResourceLimits {
+    /// Set the limits of the current process
+    ///
+    /// A soft limit above the hard one raises the hard limit too, which takes privileges,
+    /// as does a negative `nice`. Stops at the first limit that can't be set.
+    pub fn apply(&self) -> Result<(), UnconfigError> {
+        let fail =
+            |key: &str, e: io::Error| UnconfigError::Validation(format!("limits.{key}: {e}"));
+
+        if let Some(limit) = self.max_open_files {
+            sys::set_rlimit(sys::RLIMIT_NOFILE, limit).map_err(|e| fail("max_open_files", e))?;
+            info!("Limited open files to {limit}");
+        }
+        if let Some(limit) = self.core_dump_size {
+            sys::set_rlimit(sys::RLIMIT_CORE, limit).map_err(|e| fail("core_dump_size", e))?;
+            info!("Limited core dumps to {limit}");
+        }
+        if let Some(nice) = self.nice {
+            sys::set_nice(nice).map_err(|e| fail("nice", e))?;
+            info!("Set nice to {nice}");
+        }
+
+        Ok(())
+    }
+}
+
+/// Apply the `limits` section of the config at `path`, if it has one
+#[doc(hidden)]
+pub fn apply_resource_limits(path: impl AsRef<Path>) {
+    let path = path.as_ref();
+
+    let limits = match UpperResourceLimits::load_path_section(path, "limits") {
+        Ok(upper) => upper.limits,
+        // No config, no limits to apply
+        Err(UnconfigError::Io { source, .. }) if source.kind() == io::ErrorKind::NotFound => return,
+        Err(e) => panic!(
+            "Failed to read the resource limits of {}: {e}",
+            path.display()
+        ),
+    };
+
+    if let Err(e) = limits.apply() {
+        panic!(
+            "Failed to apply the resource limits of {}: {e}",
+            path.display()
+        );
+    }
+}
+
+#[cfg(all(
+    any(target_os = "linux", target_os = "android", target_os = "macos"),
+    target_pointer_width = "64"
+))]
+mod sys {
+    use std::{ffi::c_int, io};
+
+    use super::Rlimit;
+
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub(super) const RLIMIT_NOFILE: c_int = 7;
+    #[cfg(target_os = "macos")]
+    pub(super) const RLIMIT_NOFILE: c_int = 8;
+    pub(super) const RLIMIT_CORE: c_int = 4;
+
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    const RLIM_INFINITY: u64 = u64::MAX;
+    #[cfg(target_os = "macos")]
+    const RLIM_INFINITY: u64 = i64::MAX as u64;
+
+    const PRIO_PROCESS: c_int = 0;
+
+    #[repr(C)]
+    struct rlimit {
+        rlim_cur: u64,
+        rlim_max: u64,
+    }
+
+    extern "C" {
+        fn getrlimit(resource: c_int, rlim: *mut rlimit) -> c_int;
+        fn setrlimit(resource: c_int, rlim: *const rlimit) -> c_int;
+        fn setpriority(which: c_int, who: u32, prio: c_int) -> c_int;
+    }
+
+    pub(super) fn set_rlimit(resource: c_int, limit: Rlimit) -> io::Result<()> {
+        let value = match limit {
+            Rlimit::Limited(value) if value < RLIM_INFINITY => value,
+            _ => RLIM_INFINITY,
+        };
+
+        let mut current = rlimit {
+            rlim_cur: 0,
+            rlim_max: 0,
+        };
+        // SAFETY: `current` is a valid `rlimit` for the call to fill in
+        if unsafe { getrlimit(resource, &mut current) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let limit = rlimit {
+            rlim_cur: value,
+            rlim_max: current.rlim_max.max(value),
+        };
+        // SAFETY: `limit` is a valid `rlimit` that outlives the call
+        if unsafe { setrlimit(resource, &limit) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(())
+    }
+
+    pub(super) fn set_nice(nice: i32) -> io::Result<()> {
+        // SAFETY: plain values, `0` is the calling process
+        if unsafe { setpriority(PRIO_PROCESS, 0, nice) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(not(all(
+    any(target_os = "linux", target_os = "android", target_os = "macos"),
+    target_pointer_width = "64"
+)))]
+mod sys {
+    use std::io;
+
+    use super::Rlimit;
+
+    pub(super) const RLIMIT_NOFILE: i32 = 0;
+    pub(super) const RLIMIT_CORE: i32 = 0;
+
+    pub(super) fn set_rlimit(_: i32, _: Rlimit) -> io::Result<()> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+
+    pub(super) fn set_nice(_: i32) -> io::Result<()> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+}
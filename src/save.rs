@@ -0,0 +1,65 @@
+use std::{fs, io, path::Path};
+
+use serde::Serialize;
+use serde_yaml::{Mapping, Value};
+
+use crate::{format::Format, full_path, secret, RenderOptions, UnconfigError};
+
+/// `config` as saved, without its secrets
+pub(crate) fn to_value<T: Serialize>(config: &T) -> Result<Value, UnconfigError> {
+    secret::saving(|| serde_yaml::to_value(config))
+        .map_err(|e| UnconfigError::Validation(e.to_string()))
+}
+
+/// `value` under the top-level `section`
+pub(crate) fn nested(section: &str, value: Value) -> Value {
+    let mut mapping = Mapping::new();
+    mapping.insert(section.into(), value);
+
+    Value::Mapping(mapping)
+}
+
+/// Write `value` to `path`, replacing only its `section` if one is given
+pub(crate) fn write(
+    path: &Path,
+    section: Option<&str>,
+    value: Value,
+    options: &RenderOptions,
+) -> Result<(), UnconfigError> {
+    let path = full_path(path)?;
+    let source = path.display().to_string();
+    let format = Format::of_path(&path);
+
+    let value = match section {
+        // As written, without resolving includes or `${...}` references
+        Some(section) => match fs::read_to_string(&path) {
+            Ok(content) => {
+                let mut document = format.parse(&source, &content)?;
+
+                match &mut document {
+                    Value::Mapping(mapping) => {
+                        mapping.insert(section.into(), value);
+
+                        document
+                    }
+                    // An empty file
+                    Value::Null => nested(section, value),
+                    _ => {
+                        return Err(UnconfigError::Validation(format!(
+                            "{source}: the config is not a mapping of sections"
+                        )))
+                    }
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => nested(section, value),
+            Err(e) => return Err(UnconfigError::io(&path, e)),
+        },
+        None => value,
+    };
+    let content = format.render(&source, &value, options)?;
+
+    // Write through a temporary file, so a watcher never reads half of it
+    let tmp_path = path.with_extension(format!("tmp{}", std::process::id()));
+    fs::write(&tmp_path, content).map_err(|e| UnconfigError::io(&tmp_path, e))?;
+    fs::rename(&tmp_path, &path).map_err(|e| UnconfigError::io(&path, e))
+}
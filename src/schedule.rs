@@ -0,0 +1,428 @@
+use std::{
+    cell::Cell,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{anyhow, Result};
+use serde_yaml::Value;
+
+const UNTIL: &str = "until";
+const VALUE: &str = "value";
+const THEN: &str = "then";
+
+thread_local! {
+    // Earliest upcoming switch of the values activated on this thread, while `recording`
+    static NEXT: Cell<Option<Option<i64>>> = const { Cell::new(None) };
+}
+
+/// Resolve scheduled values against the current time
+///
+/// A mapping with an `until` timestamp, a `then` value and an optional `value` is a
+/// scheduled value: it stands for `value` before `until` and for `then` from that moment
+/// on. Without `value` the key is treated as absent until the switch. `then` may be
+/// scheduled again to stage several changes.
+///
+/// ```yaml
+/// rate_limit:
+///   value: 1000
+///   until: "2025-07-01T00:00:00Z"
+///   then: 200
+/// ```
+///
+/// Returns the earliest `until` still ahead, when the value has to be activated again.
+pub(crate) fn activate(value: &mut Value) -> Result<Option<i64>> {
+    let mut next = None;
+    activate_at(value, now(), &mut next)?;
+
+    if let Some(recorded) = NEXT.get() {
+        NEXT.set(Some(earliest(recorded, next)));
+    }
+
+    Ok(next)
+}
+
+/// `f` with the earliest `until` still ahead among the values it activates
+pub(crate) fn recording<R>(f: impl FnOnce() -> R) -> (R, Option<i64>) {
+    struct Restore(Option<Option<i64>>);
+
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            NEXT.set(self.0);
+        }
+    }
+
+    let restore = Restore(NEXT.replace(Some(None)));
+    let result = f();
+    let next = NEXT.replace(None).flatten();
+    drop(restore);
+
+    (result, next)
+}
+
+fn earliest(a: Option<i64>, b: Option<i64>) -> Option<i64> {
+    a.into_iter().chain(b).min()
+}
+
+fn activate_at(value: &mut Value, now: i64, next: &mut Option<i64>) -> Result<()> {
+    match value {
+        Value::Mapping(mapping) => {
+            let mut absent = vec![];
+
+            for (k, v) in mapping.iter_mut() {
+                match select(v, now, next)? {
+                    None => activate_at(v, now, next)?,
+                    Some(Some(selected)) => {
+                        *v = selected;
+                        activate_at(v, now, next)?;
+                    }
+                    Some(None) => absent.push(k.clone()),
+                }
+            }
+
+            for k in absent {
+                mapping.shift_remove(&k);
+            }
+        }
+        Value::Sequence(seq) => {
+            let mut activated = Vec::with_capacity(seq.len());
+
+            for mut v in seq.drain(..) {
+                match select(&v, now, next)? {
+                    None => {}
+                    Some(Some(selected)) => v = selected,
+                    Some(None) => continue,
+                }
+
+                activate_at(&mut v, now, next)?;
+                activated.push(v);
+            }
+
+            *seq = activated;
+        }
+        _ => {}
+    }
+
+    Ok(())
+}
+
+fn is_scheduled(value: &Value) -> bool {
+    let Value::Mapping(mapping) = value else {
+        return false;
+    };
+
+    mapping.contains_key(UNTIL)
+        && mapping.contains_key(THEN)
+        && mapping
+            .keys()
+            .all(|k| matches!(k.as_str(), Some(UNTIL | VALUE | THEN)))
+}
+
+// None if `value` isn't scheduled, Some(None) if it is but nothing is in effect yet. `next`
+// keeps the earliest `until` ahead of `now`
+fn select(value: &Value, now: i64, next: &mut Option<i64>) -> Result<Option<Option<Value>>> {
+    if !is_scheduled(value) {
+        return Ok(None);
+    }
+
+    let until = match &value[UNTIL] {
+        Value::String(text) => parse_timestamp(text)
+            .ok_or_else(|| anyhow!("invalid `until` timestamp in scheduled value: {text}"))?,
+        other => return Err(anyhow!("`until` must be a timestamp string, got {other:?}")),
+    };
+
+    let selected = if now < until {
+        *next = earliest(*next, Some(until));
+
+        value.get(VALUE)
+    } else {
+        value.get(THEN)
+    };
+
+    match selected {
+        Some(selected) => Ok(Some(
+            select(selected, now, next)?.unwrap_or(Some(selected.clone())),
+        )),
+        None => Ok(Some(None)),
+    }
+}
+
+pub(crate) fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or_default()
+}
+
+/// Seconds since the Unix epoch for an RFC 3339 timestamp or a plain `YYYY-MM-DD` date (UTC)
+pub(crate) fn parse_timestamp(text: &str) -> Option<i64> {
+    let text = text.trim();
+    let (date, time) = match text.split_once(['T', 't', ' ']) {
+        Some((date, time)) => (date, Some(time)),
+        None => (text, None),
+    };
+
+    let mut date = date.splitn(3, '-');
+    let year = number(date.next()?)?;
+    let month = number(date.next()?)? as u32;
+    let day = number(date.next()?)? as u32;
+
+    if !(1..=12).contains(&month) || day == 0 || day > days_in_month(year, month) {
+        return None;
+    }
+
+    let mut seconds = days_from_civil(year, month, day) * 86_400;
+
+    if let Some(time) = time {
+        let (clock, offset) = if let Some(clock) = time.strip_suffix(['Z', 'z']) {
+            (clock, 0)
+        } else if let Some(split) = time.rfind(['+', '-']) {
+            let (clock, offset) = time.split_at(split);
+            let sign = if offset.starts_with('-') { -1 } else { 1 };
+            let (hours, minutes) = offset[1..].split_once(':')?;
+            let (hours, minutes) = (number(hours)?, number(minutes)?);
+
+            if hours > 23 || minutes > 59 {
+                return None;
+            }
+
+            (clock, sign * (hours * 3600 + minutes * 60))
+        } else {
+            // No offset at all is read as UTC
+            (time, 0)
+        };
+
+        // Fractional seconds don't matter for activation
+        let clock = match clock.split_once('.') {
+            Some((clock, fraction)) if is_digits(fraction) => clock,
+            Some(_) => return None,
+            None => clock,
+        };
+        let mut clock = clock.splitn(3, ':');
+        let hours = number(clock.next()?)?;
+        let minutes = number(clock.next()?)?;
+        let secs = clock.next().map_or(Some(0), number)?;
+
+        if hours > 23 || minutes > 59 || secs > 60 {
+            return None;
+        }
+
+        seconds += hours * 3600 + minutes * 60 + secs - offset;
+    }
+
+    Some(seconds)
+}
+
+// Digits only, no sign
+fn number(text: &str) -> Option<i64> {
+    match is_digits(text) {
+        true => text.parse().ok(),
+        false => None,
+    }
+}
+
+fn is_digits(text: &str) -> bool {
+    !text.is_empty() && text.bytes().all(|byte| byte.is_ascii_digit())
+}
+
+fn days_in_month(year: i64, month: u32) -> u32 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Current time as RFC 3339
+pub(crate) fn now_timestamp() -> String {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or_default();
+
+    format_timestamp(now)
+}
+
+/// RFC 3339 UTC representation of seconds since the Unix epoch
+pub(crate) fn format_timestamp(seconds: i64) -> String {
+    let days = seconds.div_euclid(86_400);
+    let secs = seconds.rem_euclid(86_400);
+    let (year, month, day) = civil_from_days(days);
+
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}Z",
+        secs / 3600,
+        secs % 3600 / 60,
+        secs % 60
+    )
+}
+
+// Inverse of `days_from_civil`
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    (year, month, day)
+}
+
+// Days since 1970-01-01 in the proleptic Gregorian calendar
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let month = month as i64;
+    let day_of_year =
+        (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day as i64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+
+    era * 146_097 + day_of_era - 719_468
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses() {
+        assert_eq!(parse_timestamp("1970-01-01T00:00:00Z"), Some(0));
+        assert_eq!(parse_timestamp("2025-07-01"), Some(1_751_328_000));
+        assert_eq!(parse_timestamp("2025-07-01T00:00:00Z"), Some(1_751_328_000));
+        assert_eq!(parse_timestamp(" 2025-07-01t00:00z "), Some(1_751_328_000));
+        assert_eq!(parse_timestamp("2025-07-01 00:00:00"), Some(1_751_328_000));
+        assert_eq!(parse_timestamp("1969-12-31T23:59:59Z"), Some(-1));
+    }
+
+    #[test]
+    fn offsets() {
+        let utc = parse_timestamp("2025-07-01T00:00:00Z");
+
+        assert_eq!(parse_timestamp("2025-07-01T02:00:00+02:00"), utc);
+        assert_eq!(parse_timestamp("2025-06-30T18:30:00-05:30"), utc);
+        assert_eq!(parse_timestamp("2025-07-01T00:00:00-00:00"), utc);
+    }
+
+    #[test]
+    fn fractional_seconds() {
+        let whole = parse_timestamp("2025-07-01T12:34:56Z");
+
+        assert_eq!(parse_timestamp("2025-07-01T12:34:56.5Z"), whole);
+        assert_eq!(
+            parse_timestamp("2025-07-01T12:34:56.999999999+00:00"),
+            whole
+        );
+        assert_eq!(
+            parse_timestamp("2025-07-01T12:34:56.123456789012345678901234Z"),
+            whole
+        );
+    }
+
+    #[test]
+    fn leap_years() {
+        assert_eq!(parse_timestamp("2024-02-29"), Some(1_709_164_800));
+        assert_eq!(parse_timestamp("2000-02-29"), Some(951_782_400));
+        assert_eq!(parse_timestamp("2024-03-01"), Some(1_709_251_200));
+        assert_eq!(parse_timestamp("2023-02-29"), None);
+        assert_eq!(parse_timestamp("1900-02-29"), None);
+        // The leap second
+        assert_eq!(
+            parse_timestamp("2016-12-31T23:59:60Z"),
+            parse_timestamp("2017-01-01T00:00:00Z")
+        );
+    }
+
+    #[test]
+    fn rejects() {
+        for text in [
+            "",
+            "soon",
+            "2025",
+            "2025-07",
+            "2025-13-01",
+            "2025-00-10",
+            "2025-07-00",
+            "2025-04-31",
+            "2025-+7-01",
+            "2025-07-01T",
+            "2025-07-01T24:00:00Z",
+            "2025-07-01T12:60:00Z",
+            "2025-07-01T12:00:61Z",
+            "2025-07-01T12:00:00.Z",
+            "2025-07-01T12:00:00.5xZ",
+            "2025-07-01T12:00:00+0200",
+            "2025-07-01T12:00:00+24:00",
+            "2025-07-01T12:00:00+02:60",
+            "2025-07-01T12:00:00+-1:00",
+        ] {
+            assert_eq!(parse_timestamp(text), None, "{text}");
+        }
+    }
+
+    #[test]
+    fn formats() {
+        assert_eq!(format_timestamp(0), "1970-01-01T00:00:00Z");
+        assert_eq!(format_timestamp(-1), "1969-12-31T23:59:59Z");
+        assert_eq!(format_timestamp(951_782_400), "2000-02-29T00:00:00Z");
+        assert_eq!(format_timestamp(1_751_373_296), "2025-07-01T12:34:56Z");
+
+        // Every day of four centuries, leap years included
+        for day in -73_000..73_000 {
+            let seconds = day * 86_400 + 45_296;
+            assert_eq!(parse_timestamp(&format_timestamp(seconds)), Some(seconds));
+        }
+
+        let before = now();
+        let parsed = parse_timestamp(&now_timestamp()).unwrap();
+        assert!((before..=now()).contains(&parsed));
+    }
+
+    #[test]
+    fn activates() {
+        let mut value: Value = serde_yaml::from_str(
+            r#"
+            past: { value: 1, until: "2000-01-01", then: 2 }
+            future: { value: 1, until: "2100-01-01", then: 2 }
+            staged:
+              value: 1
+              until: "2000-01-01"
+              then: { value: 2, until: "2090-01-01T00:00:00Z", then: 3 }
+            pending: { until: "2100-01-01", then: 2 }
+            list: [{ until: "2100-01-01", then: 2 }, { value: 1, until: "2000-01-01", then: 3 }]
+            "#,
+        )
+        .unwrap();
+
+        let next = activate(&mut value).unwrap();
+        let expected: Value =
+            serde_yaml::from_str("{ past: 2, future: 1, staged: 2, list: [3] }").unwrap();
+        assert_eq!(value, expected);
+        assert_eq!(next, parse_timestamp("2090-01-01"));
+
+        let mut invalid: Value = serde_yaml::from_str("key: { until: tomorrow, then: 2 }").unwrap();
+        assert!(activate(&mut invalid).is_err());
+    }
+
+    #[test]
+    fn records_next_switch() {
+        let mut value: Value =
+            serde_yaml::from_str(r#"{ value: 1, until: "2100-01-01", then: 2 }"#).unwrap();
+
+        let (_, next) = recording(|| {
+            activate(&mut Value::Sequence(vec![value.clone()])).unwrap();
+            activate(&mut serde_yaml::from_str(r#"[{ until: "2090-01-01", then: 2 }]"#).unwrap())
+                .unwrap();
+        });
+        assert_eq!(next, parse_timestamp("2090-01-01"));
+        assert_eq!(NEXT.get(), None);
+
+        activate(&mut value).unwrap();
+        assert_eq!(NEXT.get(), None);
+    }
+}
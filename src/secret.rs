@@ -0,0 +1,193 @@
+use std::{
+    cell::{Cell, RefCell},
+    fmt,
+};
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_yaml::Value;
+
+use crate::Merge;
+
+pub(crate) const MASK: &str = "***";
+
+thread_local! {
+    // Secrets deserialized on this thread within `recording`, `None` outside of it
+    static DESERIALIZED: RefCell<Option<Vec<Value>>> = const { RefCell::new(None) };
+    // How secrets are serialized on this thread
+    static MODE: Cell<Mode> = const { Cell::new(Mode::Masked) };
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Mode {
+    Masked,
+    // Left out, for saving
+    Skipped,
+    // As they are, for changing a config in memory
+    Exposed,
+}
+
+/// Value of a `#[secret]` field, kept out of logs and dumps
+///
+/// `Debug` and `Display` print `***`, and so does serializing it, so the value only
+/// leaves through [`Secret::expose`]. `DEBUG_CONFIG=1` masks it in the processed config
+/// it prints too.
+#[derive(Clone, Default, PartialEq, Eq, Hash)]
+pub struct Secret<T = String>(T);
+
+/// The common case of a secret, e.g. a password or a token
+pub type SecretString = Secret<String>;
+
+impl<T> Secret<T> {
+    pub fn new(value: T) -> Self {
+        Self(value)
+    }
+
+    pub fn expose(&self) -> &T {
+        &self.0
+    }
+
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> From<T> for Secret<T> {
+    fn from(value: T) -> Self {
+        Self(value)
+    }
+}
+
+impl<T> fmt::Debug for Secret<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(MASK)
+    }
+}
+
+impl<T> fmt::Display for Secret<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(MASK)
+    }
+}
+
+/// Replaced by the later layer
+impl<T> Merge for Secret<T> {
+    fn merge(self, rhs: Self) -> Self {
+        rhs
+    }
+}
+
+impl<'de, T: Deserialize<'de> + Serialize> Deserialize<'de> for Secret<T> {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let value = T::deserialize(deserializer)?;
+
+        DESERIALIZED.with_borrow_mut(|deserialized| {
+            if let Some(deserialized) = deserialized {
+                deserialized.extend(serde_yaml::to_value(&value).ok());
+            }
+        });
+
+        Ok(Self(value))
+    }
+}
+
+impl<T: Serialize> Serialize for Secret<T> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        match MODE.get() {
+            Mode::Exposed => self.0.serialize(serializer),
+            _ => serializer.serialize_str(MASK),
+        }
+    }
+}
+
+/// `f` serializing configs with their `#[secret]` fields left out rather than masked
+pub(crate) fn saving<R>(f: impl FnOnce() -> R) -> R {
+    with_mode(Mode::Skipped, f)
+}
+
+/// `f` serializing secrets as they are, for values that never leave the process
+pub(crate) fn exposing<R>(f: impl FnOnce() -> R) -> R {
+    with_mode(Mode::Exposed, f)
+}
+
+/// `f` serializing secrets masked, whatever the caller is doing
+pub(crate) fn masking<R>(f: impl FnOnce() -> R) -> R {
+    with_mode(Mode::Masked, f)
+}
+
+fn with_mode<R>(mode: Mode, f: impl FnOnce() -> R) -> R {
+    struct Restore(Mode);
+
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            MODE.set(self.0);
+        }
+    }
+
+    let _restore = Restore(MODE.replace(mode));
+
+    f()
+}
+
+/// Whether a stored `#[secret]` field is left out when serialized: unset, or saved
+#[doc(hidden)]
+pub fn skip_secret<T>(value: &Option<T>) -> bool {
+    value.is_none() || MODE.get() == Mode::Skipped
+}
+
+/// `f` with the values of the secrets it deserializes, to mask them in what it prints
+pub(crate) fn recording<R>(f: impl FnOnce() -> R) -> (R, Vec<Value>) {
+    struct Restore(Option<Vec<Value>>);
+
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            DESERIALIZED.set(self.0.take());
+        }
+    }
+
+    let restore = Restore(DESERIALIZED.replace(Some(vec![])));
+    let result = f();
+    let deserialized = DESERIALIZED.replace(None).unwrap_or_default();
+    drop(restore);
+
+    (result, deserialized)
+}
+
+// Every value of `value` equal to one of `secrets`, which may mask a value equal to a
+// secret by chance too
+pub(crate) fn mask(value: &mut Value, secrets: &[Value]) {
+    if secrets.contains(value) {
+        *value = Value::from(MASK);
+
+        return;
+    }
+
+    match value {
+        Value::Mapping(mapping) => mapping.values_mut().for_each(|value| mask(value, secrets)),
+        Value::Sequence(sequence) => sequence.iter_mut().for_each(|value| mask(value, secrets)),
+        Value::Tagged(tagged) => mask(&mut tagged.value, secrets),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records_only_while_recording() {
+        let _: SecretString = serde_yaml::from_str("outside").unwrap();
+        assert!(DESERIALIZED.with_borrow(Option::is_none));
+
+        let (secret, recorded) =
+            recording(|| serde_yaml::from_str::<SecretString>("inside").unwrap());
+        assert_eq!(secret.expose(), "inside");
+        assert_eq!(recorded, [Value::from("inside")]);
+        assert!(DESERIALIZED.with_borrow(Option::is_none));
+    }
+}
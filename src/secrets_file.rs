@@ -0,0 +1,159 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{Arc, LazyLock, Mutex},
+};
+
+use serde_yaml::Value;
+use tracing::debug;
+
+use crate::{overlay::deep_merge, profile, read_file, secret, UnconfigError};
+
+/// Stem of the secrets file merged over a config next to it, `secrets.yml` for
+/// `config.yml`
+pub const SECRETS_FILE: &str = "secrets";
+
+// Key paths of the secrets files merged so far by file, masked wherever `Secret`s are
+static PATHS: LazyLock<Mutex<HashMap<PathBuf, Vec<Vec<Value>>>>> = LazyLock::new(Default::default);
+
+/// `params` of the config at `path` with the secrets files next to it merged over them
+///
+/// `secrets.yml` comes first, then with a [`profile`](crate::profile) its own
+/// `secrets.<profile>.yml`, both with the extension of the config and both optional.
+/// They are read like any other config file, so with the `sops` feature they may be
+/// encrypted, e.g. to the age key of each environment in `SOPS_AGE_KEY_FILE`. Only the
+/// config loaded gets them, not the files it includes nor its profile's file.
+///
+/// The keys they set are treated as secrets: their values are masked in `DEBUG_CONFIG`
+/// output, validation errors and [`Config::dump_effective`](crate::Config::dump_effective),
+/// even when the field reading them isn't a [`Secret`](crate::Secret).
+pub(crate) fn overlay(path: &Path, params: Arc<Value>) -> Result<Arc<Value>, UnconfigError> {
+    if is_secrets_file(path) || is_profile_file(path) {
+        return Ok(params);
+    }
+
+    let secrets = secrets_path(path);
+    let mut files = vec![secrets.clone()];
+    files.extend(profile::profile().map(|profile| profile::profile_path(&secrets, &profile)));
+
+    let mut merged = None::<Value>;
+    for file in files.into_iter().filter(|file| file.is_file()) {
+        let (file, secrets) = read_file(&file)?;
+        #[cfg(not(feature = "sops"))]
+        if secrets
+            .get("sops")
+            .is_some_and(|sops| sops.get("mac").is_some())
+        {
+            return Err(UnconfigError::Validation(format!(
+                "{}: encrypted with SOPS, which takes the `sops` feature",
+                file.display()
+            )));
+        }
+        debug!("Merging secrets from {}", file.display());
+
+        let mut paths = vec![];
+        leaves(&secrets, &mut vec![], &mut paths);
+        PATHS.lock().unwrap().insert(file, paths);
+
+        let merged = merged.get_or_insert_with(|| params.as_ref().clone());
+        deep_merge(merged, secrets.as_ref().clone());
+    }
+
+    Ok(merged.map(Arc::new).unwrap_or(params))
+}
+
+/// Mask the values `value` has under the keys of the secrets files merged so far
+///
+/// `value` is a whole document, or with `section` possibly one of its sections: a config
+/// struct matches the keys under any section too.
+pub(crate) fn mask(value: &mut Value, section: bool) {
+    let paths = PATHS.lock().unwrap();
+
+    for path in paths.values().flatten() {
+        mask_path(value, path);
+
+        if section && path.len() > 1 {
+            mask_path(value, &path[1..]);
+        }
+    }
+}
+
+fn mask_path(value: &mut Value, path: &[Value]) {
+    let Some((key, rest)) = path.split_first() else {
+        *value = Value::from(secret::MASK);
+
+        return;
+    };
+
+    if let Some(value) = value
+        .as_mapping_mut()
+        .and_then(|mapping| mapping.get_mut(key))
+    {
+        mask_path(value, rest);
+    }
+}
+
+// `secrets.yml` next to `config.yml`
+fn secrets_path(path: &Path) -> PathBuf {
+    let name = match path.extension() {
+        Some(extension) => format!("{SECRETS_FILE}.{}", extension.to_string_lossy()),
+        None => SECRETS_FILE.to_string(),
+    };
+
+    path.with_file_name(name)
+}
+
+// A secrets file itself, or its profile's, loaded directly
+fn is_secrets_file(path: &Path) -> bool {
+    path.file_stem()
+        .and_then(|stem| stem.to_str())
+        .is_some_and(|stem| stem == SECRETS_FILE || stem.starts_with("secrets."))
+}
+
+// `config.prod.yml` of the active profile, the secrets of `config.yml` already cover it
+fn is_profile_file(path: &Path) -> bool {
+    profile::profile().is_some_and(|profile| {
+        path.file_stem()
+            .and_then(|stem| stem.to_str())
+            .is_some_and(|stem| stem.ends_with(&format!(".{profile}")))
+    })
+}
+
+// Paths of the values under the keys of `value`, a sequence is a single value
+fn leaves(value: &Value, path: &mut Vec<Value>, paths: &mut Vec<Vec<Value>>) {
+    match value {
+        Value::Mapping(mapping) if !mapping.is_empty() => {
+            for (key, value) in mapping {
+                path.push(key.clone());
+                leaves(value, path, paths);
+                path.pop();
+            }
+        }
+        _ if !path.is_empty() => paths.push(path.clone()),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn masks_by_key() {
+        let secrets: Value = serde_yaml::from_str("db: {password: s3cret, port: 5432}").unwrap();
+        let mut value: Value = serde_yaml::from_str(
+            "db: {user: admin, password: s3cret, port: 5432}\nother: {role: s3cret, port: 5432}",
+        )
+        .unwrap();
+
+        let mut paths = vec![];
+        leaves(&secrets, &mut vec![], &mut paths);
+        paths.iter().for_each(|path| mask_path(&mut value, path));
+
+        let expected: Value = serde_yaml::from_str(
+            "db: {user: admin, password: '***', port: '***'}\nother: {role: s3cret, port: 5432}",
+        )
+        .unwrap();
+        assert_eq!(value, expected);
+    }
+}
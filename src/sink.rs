@@ -0,0 +1,374 @@
+use std::{
+    fmt,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, RwLock,
+    },
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+use serde_yaml::{Mapping, Value};
+use tracing::{
+    field::{Field, Visit},
+    span, Event, Metadata, Subscriber,
+};
+use tracing_subscriber::{
+    fmt::{
+        format::{FmtSpan, Writer},
+        time, FmtContext, FormatEvent, FormatFields,
+    },
+    layer::Context,
+    registry::LookupSpan,
+    EnvFilter, Layer, Registry,
+};
+
+use crate::{
+    console, json, schedule,
+    trace_id::{self, TraceIdFormat},
+    Logger, LoggerError, LoggerFilter, LoggerParams,
+};
+
+type AppenderGuard = tracing_appender::non_blocking::WorkerGuard;
+pub(crate) type BoxedLayer<S> = Box<dyn Layer<S> + Send + Sync>;
+
+/// One output of the logger, under `sinks` in the logger params
+///
+/// ```yaml
+/// logger:
+///   default_level: debug
+///   sinks:
+///     - kind: console
+///       level: info
+///     - kind: file
+///       path: logs/app.log
+///       format: json
+/// ```
+///
+/// Each sink has its own level and filter, `default_level` and `filter` still apply to
+/// all of them first.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct SinkParams {
+    pub kind: SinkKind,
+    /// Log file prefix for `file` sinks, suffixed with the current date
+    pub path: Option<PathBuf>,
+    #[serde(default)]
+    pub format: SinkFormat,
+    /// Level of this sink, everything passing the logger's own filter when unset
+    pub level: Option<String>,
+    #[serde(default)]
+    pub filter: LoggerFilter,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum SinkKind {
+    Console,
+    Stderr,
+    File,
+    /// The Windows Event Log, under the executable's name, only available on Windows
+    EventLog,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum SinkFormat {
+    /// One line per event
+    #[default]
+    Text,
+    /// Multi-line, for humans
+    Pretty,
+    /// One JSON object per line, for log shippers
+    Json,
+}
+
+/// Layers of the configured `sinks`, file sinks add their writer guards to `guards`
+pub(crate) fn layers<S>(
+    params: &LoggerParams,
+    guards: &mut Vec<AppenderGuard>,
+) -> Result<Vec<BoxedLayer<S>>, LoggerError>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    params
+        .sinks
+        .iter()
+        .flatten()
+        .map(|sink| {
+            Ok(layer(params, sink, guards)?
+                .with_filter(filter(sink)?)
+                .boxed())
+        })
+        .collect()
+}
+
+// Level and filter of one sink
+fn filter(sink: &SinkParams) -> Result<EnvFilter, LoggerError> {
+    Logger::load_filter_info(
+        sink.level.as_deref().unwrap_or("trace"),
+        sink.filter.as_slice(),
+    )
+}
+
+fn layer<S>(
+    params: &LoggerParams,
+    sink: &SinkParams,
+    guards: &mut Vec<AppenderGuard>,
+) -> Result<BoxedLayer<S>, LoggerError>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    Ok(match sink.kind {
+        SinkKind::Console => console::layer(params, std::io::stdout, false, sink.format),
+        SinkKind::Stderr => console::layer(params, std::io::stderr, true, sink.format),
+        SinkKind::File => {
+            let path = sink.path.as_ref().ok_or(LoggerError::File)?;
+            let (dir, file_name) = params.daily_file(path)?;
+            let daily_file = tracing_appender::rolling::daily(dir, file_name);
+            let (non_blocking, guard) = Logger::non_blocking(daily_file, params);
+            crate::health::register_sink(path.display().to_string(), non_blocking.error_counter());
+            guards.push(guard);
+
+            file_layer(params, non_blocking, sink.format)
+        }
+        SinkKind::EventLog => crate::event_log::layer()?,
+    })
+}
+
+/// Handle of a sink added with [`Logger::add_sink`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SinkId(u64);
+
+struct DynamicSink {
+    id: SinkId,
+    filter: EnvFilter,
+    layer: BoxedLayer<Registry>,
+    // Dropped on removal, which flushes the file
+    _guards: Vec<AppenderGuard>,
+}
+
+/// Sinks added and removed while the logger runs
+///
+/// They only see events passing the logger's `default_level` and `filter`, lower those
+/// with [`Logger::reload`] to capture more in an added sink.
+#[derive(Clone, Default)]
+pub(crate) struct DynamicSinks {
+    sinks: Arc<RwLock<Vec<DynamicSink>>>,
+    next_id: Arc<AtomicU64>,
+}
+
+impl DynamicSinks {
+    pub(crate) fn add(
+        &self,
+        params: &LoggerParams,
+        sink: &SinkParams,
+    ) -> Result<SinkId, LoggerError> {
+        let mut guards = vec![];
+        let layer = layer(params, sink, &mut guards)?;
+        let id = SinkId(self.next_id.fetch_add(1, Ordering::Relaxed));
+
+        self.sinks.write().unwrap().push(DynamicSink {
+            id,
+            filter: filter(sink)?,
+            layer,
+            _guards: guards,
+        });
+
+        Ok(id)
+    }
+
+    pub(crate) fn remove(&self, id: SinkId) -> bool {
+        let mut sinks = self.sinks.write().unwrap();
+        let removed = sinks
+            .iter()
+            .position(|sink| sink.id == id)
+            .map(|index| sinks.remove(index));
+        drop(sinks);
+
+        removed.is_some()
+    }
+
+    // Sinks taking events and spans of `metadata`
+    fn each(&self, metadata: &Metadata<'_>, ctx: &Context<'_, Registry>, f: impl Fn(&DynamicSink)) {
+        for sink in self.sinks.read().unwrap().iter() {
+            if Layer::<Registry>::enabled(&sink.filter, metadata, ctx.clone()) {
+                f(sink);
+            }
+        }
+    }
+}
+
+impl Layer<Registry> for DynamicSinks {
+    // Every sink keeps its own view of spans, so they're all told about new ones
+    fn on_new_span(&self, attrs: &span::Attributes<'_>, id: &span::Id, ctx: Context<'_, Registry>) {
+        for sink in self.sinks.read().unwrap().iter() {
+            sink.layer.on_new_span(attrs, id, ctx.clone());
+        }
+    }
+
+    fn on_record(&self, id: &span::Id, values: &span::Record<'_>, ctx: Context<'_, Registry>) {
+        for sink in self.sinks.read().unwrap().iter() {
+            sink.layer.on_record(id, values, ctx.clone());
+        }
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, Registry>) {
+        self.each(event.metadata(), &ctx, |sink| {
+            sink.layer.on_event(event, ctx.clone())
+        });
+    }
+
+    fn on_enter(&self, id: &span::Id, ctx: Context<'_, Registry>) {
+        if let Some(span) = ctx.span(id) {
+            self.each(span.metadata(), &ctx, |sink| {
+                sink.layer.on_enter(id, ctx.clone())
+            });
+        }
+    }
+
+    fn on_exit(&self, id: &span::Id, ctx: Context<'_, Registry>) {
+        if let Some(span) = ctx.span(id) {
+            self.each(span.metadata(), &ctx, |sink| {
+                sink.layer.on_exit(id, ctx.clone())
+            });
+        }
+    }
+
+    fn on_close(&self, id: span::Id, ctx: Context<'_, Registry>) {
+        if let Some(span) = ctx.span(&id) {
+            self.each(span.metadata(), &ctx, |sink| {
+                sink.layer.on_close(id.clone(), ctx.clone())
+            });
+        }
+    }
+}
+
+fn file_layer<S>(
+    params: &LoggerParams,
+    writer: tracing_appender::non_blocking::NonBlocking,
+    format: SinkFormat,
+) -> BoxedLayer<S>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    let span_events = if params.span_timings {
+        FmtSpan::CLOSE | FmtSpan::ENTER
+    } else {
+        FmtSpan::NONE
+    };
+
+    let layer = tracing_subscriber::fmt::layer()
+        .with_ansi(false)
+        .with_span_events(span_events)
+        .with_thread_names(true)
+        .with_line_number(true)
+        .with_timer(time::time())
+        .with_writer(writer);
+
+    match format {
+        SinkFormat::Text => layer.map_event_format(TraceIdFormat).boxed(),
+        SinkFormat::Pretty => layer.pretty().map_event_format(TraceIdFormat).boxed(),
+        SinkFormat::Json => layer.event_format(JsonFormat).boxed(),
+    }
+}
+
+/// Events as JSON lines: time, level, target, location, fields, the names of the enclosing
+/// spans and the trace ids when enabled
+pub(crate) struct JsonFormat;
+
+impl<S, N> FormatEvent<S, N> for JsonFormat
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+{
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, N>,
+        mut writer: Writer<'_>,
+        event: &Event<'_>,
+    ) -> fmt::Result {
+        let metadata = event.metadata();
+        let mut line = Mapping::new();
+
+        line.insert("timestamp".into(), timestamp().into());
+        line.insert("level".into(), metadata.level().as_str().into());
+        line.insert("target".into(), metadata.target().into());
+
+        if let Some(thread) = std::thread::current().name() {
+            line.insert("thread".into(), thread.into());
+        }
+
+        if let (Some(file), Some(number)) = (metadata.file(), metadata.line()) {
+            line.insert("location".into(), format!("{file}:{number}").into());
+        }
+
+        let mut fields = JsonFields::default();
+        event.record(&mut fields);
+        line.insert("fields".into(), Value::Mapping(fields.0));
+
+        if let Some(scope) = ctx.event_scope() {
+            let spans = scope
+                .from_root()
+                .map(|span| Value::from(span.name()))
+                .collect::<Vec<_>>();
+
+            if let Some((trace_id, span_id)) = ctx.event_scope().and_then(|mut scope| {
+                let span = scope.next()?;
+                trace_id::ids(&span)
+            }) {
+                line.insert("trace_id".into(), trace_id.into());
+                line.insert("span_id".into(), span_id.into());
+            }
+
+            line.insert("spans".into(), Value::Sequence(spans));
+        }
+
+        writeln!(writer, "{}", json::to_string(&Value::Mapping(line)))
+    }
+}
+
+// RFC 3339 with microseconds
+pub(crate) fn timestamp() -> String {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    let seconds = schedule::format_timestamp(now.as_secs() as i64);
+
+    format!(
+        "{}.{:06}Z",
+        seconds.trim_end_matches('Z'),
+        now.subsec_micros()
+    )
+}
+
+#[derive(Default)]
+struct JsonFields(Mapping);
+
+impl Visit for JsonFields {
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.0.insert(field.name().into(), value.into());
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.0.insert(field.name().into(), value.into());
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.0.insert(field.name().into(), value.into());
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.0.insert(field.name().into(), value.into());
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name().into(), value.into());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.0
+            .insert(field.name().into(), format!("{value:?}").into());
+    }
+}
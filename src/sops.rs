@@ -0,0 +1,89 @@
+use std::{
+    path::Path,
+    process::Command,
+    sync::{Arc, LazyLock, Mutex},
+};
+
+use serde_yaml::Value;
+use tracing::debug;
+
+use crate::{
+    cache::{source_key, Trees},
+    env_overlay,
+    format::Format,
+    UnconfigError,
+};
+
+/// Binary decrypting the files, `sops` on the `PATH` by default
+pub const SOPS_BIN_VAR: &str = "SOPS_BIN";
+
+// Key of the metadata block SOPS adds to the files it encrypts
+const METADATA_KEY: &str = "sops";
+
+// Decrypted files by the hash and length of their encrypted text, never persisted
+static DECRYPTED: LazyLock<Mutex<Trees>> = LazyLock::new(Default::default);
+
+/// `params` of the file at `path`, decrypted by `sops` when SOPS encrypted it
+///
+/// The keys come from the environment as `sops` itself reads them: `SOPS_AGE_KEY_FILE`
+/// for age, the AWS credentials for KMS and so on, the loader's overlay included.
+pub(crate) fn decrypt(
+    path: &Path,
+    content: &str,
+    params: Arc<Value>,
+) -> Result<Arc<Value>, UnconfigError> {
+    if !is_encrypted(&params) {
+        return Ok(params);
+    }
+
+    let format = Format::of_path(path);
+    let key = source_key(content, format);
+
+    if let Some(value) = DECRYPTED.lock().unwrap().get(&key) {
+        return Ok(value);
+    }
+
+    let source = path.display().to_string();
+    let kind = match format {
+        Format::Json => "json",
+        _ => "yaml",
+    };
+    let bin = env_overlay::var(SOPS_BIN_VAR).unwrap_or_else(|_| "sops".to_string());
+
+    debug!("Decrypting {source} with {bin}");
+    let output = Command::new(&bin)
+        .args(["--decrypt", "--input-type", kind, "--output-type", kind])
+        .arg(path)
+        .env_clear()
+        .envs(env_overlay::vars())
+        .output()
+        .map_err(|e| {
+            UnconfigError::Validation(format!(
+                "{source}: encrypted with SOPS, but failed to run {bin}: {e}"
+            ))
+        })?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+
+        return Err(UnconfigError::Validation(format!(
+            "{source}: failed to decrypt with {bin}: {}",
+            stderr.trim()
+        )));
+    }
+
+    let decrypted = String::from_utf8(output.stdout).map_err(|e| {
+        UnconfigError::Validation(format!("{source}: decrypted to invalid UTF-8: {e}"))
+    })?;
+    let value = Arc::new(format.parse(&source, &decrypted)?);
+    DECRYPTED.lock().unwrap().insert(key, value.clone());
+
+    Ok(value)
+}
+
+// A top-level `sops` block with the MAC of the encrypted values
+fn is_encrypted(params: &Value) -> bool {
+    params
+        .get(METADATA_KEY)
+        .is_some_and(|metadata| metadata.get("mac").is_some())
+}
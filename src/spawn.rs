@@ -0,0 +1,38 @@
+use std::{future::Future, thread::JoinHandle};
+
+use tracing::{
+    dispatcher,
+    instrument::{Instrument, Instrumented, WithDispatch, WithSubscriber},
+    Span,
+};
+
+// Child of the current span, so the task's logs carry the caller's fields and trace id
+fn task_span() -> Span {
+    tracing::info_span!("task", config_generation = crate::health::generation())
+}
+
+/// Spawn a thread that logs like the caller: with the caller's subscriber and inside a
+/// `task` span, child of the current one, that records the config generation
+///
+/// The thread sees the caller's [`EnvOverlay`](crate::EnvOverlay) as well.
+pub fn spawn_traced<F, T>(f: F) -> JoinHandle<T>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    let span = task_span();
+    let dispatch = dispatcher::get_default(Clone::clone);
+
+    let f = crate::env_overlay::inherit(f);
+
+    std::thread::spawn(move || dispatcher::with_default(&dispatch, || span.in_scope(f)))
+}
+
+/// Same as [`spawn_traced`] for futures, to be handed to the runtime's own spawn
+///
+/// ```ignore
+/// tokio::spawn(unconfig::traced(async move { job.run().await }));
+/// ```
+pub fn traced<F: Future>(future: F) -> Instrumented<WithDispatch<F>> {
+    future.with_current_subscriber().instrument(task_span())
+}
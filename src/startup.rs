@@ -0,0 +1,122 @@
+use std::{
+    fmt::{self, Write},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use tracing::{
+    dispatcher::{self, DefaultGuard},
+    field::{Field, Visit},
+    span, warn, Dispatch, Event, Metadata, Subscriber,
+};
+
+// Startup shouldn't log more, anything beyond is counted only
+const CAPACITY: usize = 1024;
+
+/// Keeps the events emitted on this thread before the logger is up, e.g. while its own
+/// config is loaded, and replays them into the logger once it's installed
+///
+/// `#[logger]` does this around `Logger::init`. Replayed events keep their level, target
+/// and location, so the logger filters them like any other, but all their fields are
+/// folded into the message.
+pub struct StartupBuffer {
+    captured: Arc<Captured>,
+    guard: DefaultGuard,
+}
+
+#[derive(Default)]
+struct Captured {
+    events: Mutex<Vec<(&'static Metadata<'static>, String)>>,
+    dropped: AtomicU64,
+    next_span: AtomicU64,
+}
+
+impl StartupBuffer {
+    /// Capture events on this thread from now on
+    pub fn install() -> Self {
+        let captured = Arc::<Captured>::default();
+        let guard = dispatcher::set_default(&Dispatch::new(Capture(captured.clone())));
+
+        Self { captured, guard }
+    }
+
+    /// Stop capturing and emit the captured events to the subscriber in effect now
+    pub fn replay(self) {
+        let Self { captured, guard } = self;
+        drop(guard);
+
+        let events = std::mem::take(&mut *captured.events.lock().unwrap());
+
+        dispatcher::get_default(|dispatch| {
+            for (metadata, text) in &events {
+                let fields = metadata.fields();
+                let Some(field) = fields.field("message").or_else(|| fields.iter().next()) else {
+                    continue;
+                };
+
+                if dispatch.enabled(metadata) {
+                    let text = text as &dyn tracing::Value;
+                    dispatch.event(&Event::new(
+                        metadata,
+                        &fields.value_set(&[(&field, Some(text))]),
+                    ));
+                }
+            }
+        });
+
+        let dropped = captured.dropped.load(Ordering::Relaxed);
+
+        if dropped > 0 {
+            warn!("{dropped} startup log events were dropped, only the first {CAPACITY} are kept");
+        }
+    }
+}
+
+struct Capture(Arc<Captured>);
+
+impl Subscriber for Capture {
+    fn enabled(&self, _metadata: &Metadata<'_>) -> bool {
+        true
+    }
+
+    fn new_span(&self, _span: &span::Attributes<'_>) -> span::Id {
+        span::Id::from_u64(self.0.next_span.fetch_add(1, Ordering::Relaxed) + 1)
+    }
+
+    fn record(&self, _span: &span::Id, _values: &span::Record<'_>) {}
+
+    fn record_follows_from(&self, _span: &span::Id, _follows: &span::Id) {}
+
+    fn event(&self, event: &Event<'_>) {
+        let mut events = self.0.events.lock().unwrap();
+
+        if events.len() >= CAPACITY {
+            self.0.dropped.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+
+        let mut text = Text::default();
+        event.record(&mut text);
+        events.push((event.metadata(), text.0));
+    }
+
+    fn enter(&self, _span: &span::Id) {}
+
+    fn exit(&self, _span: &span::Id) {}
+}
+
+// Message first, then the other fields as `name=value`
+#[derive(Default)]
+struct Text(String);
+
+impl Visit for Text {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if field.name() == "message" {
+            self.0.insert_str(0, &format!("{value:?}"));
+        } else {
+            let _ = write!(self.0, " {}={value:?}", field.name());
+        }
+    }
+}
@@ -0,0 +1,236 @@
+use std::collections::HashSet;
+
+use anyhow::{anyhow, bail, Result};
+use serde_yaml::{Mapping, Value};
+
+use crate::limits;
+
+/// Top-level key of the fragments `!use` refers to
+pub(crate) const TEMPLATES_KEY: &str = "templates";
+const USE_TAG: &str = "use";
+// Key of a `!use` mapping naming the template, the others are its parameters
+const TEMPLATE_KEY: &str = "template";
+
+/// Replace every `!use` of `value` with the template it names, and drop the templates
+///
+/// ```yaml
+/// templates:
+///   postgres:
+///     driver: postgres
+///     host: "{{host}}"
+///     port: "{{port:5432}}"
+///
+/// primary: !use
+///   template: postgres
+///   host: db1.internal
+/// replica: !use
+///   template: postgres
+///   host: db2.internal
+///   port: 5433
+/// ```
+///
+/// A string that is only `{{name}}` takes the parameter as it is, of any type, while
+/// `{{name}}` within a longer string takes its text. `{{name:default}}` has a default,
+/// read as a YAML value such as `5432` or `[]`, the other parameters are required. `!use name` uses a template without parameters.
+/// Templates may use other templates. They come from the whole document, overlays and
+/// included files included, so any section may use them.
+pub(crate) fn resolve(value: &mut Value) -> Result<()> {
+    let templates = match value
+        .as_mapping_mut()
+        .and_then(|mapping| mapping.remove(TEMPLATES_KEY))
+    {
+        Some(Value::Mapping(templates)) => templates,
+        Some(Value::Null) | None => Mapping::new(),
+        Some(other) => bail!("`{TEMPLATES_KEY}` must map names to fragments, not {other:?}"),
+    };
+
+    Resolver {
+        templates: &templates,
+        using: vec![],
+        nodes: 0,
+    }
+    .resolve(value)
+}
+
+struct Resolver<'a> {
+    templates: &'a Mapping,
+    // Templates being instantiated, outermost first
+    using: Vec<String>,
+    // Values instantiated so far, bounded like the whole config
+    nodes: usize,
+}
+
+impl Resolver<'_> {
+    fn resolve(&mut self, value: &mut Value) -> Result<()> {
+        match value {
+            Value::Tagged(tagged) if tagged.tag == USE_TAG => {
+                *value = self.instantiate(&tagged.value)?;
+            }
+            Value::Tagged(tagged) => self.resolve(&mut tagged.value)?,
+            Value::Mapping(mapping) => {
+                for (_, value) in mapping {
+                    self.resolve(value)?;
+                }
+            }
+            Value::Sequence(sequence) => {
+                for value in sequence {
+                    self.resolve(value)?;
+                }
+            }
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    // Fragment of a `!use`, `target` being the template's name or a mapping of it and
+    // the parameters
+    fn instantiate(&mut self, target: &Value) -> Result<Value> {
+        let (name, params) = match target {
+            Value::String(name) => (name.as_str(), Mapping::new()),
+            Value::Mapping(mapping) => {
+                let mut params = mapping.clone();
+                let name = match mapping.get(TEMPLATE_KEY) {
+                    Some(Value::String(name)) => name.as_str(),
+                    _ => bail!("!{USE_TAG} needs the `{TEMPLATE_KEY}` to use, got {target:?}"),
+                };
+                params.remove(TEMPLATE_KEY);
+
+                (name, params)
+            }
+            other => bail!("!{USE_TAG} takes a template name, not {other:?}"),
+        };
+
+        if self.using.iter().any(|using| using == name) {
+            bail!(
+                "templates use each other: {} -> {name}",
+                self.using.join(" -> ")
+            );
+        }
+        let Some(template) = self.templates.get(name) else {
+            let known = self
+                .templates
+                .keys()
+                .filter_map(Value::as_str)
+                .collect::<Vec<_>>()
+                .join(", ");
+
+            if known.is_empty() {
+                bail!("unknown template `{name}`, no `{TEMPLATES_KEY}` are defined");
+            }
+            bail!("unknown template `{name}`, the templates are: {known}");
+        };
+
+        let mut fragment = template.clone();
+        let mut used = HashSet::new();
+        fill(&mut fragment, &params, &mut used).map_err(|e| anyhow!("template `{name}`: {e}"))?;
+
+        if let Some(unused) = params
+            .keys()
+            .filter_map(Value::as_str)
+            .find(|param| !used.contains(*param))
+        {
+            bail!("template `{name}` has no parameter `{unused}`");
+        }
+
+        self.using.push(name.to_string());
+        let resolved = self.resolve(&mut fragment);
+        self.using.pop();
+        resolved?;
+
+        self.nodes += count(&fragment);
+        let max_nodes = limits::limits().max_nodes;
+        if self.nodes > max_nodes {
+            bail!("templates expand to more than {max_nodes} values (CONFIG_MAX_NODES)");
+        }
+
+        Ok(fragment)
+    }
+}
+
+// Replace the `{{name}}` parameters of `value`, recording which were `used`
+fn fill(value: &mut Value, params: &Mapping, used: &mut HashSet<String>) -> Result<()> {
+    match value {
+        Value::String(text) => {
+            // The whole string, keeping the type of the parameter
+            if let Some(reference) = text
+                .strip_prefix("{{")
+                .and_then(|text| text.strip_suffix("}}"))
+                .filter(|reference| !reference.contains("{{"))
+            {
+                *value = param(reference, params, used)?;
+
+                return Ok(());
+            }
+
+            let mut filled = String::with_capacity(text.len());
+            let mut rest = text.as_str();
+            let mut replaced = false;
+
+            while let Some(start) = rest.find("{{") {
+                let Some(end) = rest[start..].find("}}") else {
+                    break;
+                };
+
+                filled.push_str(&rest[..start]);
+                match param(&rest[start + 2..start + end], params, used)? {
+                    Value::String(text) => filled.push_str(&text),
+                    Value::Number(number) => filled.push_str(&number.to_string()),
+                    Value::Bool(flag) => filled.push_str(&flag.to_string()),
+                    Value::Null => {}
+                    other => bail!("a value can't be placed within text: {other:?}"),
+                }
+                rest = &rest[start + end + 2..];
+                replaced = true;
+            }
+
+            if !replaced {
+                return Ok(());
+            }
+
+            filled.push_str(rest);
+            *value = Value::String(filled);
+        }
+        Value::Tagged(tagged) => fill(&mut tagged.value, params, used)?,
+        Value::Mapping(mapping) => {
+            for (_, value) in mapping {
+                fill(value, params, used)?;
+            }
+        }
+        Value::Sequence(sequence) => {
+            for value in sequence {
+                fill(value, params, used)?;
+            }
+        }
+        _ => {}
+    }
+
+    Ok(())
+}
+
+// Value of the parameter `reference`, `name` or `name:default`
+fn param(reference: &str, params: &Mapping, used: &mut HashSet<String>) -> Result<Value> {
+    let (name, default) = match reference.split_once(':') {
+        Some((name, default)) => (name.trim(), Some(default.trim())),
+        None => (reference.trim(), None),
+    };
+    used.insert(name.to_string());
+
+    match (params.get(name), default) {
+        (Some(value), _) => Ok(value.clone()),
+        (None, Some("")) => Ok(Value::String(String::new())),
+        (None, Some(default)) => {
+            Ok(serde_yaml::from_str(default).unwrap_or_else(|_| Value::from(default)))
+        }
+        (None, None) => Err(anyhow!("parameter `{name}` is not set")),
+    }
+}
+
+fn count(value: &Value) -> usize {
+    1 + match value {
+        Value::Tagged(tagged) => count(&tagged.value),
+        Value::Mapping(mapping) => mapping.values().map(count).sum(),
+        Value::Sequence(sequence) => sequence.iter().map(count).sum(),
+        _ => 0,
+    }
+}
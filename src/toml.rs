@@ -0,0 +1,770 @@
+use serde_yaml::{Mapping, Number, Value};
+
+use crate::{limits, UnconfigError};
+
+/// Parse a TOML document into the same tree YAML documents are parsed into
+///
+/// Dates and times have no YAML counterpart and are kept as strings. Values may be
+/// unquoted `${VAR:default}` references, as they would be in YAML.
+pub(crate) fn parse(source: &str, content: &str) -> Result<Value, UnconfigError> {
+    let mut parser = Parser {
+        src: content,
+        pos: 0,
+        depth: 0,
+    };
+
+    parser.document().map_err(|msg| {
+        let before = &content[..parser.pos.min(content.len())];
+        let line = before.matches('\n').count() + 1;
+        let column = before.len() - before.rfind('\n').map_or(0, |index| index + 1) + 1;
+
+        UnconfigError::Parse {
+            file: source.to_string(),
+            line,
+            column,
+            message: format!("invalid TOML at line {line} column {column}: {msg}"),
+        }
+    })
+}
+
+type Parsed<T> = std::result::Result<T, String>;
+
+struct Parser<'a> {
+    src: &'a str,
+    pos: usize,
+    // Arrays and inline tables being parsed, bounded like the parsed tree is
+    depth: usize,
+}
+
+impl Parser<'_> {
+    fn document(&mut self) -> Parsed<Value> {
+        let mut root = Value::Mapping(Mapping::new());
+        let mut table = vec![];
+        let mut headers = vec![];
+
+        loop {
+            self.skip_blank_lines();
+
+            match self.peek() {
+                None => return Ok(root),
+                Some('[') if self.rest().starts_with("[[") => {
+                    self.pos += 2;
+                    let path = self.key()?;
+                    self.expect("]]")?;
+
+                    let (last, parent) = path.split_last().ok_or("empty table name")?;
+                    let parent = navigate(&mut root, parent)?;
+                    let tables = parent
+                        .entry(last.as_str().into())
+                        .or_insert_with(|| Value::Sequence(vec![]));
+
+                    match tables {
+                        Value::Sequence(tables) => tables.push(Value::Mapping(Mapping::new())),
+                        _ => return Err(format!("`{}` is not an array of tables", path.join("."))),
+                    }
+
+                    table = path;
+                }
+                Some('[') => {
+                    self.pos += 1;
+                    let path = self.key()?;
+                    self.expect("]")?;
+
+                    if headers.contains(&path) {
+                        return Err(format!("table `{}` is defined twice", path.join(".")));
+                    }
+
+                    navigate(&mut root, &path)?;
+                    headers.push(path.clone());
+                    table = path;
+                }
+                Some(_) => {
+                    let key = self.key()?;
+                    self.expect("=")?;
+                    let value = self.value()?;
+
+                    let (last, parent) = key.split_last().ok_or("empty key")?;
+                    let path = table.iter().chain(parent).cloned().collect::<Vec<_>>();
+                    let mapping = navigate(&mut root, &path)?;
+
+                    if mapping.insert(last.as_str().into(), value).is_some() {
+                        return Err(format!("key `{}` is defined twice", key.join(".")));
+                    }
+                }
+            }
+
+            self.end_of_line()?;
+        }
+    }
+
+    fn rest(&self) -> &str {
+        &self.src[self.pos..]
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.rest().chars().next()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += c.len_utf8();
+
+        Some(c)
+    }
+
+    fn expect(&mut self, token: &str) -> Parsed<()> {
+        self.skip_spaces();
+
+        if self.rest().starts_with(token) {
+            self.pos += token.len();
+            Ok(())
+        } else {
+            Err(format!("expected `{token}`"))
+        }
+    }
+
+    fn skip_spaces(&mut self) {
+        while matches!(self.peek(), Some(' ' | '\t')) {
+            self.pos += 1;
+        }
+    }
+
+    fn skip_comment(&mut self) {
+        if self.peek() == Some('#') {
+            while !matches!(self.peek(), None | Some('\n')) {
+                self.next();
+            }
+        }
+    }
+
+    // Whitespace, comments and newlines, as allowed between statements and array items
+    fn skip_blank_lines(&mut self) {
+        loop {
+            self.skip_spaces();
+            self.skip_comment();
+
+            match self.peek() {
+                Some('\n') => self.pos += 1,
+                Some('\r') if self.rest().starts_with("\r\n") => self.pos += 2,
+                _ => return,
+            }
+        }
+    }
+
+    fn end_of_line(&mut self) -> Parsed<()> {
+        self.skip_spaces();
+        self.skip_comment();
+
+        match self.peek() {
+            None | Some('\n') => Ok(()),
+            Some('\r') if self.rest().starts_with("\r\n") => Ok(()),
+            Some(c) => Err(format!("unexpected `{c}` after value")),
+        }
+    }
+
+    // Dotted key: `a.b`, `"quoted key".c`
+    fn key(&mut self) -> Parsed<Vec<String>> {
+        let mut parts = vec![];
+
+        loop {
+            self.skip_spaces();
+
+            let part = match self.peek() {
+                Some('"') => {
+                    self.pos += 1;
+                    self.basic_string()?
+                }
+                Some('\'') => {
+                    self.pos += 1;
+                    self.literal_string()?
+                }
+                _ => {
+                    let len = self
+                        .rest()
+                        .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '-'))
+                        .unwrap_or(self.rest().len());
+
+                    if len == 0 {
+                        return Err("expected a key".to_string());
+                    }
+
+                    let part = self.rest()[..len].to_string();
+                    self.pos += len;
+                    part
+                }
+            };
+            parts.push(part);
+
+            self.skip_spaces();
+
+            if self.peek() != Some('.') {
+                return Ok(parts);
+            }
+
+            self.pos += 1;
+        }
+    }
+
+    fn value(&mut self) -> Parsed<Value> {
+        self.skip_spaces();
+
+        match self.peek() {
+            Some('"') if self.rest().starts_with("\"\"\"") => {
+                self.pos += 3;
+                self.multiline_basic_string().map(Value::String)
+            }
+            Some('"') => {
+                self.pos += 1;
+                self.basic_string().map(Value::String)
+            }
+            Some('\'') if self.rest().starts_with("'''") => {
+                self.pos += 3;
+                self.multiline_literal_string().map(Value::String)
+            }
+            Some('\'') => {
+                self.pos += 1;
+                self.literal_string().map(Value::String)
+            }
+            Some('[') => {
+                self.pos += 1;
+                self.nested(Self::array)
+            }
+            Some('{') => {
+                self.pos += 1;
+                self.nested(Self::inline_table)
+            }
+            Some('\n' | '\r' | '#') | None => Err("expected a value".to_string()),
+            Some(_) => self.scalar(),
+        }
+    }
+
+    fn nested(&mut self, parse: fn(&mut Self) -> Parsed<Value>) -> Parsed<Value> {
+        let max_depth = limits::limits().max_depth;
+
+        if self.depth >= max_depth {
+            return Err(format!("nested deeper than {max_depth} levels"));
+        }
+
+        self.depth += 1;
+        let value = parse(self);
+        self.depth -= 1;
+
+        value
+    }
+
+    fn array(&mut self) -> Parsed<Value> {
+        let mut items = vec![];
+
+        loop {
+            self.skip_blank_lines();
+
+            if self.peek() == Some(']') {
+                self.pos += 1;
+                return Ok(Value::Sequence(items));
+            }
+
+            items.push(self.value()?);
+            self.skip_blank_lines();
+
+            // Not consumed before it's known, so errors point at it
+            match self.peek() {
+                Some(',') => self.pos += 1,
+                Some(']') => {
+                    self.pos += 1;
+                    return Ok(Value::Sequence(items));
+                }
+                _ => return Err("expected `,` or `]` in array".to_string()),
+            }
+        }
+    }
+
+    fn inline_table(&mut self) -> Parsed<Value> {
+        let mut table = Value::Mapping(Mapping::new());
+
+        self.skip_spaces();
+
+        if self.peek() == Some('}') {
+            self.pos += 1;
+            return Ok(table);
+        }
+
+        loop {
+            let key = self.key()?;
+            self.expect("=")?;
+            let value = self.value()?;
+
+            let (last, parent) = key.split_last().ok_or("empty key")?;
+
+            if navigate(&mut table, parent)?
+                .insert(last.as_str().into(), value)
+                .is_some()
+            {
+                return Err(format!("key `{}` is defined twice", key.join(".")));
+            }
+
+            self.skip_spaces();
+
+            match self.peek() {
+                Some(',') => self.pos += 1,
+                Some('}') => {
+                    self.pos += 1;
+                    return Ok(table);
+                }
+                _ => return Err("expected `,` or `}` in inline table".to_string()),
+            }
+        }
+    }
+
+    fn escape(&mut self, out: &mut String) -> Parsed<()> {
+        let c = match self.next().ok_or("unterminated escape")? {
+            'b' => '\u{8}',
+            't' => '\t',
+            'n' => '\n',
+            'f' => '\u{c}',
+            'r' => '\r',
+            '"' => '"',
+            '\\' => '\\',
+            'e' => '\u{1b}',
+            c @ ('u' | 'U') => {
+                let len = if c == 'u' { 4 } else { 8 };
+                let hex = self.rest().get(..len).ok_or("short unicode escape")?;
+                let code = u32::from_str_radix(hex, 16).map_err(|_| "invalid unicode escape")?;
+                self.pos += len;
+
+                char::from_u32(code).ok_or("invalid unicode escape")?
+            }
+            c => return Err(format!("unknown escape `\\{c}`")),
+        };
+        out.push(c);
+
+        Ok(())
+    }
+
+    fn basic_string(&mut self) -> Parsed<String> {
+        let mut out = String::new();
+
+        loop {
+            match self.next() {
+                Some('"') => return Ok(out),
+                Some('\\') => self.escape(&mut out)?,
+                Some('\n') | None => return Err("unterminated string".to_string()),
+                Some(c) => out.push(c),
+            }
+        }
+    }
+
+    fn literal_string(&mut self) -> Parsed<String> {
+        let end = self
+            .rest()
+            .find(['\'', '\n'])
+            .filter(|end| self.rest()[*end..].starts_with('\''))
+            .ok_or("unterminated string")?;
+        let out = self.rest()[..end].to_string();
+        self.pos += end + 1;
+
+        Ok(out)
+    }
+
+    // A newline right after the opening quotes isn't part of the string
+    fn skip_first_newline(&mut self) {
+        if self.rest().starts_with('\n') {
+            self.pos += 1;
+        } else if self.rest().starts_with("\r\n") {
+            self.pos += 2;
+        }
+    }
+
+    fn multiline_basic_string(&mut self) -> Parsed<String> {
+        let mut out = String::new();
+        self.skip_first_newline();
+
+        loop {
+            if self.rest().starts_with("\"\"\"") && !self.rest().starts_with("\"\"\"\"") {
+                self.pos += 3;
+                return Ok(out);
+            }
+
+            match self.next() {
+                // Line ending backslash: the newline and leading whitespace are trimmed
+                Some('\\')
+                    if self
+                        .rest()
+                        .trim_start_matches([' ', '\t'])
+                        .starts_with(['\n', '\r']) =>
+                {
+                    while matches!(self.peek(), Some(' ' | '\t' | '\n' | '\r')) {
+                        self.pos += 1;
+                    }
+                }
+                Some('\\') => self.escape(&mut out)?,
+                Some(c) => out.push(c),
+                None => return Err("unterminated string".to_string()),
+            }
+        }
+    }
+
+    fn multiline_literal_string(&mut self) -> Parsed<String> {
+        self.skip_first_newline();
+
+        let mut end = self.rest().find("'''").ok_or("unterminated string")?;
+
+        // Up to two quotes may directly precede the closing ones
+        while self.rest()[end + 3..].starts_with('\'') {
+            end += 1;
+        }
+
+        let out = self.rest()[..end].to_string();
+        self.pos += end + 3;
+
+        Ok(out)
+    }
+
+    // Booleans, numbers, dates and times
+    fn scalar(&mut self) -> Parsed<Value> {
+        // Unquoted `${VAR:default}`, substituted later like in YAML
+        if self.rest().starts_with("${") {
+            let len = self.rest().find('}').ok_or("unterminated `${`")? + 1;
+            let value = self.rest()[..len].to_string();
+            self.pos += len;
+
+            return Ok(Value::String(value));
+        }
+
+        let mut len = self
+            .rest()
+            .find([',', ']', '}', '#', ' ', '\t', '\n', '\r'])
+            .unwrap_or(self.rest().len());
+
+        // `1979-05-27 07:32:00`: a date, a space and a time are one value
+        let token = &self.rest()[..len];
+        if is_date(token) && self.rest()[len..].starts_with(' ') {
+            let time = &self.rest()[len + 1..];
+
+            if time.len() > 2
+                && time.as_bytes()[..2].iter().all(u8::is_ascii_digit)
+                && time[2..].starts_with(':')
+            {
+                len += 1 + time
+                    .find([',', ']', '}', '#', ' ', '\t', '\n', '\r'])
+                    .unwrap_or(time.len());
+            }
+        }
+
+        let token = &self.rest()[..len];
+        let value = scalar(token).ok_or_else(|| format!("invalid value `{token}`"))?;
+        self.pos += len;
+
+        Ok(value)
+    }
+}
+
+fn is_date(token: &str) -> bool {
+    let bytes = token.as_bytes();
+
+    bytes.len() >= 10 && bytes[4] == b'-' && bytes[7] == b'-'
+}
+
+fn scalar(token: &str) -> Option<Value> {
+    match token {
+        "true" => return Some(Value::Bool(true)),
+        "false" => return Some(Value::Bool(false)),
+        "inf" | "+inf" => return Some(Value::Number(f64::INFINITY.into())),
+        "-inf" => return Some(Value::Number(f64::NEG_INFINITY.into())),
+        "nan" | "+nan" | "-nan" => return Some(Value::Number(f64::NAN.into())),
+        _ => {}
+    }
+
+    // Offset date-times, local date-times, dates and times
+    if is_date(token) || token.as_bytes().get(2) == Some(&b':') {
+        return Some(Value::String(token.to_string()));
+    }
+
+    let digits = token.replace('_', "");
+    let radix = |prefix: &str, radix: u32| {
+        digits
+            .strip_prefix(prefix)
+            .and_then(|digits| i64::from_str_radix(digits, radix).ok())
+            .map(|n| Value::Number(n.into()))
+    };
+
+    if let Some(value) = radix("0x", 16)
+        .or_else(|| radix("0o", 8))
+        .or_else(|| radix("0b", 2))
+    {
+        return Some(value);
+    }
+
+    if !digits.starts_with(|c: char| c.is_ascii_digit() || c == '+' || c == '-') {
+        return None;
+    }
+
+    if let Ok(n) = digits.parse::<i64>() {
+        return Some(Value::Number(n.into()));
+    }
+
+    digits
+        .parse::<f64>()
+        .ok()
+        .map(|n| Value::Number(Number::from(n)))
+}
+
+// The table at `path`, created along the way; arrays of tables lead into their last table
+fn navigate<'a>(root: &'a mut Value, path: &[String]) -> Parsed<&'a mut Mapping> {
+    let mut current = root;
+
+    for part in path {
+        let Value::Mapping(mapping) = current else {
+            return Err(format!("`{part}` is inside a value that is not a table"));
+        };
+
+        current = mapping
+            .entry(part.as_str().into())
+            .or_insert_with(|| Value::Mapping(Mapping::new()));
+
+        if let Value::Sequence(tables) = current {
+            current = tables
+                .last_mut()
+                .ok_or_else(|| format!("`{part}` is an empty array"))?;
+        }
+    }
+
+    match current {
+        Value::Mapping(mapping) => Ok(mapping),
+        _ => Err(format!("`{}` is not a table", path.join("."))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parsed(content: &str) -> Value {
+        parse("test.toml", content).unwrap()
+    }
+
+    fn yaml(content: &str) -> Value {
+        serde_yaml::from_str(content).unwrap()
+    }
+
+    // Line, column and message of the error parsing `content`
+    fn error(content: &str) -> (usize, usize, String) {
+        match parse("test.toml", content).unwrap_err() {
+            UnconfigError::Parse {
+                line,
+                column,
+                message,
+                ..
+            } => (line, column, message),
+            e => panic!("unexpected error: {e}"),
+        }
+    }
+
+    #[test]
+    fn tables() {
+        let value = parsed(
+            r#"
+            # Service
+            name = "api"
+            site.url = "https://example.com"
+
+            [server]
+            port = 8080 # inline comment
+            tls.enabled = true
+
+            [server.limits]
+            "max body" = 1_048_576
+
+            [database]
+            hosts = ["a", "b",]
+            "#,
+        );
+
+        assert_eq!(
+            value,
+            yaml(
+                r#"
+                name: api
+                site: { url: "https://example.com" }
+                server:
+                  port: 8080
+                  tls: { enabled: true }
+                  limits: { max body: 1048576 }
+                database: { hosts: [a, b] }
+                "#
+            )
+        );
+    }
+
+    #[test]
+    fn arrays_of_tables() {
+        let value = parsed(
+            r#"
+            [[products]]
+            name = "hammer"
+
+            [[products]]
+
+            [[products]]
+            name = "nail"
+            [products.size]
+            mm = 2
+            [[products.variants]]
+            color = "gray"
+            "#,
+        );
+
+        assert_eq!(
+            value,
+            yaml(
+                r#"
+                products:
+                  - name: hammer
+                  - {}
+                  - name: nail
+                    size: { mm: 2 }
+                    variants: [{ color: gray }]
+                "#
+            )
+        );
+    }
+
+    #[test]
+    fn inline_tables_and_arrays() {
+        let value = parsed(
+            r#"
+            point = { x = 1, y = -2.5, tag.name = "origin" }
+            empty = {}
+            nested = [[1, 2], ["a"], [{ a = 1 }]]
+            spread = [
+                1, # first
+                2,
+            ]
+            "#,
+        );
+
+        assert_eq!(
+            value,
+            yaml(
+                r#"
+                point: { x: 1, y: -2.5, tag: { name: origin } }
+                empty: {}
+                nested: [[1, 2], [a], [{ a: 1 }]]
+                spread: [1, 2]
+                "#
+            )
+        );
+    }
+
+    #[test]
+    fn scalars() {
+        let value = parsed(
+            r#"
+            hex = 0xff
+            octal = 0o17
+            binary = 0b101
+            float = 6.02e23
+            negative = -17
+            infinity = -inf
+            date = 1979-05-27
+            local = 1979-05-27 07:32:00
+            offset = 1979-05-27T07:32:00-08:00
+            time = 07:32:00
+            url = ${CONFIG_URL:http://localhost}
+            "#,
+        );
+
+        assert_eq!(
+            value,
+            yaml(
+                r#"
+                hex: 255
+                octal: 15
+                binary: 5
+                float: 6.02e23
+                negative: -17
+                infinity: -.inf
+                date: "1979-05-27"
+                local: "1979-05-27 07:32:00"
+                offset: "1979-05-27T07:32:00-08:00"
+                time: "07:32:00"
+                url: ${CONFIG_URL:http://localhost}
+                "#
+            )
+        );
+    }
+
+    #[test]
+    fn strings() {
+        let value = parsed(concat!(
+            "basic = \"tab\\there \\\"quoted\\\" \\u00e9\\U0001F600\\\\\"\n",
+            "literal = 'C:\\Users\\app'\n",
+            "multi = \"\"\"\nfirst\n  second\\n\"\"\"\n",
+            "trimmed = \"\"\"\\\n    one \\\n    two\"\"\"\n",
+            "quotes = \"\"\"two \"\" quotes\"\"\"\"\n",
+            "raw = '''\n\\n stays\n'''\n",
+            "raw_quotes = '''it's'''''\n",
+            "crlf = \"\"\"\r\nline\"\"\"\r\n",
+        ));
+
+        assert_eq!(value["basic"], "tab\there \"quoted\" é😀\\");
+        assert_eq!(value["literal"], "C:\\Users\\app");
+        assert_eq!(value["multi"], "first\n  second\n");
+        assert_eq!(value["trimmed"], "one two");
+        assert_eq!(value["quotes"], "two \"\" quotes\"");
+        assert_eq!(value["raw"], "\\n stays\n");
+        assert_eq!(value["raw_quotes"], "it's''");
+        assert_eq!(value["crlf"], "line");
+    }
+
+    #[test]
+    fn error_positions() {
+        let (line, column, message) = error("a = 1\nb = \n");
+        assert_eq!((line, column), (2, 5));
+        assert!(message.contains("expected a value"), "{message}");
+
+        let (line, column, message) = error("a = [1, 2\nb = 3");
+        assert_eq!((line, column), (2, 1));
+        assert!(message.contains("expected `,` or `]`"), "{message}");
+
+        let (line, column, message) = error("a = \"open\n");
+        assert_eq!((line, column), (2, 1));
+        assert!(message.contains("unterminated string"), "{message}");
+
+        let (line, column, message) = error("[server]\nport = 80 81\n");
+        assert_eq!((line, column), (2, 11));
+        assert!(message.contains("unexpected `8` after value"), "{message}");
+
+        let (line, _, message) = error("a = 1\na = 2\n");
+        assert_eq!(line, 2);
+        assert!(message.contains("key `a` is defined twice"), "{message}");
+
+        let (line, _, message) = error("[a]\n[b]\n[a]\n");
+        assert_eq!(line, 3);
+        assert!(message.contains("table `a` is defined twice"), "{message}");
+
+        let (line, column, message) = error("x = { a = 1 b = 2 }");
+        assert_eq!((line, column), (1, 13));
+        assert!(message.contains("expected `,` or `}`"), "{message}");
+
+        let (_, _, message) = error("a = 1\n[[a]]\n");
+        assert!(
+            message.contains("`a` is not an array of tables"),
+            "{message}"
+        );
+
+        let (_, _, message) = error("a = 1\n[a.b]\n");
+        assert!(message.contains("not a table"), "{message}");
+
+        let (_, _, message) = error("s = \"\\q\"");
+        assert!(message.contains("unknown escape `\\q`"), "{message}");
+
+        let (_, _, message) = error("s = \"\\uD800\"");
+        assert!(message.contains("invalid unicode escape"), "{message}");
+
+        let (_, _, message) = error("n = 12abc");
+        assert!(message.contains("invalid value `12abc`"), "{message}");
+
+        let (_, _, message) = error(&format!("n = {}", "[".repeat(1_000)));
+        assert!(message.contains("nested deeper than"), "{message}");
+    }
+}
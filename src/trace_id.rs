@@ -0,0 +1,175 @@
+use std::{
+    fmt,
+    hash::{BuildHasher, Hasher, RandomState},
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use tracing::{
+    field::{Field, Visit},
+    span, Event, Subscriber,
+};
+use tracing_subscriber::{
+    fmt::{format::Writer, FmtContext, FormatEvent, FormatFields},
+    layer::Context,
+    registry::{LookupSpan, SpanRef},
+    Layer,
+};
+
+/// Trace context of a span, inherited by its children
+#[derive(Debug, Clone)]
+struct TraceContext {
+    trace_id: String,
+    span_id: String,
+}
+
+/// Gives every span a trace and span id, so log lines can be joined with traces
+///
+/// The trace id is taken from the span's `trace_id` field or its W3C `traceparent` field
+/// (as received in request headers), then from the parent span. Root spans without one
+/// start a new trace. Span ids are always new, unless the span has a `span_id` field.
+#[derive(Clone, Copy)]
+pub(crate) struct TraceIds;
+
+impl<S> Layer<S> for TraceIds
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &span::Attributes<'_>, id: &span::Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+
+        let mut fields = Fields::default();
+        attrs.record(&mut fields);
+
+        let parent = span
+            .parent()
+            .and_then(|parent| parent.extensions().get::<TraceContext>().cloned());
+        let context = TraceContext {
+            trace_id: fields
+                .trace_id
+                .or(parent.map(|parent| parent.trace_id))
+                .unwrap_or_else(|| format!("{:016x}{:016x}", random_id(), random_id())),
+            span_id: fields
+                .span_id
+                .unwrap_or_else(|| format!("{:016x}", random_id())),
+        };
+
+        span.extensions_mut().insert(context);
+    }
+
+    // Ids are often only known after the span is created: `trace_id = Empty`
+    fn on_record(&self, id: &span::Id, values: &span::Record<'_>, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+
+        let mut fields = Fields::default();
+        values.record(&mut fields);
+
+        let mut extensions = span.extensions_mut();
+
+        if let Some(context) = extensions.get_mut::<TraceContext>() {
+            if let Some(trace_id) = fields.trace_id {
+                context.trace_id = trace_id;
+            }
+
+            if let Some(span_id) = fields.span_id {
+                context.span_id = span_id;
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+struct Fields {
+    trace_id: Option<String>,
+    span_id: Option<String>,
+}
+
+impl Fields {
+    fn record(&mut self, field: &Field, value: String) {
+        match field.name() {
+            "trace_id" => self.trace_id = Some(value),
+            "span_id" => self.span_id = Some(value),
+            // version-trace_id-parent_id-flags
+            "traceparent" => {
+                if let Some(trace_id) = value.split('-').nth(1).filter(|id| id.len() == 32) {
+                    self.trace_id.get_or_insert(trace_id.to_string());
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Visit for Fields {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.record(field, value.to_string());
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.record(field, format!("{value:016x}"));
+    }
+
+    fn record_u128(&mut self, field: &Field, value: u128) {
+        self.record(field, format!("{value:032x}"));
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.record(field, format!("{value:?}").trim_matches('"').to_string());
+    }
+}
+
+fn random_id() -> u64 {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u64(COUNTER.fetch_add(1, Ordering::Relaxed));
+
+    hasher.finish()
+}
+
+/// Trace and span id of a span, when `trace_ids` are enabled
+pub(crate) fn ids<S>(span: &SpanRef<'_, S>) -> Option<(String, String)>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    span.extensions()
+        .get::<TraceContext>()
+        .map(|context| (context.trace_id.clone(), context.span_id.clone()))
+}
+
+/// Prefixes events inside a span with its `trace_id` and `span_id`
+pub(crate) struct TraceIdFormat<F>(pub F);
+
+impl<S, N, F> FormatEvent<S, N> for TraceIdFormat<F>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+    F: FormatEvent<S, N>,
+{
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, N>,
+        mut writer: Writer<'_>,
+        event: &Event<'_>,
+    ) -> fmt::Result {
+        let span = event
+            .parent()
+            .and_then(|id| ctx.span(id))
+            .or_else(|| ctx.lookup_current());
+
+        if let Some(span) = span {
+            if let Some(context) = span.extensions().get::<TraceContext>() {
+                write!(
+                    writer,
+                    "trace_id={} span_id={} ",
+                    context.trace_id, context.span_id
+                )?;
+            }
+        }
+
+        self.0.format_event(ctx, writer, event)
+    }
+}
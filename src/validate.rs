@@ -0,0 +1,134 @@
+use std::fmt::{self, Display};
+
+use regex::Regex;
+
+use crate::UnconfigError;
+
+/// Checks a config passes once all of its layers are merged, before it's put in effect
+///
+/// `#[configurable]` structs implement it with their `#[validate(...)]` and `#[required]`
+/// rules and their deep merged fields. Other types implement it to be applied through
+/// [`crate::admin::AdminService`].
+pub trait Validate {
+    fn validate(&self) -> Result<(), UnconfigError>;
+}
+
+/// Value of a `#[configurable]` field breaking one of its `#[validate(...)]` rules, or a
+/// `#[required]` one left unset
+#[derive(Debug, Clone, PartialEq)]
+pub struct Violation {
+    /// `Struct.field`
+    pub field: String,
+    pub message: String,
+}
+
+impl Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.field, self.message)
+    }
+}
+
+/// Checks of the `#[validate(...)]` and `#[required]` fields of one struct, run by the
+/// generated `init()` once every layer is merged
+///
+/// Unset fields pass unless required, every rule of every set field is checked so that all violations
+/// are reported at once.
+#[doc(hidden)]
+pub struct Validator {
+    config: &'static str,
+    violations: Vec<Violation>,
+}
+
+impl Validator {
+    pub fn new(config: &'static str) -> Self {
+        Self {
+            config,
+            violations: vec![],
+        }
+    }
+
+    fn violation(mut self, field: &str, message: String) -> Self {
+        self.violations.push(Violation {
+            field: format!("{}.{field}", self.config),
+            message,
+        });
+
+        self
+    }
+
+    /// `#[required]`, `sources` are the files the value is looked up in
+    pub fn required(self, field: &str, set: bool, sources: &[&str]) -> Self {
+        if set {
+            return self;
+        }
+
+        // The runtime file is often the embedded one
+        let mut sources = sources.to_vec();
+        sources.dedup();
+
+        self.violation(
+            field,
+            format!("is required but not set in {}", sources.join(" or ")),
+        )
+    }
+
+    /// `range(min = ..., max = ...)`, both bounds inclusive
+    pub fn range<T: PartialOrd + Display>(
+        self,
+        field: &str,
+        value: Option<&T>,
+        min: Option<T>,
+        max: Option<T>,
+    ) -> Self {
+        let Some(value) = value else {
+            return self;
+        };
+
+        match (min, max) {
+            (Some(min), Some(max)) if *value < min || *value > max => {
+                self.violation(field, format!("{value} is not within {min}..={max}"))
+            }
+            (Some(min), None) if *value < min => {
+                self.violation(field, format!("{value} is less than {min}"))
+            }
+            (None, Some(max)) if *value > max => {
+                self.violation(field, format!("{value} is greater than {max}"))
+            }
+            _ => self,
+        }
+    }
+
+    /// `regex = "..."`, matching anywhere in the value unless anchored
+    pub fn regex<T: AsRef<str>>(self, field: &str, value: Option<&T>, pattern: &str) -> Self {
+        let Some(value) = value.map(AsRef::as_ref) else {
+            return self;
+        };
+
+        match Regex::new(pattern) {
+            Ok(regex) if regex.is_match(value) => self,
+            Ok(_) => self.violation(field, format!("{value:?} doesn't match `{pattern}`")),
+            Err(e) => self.violation(field, format!("invalid pattern `{pattern}`: {e}")),
+        }
+    }
+
+    /// `custom = path::to::fn`, a `fn(&T) -> Result<(), E>` whose error is the message
+    pub fn custom<T, E: Display>(
+        self,
+        field: &str,
+        value: Option<&T>,
+        check: impl FnOnce(&T) -> Result<(), E>,
+    ) -> Self {
+        match value.map(check) {
+            Some(Err(e)) => self.violation(field, e.to_string()),
+            _ => self,
+        }
+    }
+
+    pub fn finish(self) -> Result<(), UnconfigError> {
+        if self.violations.is_empty() {
+            Ok(())
+        } else {
+            Err(UnconfigError::Violations(self.violations))
+        }
+    }
+}
@@ -0,0 +1,207 @@
+use std::{
+    collections::HashMap,
+    io,
+    sync::{LazyLock, Mutex},
+};
+
+use anyhow::{anyhow, bail, Context, Result};
+use serde::de::DeserializeOwned;
+use serde_yaml::{Mapping, Value};
+use tracing::debug;
+
+use crate::{env_overlay, health, http, json, pipeline, provenance, UnconfigError, UrlOptions};
+
+/// Address of the Vault server, e.g. `http://127.0.0.1:8200`
+pub const VAULT_ADDR_VAR: &str = "VAULT_ADDR";
+/// Token to read secrets with, takes precedence over AppRole
+pub const VAULT_TOKEN_VAR: &str = "VAULT_TOKEN";
+/// AppRole credentials, exchanged for a token at the first read
+pub const VAULT_ROLE_ID_VAR: &str = "VAULT_ROLE_ID";
+pub const VAULT_SECRET_ID_VAR: &str = "VAULT_SECRET_ID";
+/// Enterprise namespace sent with every request, if set
+pub const VAULT_NAMESPACE_VAR: &str = "VAULT_NAMESPACE";
+
+// Token of the last AppRole login, kept until Vault rejects it
+static LOGIN: LazyLock<Mutex<Option<String>>> = LazyLock::new(Default::default);
+
+/// `section` of a config holding the `#[vault]` fields, each `(field, path, key)` read
+/// from the secret at `path`
+///
+/// Each path is read once, KV version 2 secrets unwrapped from their metadata. Values are
+/// taken as they are, without `${...}` substitution.
+#[doc(hidden)]
+pub fn load_vault<T: DeserializeOwned>(
+    section: &str,
+    fields: &[(&str, &str, &str)],
+) -> Result<T, UnconfigError> {
+    let result = read_fields(fields);
+    health::record_provider(
+        "vault",
+        result.as_ref().err().map(|(_, e)| format!("{e:#}")),
+    );
+    let fields = result.map_err(|(path, e)| UnconfigError::Io {
+        path: format!("vault:{path}"),
+        source: io::Error::other(format!("{e:#}")),
+    })?;
+
+    let value = Value::Mapping(Mapping::from_iter([(
+        Value::from(section),
+        Value::Mapping(fields),
+    )]));
+    let config = pipeline::deserialize("vault", &value)?;
+    if provenance::recording() {
+        provenance::record("vault", value.clone(), &value);
+    }
+
+    Ok(config)
+}
+
+// The field values, or the path that failed
+fn read_fields(fields: &[(&str, &str, &str)]) -> Result<Mapping, (String, anyhow::Error)> {
+    let mut secrets = HashMap::new();
+    let mut values = Mapping::new();
+
+    for &(field, path, key) in fields {
+        if !secrets.contains_key(path) {
+            let secret = read(path).map_err(|e| (path.to_string(), e))?;
+            secrets.insert(path, secret);
+        }
+
+        let value = secrets[path]
+            .get(key)
+            .ok_or_else(|| (path.to_string(), anyhow!("the secret has no `{key}`")))?;
+        values.insert(Value::from(field), value.clone());
+    }
+
+    Ok(values)
+}
+
+// The key-value pairs of the secret at `path`
+fn read(path: &str) -> Result<Mapping> {
+    let addr =
+        env_overlay::var(VAULT_ADDR_VAR).map_err(|_| anyhow!("{VAULT_ADDR_VAR} is not set"))?;
+    let url = format!(
+        "{}/v1/{}",
+        addr.trim_end_matches('/'),
+        path.trim_matches('/')
+    );
+
+    let (status, body) = match credentials()? {
+        Token::Static(token) => get(&url, &token)?,
+        Token::AppRole(token) => match get(&url, &token)? {
+            // Expired, log in again once
+            (403, _) => {
+                LOGIN.lock().unwrap().take();
+                let (Token::Static(token) | Token::AppRole(token)) = credentials()?;
+
+                get(&url, &token)?
+            }
+            response => response,
+        },
+    };
+
+    match status {
+        200 => {}
+        404 => bail!("no secret at {path}"),
+        status => bail!("Vault answered {status}: {}", errors(&body)),
+    }
+
+    debug!("Read the Vault secret {path}");
+
+    let response: Value = serde_yaml::from_str(&body).context("malformed Vault response")?;
+    let data = match response.get("data") {
+        // KV version 2
+        Some(data) if data.get("metadata").is_some() => data.get("data"),
+        data => data,
+    };
+
+    match data {
+        Some(Value::Mapping(data)) => Ok(data.clone()),
+        _ => bail!("malformed Vault response, expected a `data` mapping"),
+    }
+}
+
+enum Token {
+    Static(String),
+    AppRole(String),
+}
+
+fn credentials() -> Result<Token> {
+    if let Ok(token) = env_overlay::var(VAULT_TOKEN_VAR) {
+        return Ok(Token::Static(token));
+    }
+
+    let mut login = LOGIN.lock().unwrap();
+    if let Some(token) = &*login {
+        return Ok(Token::AppRole(token.clone()));
+    }
+
+    let (Ok(role_id), Ok(secret_id)) = (
+        env_overlay::var(VAULT_ROLE_ID_VAR),
+        env_overlay::var(VAULT_SECRET_ID_VAR),
+    ) else {
+        bail!(
+            "no Vault credentials, set {VAULT_TOKEN_VAR} or {VAULT_ROLE_ID_VAR} and {VAULT_SECRET_ID_VAR}"
+        );
+    };
+
+    let addr =
+        env_overlay::var(VAULT_ADDR_VAR).map_err(|_| anyhow!("{VAULT_ADDR_VAR} is not set"))?;
+    let url = format!("{}/v1/auth/approle/login", addr.trim_end_matches('/'));
+    let body = json::to_string(&Value::Mapping(Mapping::from_iter([
+        (Value::from("role_id"), Value::from(role_id)),
+        (Value::from("secret_id"), Value::from(secret_id)),
+    ])));
+
+    let (status, response) = call("POST", &url, None, &body)?;
+    if status != 200 {
+        bail!(
+            "AppRole login failed, Vault answered {status}: {}",
+            errors(&response)
+        );
+    }
+
+    let response: Value = serde_yaml::from_str(&response).context("malformed Vault response")?;
+    let token = response
+        .get("auth")
+        .and_then(|auth| auth.get("client_token"))
+        .and_then(Value::as_str)
+        .ok_or(anyhow!(
+            "malformed AppRole login response, no `auth.client_token`"
+        ))?
+        .to_string();
+
+    debug!("Logged in to Vault with AppRole");
+    *login = Some(token.clone());
+
+    Ok(Token::AppRole(token))
+}
+
+fn get(url: &str, token: &str) -> Result<(u16, String)> {
+    call("GET", url, Some(token), "")
+}
+
+fn call(method: &str, url: &str, token: Option<&str>, body: &str) -> Result<(u16, String)> {
+    let namespace = env_overlay::var(VAULT_NAMESPACE_VAR).unwrap_or_default();
+    let mut headers = vec![];
+    if let Some(token) = token {
+        headers.push(("X-Vault-Token", token));
+    }
+    if !namespace.is_empty() {
+        headers.push(("X-Vault-Namespace", namespace.as_str()));
+    }
+
+    let response = http::request(method, url, &headers, body, &UrlOptions::default())?;
+
+    Ok((response.status, response.text()?))
+}
+
+// The `errors` Vault answers with, joined
+fn errors(body: &str) -> String {
+    serde_yaml::from_str::<Value>(body)
+        .ok()
+        .and_then(|body| body.get("errors").cloned())
+        .and_then(|errors| serde_yaml::from_value::<Vec<String>>(errors).ok())
+        .map(|errors| errors.join(", "))
+        .unwrap_or_else(|| body.trim().to_string())
+}
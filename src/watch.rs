@@ -0,0 +1,399 @@
+use std::{
+    fs,
+    panic::{self, AssertUnwindSafe},
+    path::{Path, PathBuf},
+    sync::{
+        mpsc::{self, RecvTimeoutError, Sender},
+        Arc, Mutex, RwLock,
+    },
+    thread,
+    time::{Duration, SystemTime},
+};
+
+use anyhow::Result;
+use serde_yaml::Value;
+use tracing::{debug, error, warn};
+
+#[cfg(feature = "consul")]
+use crate::consul;
+#[cfg(feature = "etcd")]
+use crate::etcd;
+use crate::{drift, events, full_path, health, read_path, schedule, Event};
+
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+type Reload<T> = Box<dyn Fn() -> Result<T> + Send + Sync>;
+type Callback<T> = Arc<dyn Fn(&T, &T) + Send + Sync>;
+// Modification time and size of a file, the index of a Consul key or the digest of the
+// revisions under an etcd prefix
+type Stamp = Option<(Option<SystemTime>, u64)>;
+
+/// Latest value of a config, reloaded in the background whenever its file changes
+///
+/// `#[config(watch, User)]` makes `CONFIG_USER` one of these, loading it like the plain
+/// static and reloading through the same files afterwards. A reload that fails is logged
+/// and recorded in [`crate::health`], the previous value stays in effect.
+///
+/// The file is polled for changes of its modification time or size, so this works on
+/// any filesystem and through symlink swaps, e.g. Kubernetes config maps. A Consul key,
+/// see [`ConfigWatcher::consul`], is watched with blocking queries instead, and an etcd
+/// prefix, see [`ConfigWatcher::etcd`], by polling the revisions of its keys. Watching
+/// stops when the watcher and all its clones are dropped.
+///
+/// A scheduled value, an `until` / `then` mapping, is reloaded when its `until` passes too,
+/// so the switch doesn't wait for the next change of the file.
+pub struct ConfigWatcher<T> {
+    shared: Arc<Shared<T>>,
+    // Dropping it ends the polling thread
+    _stop: Sender<()>,
+}
+
+/// Another handle to the same watcher, watching stops when the last one is dropped
+impl<T> Clone for ConfigWatcher<T> {
+    fn clone(&self) -> Self {
+        Self {
+            shared: self.shared.clone(),
+            _stop: self._stop.clone(),
+        }
+    }
+}
+
+struct Shared<T> {
+    source: Source,
+    current: RwLock<Arc<T>>,
+    reload: Reload<T>,
+    stamp: Mutex<Stamp>,
+    // Earliest `until` ahead among the scheduled values of the last reload
+    switch: Mutex<Option<i64>>,
+    // The source as of the last reload, for the diff of the next one
+    raw: Mutex<Arc<Value>>,
+    callbacks: RwLock<Vec<Callback<T>>>,
+}
+
+impl<T: Send + Sync + 'static> ConfigWatcher<T> {
+    /// Watch `path`, starting from `initial` and loading the next values with `reload`
+    pub fn new<F, E>(path: impl AsRef<Path>, initial: T, reload: F) -> Self
+    where
+        F: Fn() -> Result<T, E> + Send + Sync + 'static,
+        E: Into<anyhow::Error>,
+    {
+        Self::with_interval(path, POLL_INTERVAL, initial, reload)
+    }
+
+    pub fn with_interval<F, E>(
+        path: impl AsRef<Path>,
+        interval: Duration,
+        initial: T,
+        reload: F,
+    ) -> Self
+    where
+        F: Fn() -> Result<T, E> + Send + Sync + 'static,
+        E: Into<anyhow::Error>,
+    {
+        // Looked up like the loaders do
+        let path = full_path(&path).unwrap_or_else(|_| path.as_ref().to_path_buf());
+
+        Self::start(Source::File(path), interval, initial, reload)
+    }
+
+    /// Watch the Consul KV `key`, reloading once its value changes
+    ///
+    /// The watcher holds a blocking query open on the key, so changes are picked up as
+    /// Consul publishes them; a query that fails is retried after a second.
+    #[cfg(feature = "consul")]
+    pub fn consul<F, E>(key: &str, initial: T, reload: F) -> Self
+    where
+        F: Fn() -> Result<T, E> + Send + Sync + 'static,
+        E: Into<anyhow::Error>,
+    {
+        Self::start(
+            Source::Consul(key.to_string()),
+            POLL_INTERVAL,
+            initial,
+            reload,
+        )
+    }
+
+    /// Watch the keys under the etcd `prefix`, reloading once one of them is put or deleted
+    ///
+    /// The revisions of the keys are polled every second, their values only read again on
+    /// a change.
+    #[cfg(feature = "etcd")]
+    pub fn etcd<F, E>(prefix: &str, initial: T, reload: F) -> Self
+    where
+        F: Fn() -> Result<T, E> + Send + Sync + 'static,
+        E: Into<anyhow::Error>,
+    {
+        Self::start(
+            Source::Etcd(prefix.to_string()),
+            POLL_INTERVAL,
+            initial,
+            reload,
+        )
+    }
+
+    fn start<F, E>(source: Source, interval: Duration, initial: T, reload: F) -> Self
+    where
+        F: Fn() -> Result<T, E> + Send + Sync + 'static,
+        E: Into<anyhow::Error>,
+    {
+        let raw = source.raw();
+        // `initial` was loaded elsewhere, its switches are told from the source
+        let switch = schedule::activate(&mut Value::clone(&raw)).unwrap_or_default();
+        let shared = Arc::new(Shared {
+            stamp: Mutex::new(source.stamp(None)),
+            switch: Mutex::new(switch),
+            raw: Mutex::new(raw),
+            source,
+            current: RwLock::new(Arc::new(initial)),
+            reload: Box::new(move || reload().map_err(Into::into)),
+            callbacks: RwLock::default(),
+        });
+        let (stop, stopped) = mpsc::channel();
+
+        let watched = shared.clone();
+        thread::Builder::new()
+            .name("config-watcher".to_string())
+            .spawn(move || {
+                while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                    watched.poll();
+                }
+            })
+            .expect("failed to spawn the config watcher thread");
+
+        Self {
+            shared,
+            _stop: stop,
+        }
+    }
+
+    /// The value in effect now, later reloads don't change it
+    pub fn load(&self) -> Arc<T> {
+        self.shared.current.read().unwrap().clone()
+    }
+
+    /// Call `f` with the previous and the new value after every successful reload
+    ///
+    /// Callbacks run in registration order on the thread that reloaded, the watcher's own
+    /// one unless [`Self::reload`] is called. They're called even if nothing the
+    /// application uses changed, compare the values to tell. A panicking callback is
+    /// logged and doesn't stop the others or the watcher.
+    pub fn on_change<F>(&self, f: F)
+    where
+        F: Fn(&T, &T) + Send + Sync + 'static,
+    {
+        self.shared.callbacks.write().unwrap().push(Arc::new(f));
+    }
+
+    /// Reload now, whether the file changed or not
+    pub fn reload(&self) -> Result<()> {
+        self.shared.reload()
+    }
+
+    /// Put `value` in effect as a reload would, until the file changes again
+    ///
+    /// Nothing is checked, validate `value` first, e.g. with [`crate::Validate`]. No
+    /// [`crate::Event`] is published either, the watcher can't tell what changed.
+    pub fn store(&self, value: T, source: &str) {
+        health::record_reload(source, None);
+        self.shared.install(value, source);
+    }
+
+    /// The watched file, key for [`ConfigWatcher::consul`] or prefix for
+    /// [`ConfigWatcher::etcd`]
+    pub fn path(&self) -> &Path {
+        match &self.shared.source {
+            Source::File(path) => path,
+            #[cfg(feature = "consul")]
+            Source::Consul(key) => Path::new(key),
+            #[cfg(feature = "etcd")]
+            Source::Etcd(prefix) => Path::new(prefix),
+        }
+    }
+}
+
+impl<T> Shared<T> {
+    fn poll(&self) {
+        let last = *self.stamp.lock().unwrap();
+        let stamp = self.source.stamp(last);
+        let changed = {
+            let mut last = self.stamp.lock().unwrap();
+            let changed = *last != stamp;
+            *last = stamp;
+
+            changed
+        };
+
+        let switched = self
+            .switch
+            .lock()
+            .unwrap()
+            .is_some_and(|until| schedule::now() >= until);
+
+        if changed {
+            debug!("{} changed, reloading", self.source.name());
+            let _ = self.reload();
+        } else if switched {
+            debug!(
+                "A scheduled value of {} switched, reloading",
+                self.source.name()
+            );
+            let _ = self.reload();
+        }
+    }
+
+    fn reload(&self) -> Result<()> {
+        let source = self.source.name();
+        let (reloaded, switch) = schedule::recording(|| (self.reload)());
+        *self.switch.lock().unwrap() = switch;
+        health::record_reload(&source, reloaded.as_ref().err().map(|e| format!("{e:#}")));
+
+        match reloaded {
+            Ok(value) => {
+                let new = self.source.raw();
+                let old = std::mem::replace(&mut *self.raw.lock().unwrap(), new.clone());
+                self.install(value, &source);
+                events::publish(Event::ReloadSucceeded {
+                    source,
+                    diff: drift::diff(&old, &new, &[]),
+                });
+
+                Ok(())
+            }
+            Err(e) => {
+                warn!("Failed to reload {source}, keeping the previous config: {e:#}");
+                events::publish(Event::ReloadFailed {
+                    source,
+                    error: format!("{e:#}"),
+                });
+
+                Err(e)
+            }
+        }
+    }
+
+    fn install(&self, value: T, source: &str) {
+        let new = Arc::new(value);
+        let old = std::mem::replace(&mut *self.current.write().unwrap(), new.clone());
+
+        // Not under the lock, callbacks may register others
+        let callbacks = self.callbacks.read().unwrap().clone();
+
+        for callback in callbacks {
+            let called = panic::catch_unwind(AssertUnwindSafe(|| callback(&old, &new)));
+
+            if called.is_err() {
+                error!("A change callback of {source} panicked");
+            }
+        }
+    }
+}
+
+// Where the config is reloaded from
+enum Source {
+    File(PathBuf),
+    #[cfg(feature = "consul")]
+    Consul(String),
+    #[cfg(feature = "etcd")]
+    Etcd(String),
+}
+
+impl Source {
+    fn name(&self) -> String {
+        match self {
+            Self::File(path) => path.display().to_string(),
+            #[cfg(feature = "consul")]
+            Self::Consul(key) => consul::source(key),
+            #[cfg(feature = "etcd")]
+            Self::Etcd(prefix) => etcd::source(prefix),
+        }
+    }
+
+    // Null when the source can't be read or parsed
+    fn raw(&self) -> Arc<Value> {
+        match self {
+            Self::File(path) => read_path(path).map(|(_, raw)| raw).unwrap_or_default(),
+            #[cfg(feature = "consul")]
+            Self::Consul(key) => consul::read(key).map(|(raw, _)| raw).unwrap_or_default(),
+            #[cfg(feature = "etcd")]
+            Self::Etcd(prefix) => etcd::read(prefix).map(|(raw, _)| raw).unwrap_or_default(),
+        }
+    }
+
+    // Changes whenever the config may have, `last` being the previous stamp
+    fn stamp(&self, last: Stamp) -> Stamp {
+        let _ = last;
+
+        match self {
+            // Size too, since a quick edit may keep the modification time
+            Self::File(path) => fs::metadata(path)
+                .ok()
+                .map(|meta| (meta.modified().ok(), meta.len())),
+            // The modify index, waited on past the last one
+            #[cfg(feature = "consul")]
+            Self::Consul(key) => {
+                let Some((_, index)) = last else {
+                    return consul::read(key).ok().map(|(_, index)| (None, index));
+                };
+
+                match consul::wait(key, index) {
+                    Ok(index) => Some((None, index)),
+                    Err(e) => {
+                        warn!("Failed to watch {}: {e:#}", consul::source(key));
+
+                        last
+                    }
+                }
+            }
+            // The keys and their modification revisions, kept as they were when etcd can't
+            // be reached
+            #[cfg(feature = "etcd")]
+            Self::Etcd(prefix) => match etcd::revisions(prefix) {
+                Ok(digest) => Some((None, digest)),
+                Err(e) => {
+                    warn!("Failed to watch {}: {e:#}", etcd::source(prefix));
+
+                    last
+                }
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use super::*;
+
+    #[test]
+    fn reloads_when_scheduled() {
+        let path = std::env::temp_dir().join(format!("unconfig-watch-{}.yml", std::process::id()));
+        let until = schedule::format_timestamp(schedule::now() + 2);
+        fs::write(
+            &path,
+            format!("limit: {{ value: 1000, until: \"{until}\", then: 200 }}\n"),
+        )
+        .unwrap();
+
+        let load = {
+            let path = path.clone();
+            move || -> Result<u64> {
+                let mut value: Value = serde_yaml::from_str(&fs::read_to_string(&path)?)?;
+                schedule::activate(&mut value)?;
+
+                Ok(serde_yaml::from_value(value["limit"].clone())?)
+            }
+        };
+        let watcher =
+            ConfigWatcher::with_interval(&path, Duration::from_millis(50), load().unwrap(), load);
+        assert_eq!(*watcher.load(), 1000);
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while *watcher.load() != 200 && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(50));
+        }
+        fs::remove_file(&path).unwrap();
+        assert_eq!(*watcher.load(), 200);
+    }
+}
@@ -0,0 +1,308 @@
+//! Build script helpers for crates configured with unconfig
+//!
+//! [`emit_docs_and_schema!`] reads the `#[configurable]` structs of the crate and writes
+//! into `OUT_DIR`, for packaging along with the binary:
+//!
+//! * `config.schema.json` - JSON Schema of the whole config file, as the macro derives it
+//! * `config.example.yml` - every section and field, with placeholder values and the doc
+//!   comments as comments
+//! * `config.md` - a table of the fields of each section
+//! * `config.template.yml` - skeleton config pointing YAML language servers at the
+//!   schema, editors only need its header line (or a `yaml.schemas` setting) to validate
+//!   and complete configs
+//!
+//! ```no_run
+//! // In `main` of build.rs, for all of `src/`, or `emit_docs_and_schema!("src/config.rs")`
+//! unconfig_build::emit_docs_and_schema!();
+//! ```
+//!
+//! The files only depend on the sources, sections and fields coming out in the same order
+//! on every build, and are left untouched when their content doesn't change.
+
+pub mod schema;
+
+use std::{
+    env, fs, io,
+    path::{Path, PathBuf},
+};
+
+use convert_case::{Case, Casing};
+use quote::ToTokens;
+use syn::{GenericArgument, Item, ItemStruct, PathArguments, Type};
+
+/// Write the config schema, example and docs of the `#[configurable]` structs into
+/// `OUT_DIR`, see the [crate] docs
+///
+/// Takes files or directories relative to the package root, `src` by default, and
+/// fails the build when one can't be read or parsed.
+#[macro_export]
+macro_rules! emit_docs_and_schema {
+    () => {
+        $crate::emit_docs_and_schema!("src")
+    };
+    ($($source:expr),+ $(,)?) => {
+        if let Err(e) = $crate::emit(&[$($source),+]) {
+            panic!("failed to emit config docs: {e}");
+        }
+    };
+}
+
+struct Section {
+    name: String,
+    doc: String,
+    fields: Vec<Field>,
+}
+
+struct Field {
+    name: String,
+    ty: Type,
+    doc: String,
+    schema: String,
+}
+
+/// What [`emit_docs_and_schema!`] expands to
+pub fn emit(sources: &[&str]) -> io::Result<()> {
+    let root = PathBuf::from(var("CARGO_MANIFEST_DIR")?);
+    let out_dir = PathBuf::from(var("OUT_DIR")?);
+    let mut sections = vec![];
+
+    for source in sources {
+        let path = root.join(source);
+        println!("cargo:rerun-if-changed={}", path.display());
+
+        collect(&path, &mut sections)?;
+    }
+
+    sections.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let schema_path = out_dir.join("config.schema.json");
+    write(&schema_path, &json_schema(&sections))?;
+    write(&out_dir.join("config.example.yml"), &example(&sections))?;
+    write(&out_dir.join("config.md"), &markdown(&sections))?;
+    write(
+        &out_dir.join("config.template.yml"),
+        &template(&schema_path, &sections),
+    )
+}
+
+fn var(name: &str) -> io::Result<String> {
+    env::var(name).map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("{name} is not set, call this from a build script"),
+        )
+    })
+}
+
+// Sources of a directory in name order, for the same output on every build
+fn collect(path: &Path, sections: &mut Vec<Section>) -> io::Result<()> {
+    if path.is_dir() {
+        let mut entries = fs::read_dir(path)?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<io::Result<Vec<_>>>()?;
+        entries.sort();
+
+        for entry in entries {
+            if entry.is_dir() || entry.extension().is_some_and(|extension| extension == "rs") {
+                collect(&entry, sections)?;
+            }
+        }
+
+        return Ok(());
+    }
+
+    let file = syn::parse_file(&fs::read_to_string(path)?).map_err(|e| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{}: {e}", path.display()),
+        )
+    })?;
+    collect_items(&file.items, sections);
+
+    Ok(())
+}
+
+fn collect_items(items: &[Item], sections: &mut Vec<Section>) {
+    for item in items {
+        match item {
+            Item::Struct(item) if is_configurable(item) => sections.push(section(item)),
+            Item::Mod(item) => {
+                if let Some((_, items)) = &item.content {
+                    collect_items(items, sections);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+fn is_configurable(item: &ItemStruct) -> bool {
+    item.attrs.iter().any(|attr| {
+        attr.path()
+            .segments
+            .last()
+            .is_some_and(|segment| segment.ident == "configurable")
+    })
+}
+
+// Named like the macro names the section
+fn section(item: &ItemStruct) -> Section {
+    let fields = item
+        .fields
+        .iter()
+        .filter_map(|field| {
+            Some(Field {
+                name: field.ident.as_ref()?.to_string(),
+                ty: field.ty.clone(),
+                doc: schema::doc(&field.attrs),
+                schema: schema::field_schema(&field.ty, &field.attrs),
+            })
+        })
+        .collect();
+
+    Section {
+        name: item.ident.to_string().to_case(Case::Snake),
+        doc: schema::doc(&item.attrs),
+        fields,
+    }
+}
+
+fn json_schema(sections: &[Section]) -> String {
+    let properties = sections
+        .iter()
+        .map(|section| {
+            let fields = section
+                .fields
+                .iter()
+                .map(|field| (field.name.clone(), field.schema.clone()))
+                .collect::<Vec<_>>();
+
+            format!(
+                "{}:{}",
+                schema::string(&section.name),
+                schema::object(&fields)
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(",");
+
+    format!(
+        r#"{{"$schema":"http://json-schema.org/draft-07/schema#","type":"object","properties":{{{properties}}}}}"#
+    )
+}
+
+fn template(schema_path: &Path, sections: &[Section]) -> String {
+    sections.iter().fold(
+        format!(
+            "# yaml-language-server: $schema={}\n",
+            schema_path.display()
+        ),
+        |acc, section| format!("{acc}\n{}:\n", section.name),
+    )
+}
+
+fn example(sections: &[Section]) -> String {
+    let comment = |doc: &str, indent: &str| {
+        doc.lines()
+            .map(|line| format!("{indent}# {line}\n"))
+            .collect::<String>()
+    };
+
+    sections
+        .iter()
+        .map(|section| {
+            let fields = section
+                .fields
+                .iter()
+                .map(|field| {
+                    format!(
+                        "{}  {}: {}\n",
+                        comment(&field.doc, "  "),
+                        field.name,
+                        placeholder(&field.ty)
+                    )
+                })
+                .collect::<String>();
+
+            format!("{}{}:\n{fields}", comment(&section.doc, ""), section.name)
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn markdown(sections: &[Section]) -> String {
+    let cell = |text: &str| text.replace('|', "\\|").replace('\n', " ");
+
+    sections
+        .iter()
+        .fold(String::from("# Configuration\n"), |acc, section| {
+            let doc = match section.doc.as_str() {
+                "" => String::new(),
+                doc => format!("{doc}\n\n"),
+            };
+            let rows = section
+                .fields
+                .iter()
+                .map(|field| {
+                    format!(
+                        "| `{}.{}` | `{}` | {} |\n",
+                        section.name,
+                        field.name,
+                        type_name(&field.ty),
+                        cell(&field.doc)
+                    )
+                })
+                .collect::<String>();
+
+            format!(
+                "{acc}\n## `{}`\n\n{doc}| Key | Type | Description |\n|---|---|---|\n{rows}",
+                section.name
+            )
+        })
+}
+
+// As written, without the spaces token streams put around punctuation
+fn type_name(ty: &Type) -> String {
+    ty.to_token_stream()
+        .to_string()
+        .replace(' ', "")
+        .replace(',', ", ")
+}
+
+// Value of the example config, of the right type where it can be told
+fn placeholder(ty: &Type) -> &'static str {
+    let Type::Path(path) = ty else {
+        return "~";
+    };
+    let Some(segment) = path.path.segments.last() else {
+        return "~";
+    };
+    let inner = match &segment.arguments {
+        PathArguments::AngleBracketed(args) => args.args.iter().find_map(|arg| match arg {
+            GenericArgument::Type(ty) => Some(ty),
+            _ => None,
+        }),
+        _ => None,
+    };
+
+    match segment.ident.to_string().as_str() {
+        "String" | "str" | "PathBuf" | "char" => "\"\"",
+        "bool" => "false",
+        "u8" | "u16" | "u32" | "u64" | "u128" | "usize" | "i8" | "i16" | "i32" | "i64" | "i128"
+        | "isize" => "0",
+        "f32" | "f64" => "0.0",
+        "Option" | "Box" | "Deep" => inner.map_or("~", placeholder),
+        "Vec" | "VecDeque" | "HashSet" | "BTreeSet" | "IndexSet" => "[]",
+        "HashMap" | "BTreeMap" | "IndexMap" => "{}",
+        _ => "~",
+    }
+}
+
+// Rewriting an unchanged file would make cargo rebuild whatever includes it
+fn write(path: &Path, content: &str) -> io::Result<()> {
+    if fs::read_to_string(path).is_ok_and(|current| current == content) {
+        return Ok(());
+    }
+
+    fs::write(path, content)
+}
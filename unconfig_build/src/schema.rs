@@ -0,0 +1,124 @@
+//! JSON Schema of config sections, told from the field types of `#[configurable]`
+//! structs, shared by the macro and the build helper so that both agree
+
+use syn::{Attribute, Expr, GenericArgument, Lit, Meta, PathArguments, Type};
+
+/// Standalone JSON Schema of one config section, as returned by the generated
+/// `json_schema()`
+pub fn section_schema(section: &str, fields: &[(String, String)]) -> String {
+    format!(
+        r#"{{"$schema":"http://json-schema.org/draft-07/schema#","title":{},{}"#,
+        string(section),
+        &object(fields)[1..]
+    )
+}
+
+/// Object with the given `(name, schema)` properties
+pub fn object(fields: &[(String, String)]) -> String {
+    let properties = fields
+        .iter()
+        .map(|(name, schema)| format!("{}:{schema}", string(name)))
+        .collect::<Vec<_>>()
+        .join(",");
+
+    format!(r#"{{"type":"object","properties":{{{properties}}}}}"#)
+}
+
+/// JSON Schema of a field type, as far as it can be told from the type's name
+///
+/// Types defined elsewhere can't be inspected from the macro and accept anything.
+pub fn type_schema(ty: &Type) -> String {
+    let Type::Path(path) = ty else {
+        return "{}".to_string();
+    };
+    let Some(segment) = path.path.segments.last() else {
+        return "{}".to_string();
+    };
+
+    let args = match &segment.arguments {
+        PathArguments::AngleBracketed(args) => args
+            .args
+            .iter()
+            .filter_map(|arg| match arg {
+                GenericArgument::Type(ty) => Some(ty),
+                _ => None,
+            })
+            .collect(),
+        _ => vec![],
+    };
+
+    match (segment.ident.to_string().as_str(), args.as_slice()) {
+        ("String" | "str" | "PathBuf" | "char", _) => r#"{"type":"string"}"#.to_string(),
+        ("bool", _) => r#"{"type":"boolean"}"#.to_string(),
+        ("u8" | "u16" | "u32" | "u64" | "u128" | "usize", _) => {
+            r#"{"type":"integer","minimum":0}"#.to_string()
+        }
+        ("i8" | "i16" | "i32" | "i64" | "i128" | "isize", _) => r#"{"type":"integer"}"#.to_string(),
+        ("f32" | "f64", _) => r#"{"type":"number"}"#.to_string(),
+        ("Option" | "Box" | "Deep", [inner]) => type_schema(inner),
+        ("Vec" | "VecDeque" | "HashSet" | "BTreeSet" | "IndexSet", [item]) => {
+            format!(r#"{{"type":"array","items":{}}}"#, type_schema(item))
+        }
+        ("HashMap" | "BTreeMap" | "IndexMap", [_, value]) => {
+            format!(
+                r#"{{"type":"object","additionalProperties":{}}}"#,
+                type_schema(value)
+            )
+        }
+        _ => "{}".to_string(),
+    }
+}
+
+/// Same as [`type_schema`], with the field's doc comment as description
+pub fn field_schema(ty: &Type, attrs: &[Attribute]) -> String {
+    let schema = type_schema(ty);
+    let doc = doc(attrs);
+
+    if doc.is_empty() {
+        return schema;
+    }
+
+    let description = format!(r#""description":{}"#, string(&doc));
+
+    match schema.as_str() {
+        "{}" => format!("{{{description}}}"),
+        _ => format!("{{{description},{}", &schema[1..]),
+    }
+}
+
+/// Doc comment lines, trimmed and joined
+pub fn doc(attrs: &[Attribute]) -> String {
+    attrs
+        .iter()
+        .filter(|attr| attr.path().is_ident("doc"))
+        .filter_map(|attr| match &attr.meta {
+            Meta::NameValue(meta) => match &meta.value {
+                Expr::Lit(expr) => match &expr.lit {
+                    Lit::Str(doc) => Some(doc.value().trim().to_string()),
+                    _ => None,
+                },
+                _ => None,
+            },
+            _ => None,
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// JSON string literal
+pub fn string(text: &str) -> String {
+    let mut out = String::from('"');
+
+    for c in text.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+
+    out.push('"');
+    out
+}